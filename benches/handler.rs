@@ -0,0 +1,60 @@
+//! Benchmarks of the CORS handler for the common kinds of requests.
+//!
+//! Run with `cargo bench`.
+
+#[macro_use] extern crate criterion;
+extern crate iron;
+extern crate iron_cors;
+extern crate iron_test;
+
+use criterion::Criterion;
+use iron::{Handler, Request, Response, IronResult, status};
+use iron::headers::{AccessControlRequestMethod, Headers, Origin};
+use iron::method::Method;
+use iron_cors::CorsMiddleware;
+use iron_test::request;
+
+struct HelloWorldHandler;
+impl Handler for HelloWorldHandler {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        Ok(Response::with((status::Ok, "Hello, world!")))
+    }
+}
+
+fn whitelist_handler() -> impl Handler {
+    CorsMiddleware::builder()
+        .allowed_origins(vec!["https://example.com".to_string(), "http://localhost:3000".to_string()])
+        .skip_same_origin(true)
+        .build()
+        .unwrap()
+        .wrap(HelloWorldHandler {})
+}
+
+fn origin_headers(scheme: &str, host: &str, port: Option<u16>) -> Headers {
+    let mut headers = Headers::new();
+    headers.set(Origin::new(scheme, host, port));
+    headers
+}
+
+fn bench_requests(c: &mut Criterion) {
+    let handler = whitelist_handler();
+    let url = "http://localhost:3000/hello";
+
+    c.bench_function("no origin", |b| b.iter(|| {
+        request::get(url, Headers::new(), &handler).unwrap()
+    }));
+    c.bench_function("same origin", |b| b.iter(|| {
+        request::get(url, origin_headers("http", "localhost", Some(3000)), &handler).unwrap()
+    }));
+    c.bench_function("allowed origin", |b| b.iter(|| {
+        request::get(url, origin_headers("https", "example.com", None), &handler).unwrap()
+    }));
+    c.bench_function("preflight", |b| b.iter(|| {
+        let mut headers = origin_headers("https", "example.com", None);
+        headers.set(AccessControlRequestMethod(Method::Put));
+        request::options(url, headers, &handler).unwrap()
+    }));
+}
+
+criterion_group!(benches, bench_requests);
+criterion_main!(benches);
@@ -1,8 +1,6 @@
 extern crate iron;
 extern crate iron_cors;
 
-use std::collections::HashSet;
-
 use iron::{Iron, Handler, Request, Response, IronResult, Chain, status};
 use iron_cors::CorsMiddleware;
 
@@ -19,7 +17,7 @@ fn main() {
     let handler = HelloWorldHandler {};
 
     // Initialize middleware
-    let allowed_hosts = ["example.com"].iter().map(ToString::to_string).collect::<HashSet<_>>();
+    let allowed_hosts = ["http://example.com"];
     println!("Allowed origin hosts: {:?}", allowed_hosts);
     let cors_middleware = CorsMiddleware::with_whitelist(allowed_hosts);
 
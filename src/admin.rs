@@ -0,0 +1,176 @@
+//! A handler that modifies a shared whitelist at runtime.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+use std::io::{self, Read};
+
+use iron::{Handler, IronResult, Request, Response, method, status};
+use iron::headers::{Allow, ContentType};
+use iron::url;
+
+use debug::json_list;
+use {AllowedOrigin, OriginStore};
+
+/// Storage where changes to the whitelist are persisted, e.g. a file or a
+/// database table.
+///
+/// Closures of the form `Fn(&HashSet<String>) -> io::Result<()>` implement
+/// this trait, as does [`WhitelistFile`](struct.WhitelistFile.html).
+pub trait OriginPersistence: Send + Sync {
+    /// Save the complete whitelist after a change.
+    fn save(&self, origins: &HashSet<String>) -> io::Result<()>;
+}
+
+impl<F> OriginPersistence for F
+    where F: Fn(&HashSet<String>) -> io::Result<()> + Send + Sync
+{
+    fn save(&self, origins: &HashSet<String>) -> io::Result<()> {
+        self(origins)
+    }
+}
+
+type Authorize = dyn Fn(&Request) -> bool + Send + Sync;
+
+/// A handler that adds origins to and removes origins from an
+/// [`OriginStore`](trait.OriginStore.html), e.g. a
+/// [`SharedOrigins`](struct.SharedOrigins.html) whitelist, at runtime.
+///
+/// * `GET` returns the whitelist as a JSON array.
+/// * `POST` adds the origin in the request body or the `origin` query
+///   parameter.
+/// * `DELETE` removes the origin in the request body or the `origin` query
+///   parameter.
+///
+/// Successful requests are answered with the updated whitelist. Requests
+/// rejected by the authorization function get a `403 Forbidden` response,
+/// invalid origins a `400 Bad Request` response. If persisting a change
+/// fails, the change is reverted and the response is `500 Internal Server
+/// Error`.
+///
+/// ```rust
+/// extern crate iron;
+/// extern crate iron_cors;
+///
+/// use iron::headers::{Authorization, Bearer};
+/// use iron_cors::{CorsAdminHandler, CorsMiddleware, SharedOrigins};
+///
+/// # fn main() {
+/// let origins = SharedOrigins::new(vec!["https://example.com".to_string()]);
+/// let middleware = CorsMiddleware::with_shared_whitelist(origins.clone());
+/// let admin = CorsAdminHandler::new(origins, |req| {
+///     req.headers.get::<Authorization<Bearer>>()
+///         .is_some_and(|auth| auth.token == "secret")
+/// });
+/// // e.g. mount.mount("/admin/cors/origins", admin);
+/// # let _ = (middleware, admin);
+/// # }
+/// ```
+pub struct CorsAdminHandler {
+    origins: Arc<dyn OriginStore>,
+    authorize: Box<Authorize>,
+    persistence: Option<Box<dyn OriginPersistence>>,
+}
+
+impl CorsAdminHandler {
+    /// Create a handler that modifies the whitelist. Only requests for which
+    /// the authorization function returns `true` are processed.
+    pub fn new<S, F>(origins: S, authorize: F) -> Self
+        where S: OriginStore + 'static, F: Fn(&Request) -> bool + Send + Sync + 'static
+    {
+        CorsAdminHandler {
+            origins: Arc::new(origins),
+            authorize: Box::new(authorize),
+            persistence: None,
+        }
+    }
+
+    /// Persist every change of the whitelist.
+    pub fn persistence<P: OriginPersistence + 'static>(mut self, persistence: P) -> Self {
+        self.persistence = Some(Box::new(persistence));
+        self
+    }
+
+    fn update(&self, req: &mut Request) -> Result<bool, Response> {
+        let origin = requested_origin(req)
+            .ok_or_else(|| Response::with((status::BadRequest, "Missing origin")))?;
+        let origin = AllowedOrigin::parse(&origin)
+            .map_err(|e| Response::with((status::BadRequest, e.to_string())))?
+            .into_string();
+        let insert = req.method == method::Post;
+        let changed = if insert {
+            self.origins.insert(origin.clone())
+        } else {
+            self.origins.remove(&origin)
+        };
+        if let (true, Some(persistence)) = (changed, self.persistence.as_ref()) {
+            if let Err(e) = persistence.save(&self.origins.list().into_iter().collect()) {
+                error!("Could not persist CORS whitelist: {}", e);
+                if insert {
+                    self.origins.remove(&origin);
+                } else {
+                    self.origins.insert(origin);
+                }
+                return Err(Response::with((status::InternalServerError, "Could not persist whitelist")));
+            }
+        }
+        if changed {
+            info!("{} {} the CORS whitelist", origin_change(insert), origin);
+        }
+        Ok(changed)
+    }
+
+    fn list(&self) -> Response {
+        let mut origins = self.origins.list();
+        origins.sort();
+        let mut response = Response::with((status::Ok, json_list(origins)));
+        response.headers.set(ContentType::json());
+        response
+    }
+}
+
+impl Handler for CorsAdminHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        if !(self.authorize)(req) {
+            return Ok(Response::with(status::Forbidden));
+        }
+        match req.method {
+            method::Get => Ok(self.list()),
+            method::Post | method::Delete => match self.update(req) {
+                Ok(_) => Ok(self.list()),
+                Err(response) => Ok(response),
+            },
+            _ => {
+                let mut response = Response::with(status::MethodNotAllowed);
+                response.headers.set(Allow(vec![method::Get, method::Post, method::Delete]));
+                Ok(response)
+            },
+        }
+    }
+}
+
+impl fmt::Debug for CorsAdminHandler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CorsAdminHandler")
+            .field("persistence", &self.persistence.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Return the origin from the `origin` query parameter or the body.
+fn requested_origin(req: &mut Request) -> Option<String> {
+    let query = AsRef::<url::Url>::as_ref(&req.url).query_pairs()
+        .find(|(name, _)| name == "origin")
+        .map(|(_, value)| value.into_owned());
+    if query.is_some() {
+        return query;
+    }
+    let mut body = String::new();
+    // An origin is short, so don't read more than necessary
+    req.body.by_ref().take(4096).read_to_string(&mut body).ok()?;
+    Some(body.trim().to_string()).filter(|origin| !origin.is_empty())
+}
+
+fn origin_change(insert: bool) -> &'static str {
+    if insert { "Added origin to" } else { "Removed origin from" }
+}
@@ -0,0 +1,81 @@
+//! Functions to apply a policy in handlers that can't be wrapped by the
+//! middleware, e.g. WebSocket upgrade endpoints.
+
+use iron::{Request, Response};
+use iron::headers;
+
+use classify::{has_null_origin, is_preflight};
+use origin::{format_cors_origin, null_origin};
+use policy::RequestKind;
+use {CorsPolicy, DisallowedOriginBehavior};
+
+/// Add the CORS headers of a response to an actual request from the origin,
+/// if the policy allows the origin. Returns whether the headers were added.
+///
+/// ```rust
+/// extern crate iron;
+/// extern crate iron_cors;
+///
+/// use iron::headers::{AccessControlAllowOrigin, Headers, Origin};
+/// use iron_cors::{apply_cors_headers, CorsPolicy};
+///
+/// # fn main() {
+/// let policy = CorsPolicy::builder()
+///     .allowed_origins(vec!["https://example.com".to_string()])
+///     .build_policy()
+///     .unwrap();
+/// let mut headers = Headers::new();
+/// assert!(apply_cors_headers(&mut headers, &policy, &Origin::new("https", "example.com", None)));
+/// assert!(headers.has::<AccessControlAllowOrigin>());
+/// # }
+/// ```
+pub fn apply_cors_headers(headers: &mut headers::Headers, policy: &CorsPolicy, origin: &headers::Origin) -> bool {
+    if !policy.is_origin_allowed(origin) {
+        return false;
+    }
+    policy.add_cors_response_headers(headers, origin);
+    true
+}
+
+/// Build the response to a preflight request, like the middleware does.
+///
+/// Returns `None` if the request is not a preflight request, or if the
+/// middleware would pass it to the inner handler, e.g. with
+/// [`DisallowedOriginBehavior::PassThrough`](enum.DisallowedOriginBehavior.html#variant.PassThrough).
+/// Denied preflights are answered according to the policy. Report-only mode
+/// is not applied.
+pub fn build_preflight_response(policy: &CorsPolicy, req: &Request) -> Option<Response> {
+    if !is_preflight(req) {
+        return None;
+    }
+    let origin = match req.headers.get::<headers::Origin>() {
+        Some(origin) => origin.clone(),
+        None if has_null_origin(req) => null_origin(),
+        None => return None,
+    };
+    match policy.classify(&req.method, &origin, req.headers.get(), req.headers.get()) {
+        RequestKind::Preflight(acrm, acrh) => {
+            let mut response = policy.empty_preflight_response();
+            policy.add_preflight_response_headers(&mut response.headers, &origin, acrm, acrh);
+            if policy.allow_credentials && policy.is_only_unrestricted(&req.method, &origin, Some(acrm)) {
+                response.headers.remove::<headers::AccessControlAllowCredentials>();
+            }
+            Some(response)
+        },
+        RequestKind::Disallowed => match policy.disallowed_origin_behavior {
+            DisallowedOriginBehavior::Reject => {
+                Some(policy.deny_response(req, &format_cors_origin(&origin), "Origin not allowed"))
+            },
+            DisallowedOriginBehavior::PassThrough => None,
+            DisallowedOriginBehavior::SpecCompliant => Some(policy.empty_preflight_response()),
+        },
+        RequestKind::InvalidPreflight(_) if policy.answers_invalid_preflights_without_headers() => {
+            Some(policy.empty_preflight_response())
+        },
+        RequestKind::InvalidPreflight(reason) => {
+            Some(policy.deny_response(req, &format_cors_origin(&origin), reason))
+        },
+        RequestKind::MethodNotAllowed => Some(policy.method_not_allowed_response()),
+        RequestKind::NotCors | RequestKind::Actual => None,
+    }
+}
@@ -0,0 +1,102 @@
+//! Check a CORS configuration file before deploying it.
+//!
+//! ```text
+//! iron-cors-check POLICY_FILE [ORIGIN [METHOD [HEADER...]]]
+//! ```
+//!
+//! The policy file is a JSON representation of a `CorsConfig`. It is
+//! validated and its warnings are printed. With an origin, the tool prints
+//! whether requests from the origin are allowed. With a method and optional
+//! request headers, it prints whether a preflight for them is allowed.
+//!
+//! The exit status is `0` if the configuration is valid and the simulated
+//! request is allowed, `1` if the request is denied and `2` if the
+//! configuration is invalid.
+
+extern crate iron;
+extern crate iron_cors;
+extern crate serde_json;
+
+use std::env;
+use std::fs;
+use std::process;
+
+use iron::headers::Origin;
+use iron::method::Method;
+use iron_cors::{CorsConfig, CorsPolicy, CorsTrace};
+
+const USAGE: &str = "Usage: iron-cors-check POLICY_FILE [ORIGIN [METHOD [HEADER...]]]";
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if args.is_empty() || args[0] == "-h" || args[0] == "--help" {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+
+    let policy = match load_policy(&args[0]) {
+        Ok(policy) => policy,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            process::exit(2);
+        },
+    };
+    let warnings = policy.validate();
+    for warning in &warnings {
+        println!("warning: {}", warning);
+    }
+    println!("policy: {}", policy);
+
+    let origin = match args.get(1) {
+        Some(origin) => origin,
+        None => {
+            if warnings.is_empty() {
+                println!("The configuration is valid.");
+            }
+            return;
+        },
+    };
+    let origin = match origin.parse::<Origin>() {
+        Ok(origin) => origin,
+        Err(_) => {
+            eprintln!("error: invalid origin: {}", origin);
+            process::exit(2);
+        },
+    };
+    let trace = match args.get(2) {
+        Some(method) => {
+            let method = match method.parse::<Method>() {
+                Ok(method) => method,
+                Err(_) => {
+                    eprintln!("error: invalid method: {}", method);
+                    process::exit(2);
+                },
+            };
+            let headers = args[3..].iter().map(String::as_str).collect::<Vec<_>>();
+            policy.explain_preflight(&origin, method, &headers)
+        },
+        None => policy.explain(&origin),
+    };
+    print_trace(&trace);
+    if !trace.is_allowed() {
+        process::exit(1);
+    }
+}
+
+/// Read, parse and validate the configuration file.
+fn load_policy(path: &str) -> Result<CorsPolicy, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("cannot read {}: {}", path, e))?;
+    let config = serde_json::from_str::<CorsConfig>(&contents)
+        .map_err(|e| format!("cannot parse {}: {}", path, e))?;
+    config.into_policy()
+        .map_err(|e| format!("invalid configuration in {}: {}", path, e))
+}
+
+fn print_trace(trace: &CorsTrace) {
+    for step in trace.steps() {
+        let result = if step.passed { "passed" } else { "failed" };
+        println!("  {} {}: {}", step.rule, result, step.detail);
+    }
+    println!("{}", if trace.is_allowed() { "allowed" } else { "denied" });
+}
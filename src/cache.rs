@@ -0,0 +1,233 @@
+//! Small bounded caches for preflight responses and origin decisions.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use origin::normalized_origin;
+use CorsMetrics;
+
+/// A map with a maximum number of entries that evicts the least recently
+/// used entry when it is full.
+///
+/// The entries are kept in a vector and linked in the order of their use,
+/// so that lookups, insertions and evictions take constant time.
+#[derive(Debug)]
+pub(crate) struct LruCache<K, V> {
+    capacity: usize,
+    index: HashMap<K, usize>,
+    entries: Vec<Entry<K, V>>,
+    /// The most recently used entry.
+    head: usize,
+    /// The least recently used entry.
+    tail: usize,
+}
+
+#[derive(Debug)]
+struct Entry<K, V> {
+    key: K,
+    value: V,
+    prev: usize,
+    next: usize,
+}
+
+/// The end of the list of entries.
+const NIL: usize = usize::MAX;
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        LruCache {
+            capacity,
+            index: HashMap::with_capacity(capacity),
+            entries: Vec::with_capacity(capacity),
+            head: NIL,
+            tail: NIL,
+        }
+    }
+
+    /// Return the value and mark the entry as recently used.
+    pub(crate) fn get<Q>(&mut self, key: &Q) -> Option<&V>
+        where K: Borrow<Q>, Q: Hash + Eq + ?Sized
+    {
+        let index = *self.index.get(key)?;
+        self.unlink(index);
+        self.push_front(index);
+        Some(&self.entries[index].value)
+    }
+
+    /// Insert the value, evicting the least recently used entry if the cache
+    /// is full.
+    pub(crate) fn insert(&mut self, key: K, value: V) {
+        if let Some(&index) = self.index.get(&key) {
+            self.entries[index].value = value;
+            self.unlink(index);
+            self.push_front(index);
+            return;
+        }
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity {
+            self.remove_at(self.tail);
+        }
+        let index = self.entries.len();
+        self.index.insert(key.clone(), index);
+        self.entries.push(Entry { key, value, prev: NIL, next: NIL });
+        self.push_front(index);
+    }
+
+    /// Remove the entry.
+    pub(crate) fn remove<Q>(&mut self, key: &Q)
+        where K: Borrow<Q>, Q: Hash + Eq + ?Sized
+    {
+        if let Some(&index) = self.index.get(key) {
+            self.remove_at(index);
+        }
+    }
+
+    /// Remove all entries.
+    pub(crate) fn clear(&mut self) {
+        self.index.clear();
+        self.entries.clear();
+        self.head = NIL;
+        self.tail = NIL;
+    }
+
+    /// Return the maximum number of entries.
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Remove the entry at the index, and move the last entry into its
+    /// place.
+    fn remove_at(&mut self, index: usize) {
+        self.unlink(index);
+        let entry = self.entries.swap_remove(index);
+        self.index.remove(&entry.key);
+        if index == self.entries.len() {
+            return;
+        }
+        let (prev, next) = (self.entries[index].prev, self.entries[index].next);
+        self.set_next(prev, index);
+        self.set_prev(next, index);
+        if let Some(moved) = self.index.get_mut(&self.entries[index].key) {
+            *moved = index;
+        }
+    }
+
+    fn unlink(&mut self, index: usize) {
+        let (prev, next) = (self.entries[index].prev, self.entries[index].next);
+        self.set_next(prev, next);
+        self.set_prev(next, prev);
+    }
+
+    fn push_front(&mut self, index: usize) {
+        let head = self.head;
+        self.entries[index].prev = NIL;
+        self.entries[index].next = head;
+        self.set_prev(head, index);
+        self.head = index;
+    }
+
+    /// Link the entry at `index` to `next`, or make `next` the head.
+    fn set_next(&mut self, index: usize, next: usize) {
+        match index {
+            NIL => self.head = next,
+            index => self.entries[index].next = next,
+        }
+    }
+
+    /// Link the entry at `index` to `prev`, or make `prev` the tail.
+    fn set_prev(&mut self, index: usize, prev: usize) {
+        match index {
+            NIL => self.tail = prev,
+            index => self.entries[index].prev = prev,
+        }
+    }
+}
+
+/// A cache of the decisions of dynamic origin checks, i.e. of
+/// [`CorsBuilder::allowed_origin_fn`](struct.CorsBuilder.html#method.allowed_origin_fn) and
+/// of an [`OriginStore`](trait.OriginStore.html), so that hot origins don't
+/// hit the backing store on every request.
+///
+/// Decisions expire after the time to live. Pass an `Arc<DecisionCache>` to
+/// [`CorsBuilder::decision_cache`](struct.CorsBuilder.html#method.decision_cache)
+/// and keep a clone to invalidate entries when the backing store changes.
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use iron_cors::{CorsMiddleware, DecisionCache};
+///
+/// let cache = Arc::new(DecisionCache::new(1000, Duration::from_secs(60)));
+/// let middleware = CorsMiddleware::builder()
+///     .allowed_origin_fn(|origin| origin.host.hostname.ends_with(".example.com"))
+///     .decision_cache(cache.clone())
+///     .build()
+///     .unwrap();
+///
+/// // Later, e.g. after the origin was removed from the database
+/// cache.invalidate("https://app.example.com");
+/// ```
+#[derive(Debug)]
+pub struct DecisionCache {
+    ttl: Duration,
+    entries: Mutex<LruCache<String, (bool, Instant)>>,
+}
+
+impl DecisionCache {
+    /// Create a cache for up to `capacity` origins, whose decisions expire
+    /// after `ttl`.
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        DecisionCache {
+            ttl,
+            entries: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Create an empty cache with the same capacity and time to live.
+    pub(crate) fn empty_copy(&self) -> Self {
+        DecisionCache::new(self.lock().capacity(), self.ttl)
+    }
+
+    /// Remove the decision for the serialized origin.
+    pub fn invalidate(&self, origin: &str) {
+        self.lock().remove(&*normalized_origin(origin));
+    }
+
+    /// Remove all decisions.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Return the cached decision for the normalized origin, or compute it.
+    /// Only successful decisions are cached, errors contain the decision to
+    /// use instead.
+    pub(crate) fn get_or_insert_with<F>(&self, origin: &str, metrics: Option<&CorsMetrics>, f: F) -> bool
+        where F: FnOnce() -> Result<bool, bool>
+    {
+        if let Some(&(allowed, inserted)) = self.lock().get(origin) {
+            if inserted.elapsed() < self.ttl {
+                if let Some(metrics) = metrics {
+                    metrics.record_cache_hit();
+                }
+                return allowed;
+            }
+        }
+        // Don't hold the lock while the backing store is queried
+        match f() {
+            Ok(allowed) => {
+                self.lock().insert(origin.to_string(), (allowed, Instant::now()));
+                allowed
+            },
+            Err(fallback) => fallback,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LruCache<String, (bool, Instant)>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,131 @@
+//! Classification of requests, independent of a policy.
+
+use std::str;
+
+use iron::Request;
+use iron::headers;
+use iron::method::Method;
+
+use origin::{format_canonical_origin, normalize_origin};
+
+/// The kind of a request, as far as CORS is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorsRequestKind {
+    /// The request has no `Origin` header.
+    NotCors,
+    /// The `Origin` header is the origin of the request URL itself.
+    SameOrigin,
+    /// A cross-origin request that is not a preflight.
+    Actual,
+    /// A preflight request: an `OPTIONS` request with the
+    /// `Access-Control-Request-Method` header.
+    Preflight,
+}
+
+/// Classify the request the way the middleware does before applying a
+/// policy, e.g. to handle CORS edge cases in a custom handler.
+///
+/// The origin of the request is determined from its URL, which is based on
+/// the `Host` header. The `null` origin is always cross-origin.
+///
+/// ```rust
+/// extern crate iron;
+/// extern crate iron_cors;
+///
+/// use iron::prelude::*;
+/// use iron::status;
+/// use iron_cors::{classify, CorsRequestKind};
+///
+/// fn handler(req: &mut Request) -> IronResult<Response> {
+///     match classify(req) {
+///         CorsRequestKind::Preflight => Ok(Response::with(status::NoContent)),
+///         _ => Ok(Response::with((status::Ok, "Hello"))),
+///     }
+/// }
+/// # fn main() { let _ = handler; }
+/// ```
+pub fn classify(req: &Request) -> CorsRequestKind {
+    let origin = match req.headers.get::<headers::Origin>() {
+        Some(origin) => format_canonical_origin(origin),
+        None if has_null_origin(req) => "null".to_string(),
+        None => return CorsRequestKind::NotCors,
+    };
+    if origin == url_origin(req) {
+        CorsRequestKind::SameOrigin
+    } else if is_preflight(req) {
+        CorsRequestKind::Preflight
+    } else {
+        CorsRequestKind::Actual
+    }
+}
+
+/// Return whether the request is a preflight request, assuming that it has
+/// an `Origin` header.
+pub(crate) fn is_preflight(req: &Request) -> bool {
+    req.method == Method::Options && req.headers.has::<headers::AccessControlRequestMethod>()
+}
+
+/// Return whether the request looks like it was sent by the `XDomainRequest`
+/// object of Internet Explorer 8 to 10, which can only send `GET` and `POST`
+/// requests without custom headers.
+pub(crate) fn is_xdomain_request(req: &Request) -> bool {
+    if req.method != Method::Get && req.method != Method::Post {
+        return false;
+    }
+    if req.headers.has::<headers::AccessControlRequestHeaders>() {
+        return false;
+    }
+    match req.headers.get::<headers::UserAgent>() {
+        Some(user_agent) => ["MSIE 8.", "MSIE 9.", "MSIE 10."].iter().any(|version| user_agent.contains(version)),
+        None => false,
+    }
+}
+
+/// Return whether the request has the `Origin: null` header.
+pub(crate) fn has_null_origin(req: &Request) -> bool {
+    raw_origin_value(req) == Some("null")
+}
+
+/// Return the trimmed value of the `Origin` header, if there is a single
+/// one.
+pub(crate) fn raw_origin_value<'r>(req: &'r Request) -> Option<&'r str> {
+    match req.headers.get_raw("Origin") {
+        Some(values) if values.len() == 1 => str::from_utf8(&values[0]).ok().map(str::trim),
+        _ => None,
+    }
+}
+
+/// Whether the request has more than one `Origin` header line, or a single
+/// line listing several origins, as proxies may combine duplicate lines.
+pub(crate) fn has_multiple_origins(req: &Request) -> bool {
+    match req.headers.get_raw("Origin") {
+        Some(values) => values.len() > 1 || values.iter().any(|value| value.contains(&b',')),
+        None => false,
+    }
+}
+
+/// Return the normalized origin from the `X-Forwarded-Proto` and
+/// `X-Forwarded-Host` headers of a reverse proxy, if it set any of them.
+/// The first value of each is used if the request passed several proxies.
+pub(crate) fn forwarded_origin(req: &Request) -> Option<String> {
+    let first_value = |name: &str| req.headers.get_raw(name)
+        .and_then(|values| values.first())
+        .and_then(|value| str::from_utf8(value).ok())
+        .and_then(|value| value.split(',').next())
+        .map(str::trim)
+        .filter(|value| !value.is_empty());
+    let url = &req.url;
+    let origin = match (first_value("X-Forwarded-Proto"), first_value("X-Forwarded-Host")) {
+        (proto, Some(host)) => format!("{}://{}", proto.unwrap_or(url.scheme()), host),
+        (Some(proto), None) => format!("{}://{}", proto, url.host()),
+        (None, None) => return None,
+    };
+    Some(normalize_origin(&origin))
+}
+
+/// Return the normalized origin of the request URL.
+pub(crate) fn url_origin(req: &Request) -> String {
+    // The URL of the request is based on the `Host` header
+    let url = &req.url;
+    normalize_origin(&format!("{}://{}:{}", url.scheme(), url.host(), url.port()))
+}
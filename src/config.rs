@@ -0,0 +1,227 @@
+//! Plain configuration values, e.g. loaded from a configuration file.
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use iron::method::Method;
+use unicase::UniCase;
+
+use origin::is_valid_origin;
+use {CorsBuilder, CorsConfigError, CorsPolicy, DisallowedOriginBehavior, NullOriginPolicy};
+
+/// A plain representation of a [`CorsPolicy`](struct.CorsPolicy.html), e.g.
+/// to keep the CORS configuration in a configuration file.
+///
+/// With the `serde` feature, this type (and `CorsPolicy` itself) can be
+/// deserialized, for example from TOML:
+///
+/// ```toml
+/// allowed_origins = ["https://example.com", "https://app.example.com"]
+/// allowed_methods = ["GET", "POST"]
+/// allow_credentials = true
+/// max_age = 3600
+/// ```
+///
+/// All fields are optional. Origins, methods and header names are validated
+/// when converting the configuration into a policy.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct CorsConfig {
+    /// The allowed origins as `scheme://host[:port]` strings. If this is not
+    /// set, any origin is allowed.
+    pub allowed_origins: Option<Vec<String>>,
+    /// Whether to set the `Access-Control-Allow-Credentials` header.
+    pub allow_credentials: bool,
+    /// The `Access-Control-Max-Age` in seconds.
+    pub max_age: Option<u64>,
+    /// The response headers that are exposed to the client.
+    pub expose_headers: Vec<String>,
+    /// The allowed methods. If this is not set, any method is allowed.
+    pub allowed_methods: Option<Vec<String>>,
+    /// The allowed request headers. If this is not set, any header is
+    /// allowed.
+    pub allowed_headers: Option<Vec<String>>,
+    /// How to respond to requests from origins that are not allowed.
+    pub disallowed_origin_behavior: DisallowedOriginBehavior,
+    /// How to handle requests with the `Origin: null` header.
+    pub null_origin_policy: NullOriginPolicy,
+}
+
+impl CorsConfig {
+    /// Validate the configuration and turn it into a policy.
+    pub fn into_policy(self) -> Result<CorsPolicy, CorsConfigError> {
+        let mut builder = CorsBuilder::new()
+            .allow_credentials(self.allow_credentials)
+            .disallowed_origin_behavior(self.disallowed_origin_behavior)
+            .null_origin_policy(self.null_origin_policy);
+        if let Some(allowed_origins) = self.allowed_origins {
+            if let Some(invalid) = allowed_origins.iter().find(|origin| !is_valid_origin(origin)) {
+                return Err(CorsConfigError::InvalidOrigin(invalid.clone()));
+            }
+            builder = builder.allowed_origins(allowed_origins);
+        }
+        if let Some(max_age) = self.max_age {
+            builder = builder.max_age(Duration::from_secs(max_age));
+        }
+        if !self.expose_headers.is_empty() {
+            builder = builder.expose_headers(validate_headers(self.expose_headers)?);
+        }
+        if let Some(allowed_methods) = self.allowed_methods {
+            let methods = allowed_methods.iter()
+                .map(|method| method.parse::<Method>()
+                    .map_err(|_| CorsConfigError::InvalidMethod(method.clone())))
+                .collect::<Result<Vec<_>, _>>()?;
+            builder = builder.allowed_methods(methods);
+        }
+        if let Some(allowed_headers) = self.allowed_headers {
+            builder = builder.allowed_headers(validate_headers(allowed_headers)?.into_iter().map(UniCase));
+        }
+        builder.build_policy()
+    }
+}
+
+impl CorsConfig {
+    /// Read the configuration from environment variables with the specified
+    /// prefix. With the prefix `CORS`, the following variables are read:
+    ///
+    /// - `CORS_ALLOWED_ORIGINS`: Comma separated list of allowed origins, or
+    ///   `*` to allow any origin
+    /// - `CORS_ALLOW_CREDENTIALS`: `true` or `false`
+    /// - `CORS_MAX_AGE`: Max age in seconds
+    /// - `CORS_EXPOSE_HEADERS`: Comma separated list of exposed headers
+    /// - `CORS_ALLOWED_METHODS`: Comma separated list of allowed methods
+    /// - `CORS_ALLOWED_HEADERS`: Comma separated list of allowed headers
+    ///
+    /// Variables that are not set keep their default value.
+    pub fn from_env(prefix: &str) -> Result<CorsConfig, CorsEnvError> {
+        let mut config = CorsConfig::default();
+        if let Some(origins) = read_env_var(prefix, "ALLOWED_ORIGINS")? {
+            if origins.trim() != "*" {
+                config.allowed_origins = Some(split_list(&origins));
+            }
+        }
+        if let Some(value) = read_env_var(prefix, "ALLOW_CREDENTIALS")? {
+            config.allow_credentials = match value.trim().to_lowercase().as_str() {
+                "true" | "1" | "yes" => true,
+                "false" | "0" | "no" => false,
+                _ => return Err(CorsEnvError::invalid(prefix, "ALLOW_CREDENTIALS", value)),
+            };
+        }
+        if let Some(value) = read_env_var(prefix, "MAX_AGE")? {
+            match value.trim().parse() {
+                Ok(max_age) => config.max_age = Some(max_age),
+                Err(_) => return Err(CorsEnvError::invalid(prefix, "MAX_AGE", value)),
+            }
+        }
+        if let Some(headers) = read_env_var(prefix, "EXPOSE_HEADERS")? {
+            config.expose_headers = split_list(&headers);
+        }
+        if let Some(methods) = read_env_var(prefix, "ALLOWED_METHODS")? {
+            config.allowed_methods = Some(split_list(&methods));
+        }
+        if let Some(headers) = read_env_var(prefix, "ALLOWED_HEADERS")? {
+            config.allowed_headers = Some(split_list(&headers));
+        }
+        Ok(config)
+    }
+}
+
+/// An error returned when reading the configuration from environment
+/// variables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CorsEnvError {
+    /// An environment variable has an invalid value.
+    InvalidValue {
+        /// The name of the environment variable.
+        variable: String,
+        /// The invalid value.
+        value: String,
+    },
+    /// An environment variable does not contain valid unicode.
+    NotUnicode(String),
+    /// The resulting configuration is invalid.
+    Config(CorsConfigError),
+}
+
+impl CorsEnvError {
+    fn invalid(prefix: &str, name: &str, value: String) -> Self {
+        CorsEnvError::InvalidValue {
+            variable: env_var_name(prefix, name),
+            value,
+        }
+    }
+}
+
+impl fmt::Display for CorsEnvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CorsEnvError::InvalidValue { ref variable, ref value } => {
+                write!(f, "Invalid value for {}: {:?}", variable, value)
+            },
+            CorsEnvError::NotUnicode(ref variable) => {
+                write!(f, "Value of {} is not valid unicode", variable)
+            },
+            CorsEnvError::Config(ref e) => write!(f, "Invalid CORS configuration: {}", e),
+        }
+    }
+}
+
+impl Error for CorsEnvError {}
+
+impl From<CorsConfigError> for CorsEnvError {
+    fn from(e: CorsConfigError) -> Self {
+        CorsEnvError::Config(e)
+    }
+}
+
+fn env_var_name(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}_{}", prefix, name)
+    }
+}
+
+fn read_env_var(prefix: &str, name: &str) -> Result<Option<String>, CorsEnvError> {
+    let variable = env_var_name(prefix, name);
+    match env::var(&variable) {
+        Ok(value) => Ok(Some(value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(_)) => Err(CorsEnvError::NotUnicode(variable)),
+    }
+}
+
+/// Split a comma separated list, ignoring whitespace and empty entries.
+fn split_list(value: &str) -> Vec<String> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(ToString::to_string)
+        .collect()
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for CorsPolicy {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: ::serde::Deserializer<'de>
+    {
+        CorsConfig::deserialize(deserializer)?
+            .into_policy()
+            .map_err(::serde::de::Error::custom)
+    }
+}
+
+/// Ensure that all header names are valid HTTP tokens.
+fn validate_headers(headers: Vec<String>) -> Result<Vec<String>, CorsConfigError> {
+    match headers.iter().find(|header| !is_token(header)) {
+        Some(invalid) => Err(CorsConfigError::InvalidHeader(invalid.clone())),
+        None => Ok(headers),
+    }
+}
+
+fn is_token(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+}
@@ -0,0 +1,225 @@
+//! A framework-agnostic interface to CORS policies.
+//!
+//! The Iron middleware is an adapter on top of the same decision logic. This
+//! module exposes it with plain strings, so that the same
+//! [`CorsPolicy`](../struct.CorsPolicy.html) can be used with other web
+//! frameworks, e.g. hyper.
+//!
+//! ```rust
+//! use iron_cors::CorsPolicy;
+//! use iron_cors::core::{CorsRequest, CorsResponse};
+//!
+//! let policy = CorsPolicy::builder()
+//!     .allowed_origins(vec!["https://example.com".to_string()])
+//!     .build_policy()
+//!     .unwrap();
+//!
+//! let request = CorsRequest {
+//!     method: "GET",
+//!     origin: Some("https://example.com"),
+//!     ..CorsRequest::default()
+//! };
+//! match policy.evaluate(&request) {
+//!     CorsResponse::Actual { headers } => {
+//!         assert!(headers.contains(&(
+//!             "Access-Control-Allow-Origin".to_string(),
+//!             "https://example.com".to_string(),
+//!         )));
+//!     },
+//!     other => panic!("Unexpected response: {:?}", other),
+//! }
+//! ```
+
+use iron::headers;
+use iron::method::Method;
+use iron::status;
+use unicase::UniCase;
+
+use deny::rejection_body;
+use fetch::parse_fetch_site;
+use origin::{normalized_origin, null_origin};
+use policy::{CorsInput, Outcome, RequestContext, MALFORMED_ORIGIN, METHOD_NOT_ALLOWED};
+use {AsteriskOptionsBehavior, CorsPolicy, MalformedOriginPolicy, SecFetchSite};
+
+/// The parts of an HTTP request that are relevant for CORS.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CorsRequest<'a> {
+    /// The method of the request, e.g. `GET`.
+    pub method: &'a str,
+    /// The value of the `Origin` header.
+    pub origin: Option<&'a str>,
+    /// The value of the `Access-Control-Request-Method` header.
+    pub request_method: Option<&'a str>,
+    /// The value of the `Access-Control-Request-Headers` header.
+    pub request_headers: Option<&'a str>,
+    /// The request target, e.g. `/path` or `*` for server-wide `OPTIONS *`
+    /// requests.
+    pub target: Option<&'a str>,
+    /// Whether the request arrived over TLS, for
+    /// [`require_tls`](../struct.CorsBuilder.html#method.require_tls).
+    pub secure: bool,
+    /// The value of the `Sec-Fetch-Site` header, for
+    /// [`reject_cross_site`](../struct.CorsBuilder.html#method.reject_cross_site).
+    pub sec_fetch_site: Option<&'a str>,
+    /// The origin of the server as seen by the client, e.g.
+    /// `https://api.example.com`, which passes the CSRF check.
+    pub own_origin: Option<&'a str>,
+    /// Whether the framework accepts the request as legitimate, e.g. by a
+    /// CSRF token. It is used instead of the
+    /// [`csrf_validator`](../struct.CorsBuilder.html#method.csrf_validator)
+    /// of the policy, which takes an Iron request.
+    pub csrf_validated: bool,
+}
+
+/// How to respond to a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CorsResponse {
+    /// Handle the request as usual, without adding any CORS headers.
+    PassThrough,
+    /// Respond without calling the handler.
+    Reject {
+        /// The status code of the response.
+        status: u16,
+        /// The body of the response.
+        body: String,
+        /// The headers of the response.
+        headers: Vec<(String, String)>,
+    },
+    /// Respond to the preflight request without calling the handler, with
+    /// an empty body.
+    Preflight {
+        /// The status code of the response.
+        status: u16,
+        /// The headers of the response.
+        headers: Vec<(String, String)>,
+    },
+    /// Handle the request as usual and add the headers to the response. If
+    /// the response already has a `Vary` header, the values should be
+    /// merged.
+    Actual {
+        /// The headers to add to the response.
+        headers: Vec<(String, String)>,
+    },
+}
+
+impl CorsPolicy {
+    /// Decide how to respond to a request.
+    ///
+    /// The decision is the same as in the Iron middleware. Requests without
+    /// a valid `Origin` header and same-origin requests, as detected from
+    /// `own_origin` and `sec_fetch_site`, are passed through. `OPTIONS *` requests are
+    /// handled according to
+    /// [`asterisk_options`](../struct.CorsBuilder.html#method.asterisk_options).
+    ///
+    /// Deny responders take an Iron request, so rejections get the plain
+    /// text response of the default responder. The credentials of requests
+    /// from disallowed origins are not stripped, as the request is not
+    /// modified.
+    pub fn evaluate(&self, request: &CorsRequest) -> CorsResponse {
+        if request.method == "OPTIONS" && request.target.map(str::trim) == Some("*") {
+            return match self.asterisk_options {
+                AsteriskOptionsBehavior::PassThrough => CorsResponse::PassThrough,
+                AsteriskOptionsBehavior::Allow => {
+                    let mut headers = headers::Headers::new();
+                    headers.set(self.asterisk_allow_header());
+                    CorsResponse::Preflight { status: 200, headers: to_pairs(&headers) }
+                },
+            };
+        }
+        let origin = match request.origin.map(str::trim) {
+            Some("null") => null_origin(),
+            Some(origin) => match self.raw_origin(origin) {
+                Some(origin) => origin,
+                None => match origin.parse::<headers::Origin>() {
+                    Ok(origin) => origin,
+                    Err(_) if self.malformed_origin_policy == MalformedOriginPolicy::Reject => {
+                        return reject(MALFORMED_ORIGIN);
+                    },
+                    Err(_) => return CorsResponse::PassThrough,
+                },
+            },
+            None => return CorsResponse::PassThrough,
+        };
+        let method = match request.method.parse::<Method>() {
+            Ok(method) => method,
+            Err(_) => return CorsResponse::PassThrough,
+        };
+        let acrm = request.request_method
+            .and_then(|method| method.trim().parse().ok())
+            .map(headers::AccessControlRequestMethod);
+        let acrh = request.request_headers.map(|value| headers::AccessControlRequestHeaders(
+            value.split(',')
+                .map(str::trim)
+                .filter(|header| !header.is_empty())
+                .map(|header| UniCase(header.to_string()))
+                .collect()
+        ));
+
+        let input = CorsInput { method: &method, origin: &origin, acrm: acrm.as_ref(), acrh: acrh.as_ref() };
+        let decision = self.decide(&input, request);
+        let pairs = |mut headers: headers::Headers| {
+            if decision.without_credentials {
+                headers.remove::<headers::AccessControlAllowCredentials>();
+            }
+            to_pairs(&headers)
+        };
+        let mut headers = headers::Headers::new();
+        match decision.outcome {
+            Outcome::NotCors | Outcome::SameOrigin | Outcome::PassThrough => CorsResponse::PassThrough,
+            Outcome::Deny(reason) => reject(reason),
+            Outcome::MethodNotAllowed => {
+                headers.set(self.allow_header());
+                CorsResponse::Reject {
+                    status: status::MethodNotAllowed.to_u16(),
+                    body: rejection_body(METHOD_NOT_ALLOWED),
+                    headers: pairs(headers),
+                }
+            },
+            Outcome::EmptyPreflight => CorsResponse::Preflight {
+                status: self.preflight_status.0.to_u16(),
+                headers: vec![],
+            },
+            Outcome::ReportOnlyPreflight(acrm, acrh) => {
+                self.add_report_only_preflight_headers(&mut headers, &origin, acrm, acrh);
+                CorsResponse::Preflight { status: self.preflight_status.0.to_u16(), headers: pairs(headers) }
+            },
+            Outcome::Preflight(acrm, acrh) => {
+                self.add_preflight_response_headers(&mut headers, &origin, acrm, acrh);
+                CorsResponse::Preflight { status: self.preflight_status.0.to_u16(), headers: pairs(headers) }
+            },
+            Outcome::ReportOnly | Outcome::Actual => {
+                self.add_cors_actual_headers(&mut headers, &origin);
+                CorsResponse::Actual { headers: pairs(headers) }
+            },
+        }
+    }
+}
+
+impl<'a> RequestContext for CorsRequest<'a> {
+    fn is_secure(&self, _: &CorsPolicy) -> bool {
+        self.secure
+    }
+
+    fn fetch_site(&self) -> Option<SecFetchSite> {
+        self.sec_fetch_site.map(str::trim).and_then(parse_fetch_site)
+    }
+
+    fn own_origin(&self, _: &CorsPolicy) -> Option<String> {
+        self.own_origin.map(|origin| normalized_origin(origin).into_owned())
+    }
+
+    fn passes_csrf_validator(&self, _: &CorsPolicy) -> bool {
+        self.csrf_validated
+    }
+}
+
+/// The rejection of the default deny responder for plain text clients.
+fn reject(reason: &str) -> CorsResponse {
+    CorsResponse::Reject { status: status::BadRequest.to_u16(), body: rejection_body(reason), headers: vec![] }
+}
+
+fn to_pairs(headers: &headers::Headers) -> Vec<(String, String)> {
+    headers.iter()
+        .map(|header| (header.name().to_string(), header.value_string()))
+        .collect()
+}
@@ -0,0 +1,86 @@
+//! Protection against cross-site request forgery.
+
+use std::fmt;
+use std::sync::Arc;
+
+use iron::Request;
+
+/// Decide whether a state-changing request that isn't from an allowed origin
+/// is legitimate, e.g. by checking a token against the session store.
+///
+/// CORS doesn't stop cross-site form submissions, which browsers send
+/// without asking. With a validator set by
+/// [`CorsBuilder::csrf_validator`](struct.CorsBuilder.html#method.csrf_validator),
+/// requests with a method other than `GET`, `HEAD`, `OPTIONS` or `TRACE`
+/// must come from an allowed origin or from the server itself, or be
+/// accepted by the validator. Otherwise they are rejected. Requests without
+/// an `Origin` header are checked by the origin of their `Referer` if
+/// [`referer_fallback`](struct.CorsBuilder.html#method.referer_fallback)
+/// is enabled.
+///
+/// ```rust
+/// extern crate iron;
+/// extern crate iron_cors;
+///
+/// use iron::Request;
+/// use iron_cors::CorsMiddleware;
+///
+/// fn is_valid_token(token: &[u8]) -> bool {
+///     // Look up the token in the session store
+///     token == b"secret"
+/// }
+///
+/// # fn main() {
+/// let middleware = CorsMiddleware::builder()
+///     .allowed_origins(vec!["https://app.example.com".to_string()])
+///     .csrf_validator(|req: &Request| {
+///         req.headers.get_raw("X-CSRF-Token")
+///             .is_some_and(|values| values.len() == 1 && is_valid_token(&values[0]))
+///     })
+///     .build()
+///     .unwrap();
+/// # }
+/// ```
+pub trait CsrfValidator: Send + Sync {
+    /// Return whether the request is legitimate.
+    fn validate(&self, req: &Request) -> bool;
+}
+
+impl<F> CsrfValidator for F
+    where F: Fn(&Request) -> bool + Send + Sync
+{
+    fn validate(&self, req: &Request) -> bool {
+        self(req)
+    }
+}
+
+/// A validator that accepts requests with a custom header, e.g.
+/// `X-Requested-With`. Forms can't set custom headers, and scripts on other
+/// origins can only set them after a successful preflight.
+#[derive(Debug, Clone)]
+pub struct CsrfHeader {
+    name: String,
+}
+
+impl CsrfHeader {
+    /// Create a validator that requires the header.
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        CsrfHeader { name: name.into() }
+    }
+}
+
+impl CsrfValidator for CsrfHeader {
+    fn validate(&self, req: &Request) -> bool {
+        req.headers.get_raw(&self.name).is_some()
+    }
+}
+
+/// A shared CSRF validator that implements `Debug`.
+#[derive(Clone)]
+pub(crate) struct SharedCsrfValidator(pub(crate) Arc<dyn CsrfValidator>);
+
+impl fmt::Debug for SharedCsrfValidator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CsrfValidator(..)")
+    }
+}
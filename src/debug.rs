@@ -0,0 +1,301 @@
+//! A handler that describes a policy, e.g. for frontend developers.
+
+use std::fmt::Write;
+
+#[cfg(feature = "serde")] use iron::{Handler, IronResult, Request, Response, status};
+#[cfg(feature = "serde")] use iron::headers::ContentType;
+#[cfg(feature = "serde")] use iron::url;
+#[cfg(feature = "serde")] use unicase::UniCase;
+
+#[cfg(feature = "serde")]
+use {AsteriskOptionsBehavior, CorsPolicy, CrossOriginEmbedderPolicy, CrossOriginOpenerPolicy,
+     CrossOriginResourcePolicy, DisallowedOriginBehavior, ErrorResponsePolicy, HeaderConflictPolicy, HeaderPolicy,
+     MalformedOriginPolicy, NullOriginPolicy, TlsRequirement, ValidatorFallback};
+
+/// A handler that serves a description of a policy as JSON, so frontend
+/// developers can check the configuration themselves.
+///
+/// The description is the JSON of
+/// [`CorsPolicy::to_json`](struct.CorsPolicy.html#method.to_json). With an
+/// `origin` query parameter, the response also tells whether the origin is
+/// allowed, e.g. for `/cors?origin=https://app.example.com`:
+///
+/// ```json
+/// {
+///   "origin_mode": "whitelist",
+///   "origins": ["https://app.example.com"],
+///   "allow_credentials": true,
+///   "max_age": 3600,
+///   ...
+///   "origin": "https://app.example.com",
+///   "origin_allowed": true
+/// }
+/// ```
+///
+/// The description reveals the configuration, so the handler should only be
+/// mounted on a protected route. The handler requires the `serde` feature.
+///
+/// ```rust
+/// extern crate iron;
+/// extern crate iron_cors;
+///
+/// use iron::prelude::*;
+/// use iron_cors::{CorsDebugHandler, CorsPolicy};
+///
+/// # fn main() {
+/// let policy = CorsPolicy::builder()
+///     .allowed_origins(vec!["https://app.example.com".to_string()])
+///     .build_policy()
+///     .unwrap();
+/// let handler = CorsDebugHandler::new(policy);
+/// // e.g. mount.mount("/admin/cors", handler);
+/// # let _ = handler;
+/// # }
+/// ```
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone)]
+pub struct CorsDebugHandler {
+    policy: CorsPolicy,
+}
+
+#[cfg(feature = "serde")]
+impl CorsDebugHandler {
+    /// Create a handler that describes the policy.
+    pub fn new(policy: CorsPolicy) -> Self {
+        CorsDebugHandler { policy }
+    }
+}
+
+/// The description served by the debug handler.
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+struct DebugDescription<'a> {
+    #[serde(flatten)]
+    policy: PolicySnapshot<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    origin: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    origin_allowed: Option<bool>,
+}
+
+#[cfg(feature = "serde")]
+impl Handler for CorsDebugHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let origin = AsRef::<url::Url>::as_ref(&req.url).query_pairs()
+            .find(|(name, _)| name == "origin")
+            .map(|(_, value)| value.into_owned());
+        let origin_allowed = origin.as_ref().map(|origin| {
+            origin.parse().ok().is_some_and(|origin| self.policy.is_origin_allowed(&origin))
+                || (origin == "null" && self.policy.is_origin_allowed(&::origin::null_origin()))
+        });
+
+        let description = DebugDescription { policy: PolicySnapshot::new(&self.policy), origin, origin_allowed };
+        let mut response = Response::with((status::Ok, to_pretty_json(&description)));
+        response.headers.set(ContentType::json());
+        Ok(response)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl CorsPolicy {
+    /// Export the configuration of the policy as pretty-printed JSON, e.g.
+    /// to log it at startup or to compare it with the intended
+    /// configuration in smoke tests.
+    ///
+    /// The origins are described by the `origin_mode` (`any`, `reflect`,
+    /// `whitelist`, `blacklist`, `matchers`, `regex`, `function` or
+    /// `union`) and the listed `origins`, if the mode has a list. Whitelists
+    /// that can change at runtime are exported as they are at the time of
+    /// the call. The other fields are named like the fields of
+    /// [`CorsConfig`](struct.CorsConfig.html) or the methods of
+    /// [`CorsBuilder`](struct.CorsBuilder.html). Sets, e.g. the excepted
+    /// origins, are sorted, so that the output is stable. Origin functions,
+    /// CSRF validators and circuit breakers are only exported as whether
+    /// they are set.
+    ///
+    /// Requires the `serde` feature.
+    ///
+    /// ```rust
+    /// use iron_cors::CorsPolicy;
+    ///
+    /// let policy = CorsPolicy::builder()
+    ///     .allowed_origins(vec!["https://app.example.com".to_string()])
+    ///     .build_policy()
+    ///     .unwrap();
+    /// assert!(policy.to_json().contains("\"origin_mode\": \"whitelist\""));
+    /// ```
+    pub fn to_json(&self) -> String {
+        to_pretty_json(&PolicySnapshot::new(self))
+    }
+}
+
+/// The exported configuration of a policy, with every option that affects
+/// how requests are handled.
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+struct PolicySnapshot<'a> {
+    origin_mode: &'static str,
+    origins: Option<Vec<String>>,
+    raw_origins: Vec<&'a str>,
+    except_origins: Vec<&'a str>,
+    allowed_schemes: Option<&'a [String]>,
+    require_https_origins: bool,
+    allow_localhost: bool,
+    ignore_port: bool,
+    null_origin_policy: NullOriginPolicy,
+    malformed_origin_policy: MalformedOriginPolicy,
+    reject_multiple_origins: bool,
+    disallowed_origin_behavior: DisallowedOriginBehavior,
+    skip_same_origin: bool,
+    self_origin: Option<&'a str>,
+    trust_sec_fetch_site: bool,
+    trusted_proxies: Vec<String>,
+    tls_requirement: TlsRequirement,
+    reject_cross_site: Vec<String>,
+    referer_fallback: bool,
+    csrf_validator: bool,
+    validator_fallback: ValidatorFallback,
+    validator_timeout_ms: Option<u128>,
+    circuit_breaker: bool,
+    allow_credentials: bool,
+    strip_credentials: bool,
+    max_age: Option<u64>,
+    expose_headers: Vec<&'a str>,
+    allowed_methods: Option<Vec<String>>,
+    allowed_headers: Option<Vec<&'a str>>,
+    default_headers: Vec<&'a str>,
+    header_policy: HeaderPolicy,
+    strict: bool,
+    enforce_allowed_methods: bool,
+    unrestricted_methods: Vec<String>,
+    preflight_method_not_allowed: bool,
+    preflight_status: u16,
+    preflight_cache_control: Option<&'a str>,
+    extra_preflight_headers: &'a [(String, String)],
+    extra_actual_headers: &'a [(String, String)],
+    omit_wildcard_authorization: bool,
+    timing_allow_origin: bool,
+    xdomain_request_compat: bool,
+    asterisk_options: AsteriskOptionsBehavior,
+    header_conflict_policy: HeaderConflictPolicy,
+    error_response_policy: &'static str,
+    error_status_classes: Option<Vec<&'static str>>,
+    resource_policy: Option<CrossOriginResourcePolicy>,
+    opener_policy: Option<CrossOriginOpenerPolicy>,
+    embedder_policy: Option<CrossOriginEmbedderPolicy>,
+    report_only: bool,
+}
+
+#[cfg(feature = "serde")]
+impl<'a> PolicySnapshot<'a> {
+    fn new(policy: &'a CorsPolicy) -> Self {
+        fn sorted<T: Ord>(items: impl IntoIterator<Item=T>) -> Vec<T> {
+            let mut items = items.into_iter().collect::<Vec<_>>();
+            items.sort();
+            items
+        }
+        let names = |items: &'a [UniCase<String>]| items.iter().map(|item| item.as_str()).collect::<Vec<_>>();
+        let methods = |methods: &[::iron::method::Method]| methods.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let (origin_mode, origins) = policy.allowed_origins.describe();
+        let (error_response_policy, error_status_classes) = match policy.error_response_policy {
+            ErrorResponsePolicy::All => ("all", None),
+            ErrorResponsePolicy::Omit => ("omit", None),
+            ErrorResponsePolicy::StatusClasses(ref classes) => {
+                ("status_classes", Some(classes.iter().map(|class| status_class_name(*class)).collect()))
+            },
+        };
+        let settings = &policy.validator_settings;
+        PolicySnapshot {
+            origin_mode,
+            origins,
+            raw_origins: sorted(policy.raw_origins.iter().map(String::as_str)),
+            except_origins: sorted(policy.except_origins.iter().map(String::as_str)),
+            allowed_schemes: policy.allowed_schemes.as_deref(),
+            require_https_origins: policy.require_https_origins,
+            allow_localhost: policy.allow_localhost,
+            ignore_port: policy.ignore_port,
+            null_origin_policy: policy.null_origin_policy,
+            malformed_origin_policy: policy.malformed_origin_policy,
+            reject_multiple_origins: policy.reject_multiple_origins,
+            disallowed_origin_behavior: policy.disallowed_origin_behavior,
+            skip_same_origin: policy.skip_same_origin,
+            self_origin: policy.self_origin.as_deref(),
+            trust_sec_fetch_site: policy.trust_sec_fetch_site,
+            trusted_proxies: sorted(policy.trusted_proxies.iter()).into_iter().map(ToString::to_string).collect(),
+            tls_requirement: policy.tls_requirement,
+            reject_cross_site: methods(&policy.reject_cross_site),
+            referer_fallback: policy.referer_fallback,
+            csrf_validator: policy.csrf_validator.is_some(),
+            validator_fallback: settings.fallback,
+            validator_timeout_ms: settings.timeout.map(|timeout| timeout.as_millis()),
+            circuit_breaker: settings.breaker.is_some(),
+            allow_credentials: policy.allow_credentials,
+            strip_credentials: policy.strip_credentials,
+            max_age: policy.max_age.map(|max_age| max_age.as_secs()),
+            expose_headers: names(&policy.expose_headers),
+            allowed_methods: policy.allowed_methods.as_deref().map(methods),
+            allowed_headers: policy.allowed_headers.as_deref().map(names),
+            default_headers: names(&policy.default_headers),
+            header_policy: policy.header_policy,
+            strict: policy.strict,
+            enforce_allowed_methods: policy.enforce_allowed_methods,
+            unrestricted_methods: methods(&policy.unrestricted_methods),
+            preflight_method_not_allowed: policy.preflight_method_not_allowed,
+            preflight_status: policy.preflight_status.0.to_u16(),
+            preflight_cache_control: policy.preflight_cache_control.as_deref(),
+            extra_preflight_headers: &policy.extra_preflight_headers,
+            extra_actual_headers: &policy.extra_actual_headers,
+            omit_wildcard_authorization: policy.omit_wildcard_authorization,
+            timing_allow_origin: policy.timing_allow_origin,
+            xdomain_request_compat: policy.xdomain_request_compat,
+            asterisk_options: policy.asterisk_options,
+            header_conflict_policy: policy.header_conflict_policy,
+            error_response_policy,
+            error_status_classes,
+            resource_policy: policy.resource_policy,
+            opener_policy: policy.opener_policy,
+            embedder_policy: policy.embedder_policy,
+            report_only: policy.report_only,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+fn status_class_name(class: status::StatusClass) -> &'static str {
+    match class {
+        status::StatusClass::Informational => "1xx",
+        status::StatusClass::Success => "2xx",
+        status::StatusClass::Redirection => "3xx",
+        status::StatusClass::ClientError => "4xx",
+        status::StatusClass::ServerError => "5xx",
+        status::StatusClass::NoClass => "other",
+    }
+}
+
+#[cfg(feature = "serde")]
+fn to_pretty_json<T: ::serde::Serialize>(value: &T) -> String {
+    ::serde_json::to_string_pretty(value).expect("a policy snapshot is always serializable")
+}
+
+pub(crate) fn json_list<I: IntoIterator<Item=String>>(items: I) -> String {
+    let items = items.into_iter().map(|item| json_string(&item)).collect::<Vec<_>>();
+    format!("[{}]", items.join(","))
+}
+
+pub(crate) fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(json, "\\u{:04x}", c as u32); },
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
@@ -0,0 +1,130 @@
+//! Responses to rejected CORS requests.
+
+use std::fmt;
+use std::sync::Arc;
+
+use iron::{Request, Response};
+use iron::headers::{Accept, ContentType};
+use iron::mime::{Mime, SubLevel, TopLevel};
+use iron::status;
+
+use debug::json_string;
+
+/// A CORS request that the middleware rejects.
+#[derive(Debug, Clone, Copy)]
+pub struct CorsRejection<'a> {
+    /// The serialized origin, or the raw value of a malformed `Origin`
+    /// header.
+    pub origin: &'a str,
+    /// Why the request is rejected, e.g. `Origin not allowed`.
+    pub reason: &'a str,
+}
+
+/// Build the response to a rejected CORS request, e.g. to return an error
+/// document in the format of the API.
+///
+/// The responder replaces the `400 Bad Request` responses of the
+/// middleware. `405 Method Not Allowed` responses to preflights are not
+/// affected. The default is
+/// [`NegotiatingDenyResponder`](struct.NegotiatingDenyResponder.html).
+///
+/// ```rust
+/// extern crate iron;
+/// extern crate iron_cors;
+///
+/// use iron::{status, Request, Response};
+/// use iron_cors::{CorsMiddleware, CorsRejection};
+///
+/// # fn main() {
+/// let middleware = CorsMiddleware::builder()
+///     .allowed_origins(vec!["https://app.example.com".to_string()])
+///     .deny_responder(|_: &Request, rejection: &CorsRejection| {
+///         Response::with((status::Forbidden, rejection.reason))
+///     })
+///     .build()
+///     .unwrap();
+/// # }
+/// ```
+pub trait DenyResponder: Send + Sync {
+    /// Return the response to the rejected request.
+    fn respond(&self, req: &Request, rejection: &CorsRejection) -> Response;
+}
+
+impl<F> DenyResponder for F
+    where F: Fn(&Request, &CorsRejection) -> Response + Send + Sync
+{
+    fn respond(&self, req: &Request, rejection: &CorsRejection) -> Response {
+        self(req, rejection)
+    }
+}
+
+/// The default responder, which honors the `Accept` header of the request.
+///
+/// Clients that prefer JSON get an `application/problem+json` document as
+/// specified by RFC 7807, with the origin and the reason in the `origin` and
+/// `detail` fields. All other clients get a plain text body.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NegotiatingDenyResponder;
+
+impl DenyResponder for NegotiatingDenyResponder {
+    fn respond(&self, req: &Request, rejection: &CorsRejection) -> Response {
+        if !prefers_json(req) {
+            return Response::with((status::BadRequest, rejection_body(rejection.reason)));
+        }
+        let body = format!(
+            "{{\"type\":\"about:blank\",\"title\":\"Invalid CORS request\",\"status\":400,\"detail\":{},\"origin\":{}}}",
+            json_string(rejection.reason),
+            json_string(rejection.origin),
+        );
+        let mime = Mime(TopLevel::Application, SubLevel::Ext("problem+json".to_string()), vec![]);
+        let mut response = Response::with((status::BadRequest, body));
+        response.headers.set(ContentType(mime));
+        response
+    }
+}
+
+/// The plain text body of a rejection.
+pub(crate) fn rejection_body(reason: &str) -> String {
+    format!("Invalid CORS request: {}", reason)
+}
+
+/// Return whether the client accepts JSON with a higher quality than plain
+/// text. Wildcards for any type don't count for either.
+fn prefers_json(req: &Request) -> bool {
+    let accept = match req.headers.get::<Accept>() {
+        Some(accept) => accept,
+        None => return false,
+    };
+    let quality = |matches: fn(&Mime) -> bool| accept.iter()
+        .filter(|item| matches(&item.item))
+        .map(|item| item.quality)
+        .max();
+    let json = quality(|mime| match *mime {
+        Mime(TopLevel::Application, SubLevel::Json, _) | Mime(TopLevel::Application, SubLevel::Star, _) => true,
+        Mime(TopLevel::Application, SubLevel::Ext(ref sub), _) => sub.ends_with("+json"),
+        _ => false,
+    });
+    let text = quality(|mime| matches!(*mime, Mime(TopLevel::Text, SubLevel::Plain, _) | Mime(TopLevel::Text, SubLevel::Star, _)));
+    match (json, text) {
+        (Some(json), Some(text)) => json > text,
+        (Some(json), None) => json.0 > 0,
+        (None, _) => false,
+    }
+}
+
+/// A shared deny responder that implements `Debug` and defaults to the
+/// negotiating responder.
+#[derive(Clone)]
+pub(crate) struct SharedResponder(pub(crate) Arc<dyn DenyResponder>);
+
+impl Default for SharedResponder {
+    fn default() -> Self {
+        SharedResponder(Arc::new(NegotiatingDenyResponder))
+    }
+}
+
+impl fmt::Debug for SharedResponder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DenyResponder(..)")
+    }
+}
@@ -0,0 +1,71 @@
+//! Structured events about CORS decisions.
+
+use CorsDecision;
+
+/// A CORS decision with structured fields, e.g. for a log pipeline.
+///
+/// With the `tracing` feature, every event is also emitted as a `tracing`
+/// event with the target `iron_cors` at the `DEBUG` level. The messages
+/// about denials are emitted separately, with the levels and the rate limit
+/// of [`log_level`](struct.CorsBuilder.html#method.log_level) and
+/// [`log_rate_limit`](struct.CorsBuilder.html#method.log_rate_limit).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CorsEvent<'a> {
+    /// The serialized origin of the request.
+    pub origin: &'a str,
+    /// The path of the request.
+    pub path: &'a str,
+    /// The decision about the request.
+    pub decision: &'a CorsDecision,
+    /// The rule that rejected the request, e.g. `allowed_origins`, if it was
+    /// denied. The names are the same as in a
+    /// [`CorsTrace`](struct.CorsTrace.html).
+    pub rule: Option<&'static str>,
+    /// Whether the request is a preflight.
+    pub preflight: bool,
+}
+
+/// A receiver of [`CorsEvent`](struct.CorsEvent.html)s, registered with
+/// [`CorsBuilder::event_sink`](struct.CorsBuilder.html#method.event_sink).
+///
+/// ```rust
+/// use iron_cors::{CorsEvent, CorsEventSink, CorsMiddleware};
+///
+/// struct JsonLines;
+///
+/// impl CorsEventSink for JsonLines {
+///     fn event(&self, event: &CorsEvent) {
+///         println!(
+///             r#"{{"origin":{:?},"path":{:?},"rule":{:?},"preflight":{}}}"#,
+///             event.origin, event.path, event.rule, event.preflight,
+///         );
+///     }
+/// }
+///
+/// let middleware = CorsMiddleware::builder()
+///     .allowed_origins(vec!["https://example.com".to_string()])
+///     .event_sink(JsonLines)
+///     .build()
+///     .unwrap();
+/// ```
+pub trait CorsEventSink: Send + Sync {
+    /// Receive an event. This is called for every CORS request, so it
+    /// should not block.
+    fn event(&self, event: &CorsEvent);
+}
+
+impl<'a> CorsEvent<'a> {
+    #[cfg(feature = "tracing")]
+    pub(crate) fn emit_tracing(&self) {
+        let decision = match *self.decision {
+            CorsDecision::NotCors => "not_cors",
+            CorsDecision::SameOrigin => "same_origin",
+            CorsDecision::Allowed(_) => "allowed",
+            CorsDecision::Denied(_) => "denied",
+            CorsDecision::Preflight(_) => "preflight",
+        };
+        let rule = self.rule.unwrap_or("");
+        tracing::debug!(target: "iron_cors", origin = self.origin, path = self.path, decision, rule,
+                        preflight = self.preflight, "CORS request handled");
+    }
+}
@@ -0,0 +1,120 @@
+//! Fetch metadata request headers (`Sec-Fetch-*`).
+
+use std::str;
+
+use iron::Request;
+use iron::typemap;
+
+/// The value of the `Sec-Fetch-Site` header: how the origin of the request
+/// relates to the origin of the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecFetchSite {
+    /// The request comes from another site.
+    CrossSite,
+    /// The request comes from another origin of the same site, e.g. another
+    /// subdomain.
+    SameSite,
+    /// The request comes from the origin of the server itself.
+    SameOrigin,
+    /// The request was initiated by the user, e.g. by entering the URL.
+    None,
+}
+
+/// The value of the `Sec-Fetch-Mode` header: the mode of the request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecFetchMode {
+    /// A request with CORS, e.g. `fetch()` from a script.
+    Cors,
+    /// A navigation between documents.
+    Navigate,
+    /// A request without CORS, e.g. for an image.
+    NoCors,
+    /// A same-origin request.
+    SameOrigin,
+    /// A WebSocket connection.
+    Websocket,
+}
+
+/// The parsed fetch metadata headers of a request.
+///
+/// The middleware inserts them into the request extensions if the request
+/// has a `Sec-Fetch-Site` or `Sec-Fetch-Mode` header. Unknown values are
+/// `None`, as are the values of browsers that don't send the headers.
+///
+/// ```rust
+/// extern crate iron;
+/// extern crate iron_cors;
+///
+/// use iron::prelude::*;
+/// use iron::status;
+/// use iron_cors::{FetchMetadata, SecFetchSite};
+///
+/// fn handler(req: &mut Request) -> IronResult<Response> {
+///     let cross_site = req.extensions.get::<FetchMetadata>()
+///         .map_or(false, |metadata| metadata.site == Some(SecFetchSite::CrossSite));
+///     Ok(Response::with((status::Ok, format!("cross-site: {}", cross_site))))
+/// }
+/// # fn main() { let _ = handler; }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FetchMetadata {
+    /// The value of the `Sec-Fetch-Site` header.
+    pub site: Option<SecFetchSite>,
+    /// The value of the `Sec-Fetch-Mode` header.
+    pub mode: Option<SecFetchMode>,
+}
+
+impl FetchMetadata {
+    /// Parse the fetch metadata headers of the request, or return `None`
+    /// if it has neither of them.
+    pub fn from_request(req: &Request) -> Option<FetchMetadata> {
+        let site = header_value(req, "Sec-Fetch-Site");
+        let mode = header_value(req, "Sec-Fetch-Mode");
+        if site.is_none() && mode.is_none() {
+            return None;
+        }
+        Some(FetchMetadata {
+            site: site.and_then(parse_fetch_site),
+            mode: mode.and_then(|mode| match mode {
+                "cors" => Some(SecFetchMode::Cors),
+                "navigate" => Some(SecFetchMode::Navigate),
+                "no-cors" => Some(SecFetchMode::NoCors),
+                "same-origin" => Some(SecFetchMode::SameOrigin),
+                "websocket" => Some(SecFetchMode::Websocket),
+                _ => None,
+            }),
+        })
+    }
+}
+
+impl typemap::Key for FetchMetadata {
+    type Value = FetchMetadata;
+}
+
+/// Parse the value of a `Sec-Fetch-Site` header.
+pub(crate) fn parse_fetch_site(value: &str) -> Option<SecFetchSite> {
+    match value {
+        "cross-site" => Some(SecFetchSite::CrossSite),
+        "same-site" => Some(SecFetchSite::SameSite),
+        "same-origin" => Some(SecFetchSite::SameOrigin),
+        "none" => Some(SecFetchSite::None),
+        _ => None,
+    }
+}
+
+/// Return the `Sec-Fetch-Site` of the request, from the extensions if the
+/// middleware already parsed it.
+pub(crate) fn fetch_site(req: &Request) -> Option<SecFetchSite> {
+    match req.extensions.get::<FetchMetadata>() {
+        Some(metadata) => metadata.site,
+        None => FetchMetadata::from_request(req).and_then(|metadata| metadata.site),
+    }
+}
+
+/// Return the trimmed value of the header, if there is a single one.
+fn header_value<'r>(req: &'r Request, name: &str) -> Option<&'r str> {
+    match req.headers.get_raw(name) {
+        Some(values) if values.len() == 1 => str::from_utf8(&values[0]).ok().map(str::trim),
+        _ => None,
+    }
+}
@@ -0,0 +1,134 @@
+//! A whitelist of origins that is loaded from a file and reloaded when the
+//! file changes.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use {OriginPersistence, SharedOrigins};
+
+/// A whitelist of origins that is loaded from a file.
+///
+/// The file contains one origin (`scheme://host[:port]`) per line. Empty
+/// lines and lines starting with `#` are ignored.
+///
+/// When a request is processed and the check interval has passed since the
+/// last check, the modification time of the file is compared to the one of
+/// the last load. If the file was modified, the whitelist is reloaded. If
+/// reloading fails, a warning is logged and the previous whitelist is kept.
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use iron_cors::{CorsMiddleware, WhitelistFile};
+///
+/// let file = WhitelistFile::open("/etc/cors/origins.txt")
+///     .expect("Could not read CORS whitelist")
+///     .check_interval(Duration::from_secs(10));
+/// let middleware = CorsMiddleware::with_whitelist_file(file);
+/// ```
+#[derive(Debug, Clone)]
+pub struct WhitelistFile {
+    path: PathBuf,
+    origins: SharedOrigins,
+    check_interval: Duration,
+    state: Arc<Mutex<FileState>>,
+}
+
+#[derive(Debug)]
+struct FileState {
+    last_check: Instant,
+    modified: Option<SystemTime>,
+}
+
+impl WhitelistFile {
+    /// Load the whitelist from the file at the specified path. The file is
+    /// checked for modifications at most once per second by default.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let modified = fs::metadata(&path)?.modified().ok();
+        let origins = SharedOrigins::new(read_origins(&path)?);
+        Ok(WhitelistFile {
+            path,
+            origins,
+            check_interval: Duration::from_secs(1),
+            state: Arc::new(Mutex::new(FileState {
+                last_check: Instant::now(),
+                modified,
+            })),
+        })
+    }
+
+    /// Set the minimum interval between two checks for modifications.
+    pub fn check_interval(mut self, check_interval: Duration) -> Self {
+        self.check_interval = check_interval;
+        self
+    }
+
+    /// Return the shared whitelist that is updated when the file changes.
+    pub fn origins(&self) -> SharedOrigins {
+        self.origins.clone()
+    }
+
+    /// Reload the whitelist if the file was modified since the last load.
+    ///
+    /// This is called automatically by the middleware, taking the check
+    /// interval into account. Calling it manually forces a check.
+    pub fn reload_if_modified(&self) -> io::Result<bool> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        self.check(&mut state)
+    }
+
+    /// Return the whitelist, reloading it first if necessary.
+    pub(crate) fn current(&self) -> &SharedOrigins {
+        // If another thread is currently checking the file, don't wait for it
+        if let Ok(mut state) = self.state.try_lock() {
+            if state.last_check.elapsed() >= self.check_interval {
+                if let Err(e) = self.check(&mut state) {
+                    warn!("Could not reload CORS whitelist from {}: {}", self.path.display(), e);
+                }
+            }
+        }
+        &self.origins
+    }
+
+    fn check(&self, state: &mut FileState) -> io::Result<bool> {
+        state.last_check = Instant::now();
+        let modified = fs::metadata(&self.path)?.modified().ok();
+        if modified.is_some() && modified == state.modified {
+            return Ok(false);
+        }
+        self.origins.replace(read_origins(&self.path)?);
+        state.modified = modified;
+        info!("Reloaded CORS whitelist from {}", self.path.display());
+        Ok(true)
+    }
+}
+
+/// Write the whitelist to the file, e.g. after a change through a
+/// [`CorsAdminHandler`](struct.CorsAdminHandler.html). Comments in the file
+/// are not preserved.
+impl OriginPersistence for WhitelistFile {
+    fn save(&self, origins: &HashSet<String>) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut origins = origins.iter().map(String::as_str).collect::<Vec<_>>();
+        origins.sort();
+        let mut contents = origins.join("\n");
+        contents.push('\n');
+        fs::write(&self.path, contents)?;
+        // The whitelist is already up to date, so don't reload it
+        state.modified = fs::metadata(&self.path)?.modified().ok();
+        Ok(())
+    }
+}
+
+fn read_origins(path: &Path) -> io::Result<Vec<String>> {
+    let contents = fs::read_to_string(path)?;
+    Ok(contents.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(ToString::to_string)
+        .collect())
+}
@@ -9,21 +9,21 @@
 //!
 //! ## Mode 1: Whitelist
 //!
-//! The user of the middleware must specify a list of allowed hosts (port or
-//! protocol aren't being checked by the middleware). If the `Origin` header is
-//! set on a request and if the value matches one of the allowed hosts, the
-//! `Access-Control-Allow-Origin` header for that host is added to the response.
+//! The user of the middleware must specify a list of allowed origins. Origins
+//! are matched on scheme, host and port, where scheme and host are
+//! case-insensitive and a default port is equivalent to no port. The port
+//! can be ignored with
+//! [`CorsBuilder::ignore_port`](struct.CorsBuilder.html#method.ignore_port).
+//! If the `Origin` header is set on a request and if the value matches one of
+//! the allowed origins, the `Access-Control-Allow-Origin` header for that
+//! origin is added to the response.
 //!
-//! Initialize the middleware with a `HashSet` of allowed host strings:
+//! Initialize the middleware with the allowed origins:
 //!
 //! ```rust
-//! use std::collections::HashSet;
 //! use iron_cors::CorsMiddleware;
 //!
-//! let allowed_hosts = ["example.com"].iter()
-//!                                    .map(ToString::to_string)
-//!                                    .collect::<HashSet<_>>();
-//! let middleware = CorsMiddleware::with_whitelist(allowed_hosts);
+//! let middleware = CorsMiddleware::with_whitelist(["https://example.com"]);
 //! ```
 //!
 //! See
@@ -45,234 +45,2007 @@
 //! See
 //! [`examples/allow_any.rs`](https://github.com/dbrgn/iron-cors-rs/blob/master/examples/allow_any.rs)
 //! for a full usage example.
+//!
+//! ## Builder
+//!
+//! For more control over the configuration, use the builder returned by
+//! `CorsMiddleware::builder()`:
+//!
+//! ```rust
+//! use iron_cors::CorsMiddleware;
+//!
+//! let middleware = CorsMiddleware::builder()
+//!     .allowed_origins(vec!["https://example.com".to_string()])
+//!     .allow_credentials(true)
+//!     .build()
+//!     .expect("Invalid CORS configuration");
+//! ```
+//!
+//! # Cargo features
+//!
+//! - `regex`: Match origins against regular expressions with
+//!   `CorsMiddleware::with_whitelist_regex`.
+//! - `serde`: Deserialize a `CorsPolicy` or `CorsConfig` from a configuration
+//!   file, and export a policy as JSON with `CorsPolicy::to_json` or the
+//!   `CorsDebugHandler`.
+//! - `core`: Use CORS policies with other web frameworks through the
+//!   string-based interface in the `core` module.
+//! - `tracing`: Emit structured `tracing` events about CORS decisions, and
+//!   emit the messages about rejections as `tracing` events instead of log
+//!   messages.
+//! - `prometheus`: Record metrics in a Prometheus registry with
+//!   `CorsPrometheusMetrics`.
 
 extern crate iron;
 #[macro_use] extern crate log;
+extern crate unicase;
+#[cfg(feature = "regex")] extern crate regex;
+#[cfg(feature = "serde")] #[macro_use] extern crate serde;
+#[cfg(feature = "serde")] extern crate serde_json;
+#[cfg(feature = "prometheus")] extern crate prometheus;
+#[cfg(feature = "tracing")] extern crate tracing;
 
-use std::collections::HashSet;
+mod admin;
+mod apply;
+mod cache;
+mod classify;
+mod config;
+mod csrf;
+mod debug;
+mod deny;
+mod event;
+mod fetch;
+#[cfg(feature = "core")] pub mod core;
+mod file;
+mod logging;
+mod macros;
+mod matcher;
+mod merge;
+mod mount;
+pub mod origin;
+mod metrics;
+mod policy;
+mod provider;
+mod referer;
+mod rule;
+mod shared;
+mod store;
+mod trace;
+mod validator;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use iron::{Request, Response, IronResult, AroundMiddleware, Handler};
 use iron::method::Method;
-use iron::status;
+use iron::status::Status;
 use iron::headers;
+#[cfg(feature = "regex")] use regex::Regex;
+
+use origin::{normalize_origin, normalize_origins, null_origin};
+use logging::LogLimiter;
+use policy::{max_age_header, AllowedOrigins, DeniedBy, PreflightCache, PreflightStatus, DEFAULT_METHODS, SAFELISTED_HEADERS};
+use provider::{with_request_host, BoxedProvider};
+use referer::is_state_changing;
+use rule::ResolvedRule;
+use classify::{has_multiple_origins, has_null_origin, is_preflight, raw_origin_value};
+use csrf::SharedCsrfValidator;
+use deny::SharedResponder;
+use validator::Validator;
+
+pub use admin::{CorsAdminHandler, OriginPersistence};
+pub use apply::{apply_cors_headers, build_preflight_response};
+pub use cache::DecisionCache;
+pub use classify::{classify, CorsRequestKind};
+pub use config::{CorsConfig, CorsEnvError};
+pub use csrf::{CsrfHeader, CsrfValidator};
+#[cfg(feature = "serde")] pub use debug::CorsDebugHandler;
+pub use deny::{CorsRejection, DenyResponder, NegotiatingDenyResponder};
+pub use event::{CorsEvent, CorsEventSink};
+pub use fetch::{FetchMetadata, SecFetchMode, SecFetchSite};
+pub use provider::{CorsPolicySet, HostPattern, HostPolicies, PolicyProvider};
+pub use policy::{AsteriskOptionsBehavior, CorsDecision, CorsPolicy, CrossOriginEmbedderPolicy, CrossOriginOpenerPolicy, CrossOriginResourcePolicy, DisallowedOriginBehavior, ErrorResponsePolicy, HeaderConflictPolicy, HeaderPolicy, MalformedOriginPolicy, NullOriginPolicy, TlsRequirement};
+pub use file::WhitelistFile;
+pub use logging::LogEvent;
+pub use matcher::{IpRange, OriginMatcher};
+pub use mount::CorsMount;
+pub use origin::AllowedOrigin;
+pub use referer::RefererCheck;
+pub use rule::{CorsAction, CorsRule};
+pub use metrics::{CorsMetrics, Denial, RecentDenials};
+#[cfg(feature = "prometheus")] pub use metrics::CorsPrometheusMetrics;
+pub use shared::SharedOrigins;
+pub use store::OriginStore;
+pub use trace::{CorsTrace, TraceStep};
+pub use validator::{CircuitBreaker, CircuitState, ValidatorError, ValidatorFallback};
+pub use unicase::UniCase;
+
+/// Items used by the expansion of the `cors!` macro.
+#[doc(hidden)]
+pub mod __private {
+    pub use iron::method::Method;
+}
 
 /// The struct that holds the CORS configuration.
+///
+/// The configuration is reference counted, so the middleware can be cloned
+/// cheaply to decorate multiple chains.
+#[derive(Debug, Clone)]
 pub struct CorsMiddleware {
-    allowed_hosts: Option<HashSet<String>>,
+    state: Arc<CorsState>,
 }
 
-impl CorsMiddleware {
-    /// Specify which origin hosts are allowed to access the resource.
-    pub fn with_whitelist(allowed_hosts: HashSet<String>) -> Self {
-        CorsMiddleware {
-            allowed_hosts: Some(allowed_hosts),
+/// The configuration that is shared by clones of the middleware and by
+/// their handlers.
+#[derive(Debug)]
+struct CorsState {
+    policy: CorsPolicy,
+    rules: Vec<ResolvedRule>,
+    path_rules: Vec<PathRule>,
+    method_policies: HashMap<Method, CorsPolicy>,
+    /// Selects the policy instead of the rules above if set.
+    provider: Option<BoxedProvider>,
+    /// Requests to these paths bypass all CORS processing.
+    skip_paths: Vec<PathPattern>,
+    max_age_rules: Vec<MaxAgeRule>,
+    /// The expected hosts of the `Host` header, or empty to accept any.
+    allowed_hosts: Vec<HostPattern>,
+    /// Whether any of the policies may add headers to all responses, so
+    /// that the policy is looked up for requests without an `Origin` header.
+    adds_isolation_headers: bool,
+    hooks: Hooks,
+}
+
+/// A pattern that is matched against the path of a request.
+///
+/// Patterns containing a `*` are matched as globs against the full path,
+/// where `*` matches any sequence of characters. All other patterns are
+/// matched as path prefixes, e.g. `/api` matches `/api` and `/api/users`,
+/// but not `/apiary`.
+#[derive(Debug, Clone)]
+enum PathPattern {
+    Prefix(String),
+    Glob(String),
+}
+
+impl PathPattern {
+    fn new(pattern: &str) -> Self {
+        if pattern.contains('*') {
+            PathPattern::Glob(pattern.to_string())
+        } else {
+            PathPattern::Prefix(pattern.trim_end_matches('/').to_string())
+        }
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        match *self {
+            PathPattern::Prefix(ref prefix) => {
+                path.starts_with(prefix.as_str())
+                    && (path.len() == prefix.len() || path[prefix.len()..].starts_with('/'))
+            },
+            PathPattern::Glob(ref glob) => glob_matches(glob, path),
+        }
+    }
+}
+
+/// A policy that applies to requests whose path matches the pattern.
+#[derive(Debug)]
+struct PathRule {
+    pattern: PathPattern,
+    policy: CorsPolicy,
+}
+
+/// A max-age that overrides the max-age of the policy for preflights to
+/// matching paths.
+#[derive(Debug)]
+struct MaxAgeRule {
+    pattern: PathPattern,
+    max_age: Duration,
+}
+
+/// A callback that receives the serialized origin and the path of a request.
+type Hook = Box<dyn Fn(&str, &str) + Send + Sync>;
+
+/// Callbacks and metrics that are notified about CORS decisions.
+#[derive(Default)]
+struct Hooks {
+    on_allowed: Option<Hook>,
+    on_denied: Option<Hook>,
+    metrics: Option<Arc<CorsMetrics>>,
+    recent_denials: Option<Arc<RecentDenials>>,
+    #[cfg(feature = "prometheus")]
+    prometheus: Option<CorsPrometheusMetrics>,
+    event_sink: Option<Box<dyn CorsEventSink>>,
+}
+
+impl Hooks {
+    #[cfg_attr(not(feature = "prometheus"), allow(unused_variables))]
+    fn notify(&self, req: &Request, started: Instant) {
+        let decision = req.extensions.get::<CorsDecision>();
+        if let (Some(metrics), Some(decision)) = (self.metrics.as_ref(), decision) {
+            metrics.record(decision);
+        }
+        #[cfg(feature = "prometheus")]
+        {
+            if let (Some(prometheus), Some(decision)) = (self.prometheus.as_ref(), decision) {
+                prometheus.record(decision, started.elapsed());
+            }
+        }
+
+        let (hook, origin) = match decision {
+            Some(CorsDecision::Allowed(origin)) |
+            Some(CorsDecision::Preflight(origin)) => (&self.on_allowed, origin),
+            Some(CorsDecision::Denied(origin)) => (&self.on_denied, origin),
+            _ => return,
+        };
+        let path = request_path(req);
+        if let Some(ref hook) = *hook {
+            hook(origin, &path);
+        }
+        if let (Some(recent_denials), Some(CorsDecision::Denied(_))) = (self.recent_denials.as_ref(), decision) {
+            recent_denials.record(origin, &path);
+        }
+
+        let event = CorsEvent {
+            origin,
+            path: &path,
+            decision: decision.unwrap_or(&CorsDecision::NotCors),
+            rule: req.extensions.get::<DeniedBy>().cloned(),
+            preflight: is_preflight(req),
+        };
+        #[cfg(feature = "tracing")]
+        event.emit_tracing();
+        if let Some(ref sink) = self.event_sink {
+            sink.event(&event);
         }
     }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("on_allowed", &self.on_allowed.is_some())
+            .field("on_denied", &self.on_denied.is_some())
+            .field("metrics", &self.metrics)
+            .field("recent_denials", &self.recent_denials.is_some())
+            .field("event_sink", &self.event_sink.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl CorsMiddleware {
+    fn with_allowed_origins(allowed_origins: AllowedOrigins) -> Self {
+        CorsMiddleware::from_policy(CorsPolicy {
+            allowed_origins,
+            ..CorsPolicy::default()
+        })
+    }
+
+    /// Specify which origins are allowed to access the resource.
+    ///
+    /// ```rust
+    /// use iron_cors::CorsMiddleware;
+    ///
+    /// let middleware = CorsMiddleware::with_whitelist(["https://a.example.com", "https://b.example.com"]);
+    /// ```
+    pub fn with_whitelist<I, S>(allowed_origins: I) -> Self
+        where I: IntoIterator<Item=S>, S: Into<String>
+    {
+        let origins = normalize_origins(allowed_origins.into_iter().map(Into::into));
+        CorsMiddleware::with_allowed_origins(AllowedOrigins::Whitelist(Arc::new(origins)))
+    }
+
+    /// Specify a whitelist of allowed origins that can be modified at
+    /// runtime through the [`SharedOrigins`](struct.SharedOrigins.html)
+    /// handle.
+    pub fn with_shared_whitelist(allowed_origins: SharedOrigins) -> Self {
+        CorsMiddleware::with_origin_store(allowed_origins)
+    }
+
+    /// Specify a whitelist of allowed origins that is kept in an
+    /// [`OriginStore`](trait.OriginStore.html), e.g. a database table.
+    pub fn with_origin_store<S: OriginStore + 'static>(store: S) -> Self {
+        CorsMiddleware::with_allowed_origins(AllowedOrigins::Store(Arc::new(store)))
+    }
+
+    /// Specify which origins are allowed to access the resource, validating
+    /// that each entry is of the form `scheme://host[:port]`.
+    ///
+    /// Unlike [`with_whitelist`](#method.with_whitelist), this fails if an
+    /// entry can never match an origin, e.g. `example.com` without a scheme.
+    ///
+    /// ```rust
+    /// use iron_cors::{CorsConfigError, CorsMiddleware};
+    ///
+    /// assert!(CorsMiddleware::try_with_whitelist(vec!["https://example.com"]).is_ok());
+    /// assert_eq!(
+    ///     CorsMiddleware::try_with_whitelist(vec!["example.com"]).err(),
+    ///     Some(CorsConfigError::InvalidOrigin("example.com".to_string())),
+    /// );
+    /// ```
+    pub fn try_with_whitelist<I, S>(allowed_origins: I) -> Result<Self, CorsConfigError>
+        where I: IntoIterator<Item=S>, S: AsRef<str>
+    {
+        let origins = origin::parse_origins(allowed_origins)?;
+        let whitelist = origins.into_iter().map(AllowedOrigin::into_string).collect();
+        Ok(CorsMiddleware::with_allowed_origins(AllowedOrigins::Whitelist(Arc::new(whitelist))))
+    }
+
+    /// Allow all origins except the specified ones to access the resource.
+    ///
+    /// Requests from blocked origins are handled according to the
+    /// [`DisallowedOriginBehavior`](enum.DisallowedOriginBehavior.html).
+    ///
+    /// ```rust
+    /// use iron_cors::CorsMiddleware;
+    ///
+    /// let middleware = CorsMiddleware::with_blacklist(["https://evil.example.com"]);
+    /// ```
+    pub fn with_blacklist<I, S>(blocked_origins: I) -> Self
+        where I: IntoIterator<Item=S>, S: Into<String>
+    {
+        let origins = normalize_origins(blocked_origins.into_iter().map(Into::into));
+        CorsMiddleware::with_allowed_origins(AllowedOrigins::Blacklist(Arc::new(origins)))
+    }
+
+    /// Allow origins that are matched by any of the matchers. See
+    /// [`OriginMatcher`](enum.OriginMatcher.html).
+    pub fn with_matchers(matchers: Vec<OriginMatcher>) -> Self {
+        let matchers = matchers.into_iter().map(OriginMatcher::normalize).collect();
+        CorsMiddleware::with_allowed_origins(AllowedOrigins::Matchers(Arc::new(matchers)))
+    }
+
+    /// Specify a whitelist of allowed origins that is loaded from a file and
+    /// reloaded when the file changes. See
+    /// [`WhitelistFile`](struct.WhitelistFile.html) for details.
+    pub fn with_whitelist_file(file: WhitelistFile) -> Self {
+        CorsMiddleware::with_allowed_origins(AllowedOrigins::File(file))
+    }
+
+    /// Allow any origin on the local machine (`localhost`, `127.0.0.1` or
+    /// `[::1]`) with any port to access the resource, e.g. for development.
+    /// See [`CorsBuilder::allow_localhost`](struct.CorsBuilder.html#method.allow_localhost)
+    /// to combine this with a whitelist.
+    pub fn permissive_localhost() -> Self {
+        CorsMiddleware::from_policy(CorsPolicy {
+            allowed_origins: AllowedOrigins::Whitelist(Arc::default()),
+            allow_localhost: true,
+            ..CorsPolicy::default()
+        })
+    }
 
     /// Allow all origins to access the resource. The
     /// `Access-Control-Allow-Origin` header of the response will be set to
     /// `*`.
+    ///
+    /// To exclude some origins or paths, use the builder with
+    /// [`allow_any_origin`](struct.CorsBuilder.html#method.allow_any_origin),
+    /// [`except_origins`](struct.CorsBuilder.html#method.except_origins) and
+    /// [`except_paths`](struct.CorsBuilder.html#method.except_paths).
     pub fn with_allow_any() -> Self {
+        CorsMiddleware::with_allowed_origins(AllowedOrigins::Any)
+    }
+
+    /// Allow all origins to access the resource. Unlike
+    /// [`with_allow_any`](#method.with_allow_any), the
+    /// `Access-Control-Allow-Origin` header of the response will be set to
+    /// the origin of the request (together with `Vary: Origin`), which
+    /// allows combining it with credentials.
+    ///
+    /// ```rust
+    /// use iron_cors::CorsMiddleware;
+    ///
+    /// let middleware = CorsMiddleware::builder()
+    ///     .reflect_any_origin()
+    ///     .allow_credentials(true)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn with_allow_any_reflect() -> Self {
+        CorsMiddleware::with_allowed_origins(AllowedOrigins::Reflect)
+    }
+
+    /// Specify regular expressions for the origins that are allowed to access
+    /// the resource. The expressions are matched against the serialized
+    /// origin (`scheme://host[:port]`), so they should usually be anchored.
+    ///
+    /// This requires the `regex` feature.
+    ///
+    /// ```rust
+    /// # extern crate iron_cors;
+    /// # extern crate regex;
+    /// use iron_cors::CorsMiddleware;
+    /// use regex::Regex;
+    ///
+    /// # fn main() {
+    /// let middleware = CorsMiddleware::with_whitelist_regex(vec![
+    ///     Regex::new(r"^https://app-[0-9]+\.example\.com$").unwrap(),
+    /// ]);
+    /// # }
+    /// ```
+    #[cfg(feature = "regex")]
+    pub fn with_whitelist_regex(allowed_origins: Vec<Regex>) -> Self {
+        CorsMiddleware::with_allowed_origins(AllowedOrigins::Regex(allowed_origins))
+    }
+
+    /// Decide per request whether an origin is allowed to access the
+    /// resource, using arbitrary logic (e.g. a lookup in a database).
+    ///
+    /// The function receives the parsed `Origin` header of the request. If it
+    /// returns `true`, the `Access-Control-Allow-Origin` header for that
+    /// origin is added to the response.
+    ///
+    /// ```rust
+    /// use iron_cors::CorsMiddleware;
+    ///
+    /// let middleware = CorsMiddleware::with_origin_fn(|origin| {
+    ///     origin.host.hostname.ends_with(".example.com")
+    /// });
+    /// ```
+    pub fn with_origin_fn<F>(f: F) -> Self
+        where F: Fn(&headers::Origin) -> bool + Send + Sync + 'static
+    {
+        CorsMiddleware::with_allowed_origins(AllowedOrigins::Fn(Arc::new(f)))
+    }
+
+    /// Create the middleware from a [`CorsPolicy`](struct.CorsPolicy.html).
+    pub fn from_policy(policy: CorsPolicy) -> Self {
         CorsMiddleware {
-            allowed_hosts: None,
+            state: Arc::new(CorsState {
+                adds_isolation_headers: policy.has_isolation_headers(),
+                policy,
+                rules: vec![],
+                path_rules: vec![],
+                method_policies: HashMap::new(),
+                provider: None,
+                skip_paths: vec![],
+                max_age_rules: vec![],
+                allowed_hosts: vec![],
+                hooks: Hooks::default(),
+            }),
         }
     }
-}
 
-impl AroundMiddleware for CorsMiddleware {
-    fn around(self, handler: Box<Handler>) -> Box<Handler> {
-        match self.allowed_hosts {
-            Some(allowed_hosts) => Box::new(CorsHandlerWhitelist {
-                handler: handler,
-                allowed_hosts: allowed_hosts,
-            }),
-            None => Box::new(CorsHandlerAllowAny {
-                handler: handler,
+    /// Create the middleware with a provider that selects the policy for
+    /// each request. See [`PolicyProvider`](trait.PolicyProvider.html).
+    pub fn with_policy_provider<P: PolicyProvider + 'static>(provider: P) -> Self {
+        CorsMiddleware {
+            state: Arc::new(CorsState {
+                policy: CorsPolicy::default(),
+                rules: vec![],
+                path_rules: vec![],
+                method_policies: HashMap::new(),
+                provider: Some(BoxedProvider(Box::new(provider))),
+                skip_paths: vec![],
+                max_age_rules: vec![],
+                allowed_hosts: vec![],
+                adds_isolation_headers: true,
+                hooks: Hooks::default(),
             }),
         }
     }
-}
 
-/// Handler for whitelist based rules.
-struct CorsHandlerWhitelist {
-    handler: Box<Handler>,
-    allowed_hosts: HashSet<String>,
+    /// Create the middleware from environment variables with the specified
+    /// prefix. See [`CorsConfig::from_env`](struct.CorsConfig.html#method.from_env)
+    /// for the variables that are read.
+    ///
+    /// ```rust,no_run
+    /// use iron_cors::CorsMiddleware;
+    ///
+    /// // Reads CORS_ALLOWED_ORIGINS, CORS_ALLOW_CREDENTIALS, CORS_MAX_AGE, ...
+    /// let middleware = CorsMiddleware::from_env("CORS").expect("Invalid CORS configuration");
+    /// ```
+    pub fn from_env(prefix: &str) -> Result<Self, CorsEnvError> {
+        let policy = CorsConfig::from_env(prefix)?.into_policy()?;
+        Ok(CorsMiddleware::from_policy(policy))
+    }
+
+    /// Return a [`CorsBuilder`](struct.CorsBuilder.html) to configure the
+    /// middleware step by step.
+    pub fn builder() -> CorsBuilder {
+        CorsBuilder::new()
+    }
 }
 
-/// Handler if allowing any origin.
-struct CorsHandlerAllowAny {
-    handler: Box<Handler>,
+/// A builder for the [`CorsMiddleware`](struct.CorsMiddleware.html) or a
+/// [`CorsPolicy`](struct.CorsPolicy.html).
+///
+/// By default, requests from any origin are allowed.
+///
+/// ```rust
+/// use iron_cors::CorsMiddleware;
+///
+/// let middleware = CorsMiddleware::builder()
+///     .allowed_origins(vec!["https://example.com".to_string()])
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Default)]
+pub struct CorsBuilder {
+    policy: CorsPolicy,
+    rules: Vec<CorsRule>,
+    path_rules: Vec<PathRule>,
+    method_policies: HashMap<Method, CorsPolicy>,
+    skip_paths: Vec<PathPattern>,
+    max_age_rules: Vec<MaxAgeRule>,
+    allowed_hosts: Vec<HostPattern>,
+    hooks: Hooks,
 }
 
-impl CorsHandlerWhitelist {
-    fn add_cors_header(&self, headers: &mut headers::Headers, origin: &headers::Origin) {
-        let header = format_cors_origin(origin);
-        headers.set(headers::AccessControlAllowOrigin::Value(header));
+impl CorsBuilder {
+    /// Create a new builder with the default configuration.
+    pub fn new() -> Self {
+        CorsBuilder::default()
     }
 
-    fn add_cors_preflight_headers(&self,
-                                  headers: &mut headers::Headers,
-                                  origin: &headers::Origin,
-                                  acrm: &headers::AccessControlRequestMethod,
-                                  acrh: Option<&headers::AccessControlRequestHeaders>) {
+    /// Create a builder with a permissive configuration, e.g. for public
+    /// APIs: Any origin, the common methods from `GET` to `DELETE`, the
+    /// CORS-safelisted headers plus `Authorization` and `X-Requested-With`,
+    /// and a max-age of one day. Credentials are not allowed.
+    ///
+    /// ```rust
+    /// use iron_cors::CorsBuilder;
+    ///
+    /// let middleware = CorsBuilder::permissive().build().unwrap();
+    /// ```
+    pub fn permissive() -> Self {
+        let headers = SAFELISTED_HEADERS.iter().chain(&["Authorization", "X-Requested-With"])
+            .map(|header| UniCase(header.to_string()));
+        CorsBuilder::new()
+            .allow_any_origin()
+            .allowed_methods(DEFAULT_METHODS.to_vec())
+            .allowed_headers(headers)
+            .max_age(Duration::from_secs(24 * 60 * 60))
+    }
 
-        self.add_cors_header(headers, origin);
+    /// Create a builder with a restrictive configuration: Only the origins,
+    /// the methods `GET`, `HEAD` and `POST`, the CORS-safelisted headers and
+    /// a max-age of ten minutes are allowed, credentials are not allowed,
+    /// and preflights are validated [strictly](#method.strict).
+    ///
+    /// Extend the lists for the needs of the application:
+    ///
+    /// ```rust
+    /// extern crate iron;
+    /// extern crate iron_cors;
+    ///
+    /// use iron::method::Method;
+    /// use iron_cors::CorsBuilder;
+    ///
+    /// # fn main() {
+    /// let middleware = CorsBuilder::restrictive(vec!["https://app.example.com".to_string()])
+    ///     .allowed_methods(vec![Method::Get, Method::Head, Method::Post, Method::Delete])
+    ///     .build()
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn restrictive<I>(origins: I) -> Self
+        where I: IntoIterator<Item=String>
+    {
+        let headers = SAFELISTED_HEADERS.iter().map(|header| UniCase(header.to_string()));
+        CorsBuilder::new()
+            .allowed_origins(origins)
+            .allowed_methods(vec![Method::Get, Method::Head, Method::Post])
+            .allowed_headers(headers)
+            .allow_credentials(false)
+            .max_age(Duration::from_secs(10 * 60))
+            .strict(true)
+    }
 
-        // Copy the method requested by the browser in the allowed methods header
-        headers.set(headers::AccessControlAllowMethods(vec!(acrm.0.clone())));
+    /// Create a builder that behaves as the fetch standard intends: Any
+    /// origin is allowed, no max-age is announced, so browsers use their
+    /// default of five seconds, and rejected requests are answered without
+    /// CORS headers instead of `400 Bad Request`, see
+    /// [`DisallowedOriginBehavior::SpecCompliant`](enum.DisallowedOriginBehavior.html#variant.SpecCompliant).
+    pub fn spec_default() -> Self {
+        CorsBuilder::new()
+            .allow_any_origin()
+            .disallowed_origin_behavior(DisallowedOriginBehavior::SpecCompliant)
+    }
 
-        // If we have special allowed headers, copy them in the allowed headers in the response
-        if let Some(acrh) = acrh {
-            headers.set(headers::AccessControlAllowHeaders(acrh.0.clone()));
-        }
+    /// Only allow the specified origins to access the resource.
+    ///
+    /// Origins are specified as `scheme://host[:port]` strings, for example
+    /// `https://example.com` or `http://localhost:8080`.
+    pub fn allowed_origins<I>(mut self, origins: I) -> Self
+        where I: IntoIterator<Item=String>
+    {
+        self.policy.allowed_origins = AllowedOrigins::Whitelist(Arc::new(normalize_origins(origins)));
+        self
     }
 
-    fn process_possible_preflight(&self, req: &mut Request, origin: headers::Origin) -> IronResult<Response> {
-        // Verify origin header
-        let may_process = self.allowed_hosts.contains(&format_cors_origin(&origin));
+    /// Only allow the origins in a whitelist that can be modified at runtime.
+    /// See
+    /// [`CorsMiddleware::with_shared_whitelist`](struct.CorsMiddleware.html#method.with_shared_whitelist).
+    pub fn shared_origins(self, origins: SharedOrigins) -> Self {
+        self.origin_store(origins)
+    }
 
-        if !may_process {
-            warn!("Got disallowed preflight CORS request from {}", &origin.host.hostname);
-            return Ok(Response::with((status::BadRequest, "Invalid CORS request: Origin not allowed")));
-        }
+    /// Only allow the origins in an origin store. See
+    /// [`CorsMiddleware::with_origin_store`](struct.CorsMiddleware.html#method.with_origin_store).
+    pub fn origin_store<S: OriginStore + 'static>(mut self, store: S) -> Self {
+        self.policy.allowed_origins = AllowedOrigins::Store(Arc::new(store));
+        self
+    }
 
-        {
-            let acrm = req.headers.get::<headers::AccessControlRequestMethod>();
+    /// Allow all origins except the specified ones. See
+    /// [`CorsMiddleware::with_blacklist`](struct.CorsMiddleware.html#method.with_blacklist).
+    pub fn blocked_origins<I>(mut self, origins: I) -> Self
+        where I: IntoIterator<Item=String>
+    {
+        self.policy.allowed_origins = AllowedOrigins::Blacklist(Arc::new(normalize_origins(origins)));
+        self
+    }
+
+    /// Only allow origins that are matched by any of the matchers. See
+    /// [`OriginMatcher`](enum.OriginMatcher.html).
+    pub fn allowed_origin_matchers<I>(mut self, matchers: I) -> Self
+        where I: IntoIterator<Item=OriginMatcher>
+    {
+        let matchers = matchers.into_iter().map(OriginMatcher::normalize).collect();
+        self.policy.allowed_origins = AllowedOrigins::Matchers(Arc::new(matchers));
+        self
+    }
+
+    /// Only allow the origins in a whitelist file. See
+    /// [`WhitelistFile`](struct.WhitelistFile.html).
+    pub fn whitelist_file(mut self, file: WhitelistFile) -> Self {
+        self.policy.allowed_origins = AllowedOrigins::File(file);
+        self
+    }
+
+    /// Decide per request whether an origin is allowed to access the
+    /// resource. See
+    /// [`CorsMiddleware::with_origin_fn`](struct.CorsMiddleware.html#method.with_origin_fn).
+    pub fn allowed_origin_fn<F>(mut self, f: F) -> Self
+        where F: Fn(&headers::Origin) -> bool + Send + Sync + 'static
+    {
+        self.policy.allowed_origins = AllowedOrigins::Fn(Arc::new(f));
+        self
+    }
+
+    /// Decide per request whether an origin is allowed, using a function
+    /// that can fail, e.g. a lookup in a database.
+    ///
+    /// If the function returns an error, the
+    /// [`validator_fallback`](#method.validator_fallback) decides. See also
+    /// [`circuit_breaker`](#method.circuit_breaker).
+    pub fn try_origin_fn<F>(mut self, f: F) -> Self
+        where F: Fn(&headers::Origin) -> Result<bool, ValidatorError> + Send + Sync + 'static
+    {
+        self.policy.allowed_origins = AllowedOrigins::TryFn(Arc::new(Validator::new(Arc::new(f))));
+        self
+    }
+
+    /// Specify the decision for an origin if the function of
+    /// [`try_origin_fn`](#method.try_origin_fn) fails. By default, the origin
+    /// is denied.
+    pub fn validator_fallback(mut self, fallback: ValidatorFallback) -> Self {
+        self.policy.validator_settings.fallback = fallback;
+        self
+    }
+
+    /// Stop calling the function of [`try_origin_fn`](#method.try_origin_fn)
+    /// while it fails repeatedly. See
+    /// [`CircuitBreaker`](struct.CircuitBreaker.html).
+    pub fn circuit_breaker(mut self, breaker: Arc<CircuitBreaker>) -> Self {
+        self.policy.validator_settings.breaker = Some(breaker);
+        self
+    }
+
+    /// Wait at most `timeout` for the function of
+    /// [`try_origin_fn`](#method.try_origin_fn), so a slow lookup doesn't
+    /// block the request. On expiry, the
+    /// [`validator_fallback`](#method.validator_fallback) decides.
+    ///
+    /// The function is run on a small pool of worker threads. If all workers
+    /// are busy with slow lookups, further validations fail immediately.
+    pub fn validator_timeout(mut self, timeout: Duration) -> Self {
+        self.policy.validator_settings.timeout = Some(timeout);
+        self
+    }
+
+    /// Only allow origins matching one of the regular expressions. See
+    /// [`CorsMiddleware::with_whitelist_regex`](struct.CorsMiddleware.html#method.with_whitelist_regex).
+    ///
+    /// This requires the `regex` feature.
+    #[cfg(feature = "regex")]
+    pub fn allowed_origins_regex<I>(mut self, origins: I) -> Self
+        where I: IntoIterator<Item=Regex>
+    {
+        self.policy.allowed_origins = AllowedOrigins::Regex(origins.into_iter().collect());
+        self
+    }
+
+    /// Allow all origins to access the resource. This is the default.
+    pub fn allow_any_origin(mut self) -> Self {
+        self.policy.allowed_origins = AllowedOrigins::Any;
+        self
+    }
+
+    /// Exclude origins from the allowed origins, e.g. known-bad origins when
+    /// any origin is allowed. Requests from them are handled like requests
+    /// from any other disallowed origin.
+    ///
+    /// ```rust
+    /// use iron_cors::CorsMiddleware;
+    ///
+    /// let middleware = CorsMiddleware::builder()
+    ///     .allow_any_origin()
+    ///     .except_origins(vec!["https://evil.example.com".to_string()])
+    ///     .except_paths(vec!["/internal/*".to_string()])
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn except_origins<I>(mut self, origins: I) -> Self
+        where I: IntoIterator<Item=String>
+    {
+        self.policy.except_origins = normalize_origins(origins);
+        self
+    }
+
+    /// Deny all origins for requests whose path matches one of the
+    /// patterns, so that their responses don't get any CORS headers. The
+    /// patterns are matched like in [`path_policy`](#method.path_policy).
+    ///
+    /// This adds a [`CorsRule::deny`](struct.CorsRule.html#method.deny)
+    /// rule for each pattern.
+    pub fn except_paths<I>(mut self, patterns: I) -> Self
+        where I: IntoIterator<Item=String>
+    {
+        self.rules.extend(patterns.into_iter().map(|pattern| CorsRule::deny().path(&pattern)));
+        self
+    }
 
-            // Check the Access-Control-Request-Method header
-            if let Some(acrm) = acrm {
-                // Assuming that Access-Control-Request-Method header is valid (header names can be anything)
-                let acrh = req.headers.get::<headers::AccessControlRequestHeaders>();
+    /// Allow all origins to access the resource, echoing the request origin
+    /// instead of `*`. See
+    /// [`CorsMiddleware::with_allow_any_reflect`](struct.CorsMiddleware.html#method.with_allow_any_reflect).
+    pub fn reflect_any_origin(mut self) -> Self {
+        self.policy.allowed_origins = AllowedOrigins::Reflect;
+        self
+    }
+
+    /// Ignore the port when matching origins against the whitelist, so that
+    /// e.g. `http://localhost` (or `http://localhost:*`) allows
+    /// `http://localhost:3000` and `http://localhost:8080`. By default,
+    /// the port must match exactly.
+    pub fn ignore_port(mut self, ignore_port: bool) -> Self {
+        self.policy.ignore_port = ignore_port;
+        self
+    }
 
-                let mut response = Response::with((status::Ok, ""));
-                self.add_cors_preflight_headers(&mut response.headers, &origin, acrm, acrh);
+    /// Additionally allow any origin on the local machine (`localhost`,
+    /// `127.0.0.1` or `[::1]`) with any port.
+    ///
+    /// ```rust
+    /// use iron_cors::CorsMiddleware;
+    ///
+    /// let middleware = CorsMiddleware::builder()
+    ///     .allowed_origins(vec!["https://example.com".to_string()])
+    ///     .allow_localhost(cfg!(debug_assertions))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn allow_localhost(mut self, allow_localhost: bool) -> Self {
+        self.policy.allow_localhost = allow_localhost;
+        self
+    }
+
+    /// Pass same-origin requests through without CORS processing.
+    ///
+    /// Browsers send the `Origin` header on some same-origin requests, e.g.
+    /// `POST` requests. If this is enabled, requests whose origin matches
+    /// the scheme, `Host` header and port of the request itself are passed
+    /// to the inner handler untouched, even if the origin is not
+    /// whitelisted. See [`self_origin`](#method.self_origin) for servers
+    /// behind a reverse proxy.
+    pub fn skip_same_origin(mut self, skip_same_origin: bool) -> Self {
+        self.policy.skip_same_origin = skip_same_origin;
+        self
+    }
 
-                // In case of preflight, return 200 with empty body after adding the preflight headers
-                return Ok(response);
+    /// Pass requests with the `Sec-Fetch-Site: same-origin` header through
+    /// without CORS processing, like
+    /// [`skip_same_origin`](#method.skip_same_origin) does for requests
+    /// whose `Origin` matches the server. Browsers set this header
+    /// themselves, and scripts can't forge it.
+    pub fn trust_sec_fetch_site(mut self, trust_sec_fetch_site: bool) -> Self {
+        self.policy.trust_sec_fetch_site = trust_sec_fetch_site;
+        self
+    }
+
+    /// Send the `Cross-Origin-Resource-Policy` header on all responses,
+    /// with or without an `Origin` header, unless the handler already set
+    /// it. Combine it with [`path_policy`](#method.path_policy) to protect
+    /// single routes, e.g. `same-origin` for private images.
+    ///
+    /// ```rust
+    /// use iron_cors::{CorsMiddleware, CorsPolicy, CrossOriginResourcePolicy};
+    ///
+    /// let private = CorsPolicy::builder()
+    ///     .allowed_origins(vec!["https://app.example.com".to_string()])
+    ///     .cross_origin_resource_policy(CrossOriginResourcePolicy::SameOrigin)
+    ///     .build_policy()
+    ///     .unwrap();
+    /// let middleware = CorsMiddleware::builder()
+    ///     .allowed_origins(vec!["https://app.example.com".to_string()])
+    ///     .cross_origin_resource_policy(CrossOriginResourcePolicy::SameSite)
+    ///     .path_policy("/avatars", private)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn cross_origin_resource_policy(mut self, policy: CrossOriginResourcePolicy) -> Self {
+        self.policy.resource_policy = Some(policy);
+        self
+    }
+
+    /// Send the `Cross-Origin-Opener-Policy` header on all responses except
+    /// preflight responses, unless the handler already set it.
+    ///
+    /// Together with [`cross_origin_embedder_policy`](#method.cross_origin_embedder_policy),
+    /// this makes documents cross-origin isolated, which browsers require
+    /// for features like `SharedArrayBuffer`:
+    ///
+    /// ```rust
+    /// use iron_cors::{CorsMiddleware, CrossOriginEmbedderPolicy, CrossOriginOpenerPolicy};
+    ///
+    /// let middleware = CorsMiddleware::builder()
+    ///     .allowed_origins(vec!["https://app.example.com".to_string()])
+    ///     .cross_origin_opener_policy(CrossOriginOpenerPolicy::SameOrigin)
+    ///     .cross_origin_embedder_policy(CrossOriginEmbedderPolicy::RequireCorp)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn cross_origin_opener_policy(mut self, policy: CrossOriginOpenerPolicy) -> Self {
+        self.policy.opener_policy = Some(policy);
+        self
+    }
+
+    /// Send the `Cross-Origin-Embedder-Policy` header on all responses
+    /// except preflight responses, unless the handler already set it. See
+    /// [`cross_origin_opener_policy`](#method.cross_origin_opener_policy).
+    pub fn cross_origin_embedder_policy(mut self, policy: CrossOriginEmbedderPolicy) -> Self {
+        self.policy.embedder_policy = Some(policy);
+        self
+    }
+
+    /// Require TLS for CORS responses, or only for credentialed ones, so
+    /// that a plain HTTP listener can't leak responses that are meant for
+    /// secure origins. See [`TlsRequirement`](enum.TlsRequirement.html).
+    pub fn require_tls(mut self, requirement: TlsRequirement) -> Self {
+        self.policy.tls_requirement = requirement;
+        self
+    }
+
+    /// Remove the `Cookie` and `Authorization` headers from requests from
+    /// disallowed origins before they are passed to the inner handler, with
+    /// [`DisallowedOriginBehavior::PassThrough`](enum.DisallowedOriginBehavior.html#variant.PassThrough)
+    /// or `SpecCompliant`. The handler then serves them like anonymous
+    /// requests, even though the browser sent the credentials of the user.
+    pub fn strip_credentials(mut self, strip_credentials: bool) -> Self {
+        self.policy.strip_credentials = strip_credentials;
+        self
+    }
+
+    /// Check the `Referer` header of state-changing requests without an
+    /// `Origin` header, as sent by some older clients, e.g. `POST` requests.
+    ///
+    /// The origin of the referer is checked like an `Origin` header, and
+    /// the result is inserted into the request extensions as a
+    /// [`RefererCheck`](struct.RefererCheck.html). The origin of the server
+    /// itself is always allowed. Requests are not rejected by the check.
+    pub fn referer_fallback(mut self, referer_fallback: bool) -> Self {
+        self.policy.referer_fallback = referer_fallback;
+        self
+    }
+
+    /// Reject requests with these methods if their `Sec-Fetch-Site` header
+    /// is `cross-site`, even if the origin is allowed, e.g. to keep other
+    /// sites from changing state with the credentials of the user. For
+    /// preflights, the requested method is checked.
+    ///
+    /// The rejection is answered like a disallowed origin with
+    /// [`DisallowedOriginBehavior::Reject`](enum.DisallowedOriginBehavior.html#variant.Reject).
+    /// Requests from browsers without fetch metadata are not affected.
+    ///
+    /// ```rust
+    /// extern crate iron;
+    /// extern crate iron_cors;
+    ///
+    /// use iron::method::Method;
+    /// use iron_cors::CorsMiddleware;
+    ///
+    /// # fn main() {
+    /// let middleware = CorsMiddleware::builder()
+    ///     .allowed_origins(vec!["https://partner.example.org".to_string()])
+    ///     .reject_cross_site(vec![Method::Post, Method::Put, Method::Patch, Method::Delete])
+    ///     .build()
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn reject_cross_site(mut self, methods: Vec<Method>) -> Self {
+        self.policy.reject_cross_site = methods;
+        self
+    }
+
+    /// Set the public origin of the server itself, e.g.
+    /// `https://example.com` if TLS is terminated by a reverse proxy, and
+    /// enable [`skip_same_origin`](#method.skip_same_origin). Requests from
+    /// this origin are treated as same-origin requests.
+    pub fn self_origin(mut self, origin: &str) -> Self {
+        self.policy.self_origin = Some(normalize_origin(origin));
+        self.policy.skip_same_origin = true;
+        self
+    }
+
+    /// Trust the `X-Forwarded-Proto` and `X-Forwarded-Host` headers of
+    /// requests from these peer addresses, e.g. a reverse proxy on the same
+    /// machine. For those requests, the forwarded headers instead of the
+    /// local `Host` header determine the server's own origin for
+    /// [`skip_same_origin`](#method.skip_same_origin). The headers of other
+    /// peers are ignored, as clients could forge them.
+    pub fn trusted_proxies<I>(mut self, proxies: I) -> Self
+        where I: IntoIterator<Item=IpAddr>
+    {
+        self.policy.trusted_proxies = proxies.into_iter().collect();
+        self
+    }
+
+    /// Only allow origins with the `https` scheme, even if the host is
+    /// whitelisted. Requests from other origins are handled like requests
+    /// from any other disallowed origin, see
+    /// [`disallowed_origin_behavior`](#method.disallowed_origin_behavior).
+    pub fn require_https_origins(mut self, require_https_origins: bool) -> Self {
+        self.policy.require_https_origins = require_https_origins;
+        self
+    }
+
+    /// Only allow origins with one of the schemes, e.g. `https` and
+    /// `capacitor` for mobile webviews, even if the host is whitelisted.
+    /// Schemes are compared case-insensitively. Origins allowed with
+    /// [`allowed_raw_origins`](#method.allowed_raw_origins) are not
+    /// affected.
+    pub fn allowed_schemes<I, S>(mut self, schemes: I) -> Self
+        where I: IntoIterator<Item=S>, S: Into<String>
+    {
+        self.policy.allowed_schemes = Some(schemes.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Set the `Access-Control-Allow-Credentials: true` header on preflight
+    /// and actual responses, so that the browser exposes responses to
+    /// requests made with credentials (cookies or HTTP authentication).
+    ///
+    /// Credentials cannot be allowed together with
+    /// [`allow_any_origin`](#method.allow_any_origin), use
+    /// [`reflect_any_origin`](#method.reflect_any_origin) instead.
+    pub fn allow_credentials(mut self, allow_credentials: bool) -> Self {
+        self.policy.allow_credentials = allow_credentials;
+        self
+    }
+
+    /// Set the `Access-Control-Max-Age` header on preflight responses, which
+    /// allows the browser to cache the preflight result for the specified
+    /// duration. Sub-second precision is ignored.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.policy.max_age = Some(max_age);
+        self
+    }
+
+    /// Set the `Access-Control-Expose-Headers` header on actual (non-preflight)
+    /// responses, which allows the browser to expose the listed response
+    /// headers (e.g. `X-Total-Count`) to the client.
+    pub fn expose_headers<I>(mut self, headers: I) -> Self
+        where I: IntoIterator<Item=String>
+    {
+        self.policy.expose_headers = headers.into_iter().map(UniCase).collect();
+        self
+    }
+
+    /// Restrict the methods that may be used for cross-origin requests.
+    ///
+    /// Preflight requests asking for a method that is not in the list are
+    /// rejected, and successful preflights announce the full list in the
+    /// `Access-Control-Allow-Methods` header. If this is not set, the method
+    /// requested by the browser is allowed.
+    pub fn allowed_methods<I>(mut self, methods: I) -> Self
+        where I: IntoIterator<Item=Method>
+    {
+        self.policy.allowed_methods = Some(methods.into_iter().collect());
+        self
+    }
+
+    /// Restrict the headers that may be used for cross-origin requests.
+    ///
+    /// Preflight requests asking for a header that is not in the list are
+    /// rejected, and successful preflights announce the full list in the
+    /// `Access-Control-Allow-Headers` header. Header names are compared
+    /// case-insensitively. If this is not set, the headers requested by the
+    /// browser are allowed.
+    pub fn allowed_headers<I>(mut self, headers: I) -> Self
+        where I: IntoIterator<Item=UniCase<String>>
+    {
+        self.policy.allowed_headers = Some(headers.into_iter().collect());
+        self
+    }
+
+    /// Set the headers that are always announced in the
+    /// `Access-Control-Allow-Headers` header of preflight responses, even if
+    /// the browser didn't request them, e.g. `Content-Type`. This only
+    /// applies if the requested headers are echoed, i.e. without
+    /// [allowed headers](#method.allowed_headers).
+    pub fn default_allowed_headers<I>(mut self, headers: I) -> Self
+        where I: IntoIterator<Item=UniCase<String>>
+    {
+        self.policy.default_headers = headers.into_iter().collect();
+        self
+    }
+
+    /// Add the headers to every successful preflight response, e.g.
+    /// `Access-Control-*` extensions that have no typed header. Headers that
+    /// are set by the policy are replaced.
+    pub fn extra_preflight_headers(mut self, headers: Vec<(String, String)>) -> Self {
+        self.policy.extra_preflight_headers = headers;
+        self
+    }
+
+    /// Add the headers to the response of every allowed actual CORS request,
+    /// e.g. `X-Api-Version`. Headers that are set by the policy are
+    /// replaced.
+    pub fn extra_actual_headers(mut self, headers: Vec<(String, String)>) -> Self {
+        self.policy.extra_actual_headers = headers;
+        self
+    }
+
+    /// Specify how to handle CORS headers that the inner handler already set,
+    /// e.g. a route-specific `Access-Control-Allow-Origin`. By default, they
+    /// are overwritten. The `Vary` header is always merged.
+    pub fn header_conflict_policy(mut self, policy: HeaderConflictPolicy) -> Self {
+        self.policy.header_conflict_policy = policy;
+        self
+    }
+
+    /// Build the responses to rejected requests with the responder instead
+    /// of the [`NegotiatingDenyResponder`](struct.NegotiatingDenyResponder.html).
+    /// See [`DenyResponder`](trait.DenyResponder.html).
+    pub fn deny_responder<R: DenyResponder + 'static>(mut self, responder: R) -> Self {
+        self.policy.deny_responder = SharedResponder(Arc::new(responder));
+        self
+    }
+
+    /// Require state-changing requests to come from an allowed origin or to
+    /// be accepted by the validator, to protect against cross-site request
+    /// forgery. Rejected requests are answered by the
+    /// [deny responder](#method.deny_responder). See
+    /// [`CsrfValidator`](trait.CsrfValidator.html).
+    pub fn csrf_validator<V: CsrfValidator + 'static>(mut self, validator: V) -> Self {
+        self.policy.csrf_validator = Some(SharedCsrfValidator(Arc::new(validator)));
+        self
+    }
+
+    /// Specify which error responses of the inner handler get CORS headers,
+    /// e.g. to keep server errors unreadable for cross-origin scripts. By
+    /// default, all error responses get CORS headers.
+    ///
+    /// ```rust
+    /// extern crate iron;
+    /// extern crate iron_cors;
+    ///
+    /// use iron::status::StatusClass;
+    /// use iron_cors::{CorsMiddleware, ErrorResponsePolicy};
+    ///
+    /// # fn main() {
+    /// let middleware = CorsMiddleware::builder()
+    ///     .allowed_origins(vec!["https://app.example.com".to_string()])
+    ///     .error_response_policy(ErrorResponsePolicy::StatusClasses(vec![StatusClass::ClientError]))
+    ///     .build()
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn error_response_policy(mut self, policy: ErrorResponsePolicy) -> Self {
+        self.policy.error_response_policy = policy;
+        self
+    }
+
+    /// Set the `Cache-Control` header of successful preflight responses, e.g.
+    /// `public, max-age=600`, so that intermediaries can cache them.
+    ///
+    /// Preflight responses always have an empty body with
+    /// `Content-Length: 0`. Caches must still take the `Vary` header into
+    /// account, as the response depends on the origin and the requested
+    /// method and headers.
+    pub fn preflight_cache_control<S: Into<String>>(mut self, value: S) -> Self {
+        self.policy.preflight_cache_control = Some(value.into());
+        self
+    }
+
+    /// Cache the headers of up to `capacity` preflight responses, by origin,
+    /// requested method and requested headers, so repeated preflights don't
+    /// build the same headers again.
+    ///
+    /// This is useful with many preflights from a whitelist of origins. If
+    /// any origin is allowed, the preflight headers are always computed only
+    /// once.
+    pub fn preflight_cache(mut self, capacity: usize) -> Self {
+        self.policy.preflight_cache = Some(Arc::new(Mutex::new(PreflightCache::new(capacity))));
+        self
+    }
+
+    /// Cache the decisions of a dynamic origin check, i.e. of
+    /// [`allowed_origin_fn`](#method.allowed_origin_fn) or of an
+    /// [`OriginStore`](trait.OriginStore.html). See
+    /// [`DecisionCache`](struct.DecisionCache.html).
+    pub fn decision_cache(mut self, cache: Arc<DecisionCache>) -> Self {
+        self.policy.decision_cache = Some(cache);
+        self
+    }
+
+    /// Additionally allow origins by their exact serialization, e.g. browser
+    /// extensions like `chrome-extension://<id>` or
+    /// `safari-web-extension://<UUID>`.
+    ///
+    /// The value of the `Origin` header is compared byte for byte, without
+    /// normalization, and echoed unchanged in the
+    /// `Access-Control-Allow-Origin` header. This also works for origins that
+    /// can't be parsed into a typed `Origin` header.
+    pub fn allowed_raw_origins<I>(mut self, origins: I) -> Self
+        where I: IntoIterator<Item=String>
+    {
+        self.policy.raw_origins.extend(origins);
+        self
+    }
+
+    /// Specify how to handle requests with an `Origin` header that can't be
+    /// parsed. By default, they are handled like requests without an
+    /// `Origin` header.
+    pub fn malformed_origin_policy(mut self, policy: MalformedOriginPolicy) -> Self {
+        self.policy.malformed_origin_policy = policy;
+        self
+    }
+
+    /// Reject requests with more than one `Origin` header line with
+    /// `400 Bad Request`, as they may be header smuggling attempts through
+    /// misbehaving proxies. A single line listing several comma-separated
+    /// origins is rejected as well. By default, multiple lines are handled
+    /// like requests without an `Origin` header.
+    pub fn reject_multiple_origins(mut self, reject: bool) -> Self {
+        self.policy.reject_multiple_origins = reject;
+        self
+    }
+
+    /// Specify how to respond to requests from origins that are not allowed.
+    pub fn disallowed_origin_behavior(mut self, behavior: DisallowedOriginBehavior) -> Self {
+        self.policy.disallowed_origin_behavior = behavior;
+        self
+    }
+
+    /// Specify how to handle requests with the `Origin: null` header. By
+    /// default, they are passed through without CORS processing.
+    pub fn null_origin_policy(mut self, policy: NullOriginPolicy) -> Self {
+        self.policy.null_origin_policy = policy;
+        self
+    }
+
+    /// Specify how to handle server-wide `OPTIONS *` requests. By default,
+    /// they are passed through without CORS processing.
+    ///
+    /// Iron itself rejects requests with an asterisk-form target before any
+    /// middleware runs, so this applies to
+    /// [`CorsPolicy::evaluate`](struct.CorsPolicy.html#method.evaluate) with
+    /// the `core` feature, e.g. with hyper.
+    pub fn asterisk_options(mut self, behavior: AsteriskOptionsBehavior) -> Self {
+        self.policy.asterisk_options = behavior;
+        self
+    }
+
+    /// Set the status code of successful preflight responses. The default is
+    /// `200 OK`, another common choice is `204 No Content`. The body of the
+    /// response is always empty.
+    pub fn preflight_status(mut self, status: Status) -> Self {
+        self.policy.preflight_status = PreflightStatus(status);
+        self
+    }
+
+    /// Send the `Timing-Allow-Origin` header on actual responses to allowed
+    /// origins, so they can read the full Resource Timing data. The header
+    /// contains the same value as `Access-Control-Allow-Origin`.
+    pub fn timing_allow_origin(mut self, timing_allow_origin: bool) -> Self {
+        self.policy.timing_allow_origin = timing_allow_origin;
+        self
+    }
+
+    /// Answer requests from the `XDomainRequest` object of Internet Explorer
+    /// 8 to 10 with only the `Access-Control-Allow-Origin` header.
+    ///
+    /// Such requests are recognized as `GET` or `POST` requests without the
+    /// `Access-Control-Request-Headers` header from a user agent that
+    /// identifies as one of these browsers. `XDomainRequest` never sends
+    /// credentials or custom headers and can't read response headers, so the
+    /// other CORS headers and the extra headers are omitted.
+    pub fn xdomain_request_compat(mut self, xdomain_request_compat: bool) -> Self {
+        self.policy.xdomain_request_compat = xdomain_request_compat;
+        self
+    }
+
+    /// Validate preflight requests strictly.
+    ///
+    /// Without a configured list of allowed methods, only common methods
+    /// such as `GET` and `DELETE` are allowed instead of any requested
+    /// method. Without a configured list of allowed headers, only the
+    /// CORS-safelisted headers `Accept`, `Accept-Language`,
+    /// `Content-Language` and `Content-Type` are allowed.
+    ///
+    /// Invalid preflights are answered with an empty response without any
+    /// CORS headers, so the browser fails the preflight as specified by the
+    /// fetch standard, instead of a `400 Bad Request`.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.policy.strict = strict;
+        self
+    }
+
+    /// Reject actual CORS requests whose method is not in the
+    /// [allowed methods](#method.allowed_methods) with a
+    /// `405 Method Not Allowed` response listing the allowed methods in the
+    /// `Allow` header.
+    ///
+    /// Browsers only send preflights for some methods, so without this, a
+    /// cross-origin `POST` reaches the handler even if only `GET` is allowed.
+    pub fn enforce_allowed_methods(mut self, enforce: bool) -> Self {
+        self.policy.enforce_allowed_methods = enforce;
+        self
+    }
+
+    /// Answer preflights for a method that is not in the
+    /// [allowed methods](#method.allowed_methods) with a
+    /// `405 Method Not Allowed` response listing the allowed methods in the
+    /// `Allow` header, instead of `400 Bad Request`.
+    ///
+    /// This takes precedence over the empty preflight responses of
+    /// [`strict`](#method.strict) mode.
+    pub fn preflight_method_not_allowed(mut self, enabled: bool) -> Self {
+        self.policy.preflight_method_not_allowed = enabled;
+        self
+    }
+
+    /// Specify how preflight responses announce the allowed methods and
+    /// headers if no lists are configured. By default, the requested method
+    /// and headers are echoed, except by policies that allow any origin with
+    /// `*`. See [`HeaderPolicy::Echo`](enum.HeaderPolicy.html#variant.Echo).
+    pub fn header_policy(mut self, policy: HeaderPolicy) -> Self {
+        self.policy.header_policy = policy;
+        self
+    }
+
+    /// Whether to list `Authorization` next to the `*` wildcard in the
+    /// `Access-Control-Allow-Headers` header. Browsers never match
+    /// `Authorization` against the wildcard, so requests with bearer tokens
+    /// would fail otherwise. This is enabled by default.
+    pub fn wildcard_authorization(mut self, include: bool) -> Self {
+        self.policy.omit_wildcard_authorization = !include;
+        self
+    }
+
+    /// Record which checks of the policy passed or failed for every CORS
+    /// request. The [`CorsTrace`](struct.CorsTrace.html) is inserted into
+    /// the request extensions and logged at debug level.
+    ///
+    /// This is meant for debugging why an origin is rejected, as it adds
+    /// some work to every request.
+    pub fn trace(mut self, trace: bool) -> Self {
+        self.policy.trace_decisions = trace;
+        self
+    }
+
+    /// Evaluate the policy without enforcing it: Requests that would be
+    /// rejected are logged and recorded as
+    /// [`CorsDecision::Denied`](enum.CorsDecision.html), e.g. for the
+    /// [`on_denied`](#method.on_denied) hook and the metrics, but they are
+    /// allowed, and preflights are answered with the requested method and
+    /// headers.
+    ///
+    /// This helps to roll out a stricter policy without breaking clients.
+    pub fn report_only(mut self, report_only: bool) -> Self {
+        self.policy.report_only = report_only;
+        self
+    }
+
+    /// Send a structured [`CorsEvent`](struct.CorsEvent.html) to the sink for
+    /// every allowed or denied CORS request.
+    pub fn event_sink<S: CorsEventSink + 'static>(mut self, sink: S) -> Self {
+        self.hooks.event_sink = Some(Box::new(sink));
+        self
+    }
+
+    /// Set the level of the log messages about rejected requests of the
+    /// kind, or disable them with `None`. By default, all messages are
+    /// logged at the `Warn` level.
+    ///
+    /// ```rust
+    /// extern crate iron_cors;
+    /// extern crate log;
+    ///
+    /// use iron_cors::{CorsMiddleware, LogEvent};
+    ///
+    /// # fn main() {
+    /// let middleware = CorsMiddleware::builder()
+    ///     .allowed_origins(vec!["https://example.com".to_string()])
+    ///     .log_level(LogEvent::DisallowedOrigin, Some(log::Level::Info))
+    ///     .log_level(LogEvent::InvalidPreflight, None)
+    ///     .build()
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn log_level(mut self, event: LogEvent, level: Option<log::Level>) -> Self {
+        self.policy.log_levels.set(event, level);
+        self
+    }
+
+    /// Log at most `max_messages` messages about rejected requests per
+    /// origin within the time window. The number of suppressed messages is
+    /// logged with the next message after the window ends.
+    pub fn log_rate_limit(mut self, max_messages: u32, window: Duration) -> Self {
+        self.policy.log_limiter = Some(Arc::new(Mutex::new(LogLimiter::new(max_messages, window))));
+        self
+    }
+
+    /// Call the function for every allowed CORS request, including
+    /// preflights, with the serialized origin and the path of the request.
+    pub fn on_allowed<F>(mut self, f: F) -> Self
+        where F: Fn(&str, &str) + Send + Sync + 'static
+    {
+        self.hooks.on_allowed = Some(Box::new(f));
+        self
+    }
+
+    /// Call the function for every denied CORS request, with the serialized
+    /// origin and the path of the request.
+    ///
+    /// ```rust
+    /// use iron_cors::CorsMiddleware;
+    ///
+    /// let middleware = CorsMiddleware::builder()
+    ///     .allowed_origins(vec!["https://example.com".to_string()])
+    ///     .on_denied(|origin, path| println!("Denied CORS request from {} to {}", origin, path))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn on_denied<F>(mut self, f: F) -> Self
+        where F: Fn(&str, &str) + Send + Sync + 'static
+    {
+        self.hooks.on_denied = Some(Box::new(f));
+        self
+    }
+
+    /// Count the processed requests and the cache hits of all policies in
+    /// the metrics. See [`CorsMetrics`](struct.CorsMetrics.html).
+    pub fn metrics(mut self, metrics: Arc<CorsMetrics>) -> Self {
+        self.hooks.metrics = Some(metrics);
+        self
+    }
+
+    /// Record the most recent denied requests. See
+    /// [`RecentDenials`](struct.RecentDenials.html).
+    pub fn recent_denials(mut self, recent_denials: Arc<RecentDenials>) -> Self {
+        self.hooks.recent_denials = Some(recent_denials);
+        self
+    }
+
+    /// Record the processed requests in Prometheus metrics. See
+    /// [`CorsPrometheusMetrics`](struct.CorsPrometheusMetrics.html).
+    #[cfg(feature = "prometheus")]
+    pub fn prometheus(mut self, metrics: CorsPrometheusMetrics) -> Self {
+        self.hooks.prometheus = Some(metrics);
+        self
+    }
+
+    /// Apply a different policy to requests whose path matches the pattern.
+    ///
+    /// Path rules are evaluated in the order in which they were added, and
+    /// the policy of the first matching rule is used. Requests that don't
+    /// match any rule use the policy configured on this builder.
+    ///
+    /// Patterns containing a `*` are matched as globs against the full path,
+    /// where `*` matches any sequence of characters. All other patterns are
+    /// matched as path prefixes, e.g. `/api` matches `/api` and
+    /// `/api/users`, but not `/apiary`.
+    ///
+    /// ```rust
+    /// use iron_cors::CorsMiddleware;
+    ///
+    /// let public = CorsMiddleware::builder()
+    ///     .allow_any_origin()
+    ///     .build_policy()
+    ///     .unwrap();
+    /// let middleware = CorsMiddleware::builder()
+    ///     .allowed_origins(vec!["https://admin.example.com".to_string()])
+    ///     .path_policy("/api/public", public)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn path_policy(mut self, pattern: &str, policy: CorsPolicy) -> Self {
+        self.path_rules.push(PathRule {
+            pattern: PathPattern::new(pattern),
+            policy,
+        });
+        self
+    }
+
+    /// Announce a different max-age in the preflight responses of the
+    /// middleware for paths that match the pattern, whichever policy
+    /// applies to them, e.g.
+    /// a long max-age for a static schema and zero for authentication
+    /// endpoints. Patterns are matched like in
+    /// [`path_policy`](#method.path_policy), and the first matching pattern
+    /// wins.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use iron_cors::CorsMiddleware;
+    ///
+    /// let middleware = CorsMiddleware::builder()
+    ///     .allowed_origins(vec!["https://app.example.com".to_string()])
+    ///     .max_age(Duration::from_secs(600))
+    ///     .path_max_age("/api/schema", Duration::from_secs(24 * 60 * 60))
+    ///     .path_max_age("/auth", Duration::from_secs(0))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn path_max_age(mut self, pattern: &str, max_age: Duration) -> Self {
+        self.max_age_rules.push(MaxAgeRule {
+            pattern: PathPattern::new(pattern),
+            max_age,
+        });
+        self
+    }
+
+    /// Add a rule that applies an action to the requests it matches. Rules
+    /// are evaluated in the order in which they are added, and the first
+    /// matching rule wins. They take precedence over path rules and method
+    /// policies. See [`CorsRule`](struct.CorsRule.html).
+    pub fn rule(mut self, rule: CorsRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Don't check the origin of requests with one of the methods, e.g.
+    /// `GET` and `HEAD` for intentionally public read-only endpoints, while
+    /// the origin of writes is still checked. Requests with these methods
+    /// get CORS headers for any origin. For preflight requests, the method
+    /// in the `Access-Control-Request-Method` header is used.
+    ///
+    /// Credentials are only announced to allowed origins. Requests from
+    /// other origins that pass because of their method don't get the
+    /// `Access-Control-Allow-Credentials` header, so browsers don't expose
+    /// authenticated responses to them.
+    ///
+    /// ```rust
+    /// extern crate iron;
+    /// extern crate iron_cors;
+    ///
+    /// use iron::method::Method;
+    /// use iron_cors::CorsMiddleware;
+    ///
+    /// # fn main() {
+    /// let middleware = CorsMiddleware::builder()
+    ///     .allowed_origins(vec!["https://app.example.com".to_string()])
+    ///     .unrestricted_methods(vec![Method::Get, Method::Head])
+    ///     .build()
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn unrestricted_methods(mut self, methods: Vec<Method>) -> Self {
+        self.policy.unrestricted_methods = methods;
+        self
+    }
+
+    /// Pass requests whose path matches one of the patterns to the inner
+    /// handler without any CORS processing, e.g. health checks, metrics
+    /// endpoints and webhook receivers. Their `Origin` header isn't
+    /// validated, and the responses don't get any CORS headers. The patterns
+    /// are matched like in [`path_policy`](#method.path_policy), and take
+    /// precedence over all rules and policies.
+    ///
+    /// ```rust
+    /// use iron_cors::CorsMiddleware;
+    ///
+    /// let middleware = CorsMiddleware::builder()
+    ///     .allowed_origins(vec!["https://app.example.com".to_string()])
+    ///     .skip_paths(vec!["/health".to_string(), "/webhooks/*".to_string()])
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn skip_paths<I>(mut self, patterns: I) -> Self
+        where I: IntoIterator<Item=String>
+    {
+        self.skip_paths.extend(patterns.into_iter().map(|pattern| PathPattern::new(&pattern)));
+        self
+    }
+
+    /// Reject requests whose `Host` header doesn't match one of the host
+    /// names, to protect against DNS rebinding attacks, which CORS can't
+    /// prevent. Host names are matched like in
+    /// [`HostPolicies`](struct.HostPolicies.html), so `*.example.com`
+    /// matches all subdomains. The port is not checked.
+    ///
+    /// The rejection is built by the [deny responder](#method.deny_responder)
+    /// of the policy for the request and reported to the hooks like any other
+    /// denial. In [report-only mode](#method.report_only) unexpected hosts
+    /// are only logged.
+    ///
+    /// All requests are checked, with or without an `Origin` header, except
+    /// requests to [skipped paths](#method.skip_paths), e.g. health checks
+    /// that use the IP address of the server.
+    ///
+    /// ```rust
+    /// use iron_cors::CorsMiddleware;
+    ///
+    /// let middleware = CorsMiddleware::builder()
+    ///     .allowed_origins(vec!["https://app.example.com".to_string()])
+    ///     .allowed_hosts(vec!["api.example.com", "localhost"])
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn allowed_hosts<I, S>(mut self, hosts: I) -> Self
+        where I: IntoIterator<Item=S>, S: AsRef<str>
+    {
+        self.allowed_hosts.extend(hosts.into_iter().map(|host| HostPattern::new(host.as_ref())));
+        self
+    }
+
+    /// Apply a different policy to requests with the method. For preflight
+    /// requests, the method in the `Access-Control-Request-Method` header is
+    /// used.
+    ///
+    /// Path rules take precedence over method policies.
+    ///
+    /// ```rust
+    /// extern crate iron;
+    /// extern crate iron_cors;
+    ///
+    /// use iron::method::Method;
+    /// use iron_cors::CorsMiddleware;
+    ///
+    /// # fn main() {
+    /// let read_only = CorsMiddleware::builder().allow_any_origin().build_policy().unwrap();
+    /// let middleware = CorsMiddleware::builder()
+    ///     .allowed_origins(vec!["https://admin.example.com".to_string()])
+    ///     .method_policy(Method::Get, read_only.clone())
+    ///     .method_policy(Method::Head, read_only)
+    ///     .build()
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn method_policy(mut self, method: Method, policy: CorsPolicy) -> Self {
+        self.method_policies.insert(method, policy);
+        self
+    }
+
+    /// Create a [`CorsPolicy`](struct.CorsPolicy.html) from this builder,
+    /// e.g. to use it in a path rule. Path rules and method policies
+    /// configured on this builder are not part of the policy.
+    ///
+    /// This fails if the configuration is invalid, e.g. if credentials are
+    /// allowed for any origin.
+    pub fn build_policy(self) -> Result<CorsPolicy, CorsConfigError> {
+        self.policy.check()?;
+        Ok(self.policy)
+    }
+
+    /// Create the middleware from this builder.
+    ///
+    /// This fails if the configuration is invalid, e.g. if credentials are
+    /// allowed for any origin.
+    pub fn build(mut self) -> Result<CorsMiddleware, CorsConfigError> {
+        self.policy.check()?;
+        let main = &self.policy;
+        let mut rules = self.rules.into_iter().map(|rule| rule.into_resolved(main)).collect::<Vec<_>>();
+        if let Some(ref metrics) = self.hooks.metrics {
+            // Count the cache hits of all policies
+            let policies = rules.iter_mut().filter_map(|rule| rule.policy.as_mut())
+                .chain(self.path_rules.iter_mut().map(|rule| &mut rule.policy))
+                .chain(self.method_policies.values_mut())
+                .chain(Some(&mut self.policy));
+            for policy in policies {
+                policy.metrics = Some(metrics.clone());
             }
         }
-
-        // If we don't have an Access-Control-Request-Method header, treat as a possible OPTION CORS call
-        return self.process_possible_cors_request(req, origin)
+        let policy = &self.policy;
+        let adds_isolation_headers = policy.has_isolation_headers()
+            || rules.iter().filter_map(|rule| rule.policy.as_ref()).any(CorsPolicy::has_isolation_headers)
+            || self.path_rules.iter().any(|rule| rule.policy.has_isolation_headers())
+            || self.method_policies.values().any(CorsPolicy::has_isolation_headers);
+        Ok(CorsMiddleware {
+            state: Arc::new(CorsState {
+                rules,
+                adds_isolation_headers,
+                policy: self.policy,
+                path_rules: self.path_rules,
+                method_policies: self.method_policies,
+                provider: None,
+                skip_paths: self.skip_paths,
+                max_age_rules: self.max_age_rules,
+                allowed_hosts: self.allowed_hosts,
+                hooks: self.hooks,
+            }),
+        })
     }
+}
 
-    fn process_possible_cors_request(&self, req: &mut Request, origin: headers::Origin) -> IronResult<Response> {
-        // Verify origin header
-        let may_process = self.allowed_hosts.contains(&format_cors_origin(&origin));
-        // Process request
-        if may_process {
-            // Everything OK, process request and add CORS header to response
-            self.handler.handle(req)
-                .map(|mut res| { self.add_cors_header(&mut res.headers, &origin); res })
-                .map_err(|mut err| { self.add_cors_header(&mut err.response.headers, &origin); err })
-        } else {
-            // Not adding headers
-            warn!("Got disallowed CORS request from {}", &origin.host.hostname);
-            Ok(Response::with((status::BadRequest, "Invalid CORS request: Origin not allowed")))
+/// An error returned when building an invalid CORS configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CorsConfigError {
+    /// Credentials may not be allowed together with a wildcard
+    /// (`Access-Control-Allow-Origin: *`) origin.
+    CredentialsWithAnyOrigin,
+    /// An origin is not of the form `scheme://host[:port]`.
+    InvalidOrigin(String),
+    /// Several origins are not of the form `scheme://host[:port]`.
+    InvalidOrigins(Vec<String>),
+    /// A method name is not valid.
+    InvalidMethod(String),
+    /// A header name is not valid.
+    InvalidHeader(String),
+}
+
+impl fmt::Display for CorsConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CorsConfigError::CredentialsWithAnyOrigin => {
+                write!(f, "Credentials cannot be allowed for any origin")
+            },
+            CorsConfigError::InvalidOrigin(ref origin) => write!(f, "Invalid origin: {:?}", origin),
+            CorsConfigError::InvalidOrigins(ref origins) => write!(f, "Invalid origins: {:?}", origins),
+            CorsConfigError::InvalidMethod(ref method) => write!(f, "Invalid method: {:?}", method),
+            CorsConfigError::InvalidHeader(ref header) => write!(f, "Invalid header name: {:?}", header),
         }
     }
 }
 
-/// The handler that acts as an AroundMiddleware.
-///
-/// It first checks an incoming request for appropriate CORS headers. If the
-/// `Origin` header is present and the header value is in the whitelist, add
-/// the `Access-Control-Allow-Origin` header for that domain to the response.
-/// Otherwise, the request is processed as usual.
-impl Handler for CorsHandlerWhitelist {
-    fn handle(&self, req: &mut Request) -> IronResult<Response> {
-        // Extract origin header
-        let origin = match req.headers.get::<headers::Origin>().cloned() {
-            Some(o) => o,
-            None => {
-                return self.handler.handle(req);
-            }
-        };
+impl Error for CorsConfigError {}
+
+/// A warning about a dangerous or nonsensical CORS configuration, returned by
+/// [`CorsPolicy::validate`](struct.CorsPolicy.html#method.validate).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CorsConfigWarning {
+    /// Credentials are allowed for any origin by reflecting the origin, so
+    /// any website can make authenticated requests.
+    CredentialsWithAnyOrigin,
+    /// An origin of the whitelist, blacklist or exclusions has no scheme, so
+    /// it never matches.
+    OriginWithoutScheme(String),
+    /// An allowed header is a forbidden request header, which browsers never
+    /// let scripts set.
+    ForbiddenHeader(String),
+    /// Credentials are allowed with a preflight max-age of zero, so every
+    /// credentialed request is preflighted.
+    ZeroMaxAgeWithCredentials,
+}
 
-        match req.method {
-            // If this is an OPTION request, check for preflight
-            Method::Options => self.process_possible_preflight(req, origin),
-            // If is not an OPTION request, we assume a normal CORS (no preflight)
-            _ => self.process_possible_cors_request(req, origin),
+impl fmt::Display for CorsConfigWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CorsConfigWarning::CredentialsWithAnyOrigin => {
+                write!(f, "Credentials are allowed for any origin")
+            },
+            CorsConfigWarning::OriginWithoutScheme(ref origin) => write!(f, "Origin without scheme: {:?}", origin),
+            CorsConfigWarning::ForbiddenHeader(ref header) => write!(f, "Forbidden request header allowed: {:?}", header),
+            CorsConfigWarning::ZeroMaxAgeWithCredentials => {
+                write!(f, "Credentials are allowed with a preflight max-age of zero")
+            },
         }
     }
 }
 
-impl CorsHandlerAllowAny {
-    fn add_cors_header(&self, headers: &mut headers::Headers) {
-        headers.set(headers::AccessControlAllowOrigin::Any);
+impl CorsMiddleware {
+    /// Return the main policy, which applies to requests that no rule, path
+    /// rule or method policy matches. With a
+    /// [`PolicyProvider`](trait.PolicyProvider.html), this is the unused
+    /// default policy.
+    pub fn policy(&self) -> &CorsPolicy {
+        &self.state.policy
     }
 
-    fn add_cors_preflight_headers(&self,
-                                  headers: &mut headers::Headers,
-                                  acrm: &headers::AccessControlRequestMethod,
-                                  acrh: Option<&headers::AccessControlRequestHeaders>) {
+    /// Return whether the main policy allows any origin. See
+    /// [`CorsPolicy::is_allow_any`](struct.CorsPolicy.html#method.is_allow_any).
+    pub fn is_allow_any(&self) -> bool {
+        self.policy().is_allow_any()
+    }
 
-        self.add_cors_header(headers);
+    /// Return the whitelisted origins of the main policy. See
+    /// [`CorsPolicy::allowed_origins`](struct.CorsPolicy.html#method.allowed_origins).
+    pub fn allowed_origins(&self) -> Option<Vec<String>> {
+        self.policy().allowed_origins()
+    }
+
+    /// Return the max-age of the main policy.
+    pub fn max_age(&self) -> Option<Duration> {
+        self.policy().max_age()
+    }
 
-        // Copy the method requested by the browser into the allowed methods header
-        headers.set(headers::AccessControlAllowMethods(vec!(acrm.0.clone())));
+    /// Return whether the main policy allows credentials.
+    pub fn allow_credentials(&self) -> bool {
+        self.policy().allow_credentials()
+    }
+}
 
-        // If we have special allowed headers, copy them into the allowed headers in the response
-        if let Some(acrh) = acrh {
-            headers.set(headers::AccessControlAllowHeaders(acrh.0.clone()));
+/// Summarize the effective configuration, e.g. for startup logs.
+///
+/// ```rust
+/// use iron_cors::CorsMiddleware;
+///
+/// let middleware = CorsMiddleware::with_whitelist(["https://example.com"]);
+/// assert_eq!(
+///     middleware.to_string(),
+///     "origins: whitelist (https://example.com); methods: any; headers: any; credentials: not allowed; max-age: none",
+/// );
+/// ```
+impl fmt::Display for CorsMiddleware {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = &self.state;
+        if state.provider.is_some() {
+            return write!(f, "policies selected by a provider");
+        }
+        write!(f, "{}", state.policy)?;
+        let counts = [
+            (state.skip_paths.len(), "skipped paths"),
+            (state.rules.len(), "rules"),
+            (state.path_rules.len(), "path policies"),
+            (state.method_policies.len(), "method policies"),
+        ];
+        for &(count, name) in counts.iter().filter(|&&(count, _)| count > 0) {
+            write!(f, "; {} {}", count, name)?;
         }
+        Ok(())
     }
+}
 
-    fn process_possible_preflight(&self, req: &mut Request) -> IronResult<Response> {
-        {
-            let acrm = req.headers.get::<headers::AccessControlRequestMethod>();
+impl From<CorsPolicy> for CorsMiddleware {
+    fn from(policy: CorsPolicy) -> Self {
+        CorsMiddleware::from_policy(policy)
+    }
+}
 
-            // Check the Access-Control-Request-Method header
-            if let Some(acrm) = acrm {
-                // Assuming that Access-Control-Request-Method header is valid (header names can be anything)
-                let acrh = req.headers.get::<headers::AccessControlRequestHeaders>();
+impl CorsMiddleware {
+    /// Wrap a handler directly, without a `Chain`.
+    ///
+    /// Unlike `Chain::link_around`, the handler doesn't need to be boxed, so
+    /// it is called without dynamic dispatch.
+    ///
+    /// ```rust
+    /// extern crate iron;
+    /// extern crate iron_cors;
+    ///
+    /// use iron::prelude::*;
+    /// use iron::status;
+    /// use iron_cors::CorsMiddleware;
+    ///
+    /// fn hello(_: &mut Request) -> IronResult<Response> {
+    ///     Ok(Response::with((status::Ok, "Hello world!")))
+    /// }
+    ///
+    /// # fn main() {
+    /// let handler = CorsMiddleware::with_allow_any().wrap(hello);
+    /// # let _ = handler;
+    /// // Iron::new(handler).http("localhost:3000").unwrap();
+    /// # }
+    /// ```
+    pub fn wrap<H: Handler>(self, handler: H) -> CorsHandler<H> {
+        CorsHandler {
+            handler,
+            state: self.state,
+        }
+    }
+}
 
-                let mut response = Response::with((status::Ok, ""));
-                self.add_cors_preflight_headers(&mut response.headers, acrm, acrh);
+impl CorsMiddleware {
+    /// Wrap a handler, e.g. a router, and apply CORS only to requests whose
+    /// path matches one of the patterns. Other requests are passed through
+    /// without CORS processing. See [`CorsMount`](struct.CorsMount.html) to
+    /// apply different policies to different paths.
+    ///
+    /// The patterns are matched like in
+    /// [`CorsBuilder::path_policy`](struct.CorsBuilder.html#method.path_policy).
+    pub fn around_routes<H: Handler>(self, handler: H, paths: &[&str]) -> Box<dyn Handler> {
+        paths.iter()
+            .fold(CorsMount::new(), |mount, path| mount.mount(path, self.clone()))
+            .around(Box::new(handler))
+    }
+}
 
-                // In case of preflight, return 200 with empty body after adding the preflight headers
-                return Ok(response);
-            }
+impl AroundMiddleware for CorsMiddleware {
+    fn around(self, handler: Box<dyn Handler>) -> Box<dyn Handler> {
+        Box::new(self.wrap(handler))
+    }
+}
+
+/// The handler that wraps the inner handler and applies the CORS policies.
+///
+/// It is returned by `CorsMiddleware::wrap`.
+pub struct CorsHandler<H = Box<dyn Handler>> {
+    handler: H,
+    state: Arc<CorsState>,
+}
+
+impl CorsState {
+    /// Return whether the path of the request is one of the skipped paths.
+    fn is_skipped(&self, req: &Request) -> bool {
+        if self.skip_paths.is_empty() {
+            return false;
         }
+        let path = request_path(req);
+        self.skip_paths.iter().any(|pattern| pattern.matches(&path))
+    }
 
-        // If we don't have an Access-Control-Request-Method header, treat as a possible OPTION CORS call
-        return self.process_possible_cors_request(req)
+    /// Return whether the host of the request is one of the allowed hosts.
+    fn is_host_allowed(&self, req: &Request) -> bool {
+        if self.allowed_hosts.is_empty() {
+            return true;
+        }
+        with_request_host(req, |host| self.allowed_hosts.iter().any(|pattern| pattern.matches(host)))
     }
 
-    fn process_possible_cors_request(&self, req: &mut Request) -> IronResult<Response> {
-        self.handler.handle(req)
-            .map(|mut res| { self.add_cors_header(&mut res.headers); res })
-            .map_err(|mut err| { self.add_cors_header(&mut err.response.headers); err })
+    /// Return the policy of the provider, or the policy of the first path
+    /// rule matching the request, the policy for the method of the request,
+    /// or the default policy.
+    fn policy_for(&self, req: &Request) -> Option<&CorsPolicy> {
+        if let Some(ref provider) = self.provider {
+            return provider.0.policy_for(req);
+        }
+        if let Some(rule) = self.rules.iter().find(|rule| rule.rule.matches(req)) {
+            return rule.policy.as_ref();
+        }
+        if !self.path_rules.is_empty() {
+            let path = request_path(req);
+            if let Some(rule) = self.path_rules.iter().find(|rule| rule.pattern.matches(&path)) {
+                return Some(&rule.policy);
+            }
+        }
+        if self.method_policies.is_empty() {
+            return Some(&self.policy);
+        }
+        // Preflights are checked against the method of the actual request
+        let method = match (&req.method, req.headers.get::<headers::AccessControlRequestMethod>()) {
+            (&Method::Options, Some(acrm)) => &acrm.0,
+            (method, _) => method,
+        };
+        Some(self.method_policies.get(method).unwrap_or(&self.policy))
     }
 }
 
 /// The handler that acts as an AroundMiddleware.
 ///
 /// It first checks an incoming request for appropriate CORS headers. If the
-/// `Origin` header is present, then the `Access-Control-Allow-Origin: *`
-/// header is added to the response. If not, the request is processed as usual.
-impl Handler for CorsHandlerAllowAny {
+/// `Origin` header is present and the origin is allowed by the policy, add
+/// the `Access-Control-Allow-Origin` header to the response. Otherwise, the
+/// request is processed as usual.
+impl<H: Handler> Handler for CorsHandler<H> {
     fn handle(&self, req: &mut Request) -> IronResult<Response> {
-        match req.headers.get::<headers::Origin>() {
-            None => {
-                self.handler.handle(req)
-            },
-            Some(_) => {
-                match req.method {
-                    //If is an OPTION request, check for preflight
-                    Method::Options => self.process_possible_preflight(req),
-                    // If is not an OPTION request, we assume a normal CORS (no preflight)
-                    _ => self.process_possible_cors_request(req),
+        self.state.handle(&self.handler, req)
+    }
+}
+
+impl CorsState {
+    /// Apply the CORS policies to the request and call the handler, then add
+    /// the cross-origin isolation headers of the policy to the response.
+    fn handle<H: Handler>(&self, handler: &H, req: &mut Request) -> IronResult<Response> {
+        let preflight = is_preflight(req);
+        let (mut result, policy) = self.handle_cors(handler, req);
+        // Only preflights accepted by the middleware announce a max-age
+        let accepted = matches!(req.extensions.get::<CorsDecision>(), Some(CorsDecision::Preflight(_)));
+        if accepted && policy.is_some() && !self.max_age_rules.is_empty() {
+            let path = request_path(req);
+            if let Some(rule) = self.max_age_rules.iter().find(|rule| rule.pattern.matches(&path)) {
+                result = result.map(|mut res| { res.headers.set(max_age_header(rule.max_age)); res });
+            }
+        }
+        match policy {
+            Some(policy) if policy.has_isolation_headers() => result
+                .map(|mut res| { policy.add_isolation_headers(&mut res.headers, preflight); res })
+                .map_err(|mut err| { policy.add_isolation_headers(&mut err.response.headers, preflight); err }),
+            _ => result,
+        }
+    }
+
+    /// Apply the CORS policies to the request and call the handler. Returns
+    /// the policy that was applied, unless the request skipped CORS
+    /// processing.
+    fn handle_cors<H: Handler>(&self, handler: &H, req: &mut Request) -> (IronResult<Response>, Option<&CorsPolicy>) {
+        if let Some(metadata) = FetchMetadata::from_request(req) {
+            req.extensions.insert::<FetchMetadata>(metadata);
+        }
+        if self.is_skipped(req) {
+            req.extensions.insert::<CorsDecision>(CorsDecision::NotCors);
+            return (handler.handle(req), None);
+        }
+        if !self.is_host_allowed(req) {
+            let started = Instant::now();
+            let host = req.url.host().to_string();
+            // Hosts without a policy are rejected with the default policy
+            let policy = self.policy_for(req);
+            if let Some(response) = policy.unwrap_or(&self.policy).reject_unexpected_host(req, &host) {
+                self.hooks.notify(req, started);
+                return (Ok(response), policy);
+            }
+        }
+        if req.headers.get_raw("Origin").is_none() {
+            let state_changing = is_state_changing(&req.method);
+            // Only look up the policy if it is needed
+            let policy = if state_changing || self.adds_isolation_headers { self.policy_for(req) } else { None };
+            if let (true, Some(policy)) = (state_changing, policy) {
+                policy.check_referer(req);
+                if !policy.passes_referer_csrf_check(req) {
+                    return (Ok(policy.reject_csrf(req, "")), Some(policy));
                 }
+            }
+            req.extensions.insert::<CorsDecision>(CorsDecision::NotCors);
+            return (handler.handle(req), policy);
+        }
+
+        let started = Instant::now();
+        let policy = match self.policy_for(req) {
+            Some(policy) => policy,
+            None => {
+                req.extensions.insert::<CorsDecision>(CorsDecision::NotCors);
+                return (handler.handle(req), None);
             },
+        };
+        if policy.reject_multiple_origins && has_multiple_origins(req) {
+            let result = policy.process_multiple_origins(handler, req);
+            self.hooks.notify(req, started);
+            return (result, Some(policy));
         }
+        // The `null` origin and raw origins can't be parsed into a typed
+        // header.
+        let raw_origin = raw_origin_value(req).and_then(|value| policy.raw_origin(value));
+        let origin = match (raw_origin, req.headers.get::<headers::Origin>()) {
+            (Some(origin), _) => origin,
+            (None, Some(origin)) => origin.clone(),
+            (None, None) if has_null_origin(req) => null_origin(),
+            (None, None) => {
+                let result = match raw_origin_value(req).map(str::to_string) {
+                    Some(value) => policy.process_malformed_origin(handler, req, &value),
+                    None => policy.process_multiple_origins(handler, req),
+                };
+                self.hooks.notify(req, started);
+                return (result, Some(policy));
+            },
+        };
+        let result = policy.process(handler, req, origin);
+        self.hooks.notify(req, started);
+        (result, Some(policy))
     }
 }
 
-fn format_cors_origin(origin: &headers::Origin) -> String {
-    match origin.host.port {
-        Some(port) => format!("{}://{}:{}", &origin.scheme, &origin.host.hostname, &port),
-        None => format!("{}://{}", &origin.scheme, &origin.host.hostname),
+/// Return the path of the request, starting with a `/`.
+fn request_path(req: &Request) -> String {
+    format!("/{}", req.url.path().join("/"))
+}
+
+/// Match a path against a glob pattern, where `*` matches any sequence of
+/// characters.
+fn glob_matches(pattern: &str, path: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // The part before the first `*` must be a prefix
+    let first = parts.next().unwrap_or("");
+    if !path.starts_with(first) {
+        return false;
+    }
+    let mut rest = &path[first.len()..];
+    let mut parts: Vec<&str> = parts.collect();
+    // The part after the last `*` must be a suffix
+    let last = match parts.pop() {
+        Some(last) => last,
+        None => return rest.is_empty(),
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
     }
+    rest.len() >= last.len() && rest.ends_with(last)
 }
@@ -0,0 +1,172 @@
+//! Configurable logging of rejected requests.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use log::Level;
+
+/// The kinds of log messages about rejected requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LogEvent {
+    /// A request from an origin that is not allowed.
+    DisallowedOrigin,
+    /// A preflight for a method or headers that are not allowed.
+    InvalidPreflight,
+    /// An actual request with a method that is not allowed, if allowed
+    /// methods are enforced.
+    MethodNotAllowed,
+    /// A request that would have been rejected in report-only mode.
+    ReportOnly,
+    /// A request with a malformed `Origin` header, if such requests are
+    /// rejected.
+    MalformedOrigin,
+    /// A request with multiple `Origin` headers, if such requests are
+    /// rejected.
+    MultipleOrigins,
+    /// A request with a `Host` header that is not one of the allowed hosts.
+    UnexpectedHost,
+}
+
+/// The log level of each kind of message, or `None` if it is not logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LogLevels {
+    disallowed_origin: Option<Level>,
+    invalid_preflight: Option<Level>,
+    method_not_allowed: Option<Level>,
+    report_only: Option<Level>,
+    malformed_origin: Option<Level>,
+    multiple_origins: Option<Level>,
+    unexpected_host: Option<Level>,
+}
+
+impl Default for LogLevels {
+    fn default() -> Self {
+        LogLevels {
+            disallowed_origin: Some(Level::Warn),
+            invalid_preflight: Some(Level::Warn),
+            method_not_allowed: Some(Level::Warn),
+            report_only: Some(Level::Warn),
+            malformed_origin: Some(Level::Warn),
+            multiple_origins: Some(Level::Warn),
+            unexpected_host: Some(Level::Warn),
+        }
+    }
+}
+
+impl LogLevels {
+    pub(crate) fn get(&self, event: LogEvent) -> Option<Level> {
+        match event {
+            LogEvent::DisallowedOrigin => self.disallowed_origin,
+            LogEvent::InvalidPreflight => self.invalid_preflight,
+            LogEvent::MethodNotAllowed => self.method_not_allowed,
+            LogEvent::ReportOnly => self.report_only,
+            LogEvent::MalformedOrigin => self.malformed_origin,
+            LogEvent::MultipleOrigins => self.multiple_origins,
+            LogEvent::UnexpectedHost => self.unexpected_host,
+        }
+    }
+
+    pub(crate) fn set(&mut self, event: LogEvent, level: Option<Level>) {
+        match event {
+            LogEvent::DisallowedOrigin => self.disallowed_origin = level,
+            LogEvent::InvalidPreflight => self.invalid_preflight = level,
+            LogEvent::MethodNotAllowed => self.method_not_allowed = level,
+            LogEvent::ReportOnly => self.report_only = level,
+            LogEvent::MalformedOrigin => self.malformed_origin = level,
+            LogEvent::MultipleOrigins => self.multiple_origins = level,
+            LogEvent::UnexpectedHost => self.unexpected_host = level,
+        }
+    }
+}
+
+/// Limits the number of log messages per origin and time window.
+#[derive(Debug)]
+pub(crate) struct LogLimiter {
+    max_messages: u32,
+    window: Duration,
+    origins: HashMap<String, Window>,
+}
+
+#[derive(Debug)]
+struct Window {
+    started: Instant,
+    messages: u32,
+}
+
+/// The number of origins after which expired windows are removed.
+const MAX_ORIGINS: usize = 1024;
+
+impl LogLimiter {
+    pub(crate) fn new(max_messages: u32, window: Duration) -> Self {
+        LogLimiter {
+            max_messages,
+            window,
+            origins: HashMap::new(),
+        }
+    }
+
+    /// Log the message unless the limit for the origin is reached. When the
+    /// window of the origin ends, the number of suppressed messages is
+    /// logged.
+    pub(crate) fn log(&mut self, level: Level, origin: &str, message: fmt::Arguments) {
+        let now = Instant::now();
+        if self.origins.len() >= MAX_ORIGINS {
+            self.remove_expired(level, now);
+        }
+        let window = self.window;
+        let entry = self.origins.entry(origin.to_string()).or_insert(Window { started: now, messages: 0 });
+        if now.duration_since(entry.started) >= window {
+            log_suppressed(level, origin, entry, self.max_messages, window);
+            *entry = Window { started: now, messages: 0 };
+        }
+        entry.messages += 1;
+        if entry.messages <= self.max_messages {
+            emit(level, message);
+        }
+    }
+
+    fn remove_expired(&mut self, level: Level, now: Instant) {
+        let (max_messages, window) = (self.max_messages, self.window);
+        self.origins.retain(|origin, entry| {
+            let expired = now.duration_since(entry.started) >= window;
+            if expired {
+                log_suppressed(level, origin, entry, max_messages, window);
+            }
+            !expired
+        });
+    }
+}
+
+fn log_suppressed(level: Level, origin: &str, window: &Window, max_messages: u32, duration: Duration) {
+    if window.messages > max_messages {
+        emit(level, format_args!("Suppressed {} CORS log messages about {} in {:?}",
+                                 window.messages - max_messages, origin, duration));
+    }
+}
+
+/// Return whether messages of the level are emitted.
+pub(crate) fn enabled(level: Level) -> bool {
+    // `tracing` subscribers filter the events themselves
+    cfg!(feature = "tracing") || log_enabled!(level)
+}
+
+/// Emit a message at the level, as a `tracing` event with the `tracing`
+/// feature, and as a log message otherwise.
+#[cfg(feature = "tracing")]
+pub(crate) fn emit(level: Level, message: fmt::Arguments) {
+    match level {
+        Level::Error => tracing::error!(target: "iron_cors", "{}", message),
+        Level::Warn => tracing::warn!(target: "iron_cors", "{}", message),
+        Level::Info => tracing::info!(target: "iron_cors", "{}", message),
+        Level::Debug => tracing::debug!(target: "iron_cors", "{}", message),
+        Level::Trace => tracing::trace!(target: "iron_cors", "{}", message),
+    }
+}
+
+/// Emit a message at the level, as a `tracing` event with the `tracing`
+/// feature, and as a log message otherwise.
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn emit(level: Level, message: fmt::Arguments) {
+    log!(level, "{}", message);
+}
@@ -0,0 +1,65 @@
+//! The `cors!` configuration macro.
+
+/// Build a [`CorsPolicy`](struct.CorsPolicy.html) declaratively.
+///
+/// The options expand to the calls of the
+/// [`CorsBuilder`](struct.CorsBuilder.html) methods, and the macro evaluates
+/// to the result of
+/// [`build_policy`](struct.CorsBuilder.html#method.build_policy). Method
+/// names are variants of `iron::method::Method`, so typos fail to compile.
+///
+/// | Option           | Builder method                  |
+/// |------------------|---------------------------------|
+/// | `origins: *`     | `allow_any_origin`              |
+/// | `origins: [..]`  | `allowed_origins`               |
+/// | `methods: [..]`  | `allowed_methods`               |
+/// | `headers: [..]`  | `allowed_headers`               |
+/// | `expose: [..]`   | `expose_headers`                |
+/// | `credentials: b` | `allow_credentials`             |
+/// | `max_age: secs`  | `max_age`, in seconds           |
+///
+/// ```rust
+/// #[macro_use] extern crate iron_cors;
+///
+/// use iron_cors::CorsMiddleware;
+///
+/// # fn main() {
+/// let policy = cors! {
+///     origins: ["https://app.example.com"],
+///     methods: [Get, Post],
+///     headers: ["content-type", "authorization"],
+///     credentials: true,
+///     max_age: 3600,
+/// }.unwrap();
+/// let middleware = CorsMiddleware::from_policy(policy);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! cors {
+    ($($option:ident : $value:tt),* $(,)*) => {{
+        let builder = $crate::CorsPolicy::builder();
+        $( let builder = cors!(@option builder, $option, $value); )*
+        builder.build_policy()
+    }};
+    (@option $builder:ident, origins, *) => {
+        $builder.allow_any_origin()
+    };
+    (@option $builder:ident, origins, [$($origin:expr),* $(,)*]) => {
+        $builder.allowed_origins(vec![$(::std::string::String::from($origin)),*])
+    };
+    (@option $builder:ident, methods, [$($method:ident),* $(,)*]) => {
+        $builder.allowed_methods(vec![$($crate::__private::Method::$method),*])
+    };
+    (@option $builder:ident, headers, [$($header:expr),* $(,)*]) => {
+        $builder.allowed_headers(vec![$($crate::UniCase(::std::string::String::from($header))),*])
+    };
+    (@option $builder:ident, expose, [$($header:expr),* $(,)*]) => {
+        $builder.expose_headers(vec![$(::std::string::String::from($header)),*])
+    };
+    (@option $builder:ident, credentials, $credentials:tt) => {
+        $builder.allow_credentials($credentials)
+    };
+    (@option $builder:ident, max_age, $seconds:tt) => {
+        $builder.max_age(::std::time::Duration::from_secs($seconds))
+    };
+}
@@ -0,0 +1,249 @@
+//! Composable origin matchers.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use iron::headers::Origin;
+#[cfg(feature = "regex")] use regex::Regex;
+
+use origin::{default_port, normalize_origin, normalized_origin, with_canonical_origin};
+use CorsConfigError;
+
+/// A rule that decides whether an origin is allowed.
+///
+/// Matchers can be combined with [`or`](#method.or) and
+/// [`and`](#method.and), and a list of matchers can be passed to
+/// [`CorsMiddleware::with_matchers`](struct.CorsMiddleware.html#method.with_matchers),
+/// which allows an origin if any of the matchers matches it.
+///
+/// ```rust
+/// extern crate iron;
+/// extern crate iron_cors;
+///
+/// use iron::headers::Origin;
+/// use iron_cors::OriginMatcher;
+///
+/// # fn main() {
+/// let matcher = OriginMatcher::Subdomain("example.com".to_string())
+///     .and(OriginMatcher::Prefix("https://".to_string()))
+///     .or(OriginMatcher::Exact("http://localhost:8080".to_string()));
+/// assert!(matcher.matches(&Origin::new("https", "app.example.com", None)));
+/// assert!(!matcher.matches(&Origin::new("http", "app.example.com", None)));
+/// assert!(matcher.matches(&Origin::new("http", "localhost", Some(8080))));
+/// # }
+/// ```
+pub enum OriginMatcher {
+    /// Matches any origin.
+    Any,
+    /// Matches the serialized origin (`scheme://host[:port]`). Scheme and
+    /// host are case-insensitive, and a default port is equivalent to no
+    /// port.
+    Exact(String),
+    /// Matches origins whose host is the domain or any of its subdomains,
+    /// with any scheme and port. The host is case-insensitive.
+    Subdomain(String),
+    /// Matches serialized origins (`scheme://host[:port]`) that start with
+    /// the prefix. Scheme and host are case-insensitive, and a default port
+    /// is equivalent to no port.
+    ///
+    /// The prefix must end at a boundary: It either matches the whole
+    /// origin, or ends with `/`, `.` or `:`, e.g. `https://` or
+    /// `https://app.`. So `https://example.com` matches neither
+    /// `https://example.com.evil.net` nor `https://example.com:8443`.
+    Prefix(String),
+    /// Matches serialized origins that match the regular expression.
+    #[cfg(feature = "regex")]
+    Regex(Regex),
+    /// Matches origins whose host is an IP address in the range.
+    IpRange(IpRange),
+    /// Matches origins for which the function returns `true`.
+    Fn(Box<dyn Fn(&Origin) -> bool + Send + Sync>),
+    /// Matches origins that are matched by either of the matchers.
+    Or(Box<OriginMatcher>, Box<OriginMatcher>),
+    /// Matches origins that are matched by both of the matchers.
+    And(Box<OriginMatcher>, Box<OriginMatcher>),
+}
+
+impl OriginMatcher {
+    /// Return whether the origin is matched.
+    pub fn matches(&self, origin: &Origin) -> bool {
+        match *self {
+            OriginMatcher::Any => true,
+            OriginMatcher::Exact(ref expected) => {
+                with_canonical_origin(origin, |origin| origin == normalized_origin(expected))
+            },
+            OriginMatcher::Subdomain(ref domain) => is_subdomain(&origin.host.hostname, domain),
+            OriginMatcher::Prefix(ref prefix) => with_canonical_origin(origin, |origin| has_prefix(origin, prefix)),
+            #[cfg(feature = "regex")]
+            OriginMatcher::Regex(ref regex) => with_canonical_origin(origin, |origin| regex.is_match(origin)),
+            OriginMatcher::IpRange(ref range) => range.matches(origin),
+            OriginMatcher::Fn(ref f) => f(origin),
+            OriginMatcher::Or(ref a, ref b) => a.matches(origin) || b.matches(origin),
+            OriginMatcher::And(ref a, ref b) => a.matches(origin) && b.matches(origin),
+        }
+    }
+
+    /// Normalize the origins of exact matchers and the domains of subdomain
+    /// matchers once, so that they are compared without allocating.
+    pub(crate) fn normalize(self) -> Self {
+        match self {
+            OriginMatcher::Exact(expected) => OriginMatcher::Exact(normalize_origin(&expected)),
+            OriginMatcher::Subdomain(domain) => OriginMatcher::Subdomain(domain.to_ascii_lowercase()),
+            OriginMatcher::Or(a, b) => OriginMatcher::Or(Box::new(a.normalize()), Box::new(b.normalize())),
+            OriginMatcher::And(a, b) => OriginMatcher::And(Box::new(a.normalize()), Box::new(b.normalize())),
+            matcher => matcher,
+        }
+    }
+
+    /// Return a matcher that matches origins that are matched by this or the
+    /// other matcher.
+    pub fn or(self, other: OriginMatcher) -> Self {
+        OriginMatcher::Or(Box::new(self), Box::new(other))
+    }
+
+    /// Return a matcher that matches origins that are matched by this and
+    /// the other matcher.
+    pub fn and(self, other: OriginMatcher) -> Self {
+        OriginMatcher::And(Box::new(self), Box::new(other))
+    }
+}
+
+/// Return whether the host is the domain or one of its subdomains, compared
+/// case-insensitively.
+fn is_subdomain(host: &str, domain: &str) -> bool {
+    let (host, domain) = (host.as_bytes(), domain.as_bytes());
+    let start = match host.len().checked_sub(domain.len()) {
+        Some(start) => start,
+        None => return false,
+    };
+    host[start..].eq_ignore_ascii_case(domain) && (start == 0 || host[start - 1] == b'.')
+}
+
+/// Return whether the serialized origin starts with the prefix, compared
+/// case-insensitively, and the prefix ends at a boundary.
+fn has_prefix(origin: &str, prefix: &str) -> bool {
+    let matches = origin.len() >= prefix.len()
+        && origin.as_bytes()[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes());
+    matches && (origin.len() == prefix.len() || prefix.ends_with(['/', '.', ':']))
+}
+
+impl fmt::Debug for OriginMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OriginMatcher::Any => write!(f, "Any"),
+            OriginMatcher::Exact(ref origin) => f.debug_tuple("Exact").field(origin).finish(),
+            OriginMatcher::Subdomain(ref domain) => f.debug_tuple("Subdomain").field(domain).finish(),
+            OriginMatcher::Prefix(ref prefix) => f.debug_tuple("Prefix").field(prefix).finish(),
+            #[cfg(feature = "regex")]
+            OriginMatcher::Regex(ref regex) => f.debug_tuple("Regex").field(regex).finish(),
+            OriginMatcher::IpRange(ref range) => f.debug_tuple("IpRange").field(range).finish(),
+            OriginMatcher::Fn(_) => write!(f, "Fn(..)"),
+            OriginMatcher::Or(ref a, ref b) => f.debug_tuple("Or").field(a).field(b).finish(),
+            OriginMatcher::And(ref a, ref b) => f.debug_tuple("And").field(a).field(b).finish(),
+        }
+    }
+}
+
+/// A range of IP addresses in CIDR notation, with a scheme and an optional
+/// port, for origins whose host is an IP address.
+///
+/// The range is written as `scheme://address/prefix-length[:port]`, e.g.
+/// `http://10.0.0.0/8:*` or `https://[fd00::]/8:8443`. If the port is
+/// omitted, only the default port of the scheme matches. A port of `*`
+/// matches any port.
+///
+/// ```rust
+/// extern crate iron;
+/// extern crate iron_cors;
+///
+/// use iron::headers::Origin;
+/// use iron_cors::{IpRange, OriginMatcher};
+///
+/// # fn main() {
+/// let matcher = OriginMatcher::IpRange("http://10.0.0.0/8:*".parse::<IpRange>().unwrap());
+/// assert!(matcher.matches(&Origin::new("http", "10.1.2.3", Some(8080))));
+/// assert!(!matcher.matches(&Origin::new("http", "192.168.1.1", Some(8080))));
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpRange {
+    scheme: String,
+    network: IpAddr,
+    prefix_len: u8,
+    port: PortMatch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PortMatch {
+    Default,
+    Any,
+    Port(u16),
+}
+
+impl IpRange {
+    /// Return whether the host of the origin is an IP address in the range.
+    pub fn matches(&self, origin: &Origin) -> bool {
+        if !origin.scheme.eq_ignore_ascii_case(&self.scheme) {
+            return false;
+        }
+        let port_matches = match self.port {
+            PortMatch::Any => true,
+            PortMatch::Default => origin.host.port.is_none() || origin.host.port == default_port(&self.scheme),
+            PortMatch::Port(port) => origin.host.port == Some(port)
+                || (origin.host.port.is_none() && default_port(&self.scheme) == Some(port)),
+        };
+        let host = origin.host.hostname.trim_start_matches('[').trim_end_matches(']');
+        port_matches && host.parse::<IpAddr>().is_ok_and(|address| self.contains(address))
+    }
+
+    fn contains(&self, address: IpAddr) -> bool {
+        match (self.network, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix_len)).unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            },
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix_len)).unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            },
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = CorsConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || CorsConfigError::InvalidOrigin(s.to_string());
+        let index = s.find("://").ok_or_else(invalid)?;
+        let (scheme, rest) = (&s[..index], &s[index + 3..]);
+        let (address, rest) = if rest.starts_with('[') {
+            let end = rest.find(']').ok_or_else(invalid)?;
+            (&rest[1..end], &rest[end + 1..])
+        } else {
+            let end = rest.find('/').ok_or_else(invalid)?;
+            (&rest[..end], &rest[end..])
+        };
+        if scheme.is_empty() || !rest.starts_with('/') {
+            return Err(invalid());
+        }
+        let (prefix_len, port) = match rest[1..].find(':') {
+            Some(end) => (&rest[1..end + 1], Some(&rest[end + 2..])),
+            None => (&rest[1..], None),
+        };
+        let network = address.parse::<IpAddr>().map_err(|_| invalid())?;
+        let prefix_len = prefix_len.parse::<u8>().map_err(|_| invalid())?;
+        let max_prefix_len = if network.is_ipv4() { 32 } else { 128 };
+        if prefix_len > max_prefix_len {
+            return Err(invalid());
+        }
+        let port = match port {
+            None => PortMatch::Default,
+            Some("*") => PortMatch::Any,
+            Some(port) => PortMatch::Port(port.parse().map_err(|_| invalid())?),
+        };
+        Ok(IpRange { scheme: scheme.to_ascii_lowercase(), network, prefix_len, port })
+    }
+}
@@ -0,0 +1,213 @@
+//! Combining a base policy with overrides.
+
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+
+use policy::{AllowedOrigins, PreflightCache};
+use {CorsConfigError, CorsPolicy};
+
+impl CorsPolicy {
+    /// Combine the policy with overrides, e.g. to extend a shared base
+    /// policy for a single service.
+    ///
+    /// - The allowed origins are the union of both. The raw origins, the
+    ///   excepted origins, the trusted proxies and the unrestricted methods
+    ///   are combined as well.
+    /// - The allowed methods, the allowed headers, the exposed headers and
+    ///   the default headers are the union of both. If only one of them
+    ///   restricts the methods or headers, its restriction is kept, so any
+    ///   method or header is only allowed if neither restricts them.
+    /// - The extra headers of the overrides replace the extra headers of the
+    ///   base with the same name.
+    /// - All other options are taken from the overrides.
+    ///
+    /// Options of the overrides that have their default value leave the
+    /// base unchanged, as they can't be told apart from options that were
+    /// not set. Overrides therefore can't reset an option of the base to its
+    /// default, e.g. `allow_credentials(false)` keeps the credentials of a
+    /// base that allows them. This includes the default origins, so
+    /// overrides that allow any origin with `*` don't change the origins of
+    /// the base, while overrides that [reflect any
+    /// origin](struct.CorsBuilder.html#method.reflect_any_origin) do.
+    ///
+    /// The deny responder, the validator fallback, the circuit breaker and
+    /// the validator timeout are always taken from the base, and those of
+    /// the overrides are discarded. The CSRF validator and the log limit are
+    /// taken from the overrides if they have one, and the preflight cache
+    /// and the decision cache are replaced with empty caches of the same
+    /// capacity.
+    ///
+    /// Returns an error if the combination is invalid, e.g. if the
+    /// overrides allow credentials and the base allows any origin with `*`.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use iron_cors::CorsPolicy;
+    ///
+    /// let base = CorsPolicy::builder()
+    ///     .allowed_origins(vec!["https://www.example.com".to_string()])
+    ///     .max_age(Duration::from_secs(600))
+    ///     .build_policy()
+    ///     .unwrap();
+    /// let overrides = CorsPolicy::builder()
+    ///     .allowed_origins(vec!["https://admin.example.com".to_string()])
+    ///     .allow_credentials(true)
+    ///     .build_policy()
+    ///     .unwrap();
+    /// let policy = base.merge(&overrides).unwrap();
+    /// assert_eq!(policy.allowed_origins(), Some(vec![
+    ///     "https://admin.example.com".to_string(),
+    ///     "https://www.example.com".to_string(),
+    /// ]));
+    /// assert!(policy.allow_credentials());
+    /// assert_eq!(policy.max_age(), Some(Duration::from_secs(600)));
+    /// ```
+    pub fn merge(&self, overrides: &CorsPolicy) -> Result<CorsPolicy, CorsConfigError> {
+        let default = CorsPolicy::default();
+        macro_rules! option {
+            ($field:ident) => {
+                if overrides.$field != default.$field {
+                    overrides.$field.clone()
+                } else {
+                    self.$field.clone()
+                }
+            };
+        }
+
+        // The cached responses and decisions were made for different origins
+        let preflight_cache = overrides.preflight_cache.as_ref().or(self.preflight_cache.as_ref());
+        let decision_cache = overrides.decision_cache.as_ref().or(self.decision_cache.as_ref());
+
+        // Every field is listed, so that a new option can't be left out
+        let merged = CorsPolicy {
+            allowed_origins: merge_origins(&self.allowed_origins, &overrides.allowed_origins),
+            raw_origins: union(&self.raw_origins, &overrides.raw_origins),
+            except_origins: union(&self.except_origins, &overrides.except_origins),
+            trusted_proxies: union(&self.trusted_proxies, &overrides.trusted_proxies),
+            unrestricted_methods: concat(&self.unrestricted_methods, &overrides.unrestricted_methods),
+            reject_cross_site: concat(&self.reject_cross_site, &overrides.reject_cross_site),
+
+            allowed_methods: merge_restriction(&self.allowed_methods, &overrides.allowed_methods),
+            allowed_headers: merge_restriction(&self.allowed_headers, &overrides.allowed_headers),
+            expose_headers: concat(&self.expose_headers, &overrides.expose_headers),
+            default_headers: concat(&self.default_headers, &overrides.default_headers),
+            extra_preflight_headers: replace_headers(&self.extra_preflight_headers, &overrides.extra_preflight_headers),
+            extra_actual_headers: replace_headers(&self.extra_actual_headers, &overrides.extra_actual_headers),
+
+            allow_credentials: option!(allow_credentials),
+            max_age: option!(max_age),
+            disallowed_origin_behavior: option!(disallowed_origin_behavior),
+            preflight_status: if overrides.preflight_status.0 != default.preflight_status.0 {
+                overrides.preflight_status
+            } else {
+                self.preflight_status
+            },
+            timing_allow_origin: option!(timing_allow_origin),
+            xdomain_request_compat: option!(xdomain_request_compat),
+            ignore_port: option!(ignore_port),
+            require_https_origins: option!(require_https_origins),
+            allowed_schemes: option!(allowed_schemes),
+            null_origin_policy: option!(null_origin_policy),
+            malformed_origin_policy: option!(malformed_origin_policy),
+            reject_multiple_origins: option!(reject_multiple_origins),
+            asterisk_options: option!(asterisk_options),
+            allow_localhost: option!(allow_localhost),
+            skip_same_origin: option!(skip_same_origin),
+            self_origin: option!(self_origin),
+            trust_sec_fetch_site: option!(trust_sec_fetch_site),
+            referer_fallback: option!(referer_fallback),
+            strip_credentials: option!(strip_credentials),
+            tls_requirement: option!(tls_requirement),
+            resource_policy: option!(resource_policy),
+            opener_policy: option!(opener_policy),
+            embedder_policy: option!(embedder_policy),
+            strict: option!(strict),
+            enforce_allowed_methods: option!(enforce_allowed_methods),
+            preflight_method_not_allowed: option!(preflight_method_not_allowed),
+            header_policy: option!(header_policy),
+            omit_wildcard_authorization: option!(omit_wildcard_authorization),
+            preflight_cache_control: option!(preflight_cache_control),
+            header_conflict_policy: option!(header_conflict_policy),
+            error_response_policy: option!(error_response_policy),
+            trace_decisions: option!(trace_decisions),
+            report_only: option!(report_only),
+            log_levels: option!(log_levels),
+
+            // Responders and validator settings can't be compared to their
+            // defaults, so those of the base are kept
+            deny_responder: self.deny_responder.clone(),
+            validator_settings: self.validator_settings.clone(),
+            csrf_validator: overrides.csrf_validator.clone().or_else(|| self.csrf_validator.clone()),
+            log_limiter: overrides.log_limiter.clone().or_else(|| self.log_limiter.clone()),
+            metrics: overrides.metrics.clone().or_else(|| self.metrics.clone()),
+
+            preflight_cache: preflight_cache.map(|cache| {
+                let capacity = cache.lock().unwrap_or_else(PoisonError::into_inner).capacity();
+                Arc::new(Mutex::new(PreflightCache::new(capacity)))
+            }),
+            decision_cache: decision_cache.map(|cache| Arc::new(cache.empty_copy())),
+            static_preflight_headers: OnceLock::new(),
+        };
+
+        merged.check()?;
+        Ok(merged)
+    }
+}
+
+/// Return the origins that are allowed by either of the two.
+fn merge_origins(base: &AllowedOrigins, overrides: &AllowedOrigins) -> AllowedOrigins {
+    match (base, overrides) {
+        (_, &AllowedOrigins::Any) => base.clone(),
+        (&AllowedOrigins::Reflect, _) | (_, &AllowedOrigins::Reflect) => AllowedOrigins::Reflect,
+        (&AllowedOrigins::Any, _) => AllowedOrigins::Any,
+        (AllowedOrigins::Whitelist(base), AllowedOrigins::Whitelist(origins)) => {
+            AllowedOrigins::Whitelist(Arc::new(union(base, origins)))
+        },
+        (AllowedOrigins::Blacklist(base), AllowedOrigins::Blacklist(origins)) => {
+            AllowedOrigins::Blacklist(Arc::new(base.intersection(origins).cloned().collect()))
+        },
+        #[cfg(feature = "regex")]
+        (AllowedOrigins::Regex(base), AllowedOrigins::Regex(regexes)) => {
+            let mut combined = base.clone();
+            combined.extend(regexes.iter().filter(|regex| !base.iter().any(|r| r.as_str() == regex.as_str())).cloned());
+            AllowedOrigins::Regex(combined)
+        },
+        _ => AllowedOrigins::Union(Arc::new((base.clone(), overrides.clone()))),
+    }
+}
+
+/// Return the union of two optional lists, where `None` doesn't restrict
+/// the items and is replaced with the other list.
+fn merge_restriction<T: PartialEq + Clone>(base: &Option<Vec<T>>, other: &Option<Vec<T>>) -> Option<Vec<T>> {
+    match (base, other) {
+        (Some(base), Some(other)) => Some(concat(base, other)),
+        (Some(items), None) | (None, Some(items)) => Some(items.clone()),
+        (None, None) => None,
+    }
+}
+
+fn union<T: Hash + Eq + Clone>(base: &HashSet<T>, other: &HashSet<T>) -> HashSet<T> {
+    base.union(other).cloned().collect()
+}
+
+/// Append the items of `other` that are not in `base`.
+fn concat<T: PartialEq + Clone>(base: &[T], other: &[T]) -> Vec<T> {
+    let mut combined = base.to_vec();
+    for item in other {
+        if !combined.contains(item) {
+            combined.push(item.clone());
+        }
+    }
+    combined
+}
+
+/// Replace the headers of `base` with the headers of `other` with the same
+/// name, compared case-insensitively.
+fn replace_headers(base: &[(String, String)], other: &[(String, String)]) -> Vec<(String, String)> {
+    base.iter()
+        .filter(|&(name, _)| !other.iter().any(|(other, _)| other.eq_ignore_ascii_case(name)))
+        .chain(other)
+        .cloned()
+        .collect()
+}
@@ -0,0 +1,274 @@
+//! Counters for CORS decisions.
+
+#[cfg(feature = "prometheus")] use std::collections::HashSet;
+use std::collections::VecDeque;
+#[cfg(feature = "prometheus")] use std::sync::Arc;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "prometheus")] use std::time::Duration;
+use std::time::SystemTime;
+
+#[cfg(feature = "prometheus")]
+use prometheus::{Histogram, HistogramOpts, IntCounterVec, Opts, Registry};
+
+use CorsDecision;
+
+/// Counters of the requests processed by the middleware.
+///
+/// Pass an `Arc<CorsMetrics>` to
+/// [`CorsBuilder::metrics`](struct.CorsBuilder.html#method.metrics) and keep
+/// a clone to read the counters, e.g. to export them to a monitoring system.
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use iron_cors::{CorsMetrics, CorsMiddleware};
+///
+/// let metrics = Arc::new(CorsMetrics::new());
+/// let middleware = CorsMiddleware::builder()
+///     .metrics(metrics.clone())
+///     .build()
+///     .unwrap();
+/// assert_eq!(metrics.denied(), 0);
+/// ```
+#[derive(Debug, Default)]
+pub struct CorsMetrics {
+    preflights: AtomicU64,
+    allowed: AtomicU64,
+    denied: AtomicU64,
+    cache_hits: AtomicU64,
+}
+
+impl CorsMetrics {
+    /// Create a new set of counters, all starting at zero.
+    pub fn new() -> Self {
+        CorsMetrics::default()
+    }
+
+    /// The number of successful preflight requests that were answered.
+    pub fn preflights(&self) -> u64 {
+        self.preflights.load(Ordering::Relaxed)
+    }
+
+    /// The number of allowed actual CORS requests.
+    pub fn allowed(&self) -> u64 {
+        self.allowed.load(Ordering::Relaxed)
+    }
+
+    /// The number of denied CORS requests, including preflights.
+    pub fn denied(&self) -> u64 {
+        self.denied.load(Ordering::Relaxed)
+    }
+
+    /// The number of preflight responses and origin decisions that were
+    /// taken from the [preflight
+    /// cache](struct.CorsBuilder.html#method.preflight_cache) or the
+    /// [decision cache](struct.CorsBuilder.html#method.decision_cache).
+    pub fn cache_hits(&self) -> u64 {
+        self.cache_hits.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record(&self, decision: &CorsDecision) {
+        let counter = match *decision {
+            CorsDecision::NotCors | CorsDecision::SameOrigin => return,
+            CorsDecision::Allowed(_) => &self.allowed,
+            CorsDecision::Denied(_) => &self.denied,
+            CorsDecision::Preflight(_) => &self.preflights,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The most recent denied CORS requests, e.g. for a debug endpoint.
+///
+/// Pass an `Arc<RecentDenials>` to
+/// [`CorsBuilder::recent_denials`](struct.CorsBuilder.html#method.recent_denials)
+/// and keep a clone to read the entries. When the buffer is full, the oldest
+/// entry is dropped.
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use iron_cors::{CorsMiddleware, RecentDenials};
+///
+/// let denials = Arc::new(RecentDenials::new(100));
+/// let middleware = CorsMiddleware::builder()
+///     .allowed_origins(vec!["https://example.com".to_string()])
+///     .recent_denials(denials.clone())
+///     .build()
+///     .unwrap();
+/// for denial in denials.entries() {
+///     println!("{} denied for {}", denial.origin, denial.path);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct RecentDenials {
+    capacity: usize,
+    entries: Mutex<VecDeque<Denial>>,
+}
+
+/// A denied CORS request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Denial {
+    /// The serialized origin of the request.
+    pub origin: String,
+    /// The path of the request.
+    pub path: String,
+    /// The time of the request.
+    pub timestamp: SystemTime,
+}
+
+impl RecentDenials {
+    /// Create an empty buffer for up to `capacity` entries.
+    pub fn new(capacity: usize) -> Self {
+        RecentDenials {
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Return the entries, oldest first.
+    pub fn entries(&self) -> Vec<Denial> {
+        self.lock().iter().cloned().collect()
+    }
+
+    /// Remove all entries.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    pub(crate) fn record(&self, origin: &str, path: &str) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.lock();
+        if entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(Denial {
+            origin: origin.to_string(),
+            path: path.to_string(),
+            timestamp: SystemTime::now(),
+        });
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<Denial>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Prometheus metrics of the requests processed by the middleware.
+///
+/// The following metrics are registered:
+///
+/// - `cors_requests_total`: Counter of processed CORS requests, labeled
+///   with the `decision` (`allowed`, `denied` or `preflight`)
+/// - `cors_denied_total`: Counter of denied CORS requests, labeled with the
+///   `origin`
+/// - `cors_preflight_duration_seconds`: Histogram of the time it took to
+///   answer successful preflight requests
+///
+/// The `origin` label is taken from the request, so the number of distinct
+/// labels is limited to keep clients from creating unbounded time series.
+/// Denials from further origins are counted with the origin `other`. The
+/// limit defaults to 100 and can be changed with
+/// [`max_origin_labels`](#method.max_origin_labels).
+///
+/// ```rust
+/// extern crate iron_cors;
+/// extern crate prometheus;
+///
+/// use iron_cors::{CorsMiddleware, CorsPrometheusMetrics};
+///
+/// # fn main() {
+/// let registry = prometheus::Registry::new();
+/// let middleware = CorsMiddleware::builder()
+///     .prometheus(CorsPrometheusMetrics::new(&registry).unwrap())
+///     .build()
+///     .unwrap();
+/// # }
+/// ```
+#[cfg(feature = "prometheus")]
+#[derive(Debug, Clone)]
+pub struct CorsPrometheusMetrics {
+    requests: IntCounterVec,
+    denied: IntCounterVec,
+    preflight_duration: Histogram,
+    max_origin_labels: usize,
+    origin_labels: Arc<Mutex<HashSet<String>>>,
+}
+
+/// The default number of distinct `origin` labels of `cors_denied_total`.
+#[cfg(feature = "prometheus")]
+const DEFAULT_MAX_ORIGIN_LABELS: usize = 100;
+
+/// The `origin` label of denials from origins past the limit.
+#[cfg(feature = "prometheus")]
+const OTHER_ORIGIN: &str = "other";
+
+#[cfg(feature = "prometheus")]
+impl CorsPrometheusMetrics {
+    /// Create the metrics and register them in the registry.
+    pub fn new(registry: &Registry) -> ::prometheus::Result<Self> {
+        let requests = IntCounterVec::new(
+            Opts::new("cors_requests_total", "Number of processed CORS requests"),
+            &["decision"],
+        )?;
+        let denied = IntCounterVec::new(
+            Opts::new("cors_denied_total", "Number of denied CORS requests by origin"),
+            &["origin"],
+        )?;
+        let preflight_duration = Histogram::with_opts(HistogramOpts::new(
+            "cors_preflight_duration_seconds",
+            "Time it took to answer preflight requests",
+        ))?;
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(denied.clone()))?;
+        registry.register(Box::new(preflight_duration.clone()))?;
+        Ok(CorsPrometheusMetrics {
+            requests,
+            denied,
+            preflight_duration,
+            max_origin_labels: DEFAULT_MAX_ORIGIN_LABELS,
+            origin_labels: Arc::new(Mutex::new(HashSet::new())),
+        })
+    }
+
+    /// Label denials with at most `max` distinct origins. Denials from
+    /// further origins are counted with the origin `other`.
+    pub fn max_origin_labels(mut self, max: usize) -> Self {
+        self.max_origin_labels = max;
+        self
+    }
+
+    /// Return the `origin` label for the origin, or `other` if the limit of
+    /// distinct labels is reached.
+    fn origin_label<'a>(&self, origin: &'a str) -> &'a str {
+        let mut labels = self.origin_labels.lock().unwrap_or_else(PoisonError::into_inner);
+        if labels.contains(origin) {
+            return origin;
+        }
+        if labels.len() < self.max_origin_labels {
+            labels.insert(origin.to_string());
+            return origin;
+        }
+        OTHER_ORIGIN
+    }
+
+    pub(crate) fn record(&self, decision: &CorsDecision, elapsed: Duration) {
+        match *decision {
+            CorsDecision::NotCors | CorsDecision::SameOrigin => {},
+            CorsDecision::Allowed(_) => self.requests.with_label_values(&["allowed"]).inc(),
+            CorsDecision::Denied(ref origin) => {
+                self.requests.with_label_values(&["denied"]).inc();
+                self.denied.with_label_values(&[self.origin_label(origin)]).inc();
+            },
+            CorsDecision::Preflight(_) => {
+                self.requests.with_label_values(&["preflight"]).inc();
+                self.preflight_duration.observe(elapsed.as_secs_f64());
+            },
+        }
+    }
+}
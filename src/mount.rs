@@ -0,0 +1,93 @@
+//! Different CORS configurations for different mount points.
+
+use iron::{AroundMiddleware, Handler, IronResult, Request, Response};
+
+use {request_path, CorsDecision, CorsMiddleware, PathPattern};
+
+/// Apply different CORS configurations to different paths of a handler,
+/// e.g. the mount points of a `Mount` or the routes of a `Router`.
+///
+/// Requests are handled by the middleware of the first mount point that
+/// matches the path. Requests that don't match any mount point are passed
+/// through without CORS processing.
+///
+/// ```rust
+/// extern crate iron;
+/// extern crate iron_cors;
+///
+/// use iron::prelude::*;
+/// use iron::status;
+/// use iron_cors::{CorsMiddleware, CorsMount};
+///
+/// fn hello(_: &mut Request) -> IronResult<Response> {
+///     Ok(Response::with((status::Ok, "Hello world!")))
+/// }
+///
+/// # fn main() {
+/// let api = CorsMiddleware::with_allow_any();
+/// let auth = CorsMiddleware::builder()
+///     .allowed_origins(vec!["https://app.example.com".to_string()])
+///     .allow_credentials(true)
+///     .build()
+///     .unwrap();
+///
+/// // E.g. a `Mount` with handlers at `/api` and `/auth`
+/// let mut chain = Chain::new(hello);
+/// chain.link_around(CorsMount::new().mount("/api", api).mount("/auth", auth));
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CorsMount {
+    mounts: Vec<Mount>,
+}
+
+#[derive(Debug, Clone)]
+struct Mount {
+    pattern: PathPattern,
+    middleware: CorsMiddleware,
+}
+
+impl CorsMount {
+    /// Create a mount without any mount points.
+    pub fn new() -> Self {
+        CorsMount::default()
+    }
+
+    /// Apply the middleware (or policy) to requests whose path matches the
+    /// pattern. Patterns are matched like in
+    /// [`CorsBuilder::path_policy`](struct.CorsBuilder.html#method.path_policy).
+    pub fn mount<M: Into<CorsMiddleware>>(mut self, pattern: &str, middleware: M) -> Self {
+        self.mounts.push(Mount {
+            pattern: PathPattern::new(pattern),
+            middleware: middleware.into(),
+        });
+        self
+    }
+}
+
+impl AroundMiddleware for CorsMount {
+    fn around(self, handler: Box<dyn Handler>) -> Box<dyn Handler> {
+        Box::new(CorsMountHandler {
+            handler,
+            mounts: self.mounts,
+        })
+    }
+}
+
+struct CorsMountHandler {
+    handler: Box<dyn Handler>,
+    mounts: Vec<Mount>,
+}
+
+impl Handler for CorsMountHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let path = request_path(req);
+        match self.mounts.iter().find(|mount| mount.pattern.matches(&path)) {
+            Some(mount) => mount.middleware.state.handle(&self.handler, req),
+            None => {
+                req.extensions.insert::<CorsDecision>(CorsDecision::NotCors);
+                self.handler.handle(req)
+            },
+        }
+    }
+}
@@ -0,0 +1,363 @@
+//! Parsing, normalization and formatting of origins.
+//!
+//! The middleware compares origins in a normalized form: scheme and host are
+//! converted to lowercase, and the default port of the scheme is removed.
+//! The functions in this module can be used to apply the same normalization
+//! elsewhere, e.g. in an authentication layer.
+//!
+//! ```rust
+//! use iron_cors::origin::{normalize_origin, ParsedOrigin};
+//!
+//! let origin = ParsedOrigin::parse("HTTPS://Example.com:443").unwrap();
+//! assert_eq!(origin.host(), "example.com");
+//! assert_eq!(origin.port(), None);
+//! assert_eq!(origin.to_string(), "https://example.com");
+//! assert_eq!(normalize_origin("HTTPS://Example.com:443"), "https://example.com");
+//! ```
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+use iron::headers;
+
+use CorsConfigError;
+
+/// A validated entry of a whitelist of origins.
+///
+/// The origin must be of the form `scheme://host[:port]`, e.g.
+/// `https://example.com`. It is normalized like incoming origins: scheme
+/// and host are converted to lowercase, and the default port of the scheme
+/// is removed.
+///
+/// ```rust
+/// use iron_cors::AllowedOrigin;
+///
+/// let origin: AllowedOrigin = "HTTPS://Example.com:443".parse().unwrap();
+/// assert_eq!(origin.as_str(), "https://example.com");
+/// assert!("example.com".parse::<AllowedOrigin>().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AllowedOrigin(String);
+
+impl AllowedOrigin {
+    /// Parse and normalize a serialized origin.
+    pub fn parse(origin: &str) -> Result<Self, CorsConfigError> {
+        if is_valid_origin(origin.trim()) {
+            Ok(AllowedOrigin(normalize_origin(origin)))
+        } else {
+            Err(CorsConfigError::InvalidOrigin(origin.to_string()))
+        }
+    }
+
+    /// Return the normalized origin.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Return the normalized origin.
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl FromStr for AllowedOrigin {
+    type Err = CorsConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AllowedOrigin::parse(s)
+    }
+}
+
+impl fmt::Display for AllowedOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A parsed and normalized origin of the form `scheme://host[:port]`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ParsedOrigin {
+    scheme: String,
+    host: String,
+    port: Option<u16>,
+}
+
+impl ParsedOrigin {
+    /// Parse and normalize a serialized origin.
+    pub fn parse(origin: &str) -> Result<Self, CorsConfigError> {
+        let invalid = || CorsConfigError::InvalidOrigin(origin.to_string());
+        let trimmed = origin.trim();
+        if !is_valid_origin(trimmed) {
+            return Err(invalid());
+        }
+        let normalized = normalize_origin(trimmed);
+        let index = normalized.find("://").ok_or_else(invalid)?;
+        let scheme = &normalized[..index];
+        let host = strip_port(&normalized[index + 3..]);
+        let port = match normalized[index + 3 + host.len()..].strip_prefix(':') {
+            Some(port) => Some(port.parse().map_err(|_| invalid())?),
+            None => None,
+        };
+        Ok(ParsedOrigin { scheme: scheme.to_string(), host: host.to_string(), port })
+    }
+
+    /// The lowercase scheme, e.g. `https`.
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    /// The lowercase host. IPv6 addresses are enclosed in brackets.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The port, or `None` if it is the default port of the scheme.
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+}
+
+impl<'a> From<&'a headers::Origin> for ParsedOrigin {
+    fn from(origin: &'a headers::Origin) -> Self {
+        let scheme = origin.scheme.to_ascii_lowercase();
+        let port = origin.host.port.filter(|&port| Some(port) != default_port(&scheme));
+        ParsedOrigin { host: origin.host.hostname.to_ascii_lowercase(), scheme, port }
+    }
+}
+
+impl FromStr for ParsedOrigin {
+    type Err = CorsConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ParsedOrigin::parse(s)
+    }
+}
+
+impl fmt::Display for ParsedOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.port {
+            Some(port) => write!(f, "{}://{}:{}", self.scheme, self.host, port),
+            None => write!(f, "{}://{}", self.scheme, self.host),
+        }
+    }
+}
+
+/// Parse all origins, returning all invalid entries on failure.
+pub(crate) fn parse_origins<I, S>(origins: I) -> Result<Vec<AllowedOrigin>, CorsConfigError>
+    where I: IntoIterator<Item=S>, S: AsRef<str>
+{
+    let mut parsed = vec![];
+    let mut invalid = vec![];
+    for origin in origins {
+        match AllowedOrigin::parse(origin.as_ref()) {
+            Ok(origin) => parsed.push(origin),
+            Err(_) => invalid.push(origin.as_ref().to_string()),
+        }
+    }
+    match invalid.len() {
+        0 => Ok(parsed),
+        1 => Err(CorsConfigError::InvalidOrigin(invalid.remove(0))),
+        _ => Err(CorsConfigError::InvalidOrigins(invalid)),
+    }
+}
+
+/// Return whether the string is a serialized origin of the form
+/// `scheme://host[:port]`.
+pub(crate) fn is_valid_origin(origin: &str) -> bool {
+    let mut parts = origin.splitn(2, "://");
+    let scheme = parts.next().unwrap_or("");
+    let authority = match parts.next() {
+        Some(authority) => authority,
+        None => return false,
+    };
+    let valid_scheme = scheme.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+        && scheme.chars().all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.');
+    if !valid_scheme {
+        return false;
+    }
+    let (host, port) = match authority.rfind(':') {
+        // Don't mistake the colons of an IPv6 address for a port separator
+        Some(index) if !authority[index..].contains(']') => (&authority[..index], Some(&authority[index + 1..])),
+        _ => (authority, None),
+    };
+    let valid_host = !host.is_empty()
+        && !host.chars().any(|c| c == '/' || c == '?' || c == '#' || c == '@' || c.is_whitespace());
+    let valid_port = port.is_none_or(|port| port.parse::<u16>().is_ok());
+    valid_host && valid_port
+}
+
+/// Normalize a serialized origin for comparisons: Scheme and host are
+/// case-insensitive and converted to lowercase, and the default port of the
+/// scheme is removed.
+pub fn normalize_origin(origin: &str) -> String {
+    let origin = origin.trim().to_ascii_lowercase();
+    let scheme = origin.split("://").next().unwrap_or("");
+    let without_port = strip_port(&origin);
+    match default_port(scheme) {
+        Some(port) if origin[without_port.len()..] == format!(":{}", port) => without_port.to_string(),
+        _ => origin,
+    }
+}
+
+/// Normalize the origin like `normalize_origin`, without allocating if it
+/// is already normalized.
+pub(crate) fn normalized_origin(origin: &str) -> Cow<'_, str> {
+    let scheme = origin.split("://").next().unwrap_or("");
+    let port = &origin[strip_port(origin).len()..];
+    let has_default_port = default_port(scheme)
+        .is_some_and(|default| port.strip_prefix(':').is_some_and(|port| port.parse() == Ok(default)));
+    if origin.trim().len() != origin.len() || origin.bytes().any(|b| b.is_ascii_uppercase()) || has_default_port {
+        Cow::Owned(normalize_origin(origin))
+    } else {
+        Cow::Borrowed(origin)
+    }
+}
+
+/// Normalize all origins with `normalize_origin`.
+pub(crate) fn normalize_origins<I>(origins: I) -> HashSet<String>
+    where I: IntoIterator<Item=String>
+{
+    origins.into_iter().map(|origin| normalize_origin(&origin)).collect()
+}
+
+/// Remove the port (including a `*` wildcard port) from a serialized origin.
+pub(crate) fn strip_port(origin: &str) -> &str {
+    let authority_start = origin.find("://").map_or(0, |index| index + 3);
+    match origin.rfind(':') {
+        // Don't mistake the colons of an IPv6 address for a port separator
+        Some(index) if index >= authority_start && !origin[index..].contains(']') => &origin[..index],
+        _ => origin,
+    }
+}
+
+/// The schemes with a default port.
+const DEFAULT_PORTS: [(&str, u16); 4] = [("http", 80), ("ws", 80), ("https", 443), ("wss", 443)];
+
+/// Return the default port of the scheme.
+pub fn default_port(scheme: &str) -> Option<u16> {
+    DEFAULT_PORTS.iter().find(|&&(default_scheme, _)| scheme == default_scheme).map(|&(_, port)| port)
+}
+
+/// Return the default port of the scheme, compared case-insensitively.
+fn default_port_ignore_case(scheme: &str) -> Option<u16> {
+    DEFAULT_PORTS.iter().find(|&&(default_scheme, _)| scheme.eq_ignore_ascii_case(default_scheme)).map(|&(_, port)| port)
+}
+
+/// Serialize the origin in the form of `normalize_origin`: lowercase, and
+/// without the port if it is the default port of the scheme.
+pub(crate) fn format_canonical_origin(origin: &headers::Origin) -> String {
+    ParsedOrigin::from(origin).to_string()
+}
+
+/// Call the function with the origin serialized like
+/// `format_canonical_origin`.
+///
+/// This is used on every request, so the origin is serialized into a buffer
+/// on the stack. Only unusually long origins are allocated.
+pub(crate) fn with_canonical_origin<F, R>(origin: &headers::Origin, f: F) -> R
+    where F: FnOnce(&str) -> R
+{
+    let mut buf = OriginBuf::new();
+    let scheme = &origin.scheme;
+    let written = match origin.host.port.filter(|&port| Some(port) != default_port_ignore_case(scheme)) {
+        Some(port) => write!(buf, "{}://{}:{}", scheme, origin.host.hostname, port),
+        None => write!(buf, "{}://{}", scheme, origin.host.hostname),
+    };
+    match written {
+        Ok(()) => {
+            buf.bytes[..buf.len].make_ascii_lowercase();
+            f(buf.as_str())
+        },
+        Err(_) => f(&format_canonical_origin(origin)),
+    }
+}
+
+/// Call the function with the serialized origin like `format_cors_origin`,
+/// without allocating unless the origin is unusually long.
+pub(crate) fn with_cors_origin<F, R>(origin: &headers::Origin, f: F) -> R
+    where F: FnOnce(&str) -> R
+{
+    if is_null_origin(origin) {
+        return f("null");
+    }
+    let mut buf = OriginBuf::new();
+    let written = match origin.host.port {
+        Some(port) => write!(buf, "{}://{}:{}", &origin.scheme, &origin.host.hostname, port),
+        None => write!(buf, "{}://{}", &origin.scheme, &origin.host.hostname),
+    };
+    match written {
+        Ok(()) => f(buf.as_str()),
+        Err(_) => f(&format_cors_origin(origin)),
+    }
+}
+
+/// A fixed size buffer for a serialized origin.
+struct OriginBuf {
+    bytes: [u8; 256],
+    len: usize,
+}
+
+impl OriginBuf {
+    fn new() -> Self {
+        OriginBuf { bytes: [0; 256], len: 0 }
+    }
+
+    fn as_str(&self) -> &str {
+        // Only whole `str`s were copied into the buffer
+        std::str::from_utf8(&self.bytes[..self.len]).unwrap_or_default()
+    }
+}
+
+impl fmt::Write for OriginBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > self.bytes.len() {
+            return Err(fmt::Error);
+        }
+        self.bytes[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// Split a serialized origin into a typed `Origin` header without any
+/// normalization, so that it is echoed unchanged by `format_cors_origin`.
+///
+/// This is used for origins that are allowed by their exact serialization,
+/// e.g. `safari-web-extension://<UUID>`, which the typed header would
+/// convert to lowercase.
+pub(crate) fn parse_raw_origin(value: &str) -> Option<headers::Origin> {
+    let index = value.find("://")?;
+    let (scheme, authority) = (&value[..index], &value[index + 3..]);
+    let host = strip_port(authority);
+    let port = match authority[host.len()..].strip_prefix(':') {
+        Some(port) => Some(port.parse().ok()?),
+        None => None,
+    };
+    Some(headers::Origin::new(scheme.to_string(), host.to_string(), port))
+}
+
+/// Return the placeholder for the `null` origin, which can't be parsed into
+/// a typed `Origin` header.
+pub(crate) fn null_origin() -> headers::Origin {
+    headers::Origin::new("null", "", None)
+}
+
+/// Return whether the origin is the placeholder for the `null` origin.
+pub(crate) fn is_null_origin(origin: &headers::Origin) -> bool {
+    origin.scheme == "null" && origin.host.hostname.is_empty()
+}
+
+/// Serialize the origin as it is sent in the `Access-Control-Allow-Origin`
+/// header, without normalization.
+pub fn format_cors_origin(origin: &headers::Origin) -> String {
+    if is_null_origin(origin) {
+        return "null".to_string();
+    }
+    match origin.host.port {
+        Some(port) => format!("{}://{}:{}", &origin.scheme, &origin.host.hostname, &port),
+        None => format!("{}://{}", &origin.scheme, &origin.host.hostname),
+    }
+}
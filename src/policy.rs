@@ -0,0 +1,2020 @@
+//! CORS policies.
+
+use std::collections::HashSet;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::Duration;
+
+use iron::{Request, Response, IronError, IronResult, Handler};
+use iron::method::Method;
+use iron::status;
+use iron::typemap;
+use iron::headers;
+#[cfg(feature = "regex")] use regex::Regex;
+use unicase::UniCase;
+
+use cache::{DecisionCache, LruCache};
+use classify::{forwarded_origin, is_xdomain_request, raw_origin_value, url_origin};
+use logging::{self, LogEvent, LogLevels, LogLimiter};
+use csrf::SharedCsrfValidator;
+use deny::{rejection_body, CorsRejection, SharedResponder};
+use fetch::fetch_site;
+use referer::{is_state_changing, referer_origin};
+use validator::{Validator, ValidatorSettings};
+use origin::{format_canonical_origin, format_cors_origin, is_null_origin, parse_raw_origin, strip_port, with_canonical_origin, with_cors_origin};
+use {CorsBuilder, CorsConfigError, CorsConfigWarning, CorsMetrics, CorsTrace, OriginMatcher, OriginStore, RefererCheck, SecFetchSite, WhitelistFile};
+
+/// The origins that are allowed to access the resource.
+#[derive(Default, Clone)]
+pub(crate) enum AllowedOrigins {
+    /// Any origin is allowed.
+    #[default]
+    Any,
+    /// Any origin is allowed, and it is echoed back instead of `*`.
+    Reflect,
+    /// Only the listed origins are allowed.
+    Whitelist(Arc<HashSet<String>>),
+    /// All origins except the listed ones are allowed.
+    Blacklist(Arc<HashSet<String>>),
+    /// Only the origins in the origin store are allowed.
+    Store(Arc<dyn OriginStore>),
+    /// Only the origins in the whitelist file are allowed.
+    File(WhitelistFile),
+    /// Origins matching any of the matchers are allowed.
+    Matchers(Arc<Vec<OriginMatcher>>),
+    /// A function decides whether an origin is allowed.
+    Fn(Arc<dyn Fn(&headers::Origin) -> bool + Send + Sync>),
+    /// A fallible function decides whether an origin is allowed.
+    TryFn(Arc<Validator>),
+    /// Origins matching any of the regular expressions are allowed.
+    #[cfg(feature = "regex")]
+    Regex(Vec<Regex>),
+    /// Origins allowed by either of the two are allowed, e.g. after merging
+    /// policies with different kinds of origins.
+    Union(Arc<(AllowedOrigins, AllowedOrigins)>),
+}
+
+impl AllowedOrigins {
+    /// Whether the `Access-Control-Allow-Origin` header is set to `*`
+    /// instead of the request origin.
+    fn is_wildcard(&self) -> bool {
+        matches!(*self, AllowedOrigins::Any)
+    }
+
+    /// Return the name of the mode and the listed origins, sorted.
+    pub(crate) fn describe(&self) -> (&'static str, Option<Vec<String>>) {
+        let sorted = |origins: Vec<String>| {
+            let mut origins = origins;
+            origins.sort();
+            Some(origins)
+        };
+        match *self {
+            AllowedOrigins::Any => ("any", None),
+            AllowedOrigins::Reflect => ("reflect", None),
+            AllowedOrigins::Whitelist(ref origins) => ("whitelist", sorted(origins.iter().cloned().collect())),
+            AllowedOrigins::Blacklist(ref origins) => ("blacklist", sorted(origins.iter().cloned().collect())),
+            AllowedOrigins::Store(ref store) => ("whitelist", sorted(store.list())),
+            AllowedOrigins::File(ref file) => ("whitelist", sorted(file.current().to_set().into_iter().collect())),
+            AllowedOrigins::Matchers(ref matchers) => {
+                ("matchers", Some(matchers.iter().map(|matcher| format!("{:?}", matcher)).collect()))
+            },
+            AllowedOrigins::Fn(_) | AllowedOrigins::TryFn(_) => ("function", None),
+            #[cfg(feature = "regex")]
+            AllowedOrigins::Regex(ref regexes) => {
+                ("regex", Some(regexes.iter().map(|regex| regex.as_str().to_string()).collect()))
+            },
+            AllowedOrigins::Union(_) => ("union", None),
+        }
+    }
+
+    /// Whether the origin is checked by user code, which may be slow.
+    fn is_dynamic(&self) -> bool {
+        match *self {
+            AllowedOrigins::Fn(_) | AllowedOrigins::TryFn(_) | AllowedOrigins::Store(_) => true,
+            AllowedOrigins::Union(ref both) => both.0.is_dynamic() || both.1.is_dynamic(),
+            _ => false,
+        }
+    }
+
+    /// Return whether the origin is allowed. If `ignore_port` is set, the
+    /// ports of the origin and of the whitelist entries are ignored.
+    fn is_allowed(&self, origin: &headers::Origin, ignore_port: bool, settings: &ValidatorSettings) -> bool {
+        self.decide(origin, ignore_port, settings).unwrap_or_else(|fallback| fallback)
+    }
+
+    /// Return whether the origin is allowed, or the fallback decision as an
+    /// error if a fallible validator failed.
+    fn decide(&self, origin: &headers::Origin, ignore_port: bool, settings: &ValidatorSettings) -> Result<bool, bool> {
+        let allowed = match *self {
+            AllowedOrigins::Any | AllowedOrigins::Reflect => true,
+            AllowedOrigins::Whitelist(ref allowed_hosts) => {
+                whitelist_contains(allowed_hosts, origin, ignore_port)
+            },
+            AllowedOrigins::Blacklist(ref blocked_hosts) => {
+                !whitelist_contains(blocked_hosts, origin, ignore_port)
+            },
+            AllowedOrigins::Store(ref store) => store_contains(&**store, origin, ignore_port),
+            AllowedOrigins::File(ref file) => {
+                file.current().with_set(|set| whitelist_contains(set, origin, ignore_port))
+            },
+            AllowedOrigins::Matchers(ref matchers) => matchers.iter().any(|matcher| matcher.matches(origin)),
+            AllowedOrigins::Fn(ref f) => f(origin),
+            AllowedOrigins::TryFn(ref validator) => {
+                return with_canonical_origin(origin, |serialized| validator.validate(origin, serialized, settings));
+            },
+            #[cfg(feature = "regex")]
+            AllowedOrigins::Regex(ref regexes) => {
+                with_canonical_origin(origin, |origin| {
+                    let origin = if ignore_port { strip_port(origin) } else { origin };
+                    regexes.iter().any(|regex| regex.is_match(origin))
+                })
+            },
+            AllowedOrigins::Union(ref both) => {
+                return match both.0.decide(origin, ignore_port, settings) {
+                    Ok(true) => Ok(true),
+                    Ok(false) => both.1.decide(origin, ignore_port, settings),
+                    Err(fallback) => match both.1.decide(origin, ignore_port, settings) {
+                        Ok(true) => Ok(true),
+                        Ok(false) | Err(false) => Err(fallback),
+                        Err(true) => Err(true),
+                    },
+                };
+            },
+        };
+        Ok(allowed)
+    }
+
+    /// Describe the result of `is_allowed` for a trace.
+    fn explain(&self, origin: &headers::Origin, allowed: bool) -> String {
+        let serialized = format_canonical_origin(origin);
+        let list = |name: &str, len: usize| {
+            let result = if allowed == (name == "blacklist") { "not in" } else { "in" };
+            format!("{} is {} the {} of {} origins", serialized, result, name, len)
+        };
+        match *self {
+            AllowedOrigins::Any | AllowedOrigins::Reflect => "any origin is allowed".to_string(),
+            AllowedOrigins::Whitelist(ref allowed_hosts) => list("whitelist", allowed_hosts.len()),
+            AllowedOrigins::Blacklist(ref blocked_hosts) => list("blacklist", blocked_hosts.len()),
+            AllowedOrigins::Store(ref store) => list("origin store", store.list().len()),
+            AllowedOrigins::File(ref file) => list("whitelist file", file.current().with_set(HashSet::len)),
+            AllowedOrigins::Matchers(ref matchers) => {
+                match matchers.iter().position(|matcher| matcher.matches(origin)) {
+                    Some(index) => format!("{} matches matcher {}: {:?}", serialized, index, matchers[index]),
+                    None => format!("{} matches none of {} matchers", serialized, matchers.len()),
+                }
+            },
+            AllowedOrigins::Fn(_) => {
+                format!("the origin function returned {} for {}", allowed, serialized)
+            },
+            AllowedOrigins::TryFn(_) => {
+                format!("the origin validator or its fallback returned {} for {}", allowed, serialized)
+            },
+            #[cfg(feature = "regex")]
+            AllowedOrigins::Regex(ref regexes) => {
+                let result = if allowed { "matches" } else { "matches none of" };
+                format!("{} {} the regular expressions {:?}", serialized, result, regexes)
+            },
+            AllowedOrigins::Union(ref both) => {
+                let result = if allowed { "allowed" } else { "not allowed" };
+                format!("{} is {} by either of {:?} and {:?}", serialized, result, both.0, both.1)
+            },
+        }
+    }
+}
+
+impl fmt::Debug for AllowedOrigins {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AllowedOrigins::Any => write!(f, "Any"),
+            AllowedOrigins::Reflect => write!(f, "Reflect"),
+            AllowedOrigins::Whitelist(ref allowed_hosts) => {
+                f.debug_tuple("Whitelist").field(allowed_hosts).finish()
+            },
+            AllowedOrigins::Blacklist(ref blocked_hosts) => {
+                f.debug_tuple("Blacklist").field(blocked_hosts).finish()
+            },
+            AllowedOrigins::Store(_) => write!(f, "Store(..)"),
+            AllowedOrigins::File(ref file) => f.debug_tuple("File").field(file).finish(),
+            AllowedOrigins::Matchers(ref matchers) => f.debug_tuple("Matchers").field(matchers).finish(),
+            AllowedOrigins::Fn(_) => write!(f, "Fn(..)"),
+            AllowedOrigins::TryFn(_) => write!(f, "TryFn(..)"),
+            #[cfg(feature = "regex")]
+            AllowedOrigins::Regex(ref regexes) => f.debug_tuple("Regex").field(regexes).finish(),
+            AllowedOrigins::Union(ref both) => f.debug_tuple("Union").field(&both.0).field(&both.1).finish(),
+        }
+    }
+}
+
+/// How to respond to requests from an origin that is not allowed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum DisallowedOriginBehavior {
+    /// Respond with `400 Bad Request` without calling the inner handler.
+    /// This is the default.
+    #[default]
+    Reject,
+    /// Pass the request to the inner handler and return its response
+    /// without any CORS headers. The browser will then block the response,
+    /// while non-browser clients are not affected.
+    PassThrough,
+    /// Respond as the fetch standard intends: Answer preflights with an
+    /// empty response without any `Access-Control-*` headers, and pass
+    /// actual requests to the inner handler without CORS headers. The
+    /// browser then reports a CORS error for both.
+    ///
+    /// This also applies to preflights that request a method or headers that
+    /// are not allowed.
+    SpecCompliant,
+}
+
+/// How to handle requests with an `Origin` header that can't be parsed, e.g.
+/// from scripts or misbehaving proxies.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum MalformedOriginPolicy {
+    /// Handle the request as if it had no `Origin` header. This is the
+    /// default.
+    #[default]
+    Ignore,
+    /// Respond with `400 Bad Request` without calling the inner handler.
+    /// The rejection is logged and reported to the denial hooks with the raw
+    /// header value.
+    Reject,
+}
+
+/// How to handle requests with the `Origin: null` header, which is sent e.g.
+/// by sandboxed iframes and pages loaded from `file://` URLs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum NullOriginPolicy {
+    /// Handle the request like a request from an origin that is not
+    /// allowed.
+    Deny,
+    /// Allow the request, and respond with `Access-Control-Allow-Origin:
+    /// null` (or `*` if any origin is allowed).
+    Allow,
+    /// Pass the request to the inner handler without any CORS processing.
+    /// This is the default.
+    #[default]
+    PassThrough,
+}
+
+/// How to handle server-wide `OPTIONS *` requests, whose request target is
+/// an asterisk instead of a path.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum AsteriskOptionsBehavior {
+    /// Pass the request to the handler without any CORS processing. This is
+    /// the default.
+    #[default]
+    PassThrough,
+    /// Respond with `200 OK` and an `Allow` header listing the allowed
+    /// methods, without CORS headers.
+    Allow,
+}
+
+/// Which CORS responses require that the request arrived over TLS.
+///
+/// A request is secure if the URL of the request has the `https` scheme,
+/// or if it comes from a [trusted
+/// proxy](struct.CorsBuilder.html#method.trusted_proxies) with the
+/// `X-Forwarded-Proto: https` header.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum TlsRequirement {
+    /// CORS headers are sent regardless of the transport. This is the
+    /// default.
+    #[default]
+    None,
+    /// Credentials are only allowed over TLS. Responses to insecure
+    /// requests don't get the `Access-Control-Allow-Credentials` header.
+    Credentials,
+    /// CORS headers are only sent over TLS. Insecure cross-origin requests
+    /// are handled like requests from a disallowed origin.
+    All,
+}
+
+/// The value of the `Cross-Origin-Resource-Policy` header, which tells
+/// browsers which origins may embed a resource, e.g. as an image, without
+/// CORS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum CrossOriginResourcePolicy {
+    /// Only the origin of the server may embed the resource.
+    SameOrigin,
+    /// Origins of the same site as the server may embed the resource.
+    SameSite,
+    /// Any origin may embed the resource.
+    CrossOrigin,
+}
+
+impl CrossOriginResourcePolicy {
+    /// Return the value of the header.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            CrossOriginResourcePolicy::SameOrigin => "same-origin",
+            CrossOriginResourcePolicy::SameSite => "same-site",
+            CrossOriginResourcePolicy::CrossOrigin => "cross-origin",
+        }
+    }
+}
+
+/// The value of the `Cross-Origin-Opener-Policy` header, which tells
+/// browsers whether a document shares its browsing context group with
+/// cross-origin documents, e.g. popups.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum CrossOriginOpenerPolicy {
+    /// The document may share its browsing context group with any document.
+    UnsafeNone,
+    /// Like `SameOrigin`, but popups opened by the document keep their
+    /// reference to it.
+    SameOriginAllowPopups,
+    /// The document only shares its browsing context group with same-origin
+    /// documents. Required for cross-origin isolation.
+    SameOrigin,
+}
+
+impl CrossOriginOpenerPolicy {
+    /// Return the value of the header.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            CrossOriginOpenerPolicy::UnsafeNone => "unsafe-none",
+            CrossOriginOpenerPolicy::SameOriginAllowPopups => "same-origin-allow-popups",
+            CrossOriginOpenerPolicy::SameOrigin => "same-origin",
+        }
+    }
+}
+
+/// The value of the `Cross-Origin-Embedder-Policy` header, which tells
+/// browsers which cross-origin resources a document may load.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum CrossOriginEmbedderPolicy {
+    /// The document may load any cross-origin resource.
+    UnsafeNone,
+    /// Cross-origin resources must allow the document with CORS or a
+    /// `Cross-Origin-Resource-Policy` header. Enables cross-origin isolation
+    /// together with `Cross-Origin-Opener-Policy: same-origin`.
+    RequireCorp,
+    /// Cross-origin resources without CORS are loaded without credentials.
+    /// Enables cross-origin isolation like `RequireCorp`.
+    Credentialless,
+}
+
+impl CrossOriginEmbedderPolicy {
+    /// Return the value of the header.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            CrossOriginEmbedderPolicy::UnsafeNone => "unsafe-none",
+            CrossOriginEmbedderPolicy::RequireCorp => "require-corp",
+            CrossOriginEmbedderPolicy::Credentialless => "credentialless",
+        }
+    }
+}
+
+/// How preflight responses announce the allowed methods and headers if no
+/// lists are configured.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum HeaderPolicy {
+    /// Echo the method and headers requested by the browser. This is the
+    /// default.
+    ///
+    /// Policies that allow any origin with `*` announce a fixed list of
+    /// common methods and the `*` wildcard for headers instead, so that
+    /// their preflight responses don't depend on the request. Other methods
+    /// are still echoed, and
+    /// [`strict`](struct.CorsBuilder.html#method.strict) mode echoes the
+    /// method and headers as well.
+    #[default]
+    Echo,
+    /// Announce the `*` wildcard. Browsers treat `*` literally in responses
+    /// to credentialed requests, so the requested method and headers are
+    /// echoed if credentials are allowed.
+    Wildcard,
+}
+
+/// How to handle CORS headers that the inner handler already set on the
+/// response to an actual request.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum HeaderConflictPolicy {
+    /// Replace the headers of the handler. This is the default.
+    #[default]
+    Overwrite,
+    /// Keep the headers of the handler.
+    Preserve,
+    /// Combine list headers such as `Access-Control-Expose-Headers`, and
+    /// keep the other headers of the handler.
+    Merge,
+}
+
+/// Which error responses of the inner handler get CORS headers.
+///
+/// Error responses are the responses of `IronError`s returned by the inner
+/// handler. Without CORS headers, browsers don't let cross-origin scripts
+/// read them, e.g. to keep the details of server errors private.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum ErrorResponsePolicy {
+    /// Add CORS headers to all error responses. This is the default.
+    #[default]
+    All,
+    /// Don't add CORS headers to error responses.
+    Omit,
+    /// Only add CORS headers to error responses with a status in one of the
+    /// classes, e.g. client errors but not server errors.
+    StatusClasses(Vec<status::StatusClass>),
+}
+
+impl ErrorResponsePolicy {
+    /// Return whether an error response with the status gets CORS headers.
+    fn applies_to(&self, status: Option<status::Status>) -> bool {
+        match *self {
+            ErrorResponsePolicy::All => true,
+            ErrorResponsePolicy::Omit => false,
+            ErrorResponsePolicy::StatusClasses(ref classes) => {
+                status.is_some_and(|status| classes.contains(&status.class()))
+            },
+        }
+    }
+}
+
+/// How the middleware classified a request.
+///
+/// The decision is inserted into the request extensions before the inner
+/// handler is called, so handlers and other middlewares can branch on it:
+///
+/// ```rust
+/// extern crate iron;
+/// extern crate iron_cors;
+///
+/// use iron::prelude::*;
+/// use iron::status;
+/// use iron_cors::CorsDecision;
+///
+/// fn handler(req: &mut Request) -> IronResult<Response> {
+///     match req.extensions.get::<CorsDecision>() {
+///         Some(&CorsDecision::Allowed(ref origin)) => {
+///             Ok(Response::with((status::Ok, format!("Hello {}", origin))))
+///         },
+///         _ => Ok(Response::with((status::Ok, "Hello"))),
+///     }
+/// }
+/// # fn main() {}
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CorsDecision {
+    /// The request has no `Origin` header.
+    NotCors,
+    /// The `Origin` header matches the origin of the request itself, so it
+    /// is not a cross-origin request.
+    SameOrigin,
+    /// The request comes from the allowed origin.
+    Allowed(String),
+    /// The request comes from the origin, which is not allowed.
+    Denied(String),
+    /// The request is a valid preflight request from the allowed origin.
+    Preflight(String),
+}
+
+impl typemap::Key for CorsDecision {
+    type Value = CorsDecision;
+}
+
+/// A set of CORS rules: which origins are allowed, and which headers are
+/// added to the responses.
+///
+/// A policy is independent of Iron handlers. It can be built in a
+/// configuration module, cloned to be shared between multiple chains, and
+/// turned into a middleware with
+/// [`CorsMiddleware::from_policy`](struct.CorsMiddleware.html#method.from_policy).
+///
+/// The default policy allows requests from any origin.
+///
+/// ```rust
+/// extern crate iron;
+/// extern crate iron_cors;
+///
+/// use iron::headers::Origin;
+/// use iron_cors::{CorsMiddleware, CorsPolicy};
+///
+/// # fn main() {
+/// let policy = CorsPolicy::builder()
+///     .allowed_origins(vec!["https://example.com".to_string()])
+///     .build_policy()
+///     .unwrap();
+/// assert!(policy.is_origin_allowed(&Origin::new("https", "example.com", None)));
+/// assert!(!policy.is_origin_allowed(&Origin::new("https", "example.org", None)));
+///
+/// let middleware = CorsMiddleware::from_policy(policy.clone());
+/// # }
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct CorsPolicy {
+    pub(crate) allowed_origins: AllowedOrigins,
+    pub(crate) allow_credentials: bool,
+    pub(crate) max_age: Option<Duration>,
+    pub(crate) expose_headers: Vec<UniCase<String>>,
+    pub(crate) allowed_methods: Option<Vec<Method>>,
+    pub(crate) allowed_headers: Option<Vec<UniCase<String>>>,
+    pub(crate) disallowed_origin_behavior: DisallowedOriginBehavior,
+    pub(crate) preflight_status: PreflightStatus,
+    pub(crate) timing_allow_origin: bool,
+    pub(crate) xdomain_request_compat: bool,
+    pub(crate) ignore_port: bool,
+    pub(crate) require_https_origins: bool,
+    pub(crate) allowed_schemes: Option<Vec<String>>,
+    pub(crate) null_origin_policy: NullOriginPolicy,
+    pub(crate) malformed_origin_policy: MalformedOriginPolicy,
+    pub(crate) reject_multiple_origins: bool,
+    pub(crate) raw_origins: HashSet<String>,
+    pub(crate) except_origins: HashSet<String>,
+    pub(crate) asterisk_options: AsteriskOptionsBehavior,
+    pub(crate) allow_localhost: bool,
+    pub(crate) skip_same_origin: bool,
+    pub(crate) trust_sec_fetch_site: bool,
+    pub(crate) referer_fallback: bool,
+    pub(crate) strip_credentials: bool,
+    pub(crate) tls_requirement: TlsRequirement,
+    pub(crate) resource_policy: Option<CrossOriginResourcePolicy>,
+    pub(crate) opener_policy: Option<CrossOriginOpenerPolicy>,
+    pub(crate) embedder_policy: Option<CrossOriginEmbedderPolicy>,
+    pub(crate) reject_cross_site: Vec<Method>,
+    pub(crate) self_origin: Option<String>,
+    pub(crate) trusted_proxies: HashSet<IpAddr>,
+    pub(crate) strict: bool,
+    pub(crate) enforce_allowed_methods: bool,
+    pub(crate) unrestricted_methods: Vec<Method>,
+    pub(crate) preflight_method_not_allowed: bool,
+    pub(crate) header_policy: HeaderPolicy,
+    pub(crate) omit_wildcard_authorization: bool,
+    pub(crate) default_headers: Vec<UniCase<String>>,
+    pub(crate) extra_preflight_headers: Vec<(String, String)>,
+    pub(crate) preflight_cache_control: Option<String>,
+    pub(crate) extra_actual_headers: Vec<(String, String)>,
+    pub(crate) header_conflict_policy: HeaderConflictPolicy,
+    pub(crate) error_response_policy: ErrorResponsePolicy,
+    pub(crate) deny_responder: SharedResponder,
+    pub(crate) csrf_validator: Option<SharedCsrfValidator>,
+    /// The recently built preflight response headers, shared between clones
+    /// of the policy.
+    pub(crate) preflight_cache: Option<Arc<Mutex<PreflightCache>>>,
+    pub(crate) decision_cache: Option<Arc<DecisionCache>>,
+    pub(crate) validator_settings: ValidatorSettings,
+    pub(crate) trace_decisions: bool,
+    pub(crate) report_only: bool,
+    pub(crate) log_levels: LogLevels,
+    pub(crate) log_limiter: Option<Arc<Mutex<LogLimiter>>>,
+    /// The metrics of the middleware, which count the cache hits.
+    pub(crate) metrics: Option<Arc<CorsMetrics>>,
+    /// The preflight response headers if they don't depend on the request,
+    /// computed on the first preflight.
+    pub(crate) static_preflight_headers: OnceLock<Option<Vec<RawHeader>>>,
+}
+
+/// Return the `Access-Control-Max-Age` header for the max-age, saturated
+/// to the largest number of seconds that fits into the header.
+pub(crate) fn max_age_header(max_age: Duration) -> headers::AccessControlMaxAge {
+    let seconds = max_age.as_secs();
+    headers::AccessControlMaxAge(if seconds > u64::from(u32::MAX) { u32::MAX } else { seconds as u32 })
+}
+
+/// The name and the serialized value of a header.
+type RawHeader = (String, Vec<u8>);
+
+/// Preflight response headers by origin, requested method and requested
+/// headers.
+///
+/// Entries are looked up by a hash of the request, so that lookups don't
+/// allocate, and the stored request is compared to rule out collisions.
+#[derive(Debug)]
+pub(crate) struct PreflightCache {
+    hasher: RandomState,
+    entries: LruCache<u64, PreflightEntry>,
+}
+
+#[derive(Debug)]
+struct PreflightEntry {
+    origin: String,
+    method: Method,
+    request_headers: Vec<String>,
+    headers: Arc<[RawHeader]>,
+}
+
+impl PreflightCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        PreflightCache {
+            hasher: RandomState::new(),
+            entries: LruCache::new(capacity),
+        }
+    }
+
+    /// Return the maximum number of entries.
+    pub(crate) fn capacity(&self) -> usize {
+        self.entries.capacity()
+    }
+
+    /// Return the cached headers for the preflight, or build and cache
+    /// them.
+    fn get_or_insert_with<F>(&mut self,
+                             origin: &str,
+                             method: &Method,
+                             request_headers: &[UniCase<String>],
+                             metrics: Option<&CorsMetrics>,
+                             f: F) -> Arc<[RawHeader]>
+        where F: FnOnce() -> Vec<RawHeader>
+    {
+        let mut hasher = self.hasher.build_hasher();
+        origin.hash(&mut hasher);
+        method.as_ref().hash(&mut hasher);
+        for header in request_headers {
+            header.as_str().hash(&mut hasher);
+        }
+        let key = hasher.finish();
+        let cached = self.entries.get(&key).filter(|entry| {
+            entry.origin == origin && entry.method == *method
+                && entry.request_headers.iter().map(String::as_str).eq(request_headers.iter().map(|header| header.as_str()))
+        });
+        if let Some(entry) = cached {
+            if let Some(metrics) = metrics {
+                metrics.record_cache_hit();
+            }
+            return entry.headers.clone();
+        }
+        let headers: Arc<[RawHeader]> = f().into();
+        self.entries.insert(key, PreflightEntry {
+            origin: origin.to_string(),
+            method: method.clone(),
+            request_headers: request_headers.iter().map(|header| header.to_string()).collect(),
+            headers: headers.clone(),
+        });
+        headers
+    }
+}
+
+/// The methods announced in preflight responses if any method is allowed for
+/// any origin.
+pub(crate) const DEFAULT_METHODS: [Method; 7] = [
+    Method::Get, Method::Head, Method::Post, Method::Put, Method::Patch, Method::Delete, Method::Options,
+];
+
+/// The reason for rejecting a request with a malformed `Origin` header.
+pub(crate) const MALFORMED_ORIGIN: &str = "Malformed Origin header";
+
+/// The reason for rejecting a preflight for a method that is not allowed.
+pub(crate) const METHOD_NOT_ALLOWED: &str = "Method not allowed";
+
+/// The reason for rejecting a request that failed the CSRF check.
+const CSRF_CHECK_FAILED: &str = "CSRF check failed";
+
+/// The reason for rejecting a request to a host that is not allowed.
+const UNEXPECTED_HOST: &str = "Unexpected Host header";
+
+/// The request headers that are allowed in strict mode if no allowed headers
+/// are configured.
+pub(crate) const SAFELISTED_HEADERS: [&str; 4] = ["Accept", "Accept-Language", "Content-Language", "Content-Type"];
+
+/// The status code of successful preflight responses. Wrapped to default to
+/// `200 OK`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PreflightStatus(pub(crate) status::Status);
+
+impl Default for PreflightStatus {
+    fn default() -> Self {
+        PreflightStatus(status::Ok)
+    }
+}
+
+impl CorsPolicy {
+    /// Return a [`CorsBuilder`](struct.CorsBuilder.html) to configure a
+    /// policy step by step, finished with
+    /// [`build_policy`](struct.CorsBuilder.html#method.build_policy).
+    pub fn builder() -> CorsBuilder {
+        CorsBuilder::new()
+    }
+
+    /// Return whether any origin is allowed, with `*` or by reflecting the
+    /// origin.
+    pub fn is_allow_any(&self) -> bool {
+        matches!(self.allowed_origins, AllowedOrigins::Any | AllowedOrigins::Reflect)
+    }
+
+    /// Return the whitelisted origins, sorted, or `None` if the origins
+    /// aren't checked against a list, e.g. if any origin is allowed. The
+    /// origins of a shared whitelist or a whitelist file are returned as
+    /// they are at the time of the call.
+    pub fn allowed_origins(&self) -> Option<Vec<String>> {
+        match self.allowed_origins.describe() {
+            ("whitelist", origins) => origins,
+            _ => None,
+        }
+    }
+
+    /// Return whether credentials are allowed.
+    pub fn allow_credentials(&self) -> bool {
+        self.allow_credentials
+    }
+
+    /// Return the max-age of preflight results, if it is announced.
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    /// Return the allowed methods, or `None` if any requested method is
+    /// allowed.
+    pub fn allowed_methods(&self) -> Option<&[Method]> {
+        self.allowed_methods.as_deref()
+    }
+
+    /// Return the allowed request headers, or `None` if any requested
+    /// header is allowed.
+    pub fn allowed_headers(&self) -> Option<&[UniCase<String>]> {
+        self.allowed_headers.as_deref()
+    }
+
+    /// Return the response headers that are exposed to scripts.
+    pub fn expose_headers(&self) -> &[UniCase<String>] {
+        &self.expose_headers
+    }
+
+    /// Return whether requests from the specified origin are allowed.
+    pub fn is_origin_allowed(&self, origin: &headers::Origin) -> bool {
+        if is_null_origin(origin) {
+            return self.null_origin_policy == NullOriginPolicy::Allow;
+        }
+        if self.is_raw_origin(origin) {
+            return true;
+        }
+        if self.is_excepted(origin) {
+            return false;
+        }
+        if self.require_https_origins && !origin.scheme.eq_ignore_ascii_case("https") {
+            return false;
+        }
+        if !self.is_scheme_allowed(&origin.scheme) {
+            return false;
+        }
+        if self.allow_localhost && is_localhost(origin) {
+            return true;
+        }
+        self.is_allowed_by_list(origin)
+    }
+
+    /// Return whether origins with the scheme are acceptable. Schemes are
+    /// compared case-insensitively.
+    pub fn is_scheme_allowed(&self, scheme: &str) -> bool {
+        match self.allowed_schemes {
+            Some(ref allowed_schemes) => allowed_schemes.iter().any(|allowed| allowed.eq_ignore_ascii_case(scheme)),
+            None => true,
+        }
+    }
+
+    /// Return whether the origin is one of the raw origins, compared
+    /// exactly.
+    fn is_raw_origin(&self, origin: &headers::Origin) -> bool {
+        !self.raw_origins.is_empty()
+            && with_cors_origin(origin, |serialized| self.raw_origins.contains(serialized))
+    }
+
+    /// Return whether the origin is excluded from the allowed origins.
+    fn is_excepted(&self, origin: &headers::Origin) -> bool {
+        !self.except_origins.is_empty()
+            && with_canonical_origin(origin, |serialized| self.except_origins.contains(serialized))
+    }
+
+    /// Return the origin with its exact serialization if the value of the
+    /// `Origin` header is one of the raw origins.
+    pub(crate) fn raw_origin(&self, value: &str) -> Option<headers::Origin> {
+        if self.raw_origins.contains(value) {
+            parse_raw_origin(value)
+        } else {
+            None
+        }
+    }
+
+    /// Return whether the origin is allowed by the allowed origins, using
+    /// the decision cache for dynamic checks.
+    fn is_allowed_by_list(&self, origin: &headers::Origin) -> bool {
+        if !self.allowed_origins.is_dynamic() {
+            return self.allowed_origins.is_allowed(origin, self.ignore_port, &self.validator_settings);
+        }
+        // Fallback decisions of a failing validator are not cached
+        let decide = || self.allowed_origins.decide(origin, self.ignore_port, &self.validator_settings);
+        match self.decision_cache {
+            Some(ref cache) => with_canonical_origin(origin, |serialized| {
+                cache.get_or_insert_with(serialized, self.metrics.as_deref(), decide)
+            }),
+            None => decide().unwrap_or_else(|fallback| fallback),
+        }
+    }
+
+    /// Explain why requests from the origin are allowed or not.
+    pub fn explain(&self, origin: &headers::Origin) -> CorsTrace {
+        let mut trace = CorsTrace::default();
+        self.explain_origin(origin, &mut trace);
+        trace
+    }
+
+    /// Explain whether a preflight from the origin for the method and the
+    /// request headers is allowed, with the checks of the middleware.
+    ///
+    /// ```rust
+    /// extern crate iron;
+    /// extern crate iron_cors;
+    ///
+    /// use iron::headers::Origin;
+    /// use iron::method::Method;
+    /// use iron_cors::CorsPolicy;
+    ///
+    /// # fn main() {
+    /// let policy = CorsPolicy::builder()
+    ///     .allowed_origins(vec!["https://example.com".to_string()])
+    ///     .allowed_methods(vec![Method::Get, Method::Post])
+    ///     .build_policy()
+    ///     .unwrap();
+    /// let origin = Origin::new("https", "example.com", None);
+    /// assert!(policy.explain_preflight(&origin, Method::Post, &["Content-Type"]).is_allowed());
+    /// assert!(!policy.explain_preflight(&origin, Method::Delete, &[]).is_allowed());
+    /// # }
+    /// ```
+    pub fn explain_preflight(&self, origin: &headers::Origin, method: Method, request_headers: &[&str]) -> CorsTrace {
+        let acrm = headers::AccessControlRequestMethod(method);
+        let acrh = headers::AccessControlRequestHeaders(
+            request_headers.iter().map(|header| UniCase(header.to_string())).collect());
+        let acrh = if request_headers.is_empty() { None } else { Some(&acrh) };
+        self.trace_request(&Method::Options, origin, Some(&acrm), acrh)
+    }
+
+    /// Add the checks of `is_origin_allowed` to the trace.
+    fn explain_origin(&self, origin: &headers::Origin, trace: &mut CorsTrace) {
+        if is_null_origin(origin) {
+            let allowed = self.null_origin_policy == NullOriginPolicy::Allow;
+            trace.push("null_origin_policy", allowed, format!("the null origin is handled with {:?}", self.null_origin_policy));
+            return;
+        }
+        if self.is_raw_origin(origin) {
+            trace.push("allowed_raw_origins", true, format!("{} is in the raw origins", format_cors_origin(origin)));
+            return;
+        }
+        if self.is_excepted(origin) {
+            trace.push("except_origins", false, format!("{} is excluded", format_canonical_origin(origin)));
+            return;
+        }
+        if self.require_https_origins {
+            let https = origin.scheme.eq_ignore_ascii_case("https");
+            trace.push("require_https_origins", https, format!("the scheme of the origin is {}", origin.scheme));
+            if !https {
+                return;
+            }
+        }
+        if self.allowed_schemes.is_some() {
+            let allowed = self.is_scheme_allowed(&origin.scheme);
+            trace.push("allowed_schemes", allowed, format!("the scheme of the origin is {}", origin.scheme));
+            if !allowed {
+                return;
+            }
+        }
+        if self.allow_localhost && is_localhost(origin) {
+            trace.push("allow_localhost", true, "the origin is on the local machine");
+            return;
+        }
+        let allowed = self.is_allowed_by_list(origin);
+        trace.push("allowed_origins", allowed, self.allowed_origins.explain(origin, allowed));
+    }
+
+    /// Explain the classification of a request.
+    fn trace_request(&self,
+                     method: &Method,
+                     origin: &headers::Origin,
+                     acrm: Option<&headers::AccessControlRequestMethod>,
+                     acrh: Option<&headers::AccessControlRequestHeaders>) -> CorsTrace {
+        let mut trace = match self.unrestricted_method(method, acrm) {
+            Some(method) if !self.is_origin_allowed(origin) => {
+                let mut trace = CorsTrace::default();
+                trace.push("unrestricted_methods", true, format!("the origin is not checked for {}", method));
+                trace
+            },
+            _ => self.explain(origin),
+        };
+        if !trace.is_allowed() {
+            return trace;
+        }
+        match acrm {
+            Some(acrm) if *method == Method::Options => match self.validate_preflight(acrm, acrh) {
+                Ok(()) => trace.push("preflight", true, format!("the method {} and the requested headers are allowed", acrm.0)),
+                Err(reason) => trace.push("preflight", false, reason),
+            },
+            _ if self.enforce_allowed_methods => {
+                trace.push("enforce_allowed_methods", self.is_method_allowed(method), format!("the method is {}", method));
+            },
+            _ => {},
+        }
+        trace
+    }
+
+    /// Check the origin of the `Referer` header of a state-changing request
+    /// without an `Origin` header, and record the result.
+    pub(crate) fn check_referer(&self, req: &mut Request) {
+        if !self.referer_fallback || !is_state_changing(&req.method) {
+            return;
+        }
+        let origin = match referer_origin(req) {
+            Some(origin) => origin,
+            None => return,
+        };
+        let serialized = format_canonical_origin(&origin);
+        let allowed = self.own_origin(req) == serialized || self.is_origin_allowed(&origin);
+        req.extensions.insert::<RefererCheck>(RefererCheck { origin: serialized, allowed });
+    }
+
+    /// Return whether a state-changing request passes the CSRF check: Its
+    /// origin, or the origin of its referer if it has no `Origin` header, is
+    /// allowed or the origin of the server itself, or the CSRF validator
+    /// accepts it.
+    fn passes_csrf_check<A, V>(&self, method: &Method, allowed: A, validate: V) -> bool
+        where A: FnOnce() -> bool, V: FnOnce() -> bool
+    {
+        if self.csrf_validator.is_none() || self.report_only || !is_state_changing(method) {
+            return true;
+        }
+        allowed() || validate()
+    }
+
+    /// Return whether a state-changing request without an `Origin` header
+    /// passes the CSRF check, by the origin of its referer.
+    pub(crate) fn passes_referer_csrf_check(&self, req: &Request) -> bool {
+        self.passes_csrf_check(&req.method,
+                               || req.extensions.get::<RefererCheck>().is_some_and(|check| check.allowed),
+                               || req.passes_csrf_validator(self))
+    }
+
+    /// Reject a request without an `Origin` header that failed the CSRF
+    /// check.
+    pub(crate) fn reject_csrf(&self, req: &mut Request, origin: &str) -> Response {
+        self.log_csrf_denial(&req.method, origin);
+        req.extensions.insert::<CorsDecision>(CorsDecision::Denied(origin.to_string()));
+        req.extensions.insert::<DeniedBy>("csrf");
+        self.deny_response(req, origin, CSRF_CHECK_FAILED)
+    }
+
+    /// Reject a request to a host that is not one of the allowed hosts, or
+    /// only log it in report-only mode, in which case `None` is returned.
+    pub(crate) fn reject_unexpected_host(&self, req: &mut Request, host: &str) -> Option<Response> {
+        if self.report_only {
+            self.log_denial(LogEvent::ReportOnly, host,
+                            format_args!("Report-only mode: Allowing request with unexpected Host {}", host));
+            return None;
+        }
+        self.log_denial(LogEvent::UnexpectedHost, host,
+                        format_args!("Rejecting request with unexpected Host {}", host));
+        let origin = raw_origin_value(req).unwrap_or("").to_string();
+        let response = self.deny_response(req, &origin, UNEXPECTED_HOST);
+        req.extensions.insert::<CorsDecision>(CorsDecision::Denied(origin));
+        req.extensions.insert::<DeniedBy>("allowed_hosts");
+        Some(response)
+    }
+
+    fn log_csrf_denial(&self, method: &Method, origin: &str) {
+        self.log_denial(LogEvent::DisallowedOrigin, origin,
+                        format_args!("Got {} request that failed the CSRF check from {:?}", method, origin));
+    }
+
+    /// Return whether the request is a cross-site request with one of the
+    /// methods of `reject_cross_site`.
+    fn is_rejected_cross_site(&self, request: &CorsInput, fetch_site: Option<SecFetchSite>) -> bool {
+        if self.reject_cross_site.is_empty() || fetch_site != Some(SecFetchSite::CrossSite) {
+            return false;
+        }
+        let method = match request.acrm {
+            Some(acrm) if *request.method == Method::Options => &acrm.0,
+            _ => request.method,
+        };
+        self.reject_cross_site.contains(method)
+    }
+
+    /// Return whether the origin is the origin of the request itself, if
+    /// same-origin detection is enabled.
+    fn is_same_origin(&self, origin: &headers::Origin, context: &dyn RequestContext) -> bool {
+        if is_null_origin(origin) {
+            return false;
+        }
+        if self.trust_sec_fetch_site && context.fetch_site() == Some(SecFetchSite::SameOrigin) {
+            return true;
+        }
+        if !self.skip_same_origin {
+            return false;
+        }
+        let origin = format_canonical_origin(origin);
+        if self.self_origin.as_ref() == Some(&origin) {
+            return true;
+        }
+        context.own_origin(self).is_some_and(|own_origin| own_origin == origin)
+    }
+
+    /// Return whether the request arrived over TLS, at the server or at a
+    /// trusted proxy.
+    fn is_secure_request(&self, req: &Request) -> bool {
+        if req.url.scheme() == "https" {
+            return true;
+        }
+        self.trusted_proxies.contains(&req.remote_addr.ip())
+            && forwarded_origin(req).is_some_and(|origin| origin.starts_with("https://"))
+    }
+
+    /// Return the normalized origin of the server as seen by the client. If
+    /// the peer is a trusted proxy, its `X-Forwarded-Proto` and
+    /// `X-Forwarded-Host` headers take precedence over the request URL.
+    pub(crate) fn own_origin(&self, req: &Request) -> String {
+        if self.trusted_proxies.contains(&req.remote_addr.ip()) {
+            if let Some(origin) = forwarded_origin(req) {
+                return origin;
+            }
+        }
+        url_origin(req)
+    }
+
+    /// Return the method of the (preflighted) request if it is exempt from
+    /// the origin checks.
+    fn unrestricted_method<'m>(&self,
+                               method: &'m Method,
+                               acrm: Option<&'m headers::AccessControlRequestMethod>) -> Option<&'m Method> {
+        let method = match acrm {
+            Some(acrm) if *method == Method::Options => &acrm.0,
+            _ => method,
+        };
+        Some(method).filter(|method| self.unrestricted_methods.contains(method))
+    }
+
+    /// Return whether the (preflighted) request is only allowed because its
+    /// method is unrestricted. Credentials are never announced to such
+    /// requests.
+    pub(crate) fn is_only_unrestricted(&self,
+                                       method: &Method,
+                                       origin: &headers::Origin,
+                                       acrm: Option<&headers::AccessControlRequestMethod>) -> bool {
+        self.unrestricted_method(method, acrm).is_some() && !self.is_origin_allowed(origin)
+    }
+
+    /// Return whether cross-origin requests with the specified method are
+    /// allowed.
+    pub fn is_method_allowed(&self, method: &Method) -> bool {
+        match self.allowed_methods {
+            Some(ref allowed_methods) => allowed_methods.contains(method),
+            None => true,
+        }
+    }
+
+    /// Return whether cross-origin requests with the specified header are
+    /// allowed. Header names are compared case-insensitively.
+    pub fn is_header_allowed(&self, header: &str) -> bool {
+        match self.allowed_headers {
+            Some(ref allowed_headers) => allowed_headers.iter().any(|allowed| *allowed == UniCase(header)),
+            None => true,
+        }
+    }
+
+    /// Check the consistency of the policy.
+    pub(crate) fn check(&self) -> Result<(), CorsConfigError> {
+        if self.allow_credentials && self.allowed_origins.is_wildcard() {
+            return Err(CorsConfigError::CredentialsWithAnyOrigin);
+        }
+        Ok(())
+    }
+
+    /// Return warnings about dangerous or nonsensical settings of the
+    /// policy, e.g. to log them or to refuse to start on misconfiguration.
+    ///
+    /// ```rust
+    /// use iron_cors::{CorsConfigWarning, CorsPolicy};
+    ///
+    /// let policy = CorsPolicy::builder()
+    ///     .allowed_origins(vec!["example.com".to_string()])
+    ///     .build_policy()
+    ///     .unwrap();
+    /// for warning in policy.validate() {
+    ///     eprintln!("CORS configuration: {}", warning);
+    /// }
+    /// assert_eq!(policy.validate(), vec![CorsConfigWarning::OriginWithoutScheme("example.com".to_string())]);
+    /// ```
+    pub fn validate(&self) -> Vec<CorsConfigWarning> {
+        let mut warnings = vec![];
+        let any_origin = matches!(self.allowed_origins, AllowedOrigins::Any | AllowedOrigins::Reflect);
+        if self.allow_credentials && any_origin {
+            warnings.push(CorsConfigWarning::CredentialsWithAnyOrigin);
+        }
+        let origins = match self.allowed_origins {
+            AllowedOrigins::Whitelist(ref origins) | AllowedOrigins::Blacklist(ref origins) => Some(origins),
+            _ => None,
+        };
+        let mut without_scheme = origins.into_iter().flat_map(|origins| origins.iter())
+            .chain(&self.except_origins)
+            .filter(|origin| !origin.contains("://"))
+            .cloned()
+            .collect::<Vec<_>>();
+        without_scheme.sort();
+        warnings.extend(without_scheme.into_iter().map(CorsConfigWarning::OriginWithoutScheme));
+        if let Some(ref allowed_headers) = self.allowed_headers {
+            warnings.extend(allowed_headers.iter()
+                .filter(|header| is_forbidden_header(header))
+                .map(|header| CorsConfigWarning::ForbiddenHeader(header.to_string())));
+        }
+        if self.allow_credentials && self.max_age == Some(Duration::from_secs(0)) {
+            warnings.push(CorsConfigWarning::ZeroMaxAgeWithCredentials);
+        }
+        warnings
+    }
+
+    fn add_cors_header(&self, headers: &mut headers::Headers, origin: &headers::Origin) {
+        if self.allowed_origins.is_wildcard() {
+            headers.set(headers::AccessControlAllowOrigin::Any);
+        } else {
+            let header = format_cors_origin(origin);
+            headers.set(headers::AccessControlAllowOrigin::Value(header));
+
+            // The response depends on the origin, so caches must not share it between origins
+            add_vary_header(headers, &["Origin"]);
+        }
+        if self.allow_credentials {
+            headers.set(headers::AccessControlAllowCredentials);
+        }
+    }
+
+    /// Add the only CORS header that `XDomainRequest` understands.
+    fn add_xdomain_request_headers(&self, headers: &mut headers::Headers, origin: &headers::Origin) {
+        if self.allowed_origins.is_wildcard() {
+            headers.set(headers::AccessControlAllowOrigin::Any);
+        } else {
+            headers.set(headers::AccessControlAllowOrigin::Value(format_cors_origin(origin)));
+            add_vary_header(headers, &["Origin"]);
+        }
+    }
+
+    pub(crate) fn add_cors_actual_headers(&self, headers: &mut headers::Headers, origin: &headers::Origin) {
+        self.add_cors_header(headers, origin);
+
+        if !self.expose_headers.is_empty() {
+            headers.set(headers::AccessControlExposeHeaders(self.expose_headers.clone()));
+        }
+
+        if self.timing_allow_origin {
+            let value = if self.allowed_origins.is_wildcard() {
+                "*".to_string()
+            } else {
+                format_cors_origin(origin)
+            };
+            headers.set_raw("Timing-Allow-Origin", vec![value.into_bytes()]);
+        }
+
+        add_extra_headers(headers, &self.extra_actual_headers);
+    }
+
+    /// Add the CORS headers to the response of an actual request, according
+    /// to the conflict policy.
+    pub(crate) fn add_cors_response_headers(&self, headers: &mut headers::Headers, origin: &headers::Origin) {
+        if self.header_conflict_policy == HeaderConflictPolicy::Overwrite {
+            return self.add_cors_actual_headers(headers, origin);
+        }
+        let mut cors_headers = headers::Headers::new();
+        self.add_cors_actual_headers(&mut cors_headers, origin);
+        for header in cors_headers.iter() {
+            let name = header.name();
+            let existing = match headers.get_raw(name) {
+                Some(existing) => split_raw_list(existing),
+                None => {
+                    headers.set_raw(name.to_string(), vec![header.value_string().into_bytes()]);
+                    continue;
+                },
+            };
+            // The response must vary on everything the CORS headers depend on
+            let merge = name.eq_ignore_ascii_case("Vary") || (
+                self.header_conflict_policy == HeaderConflictPolicy::Merge
+                    && LIST_HEADERS.iter().any(|list_header| name.eq_ignore_ascii_case(list_header))
+            );
+            if merge {
+                let mut values = existing;
+                for value in split_raw_list(&[header.value_string().into_bytes()]) {
+                    if !values.iter().any(|existing| existing.eq_ignore_ascii_case(&value)) {
+                        values.push(value);
+                    }
+                }
+                headers.set_raw(name.to_string(), vec![values.join(", ").into_bytes()]);
+            }
+        }
+    }
+
+    /// Check whether a preflight request may be answered with CORS headers.
+    ///
+    /// On failure, the reason for the rejection is returned.
+    fn validate_preflight(&self,
+                          acrm: &headers::AccessControlRequestMethod,
+                          acrh: Option<&headers::AccessControlRequestHeaders>) -> Result<(), &'static str> {
+        if !self.is_method_allowed(&acrm.0) {
+            return Err(METHOD_NOT_ALLOWED);
+        }
+        // In strict mode, nothing is echoed without being configured
+        if self.strict && self.allowed_methods.is_none() && !DEFAULT_METHODS.contains(&acrm.0) {
+            return Err(METHOD_NOT_ALLOWED);
+        }
+        if let Some(acrh) = acrh {
+            // `UniCase` compares header names case-insensitively
+            let allowed = match self.allowed_headers {
+                Some(ref allowed_headers) => acrh.0.iter().all(|header| allowed_headers.contains(header)),
+                None if self.strict => acrh.0.iter().all(|header| {
+                    SAFELISTED_HEADERS.iter().any(|safelisted| header.eq_ignore_ascii_case(safelisted))
+                }),
+                None => true,
+            };
+            if !allowed {
+                return Err("Header not allowed");
+            }
+        }
+        Ok(())
+    }
+
+    fn add_cors_preflight_headers(&self,
+                                  headers: &mut headers::Headers,
+                                  origin: &headers::Origin,
+                                  acrm: &headers::AccessControlRequestMethod,
+                                  acrh: Option<&headers::AccessControlRequestHeaders>) {
+
+        self.add_cors_header(headers, origin);
+
+        match self.allowed_methods {
+            // Announce the full list of allowed methods
+            Some(ref allowed_methods) => {
+                headers.set(headers::AccessControlAllowMethods(allowed_methods.clone()));
+            },
+            None if self.announces_wildcard() => {
+                headers.set(headers::AccessControlAllowMethods(vec![Method::Extension("*".to_string())]));
+            },
+            // Copy the method requested by the browser into the allowed methods header
+            None => {
+                headers.set(headers::AccessControlAllowMethods(vec!(acrm.0.clone())));
+            },
+        }
+
+        match self.allowed_headers {
+            // Announce the full list of allowed headers
+            Some(ref allowed_headers) => {
+                headers.set(headers::AccessControlAllowHeaders(allowed_headers.clone()));
+            },
+            None if self.announces_wildcard() => {
+                headers.set(headers::AccessControlAllowHeaders(self.wildcard_headers()));
+            },
+            // Copy the headers requested by the browser into the allowed headers in the response,
+            // next to the default headers
+            None => {
+                let mut allowed_headers = self.default_headers.clone();
+                for header in acrh.iter().flat_map(|acrh| acrh.0.iter()) {
+                    if !allowed_headers.contains(header) {
+                        allowed_headers.push(header.clone());
+                    }
+                }
+                if !allowed_headers.is_empty() {
+                    headers.set(headers::AccessControlAllowHeaders(allowed_headers));
+                }
+            },
+        }
+
+        if let Some(max_age) = self.max_age_header() {
+            headers.set(max_age);
+        }
+
+        if !self.allowed_origins.is_wildcard() {
+            add_vary_header(headers, &[
+                "Access-Control-Request-Method",
+                "Access-Control-Request-Headers",
+            ]);
+        }
+    }
+
+    /// Whether unconfigured methods and headers are announced with the `*`
+    /// wildcard in preflight responses.
+    fn announces_wildcard(&self) -> bool {
+        self.header_policy == HeaderPolicy::Wildcard && !self.allow_credentials
+    }
+
+    /// Return the wildcard for `Access-Control-Allow-Headers`. Browsers
+    /// don't match `Authorization` against `*`, so it is listed explicitly.
+    fn wildcard_headers(&self) -> Vec<UniCase<String>> {
+        let mut headers = vec![UniCase("*".to_string())];
+        if !self.omit_wildcard_authorization {
+            headers.push(UniCase("Authorization".to_string()));
+        }
+        headers
+    }
+
+    fn max_age_header(&self) -> Option<headers::AccessControlMaxAge> {
+        self.max_age.map(max_age_header)
+    }
+
+    /// Return the serialized preflight response headers if they are the
+    /// same for all preflight requests with the method, i.e. if any origin
+    /// is allowed.
+    ///
+    /// Instead of echoing the request, a default list of methods, or the
+    /// `*` wildcard with the wildcard header policy, and the `*` wildcard
+    /// for headers are announced if nothing else is configured.
+    fn static_preflight_headers(&self, acrm: &headers::AccessControlRequestMethod) -> Option<&[RawHeader]> {
+        // Unusual methods are echoed instead of being added to the default list
+        if self.allowed_methods.is_none() && !self.announces_wildcard() && !DEFAULT_METHODS.contains(&acrm.0) {
+            return None;
+        }
+        // Strict mode doesn't announce the `*` wildcard for headers
+        if self.strict && self.allowed_headers.is_none() {
+            return None;
+        }
+        self.static_preflight_headers.get_or_init(|| {
+            if !self.allowed_origins.is_wildcard() {
+                return None;
+            }
+            let mut headers = headers::Headers::new();
+            headers.set(headers::AccessControlAllowOrigin::Any);
+            if self.allow_credentials {
+                headers.set(headers::AccessControlAllowCredentials);
+            }
+            let methods = match self.allowed_methods {
+                Some(ref allowed_methods) => allowed_methods.clone(),
+                None if self.announces_wildcard() => vec![Method::Extension("*".to_string())],
+                None => DEFAULT_METHODS.to_vec(),
+            };
+            headers.set(headers::AccessControlAllowMethods(methods));
+            let allowed_headers = self.allowed_headers.clone().unwrap_or_else(|| self.wildcard_headers());
+            headers.set(headers::AccessControlAllowHeaders(allowed_headers));
+            if let Some(max_age) = self.max_age_header() {
+                headers.set(max_age);
+            }
+            Some(to_raw_headers(&headers))
+        }).as_deref()
+    }
+
+    /// Classify a request with an `Origin` header according to the policy.
+    ///
+    /// This is independent of the web framework, the Iron handler and the
+    /// [`core`](core/index.html) module are adapters on top of it.
+    pub(crate) fn classify<'a>(&self,
+                               method: &Method,
+                               origin: &headers::Origin,
+                               acrm: Option<&'a headers::AccessControlRequestMethod>,
+                               acrh: Option<&'a headers::AccessControlRequestHeaders>) -> RequestKind<'a> {
+        if is_null_origin(origin) && self.null_origin_policy == NullOriginPolicy::PassThrough {
+            return RequestKind::NotCors;
+        }
+        let is_options = *method == Method::Options;
+
+        // Verify origin header, unless the method is exempt
+        if self.unrestricted_method(method, acrm).is_none() && !self.is_origin_allowed(origin) {
+            if is_options {
+                self.log_denial(LogEvent::DisallowedOrigin, &origin.host.hostname, format_args!("Got disallowed preflight CORS request from {}", &origin.host.hostname));
+            } else {
+                self.log_denial(LogEvent::DisallowedOrigin, &origin.host.hostname, format_args!("Got disallowed CORS request from {}", &origin.host.hostname));
+            }
+            return RequestKind::Disallowed;
+        }
+
+        // Check the Access-Control-Request-Method header of OPTIONS requests.
+        // Without it, treat the request as a possible OPTIONS CORS call.
+        match acrm {
+            Some(acrm) if is_options => match self.validate_preflight(acrm, acrh) {
+                Ok(()) => RequestKind::Preflight(acrm, acrh),
+                Err(METHOD_NOT_ALLOWED) if self.preflight_method_not_allowed => {
+                    self.log_denial(LogEvent::InvalidPreflight, &origin.host.hostname,
+                                    format_args!("Got preflight CORS request from {} for disallowed method {}", &origin.host.hostname, acrm.0));
+                    RequestKind::MethodNotAllowed
+                },
+                Err(reason) => {
+                    self.log_denial(LogEvent::InvalidPreflight, &origin.host.hostname,
+                                    format_args!("Got invalid preflight CORS request from {}: {}", &origin.host.hostname, reason));
+                    RequestKind::InvalidPreflight(reason)
+                },
+            },
+            _ if self.enforce_allowed_methods && !self.is_method_allowed(method) => {
+                self.log_denial(LogEvent::MethodNotAllowed, &origin.host.hostname,
+                                format_args!("Got CORS request from {} with disallowed method {}", &origin.host.hostname, method));
+                RequestKind::MethodNotAllowed
+            },
+            _ => RequestKind::Actual,
+        }
+    }
+
+    /// Log a rejected request with the configured level and limit. With the
+    /// `tracing` feature, the message is emitted as a `tracing` event.
+    pub(crate) fn log_denial(&self, event: LogEvent, key: &str, message: fmt::Arguments) {
+        let level = match self.log_levels.get(event) {
+            Some(level) if logging::enabled(level) => level,
+            _ => return,
+        };
+        match self.log_limiter {
+            Some(ref limiter) => {
+                limiter.lock().unwrap_or_else(PoisonError::into_inner).log(level, key, message);
+            },
+            None => logging::emit(level, message),
+        }
+    }
+
+    /// Return the name of the rule that rejects a request, with the names
+    /// of a `CorsTrace`.
+    fn denied_by(&self, kind: RequestKind, origin: &headers::Origin) -> Option<&'static str> {
+        match kind {
+            RequestKind::Disallowed if is_null_origin(origin) => Some("null_origin_policy"),
+            RequestKind::Disallowed if self.is_excepted(origin) => Some("except_origins"),
+            RequestKind::Disallowed if self.require_https_origins && !origin.scheme.eq_ignore_ascii_case("https") => {
+                Some("require_https_origins")
+            },
+            RequestKind::Disallowed if !self.is_scheme_allowed(&origin.scheme) => Some("allowed_schemes"),
+            RequestKind::Disallowed => Some("allowed_origins"),
+            RequestKind::InvalidPreflight(_) => Some("preflight"),
+            RequestKind::MethodNotAllowed if self.enforce_allowed_methods => Some("enforce_allowed_methods"),
+            RequestKind::MethodNotAllowed => Some("preflight"),
+            RequestKind::NotCors | RequestKind::Preflight(..) | RequestKind::Actual => None,
+        }
+    }
+
+    /// Return the value of the `Allow` header of `405 Method Not Allowed`
+    /// responses. In strict mode, the default methods are listed if no
+    /// methods are configured.
+    pub(crate) fn allow_header(&self) -> headers::Allow {
+        match self.allowed_methods {
+            Some(ref allowed_methods) => headers::Allow(allowed_methods.clone()),
+            None if self.strict => headers::Allow(DEFAULT_METHODS.to_vec()),
+            None => headers::Allow(vec![]),
+        }
+    }
+
+    /// The `405 Method Not Allowed` response to a request with a method that
+    /// is not allowed.
+    pub(crate) fn method_not_allowed_response(&self) -> Response {
+        let mut response = Response::with((status::MethodNotAllowed, rejection_body(METHOD_NOT_ALLOWED)));
+        response.headers.set(self.allow_header());
+        response
+    }
+
+    /// The `Allow` header of the response to an `OPTIONS *` request, which
+    /// lists the default methods if no methods are configured.
+    #[cfg(feature = "core")]
+    pub(crate) fn asterisk_allow_header(&self) -> headers::Allow {
+        headers::Allow(self.allowed_methods.clone().unwrap_or_else(|| DEFAULT_METHODS.to_vec()))
+    }
+
+    /// Add the headers of the response to a valid preflight request.
+    pub(crate) fn add_preflight_response_headers(&self,
+                                                 headers: &mut headers::Headers,
+                                                 origin: &headers::Origin,
+                                                 acrm: &headers::AccessControlRequestMethod,
+                                                 acrh: Option<&headers::AccessControlRequestHeaders>) {
+        match (self.static_preflight_headers(acrm), self.preflight_cache.as_ref()) {
+            (Some(static_headers), _) => set_raw_headers(headers, static_headers),
+            (None, Some(cache)) => {
+                let request_headers = acrh.map_or(&[][..], |acrh| &acrh.0[..]);
+                // The headers are built under the lock, so that concurrent
+                // preflights don't build the same entry
+                let raw_headers = with_canonical_origin(origin, |serialized| {
+                    cache.lock().unwrap_or_else(PoisonError::into_inner)
+                        .get_or_insert_with(serialized, &acrm.0, request_headers, self.metrics.as_deref(), || {
+                            let mut preflight_headers = headers::Headers::new();
+                            self.add_cors_preflight_headers(&mut preflight_headers, origin, acrm, acrh);
+                            to_raw_headers(&preflight_headers)
+                        })
+                });
+                set_raw_headers(headers, &raw_headers);
+            },
+            (None, None) => self.add_cors_preflight_headers(headers, origin, acrm, acrh),
+        }
+        if let Some(ref cache_control) = self.preflight_cache_control {
+            headers.set_raw("Cache-Control", vec![cache_control.clone().into_bytes()]);
+        }
+        add_extra_headers(headers, &self.extra_preflight_headers);
+    }
+
+    /// Return whether the policy adds headers to all responses, with or
+    /// without CORS.
+    pub(crate) fn has_isolation_headers(&self) -> bool {
+        self.resource_policy.is_some() || self.opener_policy.is_some() || self.embedder_policy.is_some()
+    }
+
+    /// Add the cross-origin isolation headers that the response doesn't
+    /// have yet, as the handler may set them for single resources. The
+    /// opener and embedder policies only apply to documents, so preflight
+    /// responses don't get them.
+    pub(crate) fn add_isolation_headers(&self, headers: &mut headers::Headers, preflight: bool) {
+        let mut add = |name: &str, value: Option<&'static str>| {
+            if let Some(value) = value {
+                if headers.get_raw(name).is_none() {
+                    headers.set_raw(name.to_string(), vec![value.as_bytes().to_vec()]);
+                }
+            }
+        };
+        add("Cross-Origin-Resource-Policy", self.resource_policy.map(|policy| policy.as_str()));
+        if !preflight {
+            add("Cross-Origin-Opener-Policy", self.opener_policy.map(|policy| policy.as_str()));
+            add("Cross-Origin-Embedder-Policy", self.embedder_policy.map(|policy| policy.as_str()));
+        }
+    }
+
+    /// Add the CORS headers to the response of an error of the inner
+    /// handler, if the error response policy applies to its status.
+    fn add_cors_error_headers(&self, err: &mut IronError, origin: &headers::Origin) {
+        if self.error_response_policy.applies_to(err.response.status) {
+            self.add_cors_response_headers(&mut err.response.headers, origin);
+        }
+    }
+
+    /// Decide how to handle a request with an `Origin` header.
+    ///
+    /// This is independent of the web framework, the Iron handler and the
+    /// [`core`](core/index.html) module turn the decision into a response.
+    pub(crate) fn decide<'a>(&self, request: &CorsInput<'a>, context: &dyn RequestContext) -> Decision<'a> {
+        let origin = request.origin;
+        let mut decision = Decision { outcome: Outcome::NotCors, denied_by: None, without_credentials: false };
+        if self.is_same_origin(origin, context) {
+            decision.outcome = Outcome::SameOrigin;
+            return decision;
+        }
+        if self.tls_requirement != TlsRequirement::None && !context.is_secure(self) {
+            if self.tls_requirement == TlsRequirement::Credentials {
+                // Credentials are not allowed without TLS
+                decision.without_credentials = true;
+            } else if self.report_only {
+                self.log_denial(LogEvent::ReportOnly, &origin.host.hostname,
+                                format_args!("Report-only mode: Allowing CORS request from {} without TLS",
+                                             format_cors_origin(origin)));
+                decision.denied_by = Some("require_tls");
+            } else {
+                self.log_denial(LogEvent::DisallowedOrigin, &origin.host.hostname,
+                                format_args!("Got CORS request from {} without TLS", format_cors_origin(origin)));
+                decision.denied_by = Some("require_tls");
+                decision.outcome = self.disallowed_outcome(request);
+                return decision;
+            }
+        }
+        if !self.report_only && self.is_rejected_cross_site(request, context.fetch_site()) {
+            self.log_denial(LogEvent::DisallowedOrigin, &origin.host.hostname,
+                            format_args!("Got cross-site {} request from {}", request.method, format_cors_origin(origin)));
+            decision.denied_by = Some("reject_cross_site");
+            decision.outcome = Outcome::Deny("Cross-site request not allowed");
+            return decision;
+        }
+        let is_own_origin = || context.own_origin(self).is_some_and(|own_origin| own_origin == format_canonical_origin(origin));
+        if !self.passes_csrf_check(request.method,
+                                   || is_own_origin() || self.is_origin_allowed(origin),
+                                   || context.passes_csrf_validator(self)) {
+            self.log_csrf_denial(request.method, &format_cors_origin(origin));
+            decision.denied_by = Some("csrf");
+            decision.outcome = Outcome::Deny(CSRF_CHECK_FAILED);
+            return decision;
+        }
+
+        let kind = self.classify(request.method, origin, request.acrm, request.acrh);
+        if let Some(rule) = self.denied_by(kind, origin) {
+            decision.denied_by = Some(rule);
+        }
+        let allowed = matches!(kind, RequestKind::Preflight(..) | RequestKind::Actual);
+        if allowed && self.allow_credentials && self.is_only_unrestricted(request.method, origin, request.acrm) {
+            decision.without_credentials = true;
+        }
+        decision.outcome = match kind {
+            RequestKind::NotCors => Outcome::NotCors,
+            RequestKind::Disallowed | RequestKind::InvalidPreflight(_) | RequestKind::MethodNotAllowed if self.report_only => {
+                self.log_denial(LogEvent::ReportOnly, &origin.host.hostname,
+                                format_args!("Report-only mode: Allowing CORS request from {} that would have been rejected", &origin.host.hostname));
+                match request.acrm {
+                    Some(acrm) if request.is_preflight() => Outcome::ReportOnlyPreflight(acrm, request.acrh),
+                    _ => Outcome::ReportOnly,
+                }
+            },
+            RequestKind::Disallowed => self.disallowed_outcome(request),
+            RequestKind::InvalidPreflight(_) if self.answers_invalid_preflights_without_headers() => Outcome::EmptyPreflight,
+            RequestKind::InvalidPreflight(reason) => Outcome::Deny(reason),
+            RequestKind::MethodNotAllowed => Outcome::MethodNotAllowed,
+            RequestKind::Preflight(acrm, acrh) => Outcome::Preflight(acrm, acrh),
+            RequestKind::Actual => Outcome::Actual,
+        };
+        decision
+    }
+
+    fn disallowed_outcome<'a>(&self, request: &CorsInput) -> Outcome<'a> {
+        match self.disallowed_origin_behavior {
+            DisallowedOriginBehavior::Reject => Outcome::Deny("Origin not allowed"),
+            // Let the browser block the response due to the missing CORS headers
+            DisallowedOriginBehavior::PassThrough => Outcome::PassThrough,
+            DisallowedOriginBehavior::SpecCompliant if request.is_preflight() => Outcome::EmptyPreflight,
+            DisallowedOriginBehavior::SpecCompliant => Outcome::PassThrough,
+        }
+    }
+
+    /// Process a request with an `Origin` header in Iron.
+    pub(crate) fn process(&self,
+                          handler: &dyn Handler,
+                          req: &mut Request,
+                          origin: headers::Origin) -> IronResult<Response> {
+        let request = CorsInput { method: &req.method, origin: &origin, acrm: req.headers.get(), acrh: req.headers.get() };
+        let decision = self.decide(&request, &*req);
+        if let Some(rule) = decision.denied_by {
+            req.extensions.insert::<DeniedBy>(rule);
+        }
+        if self.trace_decisions && !matches!(decision.outcome, Outcome::SameOrigin) {
+            let trace = self.trace_request(request.method, &origin, request.acrm, request.acrh);
+            debug!("CORS request from {}: {}", format_cors_origin(&origin), trace);
+            req.extensions.insert::<CorsTrace>(trace);
+        }
+        let without_credentials = decision.without_credentials;
+        let result = match decision.outcome {
+            Outcome::NotCors => {
+                req.extensions.insert::<CorsDecision>(CorsDecision::NotCors);
+                handler.handle(req)
+            },
+            Outcome::SameOrigin => {
+                req.extensions.insert::<CorsDecision>(CorsDecision::SameOrigin);
+                handler.handle(req)
+            },
+            Outcome::Deny(reason) => {
+                req.extensions.insert::<CorsDecision>(CorsDecision::Denied(format_cors_origin(&origin)));
+                Ok(self.deny_response(req, &format_cors_origin(&origin), reason))
+            },
+            Outcome::MethodNotAllowed => {
+                req.extensions.insert::<CorsDecision>(CorsDecision::Denied(format_cors_origin(&origin)));
+                Ok(self.method_not_allowed_response())
+            },
+            Outcome::EmptyPreflight => {
+                req.extensions.insert::<CorsDecision>(CorsDecision::Denied(format_cors_origin(&origin)));
+                Ok(self.empty_preflight_response())
+            },
+            Outcome::PassThrough => {
+                req.extensions.insert::<CorsDecision>(CorsDecision::Denied(format_cors_origin(&origin)));
+                self.handle_without_credentials(handler, req)
+            },
+            Outcome::ReportOnlyPreflight(acrm, acrh) => {
+                let mut response = self.empty_preflight_response();
+                self.add_report_only_preflight_headers(&mut response.headers, &origin, acrm, acrh);
+                req.extensions.insert::<CorsDecision>(CorsDecision::Denied(format_cors_origin(&origin)));
+                Ok(response)
+            },
+            Outcome::ReportOnly => {
+                req.extensions.insert::<CorsDecision>(CorsDecision::Denied(format_cors_origin(&origin)));
+                handler.handle(req)
+                    .map(|mut res| { self.add_cors_response_headers(&mut res.headers, &origin); res })
+                    .map_err(|mut err| { self.add_cors_error_headers(&mut err, &origin); err })
+            },
+            Outcome::Preflight(acrm, acrh) => {
+                let mut response = self.empty_preflight_response();
+                self.add_preflight_response_headers(&mut response.headers, &origin, acrm, acrh);
+                req.extensions.insert::<CorsDecision>(CorsDecision::Preflight(format_cors_origin(&origin)));
+
+                // In case of preflight, return an empty body after adding the preflight headers
+                Ok(response)
+            },
+            Outcome::Actual if self.xdomain_request_compat && is_xdomain_request(req) => {
+                req.extensions.insert::<CorsDecision>(CorsDecision::Allowed(format_cors_origin(&origin)));
+                handler.handle(req)
+                    .map(|mut res| { self.add_xdomain_request_headers(&mut res.headers, &origin); res })
+                    .map_err(|mut err| {
+                        if self.error_response_policy.applies_to(err.response.status) {
+                            self.add_xdomain_request_headers(&mut err.response.headers, &origin);
+                        }
+                        err
+                    })
+            },
+            Outcome::Actual => {
+                // Everything OK, process request and add CORS header to response
+                req.extensions.insert::<CorsDecision>(CorsDecision::Allowed(format_cors_origin(&origin)));
+                handler.handle(req)
+                    .map(|mut res| { self.add_cors_response_headers(&mut res.headers, &origin); res })
+                    .map_err(|mut err| { self.add_cors_error_headers(&mut err, &origin); err })
+            },
+        };
+        if !without_credentials {
+            return result;
+        }
+        result
+            .map(|mut res| { res.headers.remove::<headers::AccessControlAllowCredentials>(); res })
+            .map_err(|mut err| { err.response.headers.remove::<headers::AccessControlAllowCredentials>(); err })
+    }
+
+    /// Handle a request whose `Origin` header can't be parsed.
+    pub(crate) fn process_malformed_origin(&self,
+                                           handler: &dyn Handler,
+                                           req: &mut Request,
+                                           value: &str) -> IronResult<Response> {
+        match self.malformed_origin_policy {
+            MalformedOriginPolicy::Ignore => {
+                debug!("Ignoring malformed Origin header {:?}", value);
+                req.extensions.insert::<CorsDecision>(CorsDecision::NotCors);
+                handler.handle(req)
+            },
+            MalformedOriginPolicy::Reject => {
+                self.log_denial(LogEvent::MalformedOrigin, value,
+                                format_args!("Got CORS request with malformed Origin header {:?}", value));
+                req.extensions.insert::<CorsDecision>(CorsDecision::Denied(value.to_string()));
+                req.extensions.insert::<DeniedBy>("malformed_origin_policy");
+                Ok(self.deny_response(req, value, MALFORMED_ORIGIN))
+            },
+        }
+    }
+
+    /// Handle a request with more than one `Origin` header line.
+    pub(crate) fn process_multiple_origins(&self, handler: &dyn Handler, req: &mut Request) -> IronResult<Response> {
+        if !self.reject_multiple_origins {
+            req.extensions.insert::<CorsDecision>(CorsDecision::NotCors);
+            return handler.handle(req);
+        }
+        let values = req.headers.get_raw("Origin").unwrap_or_default().iter()
+            .map(|value| String::from_utf8_lossy(value).into_owned())
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.log_denial(LogEvent::MultipleOrigins, &values,
+                        format_args!("Got CORS request with multiple Origin headers: {}", values));
+        let response = self.deny_response(req, &values, "Multiple Origin headers");
+        req.extensions.insert::<CorsDecision>(CorsDecision::Denied(values));
+        req.extensions.insert::<DeniedBy>("reject_multiple_origins");
+        Ok(response)
+    }
+
+    /// Add the headers of the response to a preflight that the policy
+    /// rejects in report-only mode, which echo the requested method and
+    /// headers, whatever is configured.
+    pub(crate) fn add_report_only_preflight_headers(&self,
+                                                    headers: &mut headers::Headers,
+                                                    origin: &headers::Origin,
+                                                    acrm: &headers::AccessControlRequestMethod,
+                                                    acrh: Option<&headers::AccessControlRequestHeaders>) {
+        self.add_cors_header(headers, origin);
+        headers.set(headers::AccessControlAllowMethods(vec![acrm.0.clone()]));
+        if let Some(acrh) = acrh {
+            headers.set(headers::AccessControlAllowHeaders(acrh.0.clone()));
+        }
+        // The response depends on the requested method and headers
+        add_vary_header(headers, &["Access-Control-Request-Method", "Access-Control-Request-Headers"]);
+    }
+
+    /// Call the inner handler for a request from a disallowed origin,
+    /// without its credentials if they are stripped.
+    fn handle_without_credentials(&self, handler: &dyn Handler, req: &mut Request) -> IronResult<Response> {
+        if self.strip_credentials {
+            req.headers.remove::<headers::Cookie>();
+            req.headers.remove::<headers::Authorization<String>>();
+        }
+        handler.handle(req)
+    }
+
+    /// The response of the deny responder to a rejected request.
+    pub(crate) fn deny_response(&self, req: &Request, origin: &str, reason: &str) -> Response {
+        self.deny_responder.0.respond(req, &CorsRejection { origin, reason })
+    }
+
+    /// Whether invalid preflights get a successful response without CORS
+    /// headers instead of `400 Bad Request`.
+    pub(crate) fn answers_invalid_preflights_without_headers(&self) -> bool {
+        self.strict || self.disallowed_origin_behavior == DisallowedOriginBehavior::SpecCompliant
+    }
+
+    /// A preflight response without CORS headers, which the browser treats
+    /// as a failed preflight.
+    pub(crate) fn empty_preflight_response(&self) -> Response {
+        let mut response = Response::with(self.preflight_status.0);
+        response.headers.set(headers::ContentLength(0));
+        response
+    }
+}
+
+/// The name of the rule that rejected the request, for `CorsEvent`s.
+pub(crate) struct DeniedBy;
+
+impl typemap::Key for DeniedBy {
+    type Value = &'static str;
+}
+
+/// The parts of a request with an `Origin` header that the CORS headers
+/// depend on.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CorsInput<'a> {
+    pub(crate) method: &'a Method,
+    pub(crate) origin: &'a headers::Origin,
+    pub(crate) acrm: Option<&'a headers::AccessControlRequestMethod>,
+    pub(crate) acrh: Option<&'a headers::AccessControlRequestHeaders>,
+}
+
+impl<'a> CorsInput<'a> {
+    fn is_preflight(&self) -> bool {
+        *self.method == Method::Options && self.acrm.is_some()
+    }
+}
+
+/// The rest of a request that the decision depends on, which each adapter
+/// gets from its own kind of request.
+pub(crate) trait RequestContext {
+    /// Return whether the request arrived over TLS, at the server or at a
+    /// trusted proxy.
+    fn is_secure(&self, policy: &CorsPolicy) -> bool;
+    /// Return the `Sec-Fetch-Site` of the request.
+    fn fetch_site(&self) -> Option<SecFetchSite>;
+    /// Return the normalized origin of the server as seen by the client.
+    fn own_origin(&self, policy: &CorsPolicy) -> Option<String>;
+    /// Return whether the CSRF validator of the policy accepts the request.
+    fn passes_csrf_validator(&self, policy: &CorsPolicy) -> bool;
+}
+
+impl<'a, 'b> RequestContext for Request<'a, 'b> {
+    fn is_secure(&self, policy: &CorsPolicy) -> bool {
+        policy.is_secure_request(self)
+    }
+
+    fn fetch_site(&self) -> Option<SecFetchSite> {
+        fetch_site(self)
+    }
+
+    fn own_origin(&self, policy: &CorsPolicy) -> Option<String> {
+        Some(policy.own_origin(self))
+    }
+
+    fn passes_csrf_validator(&self, policy: &CorsPolicy) -> bool {
+        policy.csrf_validator.as_ref().is_none_or(|validator| validator.0.validate(self))
+    }
+}
+
+/// The decision of a policy about a request with an `Origin` header.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Decision<'a> {
+    /// How to respond to the request.
+    pub(crate) outcome: Outcome<'a>,
+    /// The name of the rule that rejects the request, also in report-only
+    /// mode.
+    pub(crate) denied_by: Option<&'static str>,
+    /// Whether the `Access-Control-Allow-Credentials` header is removed
+    /// from the response, as the request didn't arrive over TLS.
+    pub(crate) without_credentials: bool,
+}
+
+/// How to respond to a request with an `Origin` header.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Outcome<'a> {
+    /// Handle the request as if it had no `Origin` header.
+    NotCors,
+    /// Handle a request from the origin of the server itself as usual.
+    SameOrigin,
+    /// Reject the request with the deny responder, for the reason.
+    Deny(&'static str),
+    /// Reject an actual request with `405 Method Not Allowed`.
+    MethodNotAllowed,
+    /// Answer a preflight without CORS headers.
+    EmptyPreflight,
+    /// Handle a request from a disallowed origin without CORS headers.
+    PassThrough,
+    /// Answer a rejected preflight in report-only mode.
+    ReportOnlyPreflight(&'a headers::AccessControlRequestMethod, Option<&'a headers::AccessControlRequestHeaders>),
+    /// Handle a rejected actual request in report-only mode with CORS
+    /// headers.
+    ReportOnly,
+    /// Answer a valid preflight with CORS headers.
+    Preflight(&'a headers::AccessControlRequestMethod, Option<&'a headers::AccessControlRequestHeaders>),
+    /// Handle an allowed actual request with CORS headers.
+    Actual,
+}
+
+/// How a request with an `Origin` header is handled.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum RequestKind<'a> {
+    /// The request is handled as if it had no `Origin` header.
+    NotCors,
+    /// The origin is not allowed.
+    Disallowed,
+    /// A preflight request for a method or headers that are not allowed.
+    InvalidPreflight(&'static str),
+    /// An actual request with a method that is not allowed.
+    MethodNotAllowed,
+    /// A valid preflight request.
+    Preflight(&'a headers::AccessControlRequestMethod, Option<&'a headers::AccessControlRequestHeaders>),
+    /// An actual request from an allowed origin.
+    Actual,
+}
+
+/// Summarize the effective policy in one line, e.g. for startup logs.
+impl fmt::Display for CorsPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let list = |items: Vec<String>| if items.is_empty() { "none".to_string() } else { items.join(", ") };
+        match self.allowed_origins.describe() {
+            (mode, Some(origins)) => write!(f, "origins: {} ({})", mode, list(origins))?,
+            (mode, None) => write!(f, "origins: {}", mode)?,
+        }
+        match self.allowed_methods {
+            Some(ref methods) => write!(f, "; methods: {}", list(methods.iter().map(ToString::to_string).collect()))?,
+            None => write!(f, "; methods: any")?,
+        }
+        match self.allowed_headers {
+            Some(ref headers) => write!(f, "; headers: {}", list(headers.iter().map(ToString::to_string).collect()))?,
+            None => write!(f, "; headers: any")?,
+        }
+        if !self.expose_headers.is_empty() {
+            write!(f, "; exposed headers: {}", list(self.expose_headers.iter().map(ToString::to_string).collect()))?;
+        }
+        write!(f, "; credentials: {}", if self.allow_credentials { "allowed" } else { "not allowed" })?;
+        match self.max_age {
+            Some(max_age) => write!(f, "; max-age: {}s", max_age.as_secs()),
+            None => write!(f, "; max-age: none"),
+        }
+    }
+}
+
+/// Return whether the serialized origin is in the list.
+///
+/// The default port of the scheme is equivalent to no port, so
+/// `https://example.com` and `https://example.com:443` match each other.
+///
+/// The entries of the list must be normalized with `normalize_origin`.
+fn whitelist_contains(whitelist: &HashSet<String>, origin: &headers::Origin, ignore_port: bool) -> bool {
+    with_canonical_origin(origin, |origin| {
+        if !ignore_port {
+            return whitelist.contains(origin);
+        }
+        let origin = strip_port(origin);
+        whitelist.iter().any(|entry| strip_port(entry) == origin)
+    })
+}
+
+fn store_contains(store: &dyn OriginStore, origin: &headers::Origin, ignore_port: bool) -> bool {
+    with_canonical_origin(origin, |origin| {
+        if !ignore_port {
+            return store.contains(origin);
+        }
+        store.contains_ignoring_port(strip_port(origin))
+    })
+}
+
+/// Add the specified header names to the `Vary` header, keeping any values
+/// that are already present.
+fn add_vary_header(headers: &mut headers::Headers, names: &[&str]) {
+    let mut items = match headers.get::<headers::Vary>() {
+        // The response already varies on everything
+        Some(headers::Vary::Any) => return,
+        Some(headers::Vary::Items(items)) => items.clone(),
+        None => vec![],
+    };
+    for name in names {
+        let name = UniCase(name.to_string());
+        if !items.contains(&name) {
+            items.push(name);
+        }
+    }
+    headers.set(headers::Vary::Items(items));
+}
+
+/// The response headers whose values are comma separated lists.
+const LIST_HEADERS: [&str; 2] = ["Access-Control-Expose-Headers", "Timing-Allow-Origin"];
+
+/// Split the raw values of a list header into the list items.
+fn split_raw_list(values: &[Vec<u8>]) -> Vec<String> {
+    values.iter()
+        .flat_map(|value| String::from_utf8_lossy(value).split(',').map(|item| item.trim().to_string()).collect::<Vec<_>>())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Serialize the headers.
+fn to_raw_headers(headers: &headers::Headers) -> Vec<RawHeader> {
+    headers.iter().map(|header| (header.name().to_string(), header.value_string().into_bytes())).collect()
+}
+
+/// Set the serialized headers, replacing headers with the same name.
+fn set_raw_headers(headers: &mut headers::Headers, raw_headers: &[RawHeader]) {
+    for (name, value) in raw_headers {
+        headers.set_raw(name.clone(), vec![value.clone()]);
+    }
+}
+
+/// Add the raw headers, replacing headers with the same name.
+fn add_extra_headers(headers: &mut headers::Headers, extra_headers: &[(String, String)]) {
+    for (name, value) in extra_headers {
+        headers.set_raw(name.clone(), vec![value.clone().into_bytes()]);
+    }
+}
+
+/// Return whether browsers refuse to let scripts set the request header, as
+/// listed in the fetch standard.
+fn is_forbidden_header(header: &str) -> bool {
+    const FORBIDDEN_HEADERS: [&str; 21] = [
+        "accept-charset", "accept-encoding", "access-control-request-headers", "access-control-request-method",
+        "connection", "content-length", "cookie", "cookie2", "date", "dnt", "expect", "host", "keep-alive",
+        "origin", "referer", "set-cookie", "te", "trailer", "transfer-encoding", "upgrade", "via",
+    ];
+    let header = header.to_ascii_lowercase();
+    FORBIDDEN_HEADERS.contains(&header.as_str()) || header.starts_with("proxy-") || header.starts_with("sec-")
+}
+
+/// Return whether the host of the origin is the local machine.
+fn is_localhost(origin: &headers::Origin) -> bool {
+    let host = origin.host.hostname.to_ascii_lowercase();
+    matches!(host.as_str(), "localhost" | "127.0.0.1" | "[::1]" | "::1")
+}
@@ -0,0 +1,287 @@
+//! Selection of the policy per request.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use iron::Request;
+use iron::url::Host;
+
+use CorsPolicy;
+
+/// Select the CORS policy for a request, e.g. by the `Host` header, the path
+/// or an authentication token of the request.
+///
+/// If no policy is returned, the request is passed through without CORS
+/// processing.
+///
+/// ```rust
+/// extern crate iron;
+/// extern crate iron_cors;
+///
+/// use std::collections::HashMap;
+/// use iron::Request;
+/// use iron::headers::Host;
+/// use iron_cors::{CorsMiddleware, CorsPolicy, PolicyProvider};
+///
+/// /// One policy per tenant, selected by the host name.
+/// struct Tenants(HashMap<String, CorsPolicy>);
+///
+/// impl PolicyProvider for Tenants {
+///     fn policy_for(&self, req: &Request) -> Option<&CorsPolicy> {
+///         let host = req.headers.get::<Host>()?;
+///         self.0.get(&host.hostname)
+///     }
+/// }
+///
+/// # fn main() {
+/// let mut tenants = HashMap::new();
+/// tenants.insert("a.example.com".to_string(), CorsPolicy::builder()
+///     .allowed_origins(vec!["https://app.a.example.com".to_string()])
+///     .build_policy()
+///     .unwrap());
+/// let middleware = CorsMiddleware::with_policy_provider(Tenants(tenants));
+/// # }
+/// ```
+pub trait PolicyProvider: Send + Sync {
+    /// Return the policy for the request, or `None` to skip CORS processing.
+    fn policy_for(&self, req: &Request) -> Option<&CorsPolicy>;
+}
+
+impl PolicyProvider for CorsPolicy {
+    fn policy_for(&self, _: &Request) -> Option<&CorsPolicy> {
+        Some(self)
+    }
+}
+
+/// A function that returns the name of the policy for a request.
+type Selector = dyn for<'r, 'a, 'b> Fn(&'r Request<'a, 'b>) -> Option<&'r str> + Send + Sync;
+
+/// Policies that are registered under names, e.g. `public`, `partner` and
+/// `internal`, and a selector that maps requests to the name of a policy.
+///
+/// Requests for which the selector returns no name, or the name of a policy
+/// that isn't registered, are passed through without CORS processing.
+///
+/// ```rust
+/// extern crate iron;
+/// extern crate iron_cors;
+///
+/// use iron::Request;
+/// use iron_cors::{CorsMiddleware, CorsPolicy, CorsPolicySet};
+///
+/// # fn main() {
+/// let policies = CorsPolicySet::new(|req: &Request| {
+///     match req.url.path().first().cloned() {
+///         Some("partner") => Some("partner"),
+///         Some("internal") => None,
+///         _ => Some("public"),
+///     }
+/// })
+///     .policy("public", CorsPolicy::default())
+///     .policy("partner", CorsPolicy::builder()
+///         .allowed_origins(vec!["https://partner.example.com".to_string()])
+///         .allow_credentials(true)
+///         .build_policy()
+///         .unwrap());
+///
+/// assert!(policies.get("partner").is_some());
+/// let middleware = CorsMiddleware::with_policy_provider(policies);
+/// # }
+/// ```
+pub struct CorsPolicySet {
+    policies: HashMap<String, CorsPolicy>,
+    selector: Box<Selector>,
+}
+
+impl CorsPolicySet {
+    /// Create an empty set with the selector, which returns the name of the
+    /// policy for a request.
+    pub fn new<F>(selector: F) -> Self
+        where F: for<'r, 'a, 'b> Fn(&'r Request<'a, 'b>) -> Option<&'r str> + Send + Sync + 'static
+    {
+        CorsPolicySet {
+            policies: HashMap::new(),
+            selector: Box::new(selector),
+        }
+    }
+
+    /// Register the policy under the name, replacing a policy with the same
+    /// name.
+    pub fn policy(mut self, name: &str, policy: CorsPolicy) -> Self {
+        self.policies.insert(name.to_string(), policy);
+        self
+    }
+
+    /// Return the policy with the name.
+    pub fn get(&self, name: &str) -> Option<&CorsPolicy> {
+        self.policies.get(name)
+    }
+
+    /// Return the names of the registered policies.
+    pub fn names(&self) -> impl Iterator<Item=&str> {
+        self.policies.keys().map(String::as_str)
+    }
+
+    /// Return the name of the policy for the request as returned by the
+    /// selector.
+    pub fn select<'r>(&self, req: &'r Request) -> Option<&'r str> {
+        (self.selector)(req)
+    }
+}
+
+impl PolicyProvider for CorsPolicySet {
+    fn policy_for(&self, req: &Request) -> Option<&CorsPolicy> {
+        let name = self.select(req)?;
+        let policy = self.get(name);
+        if policy.is_none() {
+            warn!("No CORS policy named {:?}", name);
+        }
+        policy
+    }
+}
+
+impl fmt::Debug for CorsPolicySet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CorsPolicySet")
+            .field("policies", &self.policies)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A pattern that is matched against the host name of a request.
+///
+/// The pattern is either a host name like `api.example.com`, or a wildcard
+/// like `*.example.com`, which matches any subdomain of `example.com` but
+/// not `example.com` itself. Host names are compared case-insensitively.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HostPattern(String);
+
+impl HostPattern {
+    /// Create a pattern from a host name or a `*.`-prefixed wildcard.
+    pub fn new(pattern: &str) -> Self {
+        HostPattern(pattern.trim().trim_end_matches('.').to_ascii_lowercase())
+    }
+
+    /// Return whether the pattern is a wildcard.
+    pub fn is_wildcard(&self) -> bool {
+        self.0.starts_with("*.")
+    }
+
+    /// Return whether the host name matches the pattern.
+    pub fn matches(&self, host: &str) -> bool {
+        // The pattern is lowercased once when it is created
+        let host = host.trim_end_matches('.');
+        match self.0.strip_prefix('*') {
+            Some(suffix) => host.len() > suffix.len()
+                && host.as_bytes()[host.len() - suffix.len()..].eq_ignore_ascii_case(suffix.as_bytes()),
+            None => host.eq_ignore_ascii_case(&self.0),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for HostPattern {
+    fn from(pattern: &'a str) -> Self {
+        HostPattern::new(pattern)
+    }
+}
+
+impl fmt::Display for HostPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Policies for the virtual hosts of a server, selected by the host name of
+/// the request, with an optional default policy for all other hosts.
+///
+/// A policy registered for the exact host name takes precedence over
+/// wildcard patterns, and longer wildcard patterns take precedence over
+/// shorter ones. Requests to hosts without a policy are passed through
+/// without CORS processing if there is no default policy.
+///
+/// ```rust
+/// use iron_cors::{CorsMiddleware, CorsPolicy, HostPolicies};
+///
+/// let policies = HostPolicies::new()
+///     .host("*.tenant.example.com", CorsPolicy::builder()
+///         .allowed_origins(vec!["https://app.tenant.example.com".to_string()])
+///         .build_policy()
+///         .unwrap())
+///     .host("admin.example.com", CorsPolicy::builder()
+///         .allowed_origins(vec!["https://admin.example.com".to_string()])
+///         .allow_credentials(true)
+///         .build_policy()
+///         .unwrap())
+///     .default_policy(CorsPolicy::default());
+///
+/// assert!(policies.get("a.tenant.example.com").is_some());
+/// let middleware = CorsMiddleware::with_policy_provider(policies);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct HostPolicies {
+    policies: HashMap<HostPattern, CorsPolicy>,
+    default: Option<CorsPolicy>,
+}
+
+impl HostPolicies {
+    /// Create an empty set of policies without a default policy.
+    pub fn new() -> Self {
+        HostPolicies::default()
+    }
+
+    /// Register the policy for the host pattern, replacing a policy with the
+    /// same pattern.
+    pub fn host<P: Into<HostPattern>>(mut self, pattern: P, policy: CorsPolicy) -> Self {
+        self.policies.insert(pattern.into(), policy);
+        self
+    }
+
+    /// Set the policy for hosts that don't match any pattern.
+    pub fn default_policy(mut self, policy: CorsPolicy) -> Self {
+        self.default = Some(policy);
+        self
+    }
+
+    /// Return the policy for the host name.
+    pub fn get(&self, host: &str) -> Option<&CorsPolicy> {
+        if let Some(policy) = self.policies.get(&HostPattern::new(host)) {
+            return Some(policy);
+        }
+        self.policies.iter()
+            .filter(|&(pattern, _)| pattern.is_wildcard() && pattern.matches(host))
+            .max_by_key(|&(pattern, _)| pattern.0.len())
+            .map(|(_, policy)| policy)
+            .or(self.default.as_ref())
+    }
+}
+
+impl From<HashMap<HostPattern, CorsPolicy>> for HostPolicies {
+    fn from(policies: HashMap<HostPattern, CorsPolicy>) -> Self {
+        HostPolicies { policies, default: None }
+    }
+}
+
+impl PolicyProvider for HostPolicies {
+    fn policy_for(&self, req: &Request) -> Option<&CorsPolicy> {
+        with_request_host(req, |host| self.get(host))
+    }
+}
+
+/// Call the function with the host name of the request, which is only
+/// formatted into a new string for IP addresses.
+pub(crate) fn with_request_host<R, F: FnOnce(&str) -> R>(req: &Request, f: F) -> R {
+    // The URL of the request is based on the `Host` header
+    match req.url.host() {
+        Host::Domain(domain) => f(domain),
+        host => f(&host.to_string()),
+    }
+}
+
+/// A boxed policy provider that implements `Debug`.
+pub(crate) struct BoxedProvider(pub(crate) Box<dyn PolicyProvider>);
+
+impl fmt::Debug for BoxedProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PolicyProvider(..)")
+    }
+}
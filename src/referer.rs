@@ -0,0 +1,66 @@
+//! Checking the `Referer` of requests without an `Origin` header.
+
+use std::str;
+
+use iron::Request;
+use iron::headers;
+use iron::method::Method;
+use iron::typemap;
+
+/// The result of checking the origin of the `Referer` header of a request
+/// without an `Origin` header, with
+/// [`CorsBuilder::referer_fallback`](struct.CorsBuilder.html#method.referer_fallback).
+///
+/// The middleware inserts it into the request extensions. It doesn't reject
+/// requests from referers that are not allowed, the handler decides what to
+/// do with them.
+///
+/// ```rust
+/// extern crate iron;
+/// extern crate iron_cors;
+///
+/// use iron::prelude::*;
+/// use iron::status;
+/// use iron_cors::RefererCheck;
+///
+/// fn handler(req: &mut Request) -> IronResult<Response> {
+///     if let Some(&RefererCheck { allowed: false, .. }) = req.extensions.get::<RefererCheck>() {
+///         return Ok(Response::with(status::Forbidden));
+///     }
+///     Ok(Response::with((status::Ok, "Updated")))
+/// }
+/// # fn main() { let _ = handler; }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefererCheck {
+    /// The serialized origin of the referer.
+    pub origin: String,
+    /// Whether the origin is allowed by the policy, or is the origin of the
+    /// server itself.
+    pub allowed: bool,
+}
+
+impl typemap::Key for RefererCheck {
+    type Value = RefererCheck;
+}
+
+/// Return whether the method may change state on the server, so that its
+/// requests should be checked.
+pub(crate) fn is_state_changing(method: &Method) -> bool {
+    !matches!(*method, Method::Get | Method::Head | Method::Options | Method::Trace)
+}
+
+/// Return the origin of the `Referer` header, if it has one.
+pub(crate) fn referer_origin(req: &Request) -> Option<headers::Origin> {
+    let referer = req.headers.get_raw("Referer")
+        .filter(|values| values.len() == 1)
+        .and_then(|values| str::from_utf8(&values[0]).ok())?;
+    let (scheme, rest) = referer.trim().split_at(referer.trim().find("://")?);
+    let authority = rest[3..].split(['/', '?', '#']).next()?;
+    // Credentials in the URL are not part of the origin
+    let host = authority.rsplit('@').next()?;
+    if host.is_empty() {
+        return None;
+    }
+    format!("{}://{}", scheme, host).parse::<headers::Origin>().ok()
+}
@@ -0,0 +1,187 @@
+//! Ordered rules that select the action for a request.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use iron::Request;
+use iron::headers;
+use iron::method::Method;
+
+use policy::AllowedOrigins;
+use {request_path, CorsPolicy, NullOriginPolicy, OriginMatcher, PathPattern};
+
+/// What to do with a request that matches a [`CorsRule`](struct.CorsRule.html).
+#[derive(Debug)]
+pub enum CorsAction {
+    /// Allow the origin, with the other options of the policy. If the rule
+    /// has an origin matcher, the origins it matches are allowed and the
+    /// origin settings of the policy are ignored unless it allows any
+    /// origin. Otherwise, the origins are checked by the policy.
+    Allow(Box<CorsPolicy>),
+    /// Deny the origin, like an origin that is not whitelisted.
+    Deny,
+    /// Pass the request through without CORS processing.
+    PassThrough,
+}
+
+/// A rule that applies an action to the requests it matches.
+///
+/// A rule matches a request if all of its conditions match: the origin, the
+/// path and the method. A rule without conditions matches every request.
+/// For preflight requests, the method in the `Access-Control-Request-Method`
+/// header is used. The `null` origin and malformed origins never match an
+/// origin condition.
+///
+/// Rules are evaluated in the order in which they were added to the
+/// builder, and the first matching rule wins. Requests that match no rule
+/// are handled by the path policies, method policies and the main policy as
+/// usual.
+///
+/// ```rust
+/// extern crate iron;
+/// extern crate iron_cors;
+///
+/// use iron::method::Method;
+/// use iron_cors::{CorsMiddleware, CorsPolicy, CorsRule, OriginMatcher};
+///
+/// # fn main() {
+/// let middleware = CorsMiddleware::builder()
+///     .allowed_origins(vec!["https://app.example.com".to_string()])
+///     // Internal endpoints never get CORS headers
+///     .rule(CorsRule::deny().path("/internal"))
+///     // Partners may read, with credentials
+///     .rule(CorsRule::allow(CorsPolicy::builder()
+///             .reflect_any_origin()
+///             .allow_credentials(true)
+///             .build_policy()
+///             .unwrap())
+///         .origin(OriginMatcher::Subdomain("partner.example.com".to_string()))
+///         .methods(vec![Method::Get, Method::Head]))
+///     // Webhooks are not meant for browsers
+///     .rule(CorsRule::pass_through().path("/webhooks"))
+///     .build()
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct CorsRule {
+    origin: Option<OriginMatcher>,
+    path: Option<PathPattern>,
+    methods: Option<Vec<Method>>,
+    action: CorsAction,
+}
+
+impl CorsRule {
+    /// Create a rule with the action that matches every request.
+    pub fn new(action: CorsAction) -> Self {
+        CorsRule {
+            origin: None,
+            path: None,
+            methods: None,
+            action,
+        }
+    }
+
+    /// Create a rule that allows the origins it matches with the options of
+    /// the policy. See [`CorsAction::Allow`](enum.CorsAction.html#variant.Allow).
+    pub fn allow(policy: CorsPolicy) -> Self {
+        CorsRule::new(CorsAction::Allow(Box::new(policy)))
+    }
+
+    /// Create a rule that denies the origins it matches.
+    pub fn deny() -> Self {
+        CorsRule::new(CorsAction::Deny)
+    }
+
+    /// Create a rule that passes the requests it matches through without
+    /// CORS processing.
+    pub fn pass_through() -> Self {
+        CorsRule::new(CorsAction::PassThrough)
+    }
+
+    /// Only match requests from origins that match the matcher.
+    pub fn origin(mut self, matcher: OriginMatcher) -> Self {
+        self.origin = Some(matcher.normalize());
+        self
+    }
+
+    /// Only match requests whose path matches the pattern. Patterns are
+    /// matched like in
+    /// [`CorsBuilder::path_policy`](struct.CorsBuilder.html#method.path_policy).
+    pub fn path(mut self, pattern: &str) -> Self {
+        self.path = Some(PathPattern::new(pattern));
+        self
+    }
+
+    /// Only match requests with one of the methods.
+    pub fn methods(mut self, methods: Vec<Method>) -> Self {
+        self.methods = Some(methods);
+        self
+    }
+
+    /// Return the action of the rule.
+    pub fn action(&self) -> &CorsAction {
+        &self.action
+    }
+
+    /// Return whether the rule matches the request.
+    pub fn matches(&self, req: &Request) -> bool {
+        if let Some(ref matcher) = self.origin {
+            match req.headers.get::<headers::Origin>() {
+                Some(origin) if matcher.matches(origin) => {},
+                _ => return false,
+            }
+        }
+        if let Some(ref pattern) = self.path {
+            if !pattern.matches(&request_path(req)) {
+                return false;
+            }
+        }
+        if let Some(ref methods) = self.methods {
+            // Preflights are checked against the method of the actual request
+            let method = match (&req.method, req.headers.get::<headers::AccessControlRequestMethod>()) {
+                (&Method::Options, Some(acrm)) => &acrm.0,
+                (method, _) => method,
+            };
+            if !methods.contains(method) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Resolve the policy that the action applies, based on the main policy
+    /// of the middleware, or `None` to pass requests through.
+    pub(crate) fn into_resolved(self, main: &CorsPolicy) -> ResolvedRule {
+        let policy = match self.action {
+            CorsAction::Allow(ref policy) => {
+                let mut policy = (**policy).clone();
+                // The matcher already decided which origins are allowed
+                if self.origin.is_some() && !policy.is_allow_any() {
+                    policy.allowed_origins = AllowedOrigins::Reflect;
+                }
+                Some(policy)
+            },
+            CorsAction::Deny => {
+                let mut policy = main.clone();
+                policy.allowed_origins = AllowedOrigins::Whitelist(Arc::new(HashSet::new()));
+                policy.raw_origins.clear();
+                policy.allow_localhost = false;
+                policy.unrestricted_methods.clear();
+                if policy.null_origin_policy == NullOriginPolicy::Allow {
+                    policy.null_origin_policy = NullOriginPolicy::Deny;
+                }
+                Some(policy)
+            },
+            CorsAction::PassThrough => None,
+        };
+        ResolvedRule { rule: self, policy }
+    }
+}
+
+/// A rule with the policy that its action applies.
+#[derive(Debug)]
+pub(crate) struct ResolvedRule {
+    pub(crate) rule: CorsRule,
+    pub(crate) policy: Option<CorsPolicy>,
+}
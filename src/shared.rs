@@ -0,0 +1,108 @@
+//! A whitelist of origins that can be modified at runtime.
+
+use std::collections::HashSet;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use origin::{normalize_origin, normalize_origins, normalized_origin, strip_port};
+use OriginStore;
+
+/// A shared, runtime-mutable whitelist of origins.
+///
+/// The handle can be cloned cheaply. All clones refer to the same set of
+/// origins, so an admin endpoint or a background job can add or remove
+/// allowed origins while the middleware is running.
+///
+/// ```rust
+/// use iron_cors::{CorsMiddleware, SharedOrigins};
+///
+/// let origins = SharedOrigins::new(vec!["https://example.com".to_string()]);
+/// let middleware = CorsMiddleware::with_shared_whitelist(origins.clone());
+///
+/// // Later, e.g. in an admin handler
+/// origins.insert("https://app.example.com".to_string());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SharedOrigins {
+    origins: Arc<RwLock<HashSet<String>>>,
+}
+
+impl SharedOrigins {
+    /// Create a new shared whitelist with the specified origins.
+    pub fn new<I>(origins: I) -> Self
+        where I: IntoIterator<Item=String>
+    {
+        SharedOrigins {
+            origins: Arc::new(RwLock::new(normalize_origins(origins))),
+        }
+    }
+
+    /// Return whether the serialized origin is in the whitelist.
+    pub fn contains(&self, origin: &str) -> bool {
+        self.read().contains(&*normalized_origin(origin))
+    }
+
+    /// Add an origin to the whitelist. Returns `false` if the origin was
+    /// already present.
+    pub fn insert(&self, origin: String) -> bool {
+        self.write().insert(normalize_origin(&origin))
+    }
+
+    /// Remove an origin from the whitelist. Returns `false` if the origin
+    /// was not present.
+    pub fn remove(&self, origin: &str) -> bool {
+        self.write().remove(&*normalized_origin(origin))
+    }
+
+    /// Replace all origins in the whitelist.
+    pub fn replace<I>(&self, origins: I)
+        where I: IntoIterator<Item=String>
+    {
+        *self.write() = normalize_origins(origins);
+    }
+
+    /// Return a copy of the current whitelist.
+    pub fn to_set(&self) -> HashSet<String> {
+        self.read().clone()
+    }
+
+    /// Call the function with the current whitelist.
+    pub(crate) fn with_set<F, R>(&self, f: F) -> R
+        where F: FnOnce(&HashSet<String>) -> R
+    {
+        f(&self.read())
+    }
+
+    // A panic while holding the lock can't leave the set in an inconsistent
+    // state, so poisoning is ignored.
+
+    fn read(&self) -> RwLockReadGuard<'_, HashSet<String>> {
+        self.origins.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashSet<String>> {
+        self.origins.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl OriginStore for SharedOrigins {
+    fn contains(&self, origin: &str) -> bool {
+        SharedOrigins::contains(self, origin)
+    }
+
+    fn contains_ignoring_port(&self, origin: &str) -> bool {
+        let origin = normalized_origin(origin);
+        self.read().iter().any(|entry| strip_port(entry) == origin)
+    }
+
+    fn list(&self) -> Vec<String> {
+        self.read().iter().cloned().collect()
+    }
+
+    fn insert(&self, origin: String) -> bool {
+        SharedOrigins::insert(self, origin)
+    }
+
+    fn remove(&self, origin: &str) -> bool {
+        SharedOrigins::remove(self, origin)
+    }
+}
@@ -0,0 +1,67 @@
+//! Storage backends for whitelists of origins.
+
+use origin::strip_port;
+
+/// A whitelist of origins, e.g. in memory, in Redis or in a database table.
+///
+/// Origins are passed in the normalized form of
+/// [`normalize_origin`](origin/fn.normalize_origin.html). The in-memory
+/// [`SharedOrigins`](struct.SharedOrigins.html) is the default
+/// implementation.
+///
+/// `contains` is called for every CORS request, so implementations backed by
+/// a remote service should cache the whitelist. Errors can't be reported
+/// through this trait: An implementation should log them and treat the
+/// origin as not allowed.
+///
+/// ```rust
+/// use std::collections::HashSet;
+/// use std::sync::RwLock;
+/// use iron_cors::{CorsMiddleware, OriginStore};
+///
+/// #[derive(Default)]
+/// struct MyStore(RwLock<HashSet<String>>);
+///
+/// impl OriginStore for MyStore {
+///     fn contains(&self, origin: &str) -> bool {
+///         self.0.read().unwrap().contains(origin)
+///     }
+///     fn list(&self) -> Vec<String> {
+///         self.0.read().unwrap().iter().cloned().collect()
+///     }
+///     fn insert(&self, origin: String) -> bool {
+///         self.0.write().unwrap().insert(origin)
+///     }
+///     fn remove(&self, origin: &str) -> bool {
+///         self.0.write().unwrap().remove(origin)
+///     }
+/// }
+///
+/// let middleware = CorsMiddleware::with_origin_store(MyStore::default());
+/// ```
+pub trait OriginStore: Send + Sync {
+    /// Return whether the origin is in the whitelist.
+    fn contains(&self, origin: &str) -> bool;
+
+    /// Return whether an origin with the same scheme and host is in the
+    /// whitelist, whatever its port. The origin is passed without a port.
+    ///
+    /// This is called for every CORS request if
+    /// [`ignore_port`](struct.CorsBuilder.html#method.ignore_port) is
+    /// enabled. The default implementation scans a copy of the whitelist from
+    /// `list`, so stores with more than a few origins should override it.
+    fn contains_ignoring_port(&self, origin: &str) -> bool {
+        self.list().iter().any(|entry| strip_port(entry) == origin)
+    }
+
+    /// Return all origins in the whitelist.
+    fn list(&self) -> Vec<String>;
+
+    /// Add an origin to the whitelist. Returns `false` if the origin was
+    /// already present.
+    fn insert(&self, origin: String) -> bool;
+
+    /// Remove an origin from the whitelist. Returns `false` if the origin
+    /// was not present.
+    fn remove(&self, origin: &str) -> bool;
+}
@@ -0,0 +1,79 @@
+//! Explanations of policy decisions.
+
+use std::fmt;
+
+use iron::typemap;
+
+/// The checks that led to the decision about a request, in the order in
+/// which they were evaluated.
+///
+/// If tracing is enabled with
+/// [`CorsBuilder::trace`](struct.CorsBuilder.html#method.trace), the trace
+/// is inserted into the request extensions and logged at debug level. It is
+/// meant for debugging, the wording of the details may change.
+///
+/// ```rust
+/// extern crate iron;
+/// extern crate iron_cors;
+///
+/// use iron::headers::Origin;
+/// use iron_cors::CorsPolicy;
+///
+/// # fn main() {
+/// let policy = CorsPolicy::builder()
+///     .allowed_origins(vec!["https://example.com".to_string()])
+///     .build_policy()
+///     .unwrap();
+/// let trace = policy.explain(&Origin::new("https", "example.org", None));
+/// assert!(!trace.is_allowed());
+/// println!("{}", trace);
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorsTrace {
+    steps: Vec<TraceStep>,
+}
+
+/// A single check of a [`CorsTrace`](struct.CorsTrace.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceStep {
+    /// The name of the rule, e.g. `allowed_origins`.
+    pub rule: &'static str,
+    /// Whether the check passed.
+    pub passed: bool,
+    /// A description of the result.
+    pub detail: String,
+}
+
+impl CorsTrace {
+    pub(crate) fn push<S: Into<String>>(&mut self, rule: &'static str, passed: bool, detail: S) {
+        self.steps.push(TraceStep { rule, passed, detail: detail.into() });
+    }
+
+    /// Return the checks in the order in which they were evaluated.
+    pub fn steps(&self) -> &[TraceStep] {
+        &self.steps
+    }
+
+    /// Return whether all checks passed.
+    pub fn is_allowed(&self) -> bool {
+        self.steps.iter().all(|step| step.passed)
+    }
+}
+
+impl fmt::Display for CorsTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, step) in self.steps.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            let result = if step.passed { "passed" } else { "failed" };
+            write!(f, "{} {}: {}", step.rule, result, step.detail)?;
+        }
+        Ok(())
+    }
+}
+
+impl typemap::Key for CorsTrace {
+    type Value = CorsTrace;
+}
@@ -0,0 +1,298 @@
+//! Fallible origin validators, e.g. lookups in a database, and how to
+//! degrade when they fail.
+
+use std::error::Error;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use iron::headers;
+
+use cache::LruCache;
+
+/// The error of a fallible origin validator.
+pub type ValidatorError = Box<dyn Error + Send + Sync>;
+
+/// A fallible origin validator.
+pub(crate) type TryOriginFn = dyn Fn(&headers::Origin) -> Result<bool, ValidatorError> + Send + Sync;
+
+/// The number of origins whose last known decision is kept for
+/// `ValidatorFallback::LastKnown`.
+const LAST_KNOWN_CAPACITY: usize = 1024;
+
+/// The number of threads that run validators with a timeout.
+const WORKERS: usize = 4;
+
+/// The number of validations that can wait for a worker. If the queue is
+/// full, validations fail immediately.
+const QUEUE_CAPACITY: usize = 64;
+
+/// How to call a fallible validator.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ValidatorSettings {
+    pub(crate) fallback: ValidatorFallback,
+    pub(crate) breaker: Option<Arc<CircuitBreaker>>,
+    pub(crate) timeout: Option<Duration>,
+}
+
+/// The decision for an origin if the validator fails, or if it isn't called
+/// because the circuit breaker is open.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ValidatorFallback {
+    /// Deny the origin.
+    #[default]
+    Deny,
+    /// Allow the origin.
+    Allow,
+    /// Use the last successful decision for the origin, or deny it if there
+    /// is none.
+    LastKnown,
+}
+
+/// The state of a [`CircuitBreaker`](struct.CircuitBreaker.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// The validator is called.
+    Closed,
+    /// The validator failed repeatedly and isn't called until the reset
+    /// timeout has passed.
+    Open,
+    /// The reset timeout has passed, and the next call decides whether the
+    /// breaker closes or opens again.
+    HalfOpen,
+}
+
+/// A circuit breaker for a fallible origin validator, so that a failing
+/// backend isn't queried on every request.
+///
+/// After `failure_threshold` consecutive failures, the breaker opens and the
+/// [`ValidatorFallback`](enum.ValidatorFallback.html) is used without calling
+/// the validator. After the reset timeout, a single trial call is made: If it
+/// succeeds, the breaker closes, otherwise it opens again.
+///
+/// Pass an `Arc<CircuitBreaker>` to
+/// [`CorsBuilder::circuit_breaker`](struct.CorsBuilder.html#method.circuit_breaker)
+/// and keep a clone to monitor its state.
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use iron_cors::{CircuitBreaker, CircuitState, CorsMiddleware, ValidatorFallback};
+///
+/// # fn lookup(hostname: &str) -> std::io::Result<bool> { Ok(true) }
+/// let breaker = Arc::new(CircuitBreaker::new(5, Duration::from_secs(30)));
+/// let middleware = CorsMiddleware::builder()
+///     .try_origin_fn(|origin| Ok(lookup(&origin.host.hostname)?))
+///     .validator_fallback(ValidatorFallback::LastKnown)
+///     .circuit_breaker(breaker.clone())
+///     .build()
+///     .unwrap();
+/// assert_eq!(breaker.state(), CircuitState::Closed);
+/// ```
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    reset_timeout: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    failures: u32,
+    opened: Option<Instant>,
+    trial: bool,
+}
+
+impl CircuitBreaker {
+    /// Create a breaker that opens after `failure_threshold` consecutive
+    /// failures and tries again after `reset_timeout`.
+    pub fn new(failure_threshold: u32, reset_timeout: Duration) -> Self {
+        CircuitBreaker {
+            failure_threshold: failure_threshold.max(1),
+            reset_timeout,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// The current state of the breaker.
+    pub fn state(&self) -> CircuitState {
+        match self.lock().opened {
+            None => CircuitState::Closed,
+            Some(opened) if opened.elapsed() < self.reset_timeout => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// The number of consecutive failures of the validator.
+    pub fn consecutive_failures(&self) -> u32 {
+        self.lock().failures
+    }
+
+    /// Return whether the validator may be called.
+    fn try_acquire(&self) -> bool {
+        let mut state = self.lock();
+        match state.opened {
+            None => true,
+            Some(opened) if opened.elapsed() < self.reset_timeout || state.trial => false,
+            Some(_) => {
+                state.trial = true;
+                true
+            },
+        }
+    }
+
+    fn record_success(&self) {
+        let mut state = self.lock();
+        if state.opened.is_some() {
+            info!("Origin validator recovered, closing the circuit breaker");
+        }
+        *state = BreakerState::default();
+    }
+
+    fn record_failure(&self) {
+        let mut state = self.lock();
+        state.failures = state.failures.saturating_add(1);
+        if state.trial || (state.opened.is_none() && state.failures >= self.failure_threshold) {
+            warn!("Origin validator failed {} times in a row, opening the circuit breaker", state.failures);
+            state.opened = Some(Instant::now());
+            state.trial = false;
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BreakerState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A fallible validator with the last known decisions for its origins.
+pub(crate) struct Validator {
+    f: Arc<TryOriginFn>,
+    last_known: Mutex<LruCache<String, bool>>,
+    /// The workers for validations with a timeout, started on first use.
+    pool: OnceLock<WorkerPool>,
+}
+
+impl Validator {
+    pub(crate) fn new(f: Arc<TryOriginFn>) -> Self {
+        Validator {
+            f,
+            last_known: Mutex::new(LruCache::new(LAST_KNOWN_CAPACITY)),
+            pool: OnceLock::new(),
+        }
+    }
+
+    /// Call the validator unless the breaker is open. Returns the fallback
+    /// decision as an error if the validator fails, times out or isn't
+    /// called.
+    pub(crate) fn validate(&self,
+                           origin: &headers::Origin,
+                           serialized: &str,
+                           settings: &ValidatorSettings) -> Result<bool, bool> {
+        let fallback = settings.fallback;
+        let breaker = settings.breaker.as_deref();
+        if !breaker.is_none_or(CircuitBreaker::try_acquire) {
+            return Err(self.fallback(serialized, fallback));
+        }
+        let result = match settings.timeout {
+            Some(timeout) => self.call_with_timeout(origin, timeout),
+            None => (self.f)(origin),
+        };
+        match result {
+            Ok(allowed) => {
+                if let Some(breaker) = breaker {
+                    breaker.record_success();
+                }
+                if fallback == ValidatorFallback::LastKnown {
+                    self.lock().insert(serialized.to_string(), allowed);
+                }
+                Ok(allowed)
+            },
+            Err(e) => {
+                warn!("Could not validate origin {}: {}", serialized, e);
+                if let Some(breaker) = breaker {
+                    breaker.record_failure();
+                }
+                Err(self.fallback(serialized, fallback))
+            },
+        }
+    }
+
+    /// Call the validator on a worker thread, waiting at most `timeout` for
+    /// the result. A validator that times out keeps its worker busy until it
+    /// returns.
+    fn call_with_timeout(&self, origin: &headers::Origin, timeout: Duration) -> Result<bool, ValidatorError> {
+        let f = self.f.clone();
+        let origin = origin.clone();
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let job = Box::new(move || {
+            let _ = sender.send(f(&origin));
+        });
+        self.pool.get_or_init(WorkerPool::new).sender.try_send(job)
+            .map_err(|_| "all origin validator workers are busy")?;
+        match receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(format!("timed out after {:?}", timeout).into()),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err("the origin validator panicked".into()),
+        }
+    }
+
+    fn fallback(&self, serialized: &str, fallback: ValidatorFallback) -> bool {
+        match fallback {
+            ValidatorFallback::Deny => false,
+            ValidatorFallback::Allow => true,
+            ValidatorFallback::LastKnown => self.lock().get(serialized).copied().unwrap_or(false),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LruCache<String, bool>> {
+        self.last_known.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed number of threads that run validations.
+struct WorkerPool {
+    sender: SyncSender<Job>,
+}
+
+impl WorkerPool {
+    fn new() -> Self {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for index in 0..WORKERS {
+            let receiver = receiver.clone();
+            let spawned = thread::Builder::new()
+                .name(format!("iron-cors-validator-{}", index))
+                .spawn(move || WorkerPool::work(&receiver));
+            if let Err(e) = spawned {
+                warn!("Could not start origin validator worker: {}", e);
+            }
+        }
+        WorkerPool { sender }
+    }
+
+    /// Run jobs until the pool is dropped.
+    fn work(receiver: &Mutex<Receiver<Job>>) {
+        loop {
+            let job = match receiver.lock().unwrap_or_else(PoisonError::into_inner).recv() {
+                Ok(job) => job,
+                Err(_) => return,
+            };
+            // A panicking validator drops its result sender, which is
+            // reported to the waiting request
+            let _ = panic::catch_unwind(AssertUnwindSafe(job));
+        }
+    }
+}
+
+impl fmt::Debug for Validator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Validator(..)")
+    }
+}
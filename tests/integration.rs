@@ -1,16 +1,23 @@
 extern crate iron;
-extern crate iron_cors;
+#[macro_use] extern crate iron_cors;
 extern crate iron_test;
+extern crate log;
 extern crate unicase;
+#[cfg(feature = "regex")] extern crate regex;
+extern crate serde_json;
+#[cfg(feature = "prometheus")] extern crate prometheus;
 
 use unicase::UniCase;
 use std::collections::HashSet;
-use std::io::{Error, ErrorKind};
+use std::sync::{Arc, Mutex};
+use std::io::Error;
+use std::net::IpAddr;
+use std::time::Duration;
 
 use iron::{Handler, Request, Response, IronResult, IronError, Chain, status};
-use iron::headers::{Headers, Origin, AccessControlAllowOrigin, AccessControlRequestMethod, AccessControlRequestHeaders, AccessControlAllowHeaders, AccessControlAllowMethods};
+use iron::headers::{Headers, Origin, AccessControlAllowOrigin, AccessControlAllowCredentials, AccessControlRequestMethod, AccessControlRequestHeaders, AccessControlAllowHeaders, AccessControlAllowMethods, AccessControlMaxAge, AccessControlExposeHeaders, ContentLength, Vary};
 use self::iron_test::{request, response};
-use iron_cors::CorsMiddleware;
+use iron_cors::{CorsMiddleware, CorsConfig, CorsDecision, CorsMetrics, CorsConfigError, CorsEnvError, CorsPolicy, DisallowedOriginBehavior, HeaderConflictPolicy, HeaderPolicy, NullOriginPolicy, IpRange, OriginMatcher, SharedOrigins, WhitelistFile};
 
 struct HelloWorldHandler;
 impl Handler for HelloWorldHandler {
@@ -30,12 +37,20 @@ struct ErrorResultHandler;
 impl Handler for ErrorResultHandler {
     fn handle(&self, _: &mut Request) -> IronResult<Response> {
         Err(IronError::new(
-            Error::new(ErrorKind::Other, "terrible things"),
+            Error::other("terrible things"),
             (status::InternalServerError, "Oh noes")
         ))
     }
 }
 
+struct DecisionHandler;
+impl Handler for DecisionHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let decision = req.extensions.get::<CorsDecision>().cloned();
+        Ok(Response::with((status::Ok, format!("{:?}", decision))))
+    }
+}
+
 macro_rules! setup_handler {
     ("whitelist": $allowed_hosts:expr) => {{
         let mut chain = Chain::new(HelloWorldHandler {});
@@ -219,6 +234,669 @@ fn test_whitelist_unexpected_error_status() {
     assert_eq!(&result_body, "Oh noes");
 }
 
+#[test]
+fn test_error_response_policy() {
+    //! Error responses only get CORS headers according to the policy
+    use iron::status::StatusClass;
+    use iron_cors::ErrorResponsePolicy;
+
+    let cases = [
+        (ErrorResponsePolicy::All, true),
+        (ErrorResponsePolicy::Omit, false),
+        (ErrorResponsePolicy::StatusClasses(vec![StatusClass::ClientError]), false),
+        (ErrorResponsePolicy::StatusClasses(vec![StatusClass::ServerError]), true),
+    ];
+    for (policy, expected) in cases {
+        let mut handler = Chain::new(ErrorResultHandler {});
+        handler.link_around(CorsMiddleware::builder().error_response_policy(policy.clone()).build().unwrap());
+        let error = request::get("http://example.org:3000/err", setup_origin_header!("example.com"), &handler).unwrap_err();
+        assert_eq!(error.response.headers.has::<AccessControlAllowOrigin>(), expected, "{:?}", policy);
+    }
+
+    // Successful responses are not affected
+    let handler = CorsMiddleware::builder()
+        .error_response_policy(ErrorResponsePolicy::Omit)
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let response = request::get("http://example.org/", setup_origin_header!("example.com"), &handler).unwrap();
+    assert!(response.headers.has::<AccessControlAllowOrigin>());
+}
+
+#[test]
+fn test_xdomain_request_compat() {
+    //! XDomainRequest clients only get the Access-Control-Allow-Origin header
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.com".to_string()])
+        .allow_credentials(true)
+        .expose_headers(vec!["X-Total-Count".to_string()])
+        .xdomain_request_compat(true)
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let mut headers = setup_origin_header!("example.com");
+    headers.set_raw("User-Agent", vec![b"Mozilla/4.0 (compatible; MSIE 8.0; Windows NT 6.1; Trident/4.0)".to_vec()]);
+    let response = request::get("http://example.org/", headers, &handler).unwrap();
+    assert_eq!(response.headers.get::<AccessControlAllowOrigin>(),
+               Some(&AccessControlAllowOrigin::Value("http://example.com".to_string())));
+    assert!(!response.headers.has::<AccessControlAllowCredentials>());
+    assert!(!response.headers.has::<AccessControlExposeHeaders>());
+
+    // Other browsers get all headers
+    let mut headers = setup_origin_header!("example.com");
+    headers.set_raw("User-Agent", vec![b"Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:120.0) Firefox/120.0".to_vec()]);
+    let response = request::get("http://example.org/", headers, &handler).unwrap();
+    assert!(response.headers.has::<AccessControlAllowCredentials>());
+    assert!(response.headers.has::<AccessControlExposeHeaders>());
+}
+
+#[test]
+fn test_sec_fetch() {
+    //! Requests can be filtered by their fetch metadata
+    use iron::method::Method;
+    use iron_cors::{FetchMetadata, SecFetchMode, SecFetchSite};
+
+    let middleware = || CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.com".to_string()])
+        .reject_cross_site(vec![Method::Post])
+        .trust_sec_fetch_site(true)
+        .build().unwrap();
+    let handler = middleware().wrap(DecisionHandler {});
+    let with_site = |site: &str| {
+        let mut headers = setup_origin_header!("example.com");
+        headers.set_raw("Sec-Fetch-Site", vec![site.as_bytes().to_vec()]);
+        headers.set_raw("Sec-Fetch-Mode", vec![b"cors".to_vec()]);
+        headers
+    };
+    let response = request::post("http://example.org/", with_site("cross-site"), "", &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+    let response = request::get("http://example.org/", with_site("cross-site"), &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    let response = request::post("http://example.org/", with_site("same-site"), "", &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    let response = request::post("http://example.org/", setup_origin_header!("example.com"), "", &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+
+    // Same-origin requests are not processed
+    let mut headers = setup_origin_header!("example.net");
+    headers.set_raw("Sec-Fetch-Site", vec![b"same-origin".to_vec()]);
+    let response = request::get("http://example.org/", headers, &handler).unwrap();
+    assert_eq!(response::extract_body_to_string(response), "Some(SameOrigin)");
+
+    // The parsed headers are available to the handler
+    let handler = middleware().wrap(|req: &mut Request| {
+        let metadata = req.extensions.get::<FetchMetadata>().cloned().unwrap();
+        assert_eq!(metadata, FetchMetadata { site: Some(SecFetchSite::SameSite), mode: Some(SecFetchMode::Cors) });
+        Ok(Response::with(status::Ok))
+    });
+    let response = request::get("http://example.org/", with_site("same-site"), &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+}
+
+#[test]
+fn test_referer_fallback() {
+    //! The Referer of state-changing requests without Origin is checked
+    use iron_cors::RefererCheck;
+
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.com".to_string()])
+        .referer_fallback(true)
+        .build().unwrap()
+        .wrap(|req: &mut Request| {
+            let check = req.extensions.get::<RefererCheck>().cloned();
+            Ok(Response::with((status::Ok, format!("{:?}", check.map(|check| (check.origin, check.allowed))))))
+        });
+    let with_referer = |referer: &str| {
+        let mut headers = Headers::new();
+        headers.set_raw("Referer", vec![referer.as_bytes().to_vec()]);
+        headers
+    };
+    let body = |response| response::extract_body_to_string(response);
+    let response = request::post("http://example.org/", with_referer("http://example.com/form?id=1"), "", &handler).unwrap();
+    assert_eq!(body(response), r#"Some(("http://example.com", true))"#);
+    let response = request::post("http://example.org/", with_referer("https://user@evil.example:8443/"), "", &handler).unwrap();
+    assert_eq!(body(response), r#"Some(("https://evil.example:8443", false))"#);
+    let response = request::post("http://example.org/", with_referer("http://example.org/page"), "", &handler).unwrap();
+    assert_eq!(body(response), r#"Some(("http://example.org", true))"#);
+    let response = request::get("http://example.org/", with_referer("http://evil.example/"), &handler).unwrap();
+    assert_eq!(body(response), "None");
+}
+
+#[test]
+fn test_csrf_validator() {
+    //! State-changing requests need an allowed origin or a valid token
+    use iron_cors::CsrfHeader;
+
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.com".to_string()])
+        .disallowed_origin_behavior(DisallowedOriginBehavior::PassThrough)
+        .referer_fallback(true)
+        .csrf_validator(CsrfHeader::new("X-Requested-With"))
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let post = |headers| request::post("http://example.org/", headers, "", &handler).unwrap().status;
+    assert_eq!(post(setup_origin_header!("example.com")), Some(status::Ok));
+    assert_eq!(post(setup_origin_header!("example.net")), Some(status::BadRequest));
+    let mut headers = setup_origin_header!("example.net");
+    headers.set_raw("X-Requested-With", vec![b"XMLHttpRequest".to_vec()]);
+    assert_eq!(post(headers), Some(status::Ok));
+
+    // Requests from the server itself are legitimate
+    assert_eq!(post(setup_origin_header!("example.org")), Some(status::Ok));
+
+    // Without Origin, the referer decides
+    assert_eq!(post(Headers::new()), Some(status::BadRequest));
+    let mut headers = Headers::new();
+    headers.set_raw("Referer", vec![b"http://example.com/form".to_vec()]);
+    assert_eq!(post(headers), Some(status::Ok));
+
+    // Safe methods are not checked
+    let response = request::get("http://example.org/", setup_origin_header!("example.net"), &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+}
+
+#[test]
+fn test_strip_credentials() {
+    //! Credentials of requests from disallowed origins don't reach the handler
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.com".to_string()])
+        .disallowed_origin_behavior(DisallowedOriginBehavior::PassThrough)
+        .strip_credentials(true)
+        .build().unwrap()
+        .wrap(|req: &mut Request| {
+            let credentials = req.headers.get_raw("Cookie").is_some() || req.headers.get_raw("Authorization").is_some();
+            Ok(Response::with((status::Ok, credentials.to_string())))
+        });
+    let with_credentials = |host: &str| {
+        let mut headers = setup_origin_header!(host);
+        headers.set_raw("Cookie", vec![b"session=1".to_vec()]);
+        headers.set_raw("Authorization", vec![b"Bearer token".to_vec()]);
+        headers
+    };
+    let response = request::get("http://example.org/", with_credentials("example.net"), &handler).unwrap();
+    assert_eq!(response::extract_body_to_string(response), "false");
+    let response = request::get("http://example.org/", with_credentials("example.com"), &handler).unwrap();
+    assert_eq!(response::extract_body_to_string(response), "true");
+}
+
+#[test]
+fn test_allowed_hosts() {
+    //! Requests to unexpected hosts are rejected
+    use iron_cors::CorsRejection;
+
+    let handler = CorsMiddleware::builder()
+        .allowed_hosts(vec!["example.org", "*.example.net"])
+        .skip_paths(vec!["/health".to_string()])
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let get = |url| request::get(url, Headers::new(), &handler).unwrap().status;
+    assert_eq!(get("http://example.org:3000/"), Some(status::Ok));
+    assert_eq!(get("http://api.example.net/"), Some(status::Ok));
+    assert_eq!(get("http://evil.example/"), Some(status::BadRequest));
+    assert_eq!(get("http://127.0.0.1/health"), Some(status::Ok));
+    let response = request::get("http://evil.example/", setup_origin_header!("example.com"), &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+
+    // Rejections go through the deny responder and the hooks
+    let denied = Arc::new(Mutex::new(Vec::new()));
+    let denied_hook = denied.clone();
+    let handler = CorsMiddleware::builder()
+        .allowed_hosts(vec!["example.org"])
+        .deny_responder(|_: &Request, rejection: &CorsRejection| {
+            Response::with((status::Forbidden, rejection.reason))
+        })
+        .on_denied(move |origin, path| denied_hook.lock().unwrap().push(format!("{} {}", origin, path)))
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let response = request::get("http://evil.example/path", setup_origin_header!("example.com"), &handler).unwrap();
+    assert_eq!(response.status, Some(status::Forbidden));
+    assert_eq!(response::extract_body_to_string(response), "Unexpected Host header");
+    assert_eq!(*denied.lock().unwrap(), vec!["http://example.com /path".to_string()]);
+
+    // Report-only mode only logs unexpected hosts
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.com".to_string()])
+        .allowed_hosts(vec!["example.org"])
+        .report_only(true)
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let response = request::get("http://evil.example/", setup_origin_header!("example.com"), &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert!(response.headers.has::<AccessControlAllowOrigin>());
+}
+
+#[test]
+fn test_require_tls() {
+    //! CORS responses can be restricted to requests over TLS
+    use iron_cors::TlsRequirement;
+
+    let build = |requirement, report_only| CorsMiddleware::builder()
+        .allowed_origins(vec!["https://example.com".to_string()])
+        .allow_credentials(true)
+        .trusted_proxies(vec!["127.0.0.1".parse::<IpAddr>().unwrap()])
+        .require_tls(requirement)
+        .report_only(report_only)
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let origin = || {
+        let mut headers = Headers::new();
+        headers.set(Origin::new("https", "example.com", None));
+        headers
+    };
+
+    let handler = build(TlsRequirement::Credentials, false);
+    let response = request::get("http://example.org/", origin(), &handler).unwrap();
+    assert!(response.headers.has::<AccessControlAllowOrigin>());
+    assert!(!response.headers.has::<AccessControlAllowCredentials>());
+    let response = request::get("https://example.org/", origin(), &handler).unwrap();
+    assert!(response.headers.has::<AccessControlAllowCredentials>());
+
+    let handler = build(TlsRequirement::All, false);
+    let response = request::get("http://example.org/", origin(), &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+    let mut headers = origin();
+    headers.set_raw("X-Forwarded-Proto", vec![b"https".to_vec()]);
+    let response = request::get("http://example.org/", headers, &handler).unwrap();
+    assert!(response.headers.has::<AccessControlAllowCredentials>());
+
+    // Report-only mode doesn't block requests without TLS
+    let handler = build(TlsRequirement::All, true);
+    let response = request::get("http://example.org/", origin(), &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert!(response.headers.has::<AccessControlAllowOrigin>());
+}
+
+#[test]
+fn test_cross_origin_resource_policy() {
+    //! The Cross-Origin-Resource-Policy header is sent on all responses
+    use iron_cors::CrossOriginResourcePolicy;
+
+    let private = CorsPolicy::builder()
+        .cross_origin_resource_policy(CrossOriginResourcePolicy::SameOrigin)
+        .build_policy()
+        .unwrap();
+    let handler = CorsMiddleware::builder()
+        .cross_origin_resource_policy(CrossOriginResourcePolicy::CrossOrigin)
+        .path_policy("/private", private)
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let corp = |url, headers| {
+        let response = request::get(url, headers, &handler).unwrap();
+        response.headers.get_raw("Cross-Origin-Resource-Policy").map(|values| values[0].clone())
+    };
+    assert_eq!(corp("http://example.org/", Headers::new()), Some(b"cross-origin".to_vec()));
+    assert_eq!(corp("http://example.org/", setup_origin_header!("example.com")), Some(b"cross-origin".to_vec()));
+    assert_eq!(corp("http://example.org/private/image.png", Headers::new()), Some(b"same-origin".to_vec()));
+
+    // Without the option, the header is not sent
+    let handler = CorsMiddleware::with_allow_any().wrap(HelloWorldHandler {});
+    let response = request::get("http://example.org/", Headers::new(), &handler).unwrap();
+    assert!(response.headers.get_raw("Cross-Origin-Resource-Policy").is_none());
+}
+
+#[test]
+fn test_cross_origin_isolation() {
+    //! COOP and COEP are sent on all responses except preflights
+    use iron_cors::{CrossOriginEmbedderPolicy, CrossOriginOpenerPolicy};
+
+    let handler = CorsMiddleware::builder()
+        .cross_origin_opener_policy(CrossOriginOpenerPolicy::SameOrigin)
+        .cross_origin_embedder_policy(CrossOriginEmbedderPolicy::RequireCorp)
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let response = request::get("http://example.org/", Headers::new(), &handler).unwrap();
+    assert_eq!(response.headers.get_raw("Cross-Origin-Opener-Policy"), Some(&[b"same-origin".to_vec()][..]));
+    assert_eq!(response.headers.get_raw("Cross-Origin-Embedder-Policy"), Some(&[b"require-corp".to_vec()][..]));
+
+    let mut headers = setup_origin_header!("example.com");
+    headers.set(AccessControlRequestMethod(iron::method::Get));
+    let response = request::options("http://example.org/", headers, &handler).unwrap();
+    assert!(response.headers.has::<AccessControlAllowOrigin>());
+    assert!(response.headers.get_raw("Cross-Origin-Opener-Policy").is_none());
+    assert!(response.headers.get_raw("Cross-Origin-Embedder-Policy").is_none());
+}
+
+#[test]
+fn test_path_max_age() {
+    //! Path overrides change the max-age of successful preflights
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.com".to_string()])
+        .max_age(Duration::from_secs(600))
+        .path_max_age("/api/schema", Duration::from_secs(86400))
+        .path_max_age("/auth/*", Duration::from_secs(0))
+        .skip_paths(vec!["/auth/callback".to_string()])
+        .build().unwrap()
+        .wrap(|_: &mut Request| {
+            let mut response = Response::with(status::Ok);
+            response.headers.set(AccessControlAllowOrigin::Any);
+            Ok(response)
+        });
+    let cases = [
+        ("http://example.org/api/schema", 86400),
+        ("http://example.org/auth/login", 0),
+        ("http://example.org/api/users", 600),
+    ];
+    for (url, max_age) in cases {
+        let mut headers = setup_origin_header!("example.com");
+        headers.set(AccessControlRequestMethod(iron::method::Get));
+        let response = request::options(url, headers, &handler).unwrap();
+        assert_eq!(response.headers.get::<AccessControlMaxAge>(), Some(&AccessControlMaxAge(max_age)));
+    }
+
+    let mut headers = setup_origin_header!("evil.example.com");
+    headers.set(AccessControlRequestMethod(iron::method::Get));
+    let response = request::options("http://example.org/api/schema", headers, &handler).unwrap();
+    assert!(response.headers.get::<AccessControlMaxAge>().is_none());
+
+    // Preflights that the handler answers don't get the override
+    let mut headers = setup_origin_header!("example.com");
+    headers.set(AccessControlRequestMethod(iron::method::Get));
+    let response = request::options("http://example.org/auth/callback", headers, &handler).unwrap();
+    assert!(response.headers.has::<AccessControlAllowOrigin>());
+    assert!(response.headers.get::<AccessControlMaxAge>().is_none());
+}
+
+#[test]
+fn test_deny_responder() {
+    //! Rejections are answered according to the `Accept` header
+    use iron_cors::CorsRejection;
+
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.com".to_string()])
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let cases = [
+        (None, false),
+        (Some("text/html,application/xhtml+xml,*/*;q=0.8"), false),
+        (Some("application/json"), true),
+        (Some("application/problem+json, text/plain;q=0.5"), true),
+        (Some("text/plain, application/json;q=0.9"), false),
+    ];
+    for (accept, json) in cases {
+        let mut headers = setup_origin_header!("evil.example.com");
+        if let Some(accept) = accept {
+            headers.set_raw("Accept", vec![accept.as_bytes().to_vec()]);
+        }
+        let response = request::get("http://example.org/", headers, &handler).unwrap();
+        assert_eq!(response.status, Some(status::BadRequest));
+        let content_type = response.headers.get_raw("Content-Type").map(|values| values[0].clone());
+        let body = response::extract_body_to_string(response);
+        if json {
+            assert_eq!(content_type, Some(b"application/problem+json".to_vec()));
+            assert_eq!(body, "{\"type\":\"about:blank\",\"title\":\"Invalid CORS request\",\"status\":400,\
+                              \"detail\":\"Origin not allowed\",\"origin\":\"http://evil.example.com\"}");
+        } else {
+            assert_eq!(body, "Invalid CORS request: Origin not allowed", "{:?}", accept);
+        }
+    }
+
+    // The responder can be replaced
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.com".to_string()])
+        .deny_responder(|_: &Request, rejection: &CorsRejection| {
+            Response::with((status::Forbidden, format!("{}: {}", rejection.origin, rejection.reason)))
+        })
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let response = request::get("http://example.org/", setup_origin_header!("evil.example.com"), &handler).unwrap();
+    assert_eq!(response.status, Some(status::Forbidden));
+    assert_eq!(response::extract_body_to_string(response), "http://evil.example.com: Origin not allowed");
+}
+
+#[test]
+fn test_policy_validation_warnings() {
+    //! Dangerous or nonsensical settings are reported
+    use iron_cors::CorsConfigWarning;
+
+    assert!(CorsPolicy::default().validate().is_empty());
+    let policy = CorsPolicy::builder()
+        .reflect_any_origin()
+        .allow_credentials(true)
+        .max_age(Duration::from_secs(0))
+        .allowed_headers(vec![UniCase("X-Requested-With".to_string()), UniCase("Cookie".to_string()), UniCase("Sec-Fetch-Mode".to_string())])
+        .except_origins(vec!["evil.example.com".to_string()])
+        .build_policy().unwrap();
+    assert_eq!(policy.validate(), vec![
+        CorsConfigWarning::CredentialsWithAnyOrigin,
+        CorsConfigWarning::OriginWithoutScheme("evil.example.com".to_string()),
+        CorsConfigWarning::ForbiddenHeader("Cookie".to_string()),
+        CorsConfigWarning::ForbiddenHeader("Sec-Fetch-Mode".to_string()),
+        CorsConfigWarning::ZeroMaxAgeWithCredentials,
+    ]);
+    assert_eq!(CorsConfigWarning::ForbiddenHeader("Cookie".to_string()).to_string(),
+               "Forbidden request header allowed: \"Cookie\"");
+}
+
+#[test]
+fn test_presets() {
+    //! The presets encode the documented defaults
+    use iron::method::Method;
+    use iron_cors::{CorsBuilder, CorsHandler};
+
+    let preflight = |handler: &CorsHandler<HelloWorldHandler>, origin: &str, method: Method| {
+        let mut headers = setup_origin_header!(origin);
+        headers.set(AccessControlRequestMethod(method));
+        request::options("http://example.org/", headers, handler).unwrap()
+    };
+
+    let handler = CorsBuilder::permissive().build().unwrap().wrap(HelloWorldHandler {});
+    let response = preflight(&handler, "example.com", Method::Delete);
+    assert_eq!(response.headers.get::<AccessControlAllowOrigin>(), Some(&AccessControlAllowOrigin::Any));
+    assert_eq!(response.headers.get::<AccessControlMaxAge>(), Some(&AccessControlMaxAge(86400)));
+
+    let handler = CorsBuilder::restrictive(vec!["http://example.com".to_string()]).build().unwrap().wrap(HelloWorldHandler {});
+    let response = preflight(&handler, "example.com", Method::Post);
+    assert!(response.headers.has::<AccessControlAllowOrigin>());
+    assert!(response.headers.get::<AccessControlAllowCredentials>().is_none());
+    assert_eq!(response.headers.get::<AccessControlMaxAge>(), Some(&AccessControlMaxAge(600)));
+    let response = preflight(&handler, "example.com", Method::Delete);
+    assert!(!response.headers.has::<AccessControlAllowOrigin>());
+    let response = preflight(&handler, "other.example.com", Method::Post);
+    assert!(!response.headers.has::<AccessControlAllowOrigin>());
+
+    let handler = CorsBuilder::spec_default().build().unwrap().wrap(HelloWorldHandler {});
+    let response = preflight(&handler, "example.com", Method::Put);
+    assert_eq!(response.status, Some(status::Ok));
+    assert!(response.headers.get::<AccessControlMaxAge>().is_none());
+}
+
+#[test]
+fn test_cors_macro() {
+    //! The macro expands to the builder calls
+    use iron::method::Method;
+
+    let policy = cors! {
+        origins: ["http://example.com"],
+        methods: [Get, Post],
+        headers: ["content-type", "authorization"],
+        expose: ["X-Total-Count"],
+        credentials: true,
+        max_age: 3600,
+    }.unwrap();
+    assert!(policy.is_origin_allowed(&Origin::new("http", "example.com", None)));
+    assert!(!policy.is_origin_allowed(&Origin::new("http", "other.example.com", None)));
+    assert!(policy.is_method_allowed(&Method::Post));
+    assert!(!policy.is_method_allowed(&Method::Delete));
+    assert!(policy.is_header_allowed("Authorization"));
+    assert!(policy.is_header_allowed("AUTHORIZATION"));
+    assert!(!policy.is_header_allowed("X-Requested-With"));
+
+    let handler = CorsMiddleware::from_policy(policy).wrap(HelloWorldHandler {});
+    let response = request::get("http://example.org/", setup_origin_header!("example.com"), &handler).unwrap();
+    assert!(response.headers.has::<AccessControlAllowCredentials>());
+    assert_eq!(response.headers.get::<AccessControlExposeHeaders>(),
+               Some(&AccessControlExposeHeaders(vec![UniCase("X-Total-Count".to_string())])));
+
+    assert_eq!(cors! { origins: *, credentials: true }.err(), Some(CorsConfigError::CredentialsWithAnyOrigin));
+}
+
+#[test]
+fn test_introspection() {
+    //! The effective configuration can be queried and printed
+    use iron::method::Method;
+
+    let middleware = CorsMiddleware::builder()
+        .allowed_origins(vec!["https://b.example.com".to_string(), "https://a.example.com".to_string()])
+        .allowed_methods(vec![Method::Get, Method::Post])
+        .allow_credentials(true)
+        .max_age(Duration::from_secs(600))
+        .skip_paths(vec!["/health".to_string()])
+        .build().unwrap();
+    assert!(!middleware.is_allow_any());
+    assert_eq!(middleware.allowed_origins(),
+               Some(vec!["https://a.example.com".to_string(), "https://b.example.com".to_string()]));
+    assert_eq!(middleware.max_age(), Some(Duration::from_secs(600)));
+    assert!(middleware.allow_credentials());
+    assert_eq!(middleware.policy().allowed_methods(), Some(&[Method::Get, Method::Post][..]));
+    assert_eq!(middleware.policy().allowed_headers(), None);
+    assert_eq!(middleware.to_string(),
+               "origins: whitelist (https://a.example.com, https://b.example.com); methods: GET, POST; \
+                headers: any; credentials: allowed; max-age: 600s; 1 skipped paths");
+
+    let middleware = CorsMiddleware::with_allow_any();
+    assert!(middleware.is_allow_any());
+    assert_eq!(middleware.allowed_origins(), None);
+    assert_eq!(middleware.to_string(), "origins: any; methods: any; headers: any; credentials: not allowed; max-age: none");
+}
+
+#[test]
+fn test_merge_policies() {
+    //! A base policy can be extended with overrides
+    use iron::method::Method;
+    use iron_cors::{DecisionCache, ValidatorFallback};
+
+    let base = CorsPolicy::builder()
+        .allowed_origins(vec!["https://www.example.com".to_string()])
+        .allowed_methods(vec![Method::Get])
+        .max_age(Duration::from_secs(600))
+        .build_policy()
+        .unwrap();
+    let overrides = CorsPolicy::builder()
+        .allowed_origins(vec!["https://admin.example.com".to_string()])
+        .allowed_methods(vec![Method::Post])
+        .allow_credentials(true)
+        .build_policy()
+        .unwrap();
+    let policy = base.merge(&overrides).unwrap();
+    assert!(policy.is_origin_allowed(&Origin::new("https", "www.example.com", None)));
+    assert!(policy.is_origin_allowed(&Origin::new("https", "admin.example.com", None)));
+    assert!(!policy.is_origin_allowed(&Origin::new("https", "example.org", None)));
+    assert_eq!(policy.allowed_methods(), Some(&[Method::Get, Method::Post][..]));
+    assert!(policy.allow_credentials());
+    assert_eq!(policy.max_age(), Some(Duration::from_secs(600)));
+
+    // Overrides without methods or headers keep the restrictions of the base
+    let restricted = CorsPolicy::builder()
+        .allowed_origins(vec!["https://www.example.com".to_string()])
+        .allowed_methods(vec![Method::Get])
+        .allowed_headers(vec![UniCase("Content-Type".to_string())])
+        .build_policy()
+        .unwrap();
+    let origins_only = CorsPolicy::builder()
+        .allowed_origins(vec!["https://admin.example.com".to_string()])
+        .build_policy()
+        .unwrap();
+    let policy = restricted.merge(&origins_only).unwrap();
+    assert_eq!(policy.allowed_methods(), Some(&[Method::Get][..]));
+    assert_eq!(policy.allowed_headers(), Some(&[UniCase("Content-Type".to_string())][..]));
+    let policy = origins_only.merge(&restricted).unwrap();
+    assert_eq!(policy.allowed_methods(), Some(&[Method::Get][..]));
+    assert_eq!(CorsPolicy::default().merge(&origins_only).unwrap().allowed_methods(), None);
+
+    // Different kinds of origins are combined
+    let overrides = CorsPolicy::builder()
+        .allowed_origin_fn(|origin| origin.host.hostname.ends_with(".example.net"))
+        .build_policy()
+        .unwrap();
+    let policy = base.merge(&overrides).unwrap();
+    assert!(policy.is_origin_allowed(&Origin::new("https", "www.example.com", None)));
+    assert!(policy.is_origin_allowed(&Origin::new("https", "api.example.net", None)));
+    assert!(!policy.is_origin_allowed(&Origin::new("https", "example.org", None)));
+
+    // Credentials can't be allowed for any origin
+    let result = CorsPolicy::default().merge(&CorsPolicy::builder().allow_credentials(true).reflect_any_origin()
+        .build_policy().unwrap());
+    assert!(result.is_ok());
+    let credentials = CorsPolicy::builder()
+        .allowed_origins(vec!["https://www.example.com".to_string()])
+        .allow_credentials(true)
+        .build_policy()
+        .unwrap();
+    assert_eq!(CorsPolicy::default().merge(&credentials).unwrap_err(), CorsConfigError::CredentialsWithAnyOrigin);
+
+    // The merged policy doesn't share the decisions of the base
+    let base = CorsPolicy::builder()
+        .allowed_origin_fn(|origin| origin.host.hostname.ends_with(".example.net"))
+        .decision_cache(Arc::new(DecisionCache::new(100, Duration::from_secs(60))))
+        .build_policy()
+        .unwrap();
+    let overrides = CorsPolicy::builder()
+        .allowed_origins(vec!["https://admin.example.com".to_string()])
+        .build_policy()
+        .unwrap();
+    let policy = base.merge(&overrides).unwrap();
+    let admin = Origin::new("https", "admin.example.com", None);
+    assert!(policy.is_origin_allowed(&admin));
+    assert!(!base.is_origin_allowed(&admin));
+
+    // A failing validator uses the fallback of the merged policy
+    let base = CorsPolicy::builder()
+        .allowed_origins(vec!["https://www.example.com".to_string()])
+        .validator_fallback(ValidatorFallback::Allow)
+        .build_policy()
+        .unwrap();
+    let overrides = CorsPolicy::builder()
+        .try_origin_fn(|_| Err("database unavailable".into()))
+        .build_policy()
+        .unwrap();
+    let policy = base.merge(&overrides).unwrap();
+    assert!(policy.is_origin_allowed(&Origin::new("https", "example.org", None)));
+
+    // The validator settings of the overrides are discarded
+    let overrides = CorsPolicy::builder()
+        .try_origin_fn(|_| Err("database unavailable".into()))
+        .validator_fallback(ValidatorFallback::Allow)
+        .build_policy()
+        .unwrap();
+    let base = CorsPolicy::builder()
+        .allowed_origins(vec!["https://www.example.com".to_string()])
+        .build_policy()
+        .unwrap();
+    let policy = base.merge(&overrides).unwrap();
+    assert!(!policy.is_origin_allowed(&Origin::new("https", "example.org", None)));
+
+    // Overrides can't reset an option of the base to its default
+    let base = CorsPolicy::builder()
+        .allowed_origins(vec!["https://www.example.com".to_string()])
+        .allow_credentials(true)
+        .build_policy()
+        .unwrap();
+    let overrides = CorsPolicy::builder()
+        .allowed_origins(vec!["https://admin.example.com".to_string()])
+        .allow_credentials(false)
+        .build_policy()
+        .unwrap();
+    assert!(base.merge(&overrides).unwrap().allow_credentials());
+}
+
+#[test]
+fn test_explain_preflight() {
+    //! Preflights can be simulated without a request
+    use iron::method::Method;
+
+    let policy = CorsPolicy::builder()
+        .allowed_origins(vec!["https://app.example.com".to_string()])
+        .allowed_methods(vec![Method::Get, Method::Post])
+        .allowed_headers(vec![UniCase("Content-Type".to_string())])
+        .build_policy()
+        .unwrap();
+    let origin = Origin::new("https", "app.example.com", None);
+    assert!(policy.explain_preflight(&origin, Method::Post, &["content-type"]).is_allowed());
+    let trace = policy.explain_preflight(&origin, Method::Post, &["X-Foo"]);
+    assert!(!trace.is_allowed());
+    assert_eq!(trace.steps().last().unwrap().detail, "Header not allowed");
+    assert!(!policy.explain_preflight(&origin, Method::Delete, &[]).is_allowed());
+    assert!(!policy.explain_preflight(&Origin::new("https", "example.org", None), Method::Get, &[]).is_allowed());
+}
+
 #[test]
 fn test_whitelist_preflight_with_cors_headers() {
     //! OPTION requests with whitelisted host and correct CORS headers should answer 200 with empty body and the CORS headers 
@@ -282,15 +960,2561 @@ fn test_any_preflight_with_cors_headers() {
     {
     let header = response.headers.get::<AccessControlAllowHeaders>();
     assert!(header.is_some());
-    assert_eq!(*header.unwrap(), AccessControlAllowHeaders(vec![UniCase("header1".to_string()),UniCase("header2".to_string())]));
+    assert_eq!(*header.unwrap(), AccessControlAllowHeaders(vec![UniCase("*".to_string()), UniCase("Authorization".to_string())]));
     }
 
     {
     let header = response.headers.get::<AccessControlAllowMethods>();
     assert!(header.is_some());
-    assert_eq!(*header.unwrap(), AccessControlAllowMethods(vec![iron::method::Get]));
+    assert!(header.unwrap().0.contains(&iron::method::Get));
+    assert!(header.unwrap().0.contains(&iron::method::Delete));
     }
 
     let result_body = response::extract_body_to_string(response);
     assert_eq!(&result_body, "");
 }
+
+#[test]
+fn test_builder_whitelist() {
+    //! A whitelist configured through the builder behaves like `with_whitelist`
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org:3000".to_string()])
+        .build().unwrap());
+
+    let headers = setup_origin_header!("example.org", 3000);
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert_eq!(*response.headers.get::<AccessControlAllowOrigin>().unwrap(),
+               AccessControlAllowOrigin::Value("http://example.org:3000".into()));
+
+    let headers = setup_origin_header!("forbidden.org");
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+    assert!(response.headers.get::<AccessControlAllowOrigin>().is_none());
+}
+
+#[test]
+fn test_whitelist_from_array() {
+    //! `with_whitelist` accepts any iterator of strings, e.g. an array
+    let handler = CorsMiddleware::with_whitelist(["https://a.com", "https://b.com"])
+        .wrap(HelloWorldHandler {});
+
+    for host in ["a.com", "b.com"] {
+        let mut headers = Headers::new();
+        headers.set(Origin::new("https", host, None));
+        let response = request::get("http://example.org/hello", headers, &handler).unwrap();
+        assert_eq!(response.status, Some(status::Ok));
+        assert_eq!(*response.headers.get::<AccessControlAllowOrigin>().unwrap(),
+                   AccessControlAllowOrigin::Value(format!("https://{}", host)));
+    }
+    let mut headers = Headers::new();
+    headers.set(Origin::new("https", "c.com", None));
+    let response = request::get("http://example.org/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+}
+
+#[test]
+fn test_builder_default_allows_any() {
+    //! The builder allows any origin by default
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::builder().build().unwrap());
+    let headers = setup_origin_header!("example.org");
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert_eq!(*response.headers.get::<AccessControlAllowOrigin>().unwrap(), AccessControlAllowOrigin::Any);
+}
+
+#[test]
+fn test_credentials_with_whitelist() {
+    //! Allowed credentials are announced on actual and preflight responses
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org:3000".to_string()])
+        .allow_credentials(true)
+        .build().unwrap());
+
+    let headers = setup_origin_header!("example.org", 3000);
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert!(response.headers.get::<AccessControlAllowCredentials>().is_some());
+
+    let mut headers = setup_origin_header!("example.org", 3000);
+    headers.set(AccessControlRequestMethod(iron::method::Post));
+    let response = request::options("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert!(response.headers.get::<AccessControlAllowCredentials>().is_some());
+}
+
+#[test]
+fn test_credentials_disabled_by_default() {
+    //! Without `allow_credentials`, no ACAC header is sent
+    let handler = setup_handler!("whitelist": ["http://example.org:3000"]);
+    let headers = setup_origin_header!("example.org", 3000);
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert!(response.headers.get::<AccessControlAllowCredentials>().is_none());
+}
+
+#[test]
+fn test_credentials_with_any_origin_rejected() {
+    //! Credentials can't be combined with a wildcard origin
+    let result = CorsMiddleware::builder().allow_credentials(true).build();
+    assert_eq!(result.err(), Some(CorsConfigError::CredentialsWithAnyOrigin));
+}
+
+#[test]
+fn test_max_age_on_preflight() {
+    //! A configured max age is sent on preflight responses of both modes
+    let whitelist = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org:3000".to_string()])
+        .max_age(Duration::from_secs(3600))
+        .build().unwrap();
+    let any = CorsMiddleware::builder()
+        .max_age(Duration::from_secs(3600))
+        .build().unwrap();
+
+    for middleware in [whitelist, any] {
+        let mut handler = Chain::new(HelloWorldHandler {});
+        handler.link_around(middleware);
+
+        let mut headers = setup_origin_header!("example.org", 3000);
+        headers.set(AccessControlRequestMethod(iron::method::Get));
+        let response = request::options("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert_eq!(response.status, Some(status::Ok));
+        assert_eq!(*response.headers.get::<AccessControlMaxAge>().unwrap(), AccessControlMaxAge(3600));
+
+        // Not on actual requests
+        let headers = setup_origin_header!("example.org", 3000);
+        let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert!(response.headers.get::<AccessControlMaxAge>().is_none());
+    }
+}
+
+#[test]
+fn test_ignore_port() {
+    //! With ignore_port, any port of a whitelisted origin is allowed
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::builder()
+        .allowed_origins(vec!["http://localhost:*".to_string(), "http://example.org:3000".to_string()])
+        .ignore_port(true)
+        .build().unwrap());
+
+    for &(host, port) in &[("localhost", Some(5173)), ("localhost", None), ("example.org", Some(8080))] {
+        let mut headers = Headers::new();
+        headers.set(Origin::new("http", host, port));
+        let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert_eq!(response.status, Some(status::Ok));
+        // The actual origin is echoed back
+        let expected = match port {
+            Some(port) => format!("http://{}:{}", host, port),
+            None => format!("http://{}", host),
+        };
+        assert_eq!(response.headers.get::<AccessControlAllowOrigin>(), Some(&AccessControlAllowOrigin::Value(expected)));
+    }
+
+    let mut headers = Headers::new();
+    headers.set(Origin::new("https", "localhost", Some(5173)));
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+
+    // Strict by default
+    let headers = setup_origin_header!("example.org", 8080);
+    let response = request::get("http://example.org:3000/hello", headers, &setup_handler!("whitelist": ["http://example.org:3000"])).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+}
+
+#[test]
+fn test_default_port_canonicalization() {
+    //! Default ports are equivalent to no port, on both sides
+    let handler = setup_handler!("whitelist": ["http://example.org", "https://example.com:443"]);
+
+    // Set the raw headers, the typed header drops ports 80 and 443 when serializing
+    let cases = ["http://example.org:80", "http://example.org", "https://example.com", "https://example.com:443"];
+    for origin in cases {
+        let mut headers = Headers::new();
+        headers.set_raw("Origin", vec![origin.as_bytes().to_vec()]);
+        let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert_eq!(response.status, Some(status::Ok));
+    }
+
+    // Other ports still don't match
+    let mut headers = Headers::new();
+    headers.set_raw("Origin", vec![b"http://example.org:443".to_vec()]);
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+}
+
+#[test]
+fn test_require_https_origins() {
+    //! Origins with another scheme are rejected even if whitelisted
+    let whitelist = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org".to_string(), "https://example.org".to_string()])
+        .require_https_origins(true)
+        .build().unwrap();
+    let any = CorsMiddleware::builder()
+        .require_https_origins(true)
+        .build().unwrap();
+
+    for middleware in [whitelist, any] {
+        let mut handler = Chain::new(HelloWorldHandler {});
+        handler.link_around(middleware);
+
+        let mut headers = Headers::new();
+        headers.set(Origin::new("https", "example.org", None));
+        let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert_eq!(response.status, Some(status::Ok));
+
+        let headers = setup_origin_header!("example.org");
+        let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert_eq!(response.status, Some(status::BadRequest));
+    }
+}
+
+#[test]
+fn test_null_origin_policy() {
+    //! The `null` origin is handled according to the policy
+    let cases = [
+        (NullOriginPolicy::PassThrough, status::Ok, None),
+        (NullOriginPolicy::Deny, status::BadRequest, None),
+        (NullOriginPolicy::Allow, status::Ok, Some(AccessControlAllowOrigin::Value("null".to_string()))),
+    ];
+    for (policy, expected_status, expected_header) in cases {
+        let mut handler = Chain::new(HelloWorldHandler {});
+        handler.link_around(CorsMiddleware::builder()
+            .allowed_origins(vec!["http://example.org".to_string()])
+            .null_origin_policy(policy)
+            .build().unwrap());
+
+        let mut headers = Headers::new();
+        headers.set_raw("Origin", vec![b"null".to_vec()]);
+        let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert_eq!(response.status, Some(expected_status));
+        assert_eq!(response.headers.get::<AccessControlAllowOrigin>(), expected_header.as_ref());
+    }
+}
+
+#[test]
+fn test_blacklist() {
+    //! All origins except the blocked ones are allowed
+    let blacklist = ["http://evil.example.com".to_string()].iter().cloned().collect::<HashSet<_>>();
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::with_blacklist(blacklist));
+
+    let headers = setup_origin_header!("example.org", 3000);
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    let header = response.headers.get::<AccessControlAllowOrigin>().unwrap();
+    assert_eq!(*header, AccessControlAllowOrigin::Value("http://example.org:3000".to_string()));
+
+    let headers = setup_origin_header!("evil.example.com");
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+    assert!(response.headers.get::<AccessControlAllowOrigin>().is_none());
+}
+
+#[test]
+fn test_origin_matchers() {
+    //! Origins matched by any of the matchers are allowed
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::with_matchers(vec![
+        OriginMatcher::Exact("HTTP://Example.org:3000".to_string()),
+        OriginMatcher::Exact("https://example.net:443".to_string()),
+        OriginMatcher::Subdomain("example.com".to_string())
+            .and(OriginMatcher::Fn(Box::new(|origin| origin.host.port.is_none()))),
+    ]));
+
+    for host in &["example.com", "api.example.com"] {
+        let headers = setup_origin_header!(*host);
+        let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert_eq!(response.status, Some(status::Ok));
+    }
+    let headers = setup_origin_header!("example.org", 3000);
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    let mut headers = Headers::new();
+    headers.set(Origin::new("https", "example.net", None));
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+
+    for headers in [setup_origin_header!("notexample.com"), setup_origin_header!("api.example.com", 8080)] {
+        let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert_eq!(response.status, Some(status::BadRequest));
+    }
+
+    // Prefixes end at a boundary
+    let prefix = OriginMatcher::Prefix("https://example.com".to_string());
+    assert!(prefix.matches(&Origin::new("HTTPS", "Example.com", Some(443))));
+    assert!(!prefix.matches(&Origin::new("https", "example.com.evil.net", None)));
+    assert!(!prefix.matches(&Origin::new("https", "example.com", Some(8443))));
+    let prefix = OriginMatcher::Prefix("https://app.".to_string());
+    assert!(prefix.matches(&Origin::new("https", "APP.example.com", None)));
+    assert!(!prefix.matches(&Origin::new("https", "application.example.com", None)));
+    assert!(OriginMatcher::Prefix("http://localhost:".to_string()).matches(&Origin::new("http", "localhost", Some(8080))));
+
+    // Domains are compared case-insensitively, whether normalized or not
+    let subdomain = OriginMatcher::Subdomain("Example.COM".to_string());
+    assert!(subdomain.matches(&Origin::new("https", "API.example.com", None)));
+    assert!(subdomain.matches(&Origin::new("https", "example.com", None)));
+    assert!(!subdomain.matches(&Origin::new("https", "notexample.com", None)));
+    assert!(!subdomain.matches(&Origin::new("https", "com", None)));
+}
+
+#[test]
+fn test_case_insensitive_origins() {
+    //! Scheme and host are compared case-insensitively
+    let handler = setup_handler!("whitelist": ["http://Example.org", "https://example.COM"]);
+
+    for origin in ["http://example.org", "HTTP://EXAMPLE.ORG", "https://Example.com"] {
+        let mut headers = Headers::new();
+        headers.set_raw("Origin", vec![origin.as_bytes().to_vec()]);
+        let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert_eq!(response.status, Some(status::Ok));
+    }
+
+    let origins = SharedOrigins::new(vec!["https://Example.com".to_string()]);
+    assert!(origins.contains("https://example.com:443"));
+    assert!(origins.remove("HTTPS://EXAMPLE.COM"));
+}
+
+#[test]
+fn test_ip_range_matcher() {
+    //! Origins with an IP address in the range are allowed
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::with_matchers(vec![
+        OriginMatcher::IpRange("http://10.0.0.0/8:*".parse().unwrap()),
+        OriginMatcher::IpRange("https://[fd00::]/8".parse().unwrap()),
+    ]));
+
+    let cases = [
+        (Origin::new("http", "10.20.30.40", Some(8080)), status::Ok),
+        (Origin::new("http", "10.0.0.1", None), status::Ok),
+        (Origin::new("http", "11.0.0.1", None), status::BadRequest),
+        (Origin::new("https", "10.0.0.1", None), status::BadRequest),
+        (Origin::new("http", "example.org", None), status::BadRequest),
+        (Origin::new("https", "[fd12::1]", None), status::Ok),
+        (Origin::new("https", "[fd12::1]", Some(8443)), status::BadRequest),
+    ];
+    for (origin, expected) in cases {
+        let mut headers = Headers::new();
+        headers.set(origin);
+        let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert_eq!(response.status, Some(expected));
+    }
+
+    for invalid in ["10.0.0.0/8", "http://10.0.0.0/33", "http://10.0.0.0", "http://10.0.0.0/8:x"] {
+        assert_eq!(invalid.parse::<IpRange>(), Err(CorsConfigError::InvalidOrigin(invalid.to_string())));
+    }
+}
+
+#[test]
+fn test_allow_localhost() {
+    //! Local origins with any port are allowed in addition to the whitelist
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org".to_string()])
+        .allow_localhost(true)
+        .build().unwrap());
+
+    let cases = [
+        (Origin::new("http", "localhost", Some(5173)), status::Ok),
+        (Origin::new("http", "127.0.0.1", Some(8080)), status::Ok),
+        (Origin::new("https", "[::1]", None), status::Ok),
+        (Origin::new("http", "example.org", None), status::Ok),
+        (Origin::new("http", "localhost.example.com", None), status::BadRequest),
+    ];
+    for (origin, expected) in cases {
+        let mut headers = Headers::new();
+        headers.set(origin);
+        let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert_eq!(response.status, Some(expected));
+    }
+
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::permissive_localhost());
+    let headers = setup_origin_header!("localhost", 3000);
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    let headers = setup_origin_header!("example.org");
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+}
+
+#[test]
+fn test_try_with_whitelist() {
+    //! Invalid whitelist entries are rejected at startup
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::try_with_whitelist(vec!["http://Example.org:3000"]).unwrap());
+    let headers = setup_origin_header!("example.org", 3000);
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+
+    let result = CorsMiddleware::try_with_whitelist(vec!["example.com", "https://example.org", "http://x:port"]);
+    assert_eq!(result.err(), Some(CorsConfigError::InvalidOrigins(vec![
+        "example.com".to_string(),
+        "http://x:port".to_string(),
+    ])));
+}
+
+#[test]
+fn test_parsed_origin() {
+    //! Origins are normalized the same way as by the middleware
+    use iron_cors::origin::{format_cors_origin, ParsedOrigin};
+
+    let parsed = ParsedOrigin::parse("HTTP://[::1]:8080").unwrap();
+    assert_eq!((parsed.scheme(), parsed.host(), parsed.port()), ("http", "[::1]", Some(8080)));
+    assert_eq!(parsed.to_string(), "http://[::1]:8080");
+    assert_eq!(ParsedOrigin::parse("https://example.com:443").unwrap().to_string(), "https://example.com");
+
+    let header = Origin::new("http", "Example.org", Some(80));
+    assert_eq!(ParsedOrigin::from(&header), ParsedOrigin::parse("http://example.org").unwrap());
+    assert_eq!(format_cors_origin(&header), "http://Example.org:80");
+
+    assert!(ParsedOrigin::parse("example.org").is_err());
+}
+
+#[test]
+fn test_skip_same_origin() {
+    //! Same-origin requests are passed through without CORS processing
+    let mut handler = Chain::new(DecisionHandler {});
+    handler.link_around(CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.com".to_string()])
+        .self_origin("https://api.example.org")
+        .build().unwrap());
+
+    let cases = [
+        Origin::new("http", "example.org", Some(3000)),
+        Origin::new("https", "api.example.org", None),
+    ];
+    for origin in cases {
+        let mut headers = Headers::new();
+        headers.set(origin);
+        let response = request::post("http://example.org:3000/hello", headers, "", &handler).unwrap();
+        assert_eq!(response.status, Some(status::Ok));
+        assert!(response.headers.get::<AccessControlAllowOrigin>().is_none());
+        assert_eq!(response::extract_body_to_string(response), format!("{:?}", Some(CorsDecision::SameOrigin)));
+    }
+
+    // Other ports are other origins
+    let headers = setup_origin_header!("example.org", 8080);
+    let response = request::post("http://example.org:3000/hello", headers, "", &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+}
+
+#[test]
+fn test_trusted_proxies() {
+    //! The forwarded headers of trusted proxies determine the own origin
+    let build = |proxies: Vec<IpAddr>| {
+        let mut handler = Chain::new(DecisionHandler {});
+        handler.link_around(CorsMiddleware::builder()
+            .allowed_origins(vec!["http://example.com".to_string()])
+            .skip_same_origin(true)
+            .trusted_proxies(proxies)
+            .build().unwrap());
+        handler
+    };
+    let forwarded_headers = || {
+        let mut headers = Headers::new();
+        headers.set(Origin::new("https", "api.example.org", None));
+        headers.set_raw("X-Forwarded-Proto", vec![b"https".to_vec()]);
+        headers.set_raw("X-Forwarded-Host", vec![b"api.example.org, internal.example.org".to_vec()]);
+        headers
+    };
+
+    let handler = build(vec!["127.0.0.1".parse().unwrap()]);
+    let response = request::post("http://localhost:3000/hello", forwarded_headers(), "", &handler).unwrap();
+    assert_eq!(response::extract_body_to_string(response), format!("{:?}", Some(CorsDecision::SameOrigin)));
+
+    // The forwarded headers of untrusted peers are ignored
+    let handler = build(vec!["10.0.0.1".parse().unwrap()]);
+    let response = request::post("http://localhost:3000/hello", forwarded_headers(), "", &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+}
+
+#[test]
+fn test_rules() {
+    //! The first matching rule decides, before path rules and the main policy
+    use iron::method::Method;
+    use iron_cors::CorsRule;
+
+    let mut handler = Chain::new(DecisionHandler {});
+    handler.link_around(CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.com".to_string()])
+        .rule(CorsRule::deny().path("/internal"))
+        .rule(CorsRule::allow(CorsPolicy::default())
+            .origin(OriginMatcher::Subdomain("partner.example.org".to_string()))
+            .methods(vec![Method::Get]))
+        .rule(CorsRule::pass_through().path("/webhooks"))
+        .rule(CorsRule::deny().origin(OriginMatcher::Subdomain("partner.example.org".to_string())))
+        .path_policy("/internal", CorsPolicy::default())
+        .build().unwrap());
+
+    let cases = [
+        (Method::Get, "/", "example.com", "Some(Allowed(\"http://example.com\"))"),
+        (Method::Get, "/", "api.partner.example.org", "Some(Allowed(\"http://api.partner.example.org\"))"),
+        (Method::Post, "/", "api.partner.example.org", ""),
+        (Method::Get, "/internal/users", "example.com", ""),
+        (Method::Get, "/internal/users", "api.partner.example.org", ""),
+        (Method::Post, "/webhooks", "api.partner.example.org", "Some(NotCors)"),
+        (Method::Get, "/webhooks", "api.partner.example.org", "Some(Allowed(\"http://api.partner.example.org\"))"),
+        (Method::Post, "/", "other.example.org", ""),
+    ];
+    for (method, path, origin, expected) in cases {
+        let url = format!("http://example.net{}", path);
+        let response = request::request(method.clone(), &url, "", setup_origin_header!(origin), &handler).unwrap();
+        if expected.is_empty() {
+            assert_eq!(response.status, Some(status::BadRequest), "{} {} {}", method, path, origin);
+        } else {
+            assert_eq!(response::extract_body_to_string(response), expected, "{} {} {}", method, path, origin);
+        }
+    }
+}
+
+#[test]
+fn test_allow_rule_without_origin_matcher() {
+    //! An allow rule without an origin matcher checks the origins of its policy
+    use iron_cors::CorsRule;
+
+    let mut handler = Chain::new(DecisionHandler {});
+    handler.link_around(CorsMiddleware::builder()
+        .allow_any_origin()
+        .rule(CorsRule::allow(CorsPolicy::builder()
+                .allowed_origins(vec!["http://example.com".to_string()])
+                .allow_credentials(true)
+                .build_policy().unwrap())
+            .path("/api"))
+        .build().unwrap());
+
+    let response = request::get("http://example.net/api", setup_origin_header!("example.com"), &handler).unwrap();
+    assert_eq!(response.headers.get::<AccessControlAllowCredentials>(), Some(&AccessControlAllowCredentials));
+    assert_eq!(response::extract_body_to_string(response), "Some(Allowed(\"http://example.com\"))");
+
+    let response = request::get("http://example.net/api", setup_origin_header!("evil.example.com"), &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+    assert!(!response.headers.has::<AccessControlAllowOrigin>());
+    assert!(!response.headers.has::<AccessControlAllowCredentials>());
+}
+
+#[test]
+fn test_except_origins_and_paths() {
+    //! Origins and paths can be excluded when any origin is allowed
+    let handler = CorsMiddleware::builder()
+        .allow_any_origin()
+        .except_origins(vec!["http://evil.example.com".to_string(), "HTTPS://Bad.example.com:443".to_string()])
+        .except_paths(vec!["/internal/*".to_string()])
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let cases = [
+        ("/", Origin::new("http", "example.com", None), status::Ok),
+        ("/internal/users", Origin::new("http", "example.com", None), status::BadRequest),
+        ("/", Origin::new("http", "evil.example.com", None), status::BadRequest),
+        ("/", Origin::new("https", "bad.example.com", None), status::BadRequest),
+        ("/", Origin::new("http", "bad.example.com", None), status::Ok),
+    ];
+    for (path, origin, expected) in cases {
+        let mut headers = Headers::new();
+        headers.set(origin);
+        let response = request::get(&format!("http://example.org{}", path), headers, &handler).unwrap();
+        assert_eq!(response.status, Some(expected), "{}", path);
+        assert_eq!(response.headers.has::<AccessControlAllowOrigin>(), expected == status::Ok);
+    }
+}
+
+#[test]
+fn test_skip_paths() {
+    //! Skipped paths bypass all CORS processing
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.com".to_string()])
+        .skip_paths(vec!["/health".to_string(), "/webhooks/*".to_string()])
+        .build().unwrap()
+        .wrap(DecisionHandler {});
+    for path in ["/health", "/webhooks/github"] {
+        let url = format!("http://example.org{}", path);
+        let response = request::post(&url, setup_origin_header!("sender.example.net"), "", &handler).unwrap();
+        assert_eq!(response.status, Some(status::Ok));
+        assert!(response.headers.get::<AccessControlAllowOrigin>().is_none());
+        assert_eq!(response::extract_body_to_string(response), "Some(NotCors)");
+    }
+    let response = request::post("http://example.org/healthz", setup_origin_header!("sender.example.net"), "", &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+}
+
+#[test]
+fn test_unrestricted_methods() {
+    //! The origin isn't checked for unrestricted methods
+    use iron::method::Method;
+
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.com".to_string()])
+        .unrestricted_methods(vec![Method::Get, Method::Head])
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let response = request::get("http://example.org/", setup_origin_header!("other.example.com"), &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert_eq!(response.headers.get::<AccessControlAllowOrigin>(),
+               Some(&AccessControlAllowOrigin::Value("http://other.example.com".to_string())));
+    let response = request::post("http://example.org/", setup_origin_header!("other.example.com"), "", &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+
+    // Preflights are checked against the requested method
+    let mut headers = setup_origin_header!("other.example.com");
+    headers.set(AccessControlRequestMethod(Method::Get));
+    let response = request::options("http://example.org/", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert!(response.headers.has::<AccessControlAllowOrigin>());
+    let mut headers = setup_origin_header!("other.example.com");
+    headers.set(AccessControlRequestMethod(Method::Put));
+    let response = request::options("http://example.org/", headers, &handler).unwrap();
+    assert!(!response.headers.has::<AccessControlAllowOrigin>());
+
+    // Excepted paths deny unrestricted methods as well
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.com".to_string()])
+        .unrestricted_methods(vec![Method::Get])
+        .except_paths(vec!["/internal/*".to_string()])
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let response = request::get("http://example.org/public", setup_origin_header!("other.example.com"), &handler).unwrap();
+    assert!(response.headers.has::<AccessControlAllowOrigin>());
+    let response = request::get("http://example.org/internal/users", setup_origin_header!("other.example.com"), &handler)
+        .unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+    assert!(!response.headers.has::<AccessControlAllowOrigin>());
+
+    // Credentials are only announced to allowed origins
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.com".to_string()])
+        .allow_credentials(true)
+        .unrestricted_methods(vec![Method::Get])
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let mut headers = setup_origin_header!("other.example.com");
+    headers.set_raw("Cookie", vec![b"session=secret".to_vec()]);
+    let response = request::get("http://example.org/", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert!(response.headers.has::<AccessControlAllowOrigin>());
+    assert!(!response.headers.has::<AccessControlAllowCredentials>());
+    let mut headers = setup_origin_header!("other.example.com");
+    headers.set(AccessControlRequestMethod(Method::Get));
+    let response = request::options("http://example.org/", headers, &handler).unwrap();
+    assert!(response.headers.has::<AccessControlAllowOrigin>());
+    assert!(!response.headers.has::<AccessControlAllowCredentials>());
+    let response = request::get("http://example.org/", setup_origin_header!("example.com"), &handler).unwrap();
+    assert!(response.headers.has::<AccessControlAllowCredentials>());
+}
+
+#[test]
+fn test_method_policies() {
+    //! The policy depends on the method of the (preflighted) request
+    let read_only = CorsPolicy::builder().allow_any_origin().build_policy().unwrap();
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::builder()
+        .allowed_origins(vec!["http://admin.example.org".to_string()])
+        .method_policy(iron::method::Get, read_only)
+        .build().unwrap());
+
+    let headers = setup_origin_header!("example.com");
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    let headers = setup_origin_header!("example.com");
+    let response = request::post("http://example.org:3000/hello", headers, "", &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+
+    for (method, expected) in [(iron::method::Get, status::Ok), (iron::method::Delete, status::BadRequest)] {
+        let mut headers = setup_origin_header!("example.com");
+        headers.set(AccessControlRequestMethod(method));
+        let response = request::options("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert_eq!(response.status, Some(expected));
+    }
+}
+
+#[test]
+fn test_preflight_status() {
+    //! Preflights are answered with the configured status and an empty body
+    let default = CorsMiddleware::with_allow_any();
+    let no_content = CorsMiddleware::builder()
+        .preflight_status(status::NoContent)
+        .build().unwrap();
+
+    for (middleware, expected) in [(default, status::Ok), (no_content, status::NoContent)] {
+        let mut handler = Chain::new(HelloWorldHandler {});
+        handler.link_around(middleware);
+
+        let mut headers = setup_origin_header!("example.org", 3000);
+        headers.set(AccessControlRequestMethod(iron::method::Get));
+        let response = request::options("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert_eq!(response.status, Some(expected));
+        assert_eq!(*response.headers.get::<ContentLength>().unwrap(), ContentLength(0));
+        assert_eq!(response::extract_body_to_string(response), "");
+    }
+}
+
+#[test]
+fn test_timing_allow_origin() {
+    //! Timing-Allow-Origin mirrors Access-Control-Allow-Origin on actual responses
+    let whitelist = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org:3000".to_string()])
+        .timing_allow_origin(true)
+        .build().unwrap();
+    let any = CorsMiddleware::builder()
+        .timing_allow_origin(true)
+        .build().unwrap();
+
+    for (middleware, expected) in [(whitelist, "http://example.org:3000"), (any, "*")] {
+        let mut handler = Chain::new(HelloWorldHandler {});
+        handler.link_around(middleware);
+
+        let headers = setup_origin_header!("example.org", 3000);
+        let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert_eq!(response.headers.get_raw("Timing-Allow-Origin").unwrap(), &[expected.as_bytes().to_vec()]);
+
+        // Not for other origins
+        let headers = setup_origin_header!("example.com");
+        let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert_eq!(response.headers.get_raw("Timing-Allow-Origin").is_some(), expected == "*");
+    }
+
+    let headers = setup_origin_header!("example.org", 3000);
+    let response = request::get("http://example.org:3000/hello", headers, &setup_handler!("any")).unwrap();
+    assert!(response.headers.get_raw("Timing-Allow-Origin").is_none());
+}
+
+#[test]
+fn test_expose_headers_on_actual_response() {
+    //! Exposed headers are sent on actual responses, but not on preflights
+    let whitelist = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org:3000".to_string()])
+        .expose_headers(vec!["X-Total-Count".to_string()])
+        .build().unwrap();
+    let any = CorsMiddleware::builder()
+        .expose_headers(vec!["X-Total-Count".to_string()])
+        .build().unwrap();
+
+    for middleware in [whitelist, any] {
+        let mut handler = Chain::new(HelloWorldHandler {});
+        handler.link_around(middleware);
+
+        let headers = setup_origin_header!("example.org", 3000);
+        let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert_eq!(response.status, Some(status::Ok));
+        assert_eq!(*response.headers.get::<AccessControlExposeHeaders>().unwrap(),
+                   AccessControlExposeHeaders(vec![UniCase("X-Total-Count".to_string())]));
+
+        let mut headers = setup_origin_header!("example.org", 3000);
+        headers.set(AccessControlRequestMethod(iron::method::Get));
+        let response = request::options("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert!(response.headers.get::<AccessControlExposeHeaders>().is_none());
+    }
+}
+
+#[test]
+fn test_allowed_methods_preflight() {
+    //! Preflights announce all allowed methods and reject other methods
+    let whitelist = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org:3000".to_string()])
+        .allowed_methods(vec![iron::method::Get, iron::method::Post])
+        .build().unwrap();
+    let any = CorsMiddleware::builder()
+        .allowed_methods(vec![iron::method::Get, iron::method::Post])
+        .build().unwrap();
+
+    for middleware in [whitelist, any] {
+        let mut handler = Chain::new(HelloWorldHandler {});
+        handler.link_around(middleware);
+
+        let mut headers = setup_origin_header!("example.org", 3000);
+        headers.set(AccessControlRequestMethod(iron::method::Post));
+        let response = request::options("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert_eq!(response.status, Some(status::Ok));
+        assert_eq!(*response.headers.get::<AccessControlAllowMethods>().unwrap(),
+                   AccessControlAllowMethods(vec![iron::method::Get, iron::method::Post]));
+
+        let mut headers = setup_origin_header!("example.org", 3000);
+        headers.set(AccessControlRequestMethod(iron::method::Delete));
+        let response = request::options("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert_eq!(response.status, Some(status::BadRequest));
+        assert!(response.headers.get::<AccessControlAllowOrigin>().is_none());
+        assert!(response.headers.get::<AccessControlAllowMethods>().is_none());
+        let result_body = response::extract_body_to_string(response);
+        assert_eq!(&result_body, "Invalid CORS request: Method not allowed");
+    }
+}
+
+#[test]
+fn test_allowed_headers_preflight() {
+    //! Preflights announce all allowed headers and reject other headers
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org:3000".to_string()])
+        .allowed_headers(vec![UniCase("Content-Type".to_string()), UniCase("X-Requested-With".to_string())])
+        .build().unwrap());
+
+    // Header names are compared case-insensitively
+    let mut headers = setup_origin_header!("example.org", 3000);
+    headers.set(AccessControlRequestMethod(iron::method::Post));
+    headers.set(AccessControlRequestHeaders(vec![UniCase("content-type".to_string())]));
+    let response = request::options("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert_eq!(*response.headers.get::<AccessControlAllowHeaders>().unwrap(),
+               AccessControlAllowHeaders(vec![UniCase("Content-Type".to_string()), UniCase("X-Requested-With".to_string())]));
+
+    let mut headers = setup_origin_header!("example.org", 3000);
+    headers.set(AccessControlRequestMethod(iron::method::Post));
+    headers.set(AccessControlRequestHeaders(vec![UniCase("content-type".to_string()), UniCase("x-evil".to_string())]));
+    let response = request::options("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+    assert!(response.headers.get::<AccessControlAllowHeaders>().is_none());
+    let result_body = response::extract_body_to_string(response);
+    assert_eq!(&result_body, "Invalid CORS request: Header not allowed");
+}
+
+struct VaryHandler;
+impl Handler for VaryHandler {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        let mut response = Response::with((status::Ok, "Hello, world!"));
+        response.headers.set(Vary::Items(vec![UniCase("Accept-Encoding".to_string())]));
+        Ok(response)
+    }
+}
+
+#[test]
+fn test_whitelist_vary_origin() {
+    //! Whitelist responses vary on the origin, merged with the handler's Vary header
+    let mut handler = Chain::new(VaryHandler {});
+    let whitelist = ["http://example.org:3000"].iter().map(ToString::to_string).collect::<HashSet<_>>();
+    handler.link_around(CorsMiddleware::with_whitelist(whitelist));
+    let headers = setup_origin_header!("example.org", 3000);
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert_eq!(*response.headers.get::<Vary>().unwrap(),
+               Vary::Items(vec![UniCase("Accept-Encoding".to_string()), UniCase("Origin".to_string())]));
+}
+
+#[test]
+fn test_whitelist_preflight_vary() {
+    //! Whitelist preflight responses vary on the origin and the request headers
+    let handler = setup_handler!("whitelist": ["http://example.org:3000"]);
+    let mut headers = setup_origin_header!("example.org", 3000);
+    headers.set(AccessControlRequestMethod(iron::method::Get));
+    let response = request::options("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert_eq!(*response.headers.get::<Vary>().unwrap(), Vary::Items(vec![
+        UniCase("Origin".to_string()),
+        UniCase("Access-Control-Request-Method".to_string()),
+        UniCase("Access-Control-Request-Headers".to_string()),
+    ]));
+}
+
+#[test]
+fn test_origin_fn() {
+    //! A custom function decides which origins are allowed
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::with_origin_fn(|origin| origin.host.hostname.ends_with(".example.org")));
+
+    let headers = setup_origin_header!("app.example.org");
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert_eq!(*response.headers.get::<AccessControlAllowOrigin>().unwrap(),
+               AccessControlAllowOrigin::Value("http://app.example.org".into()));
+
+    let headers = setup_origin_header!("example.com");
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+    assert!(response.headers.get::<AccessControlAllowOrigin>().is_none());
+}
+
+#[test]
+fn test_origin_fn_with_credentials() {
+    //! Credentials may be combined with a custom origin function
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::builder()
+        .allowed_origin_fn(|origin| origin.host.port == Some(3000))
+        .allow_credentials(true)
+        .build().unwrap());
+
+    let headers = setup_origin_header!("example.org", 3000);
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert!(response.headers.get::<AccessControlAllowCredentials>().is_some());
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn test_whitelist_regex() {
+    //! Origins matching one of the regular expressions are allowed
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::with_whitelist_regex(vec![
+        regex::Regex::new(r"^http://app-[0-9]+\.example\.org$").unwrap(),
+    ]));
+
+    let headers = setup_origin_header!("app-42.example.org");
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert_eq!(*response.headers.get::<AccessControlAllowOrigin>().unwrap(),
+               AccessControlAllowOrigin::Value("http://app-42.example.org".into()));
+
+    let headers = setup_origin_header!("app-x.example.org");
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+}
+
+#[test]
+fn test_allow_any_reflect() {
+    //! In reflect mode, any origin is echoed back and credentials are allowed
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::builder()
+        .reflect_any_origin()
+        .allow_credentials(true)
+        .build().unwrap());
+
+    let headers = setup_origin_header!("example.org", 3000);
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert_eq!(*response.headers.get::<AccessControlAllowOrigin>().unwrap(),
+               AccessControlAllowOrigin::Value("http://example.org:3000".into()));
+    assert!(response.headers.get::<AccessControlAllowCredentials>().is_some());
+    assert_eq!(*response.headers.get::<Vary>().unwrap(), Vary::Items(vec![UniCase("Origin".to_string())]));
+}
+
+#[test]
+fn test_whitelist_disallowed_pass_through() {
+    //! With `PassThrough`, disallowed origins reach the handler without CORS headers
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org:3000".to_string()])
+        .disallowed_origin_behavior(DisallowedOriginBehavior::PassThrough)
+        .build().unwrap());
+
+    let headers = setup_origin_header!("forbidden.org");
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert!(response.headers.get::<AccessControlAllowOrigin>().is_none());
+    let result_body = response::extract_body_to_string(response);
+    assert_eq!(&result_body, "Hello, world!");
+
+    let mut headers = setup_origin_header!("forbidden.org");
+    headers.set(AccessControlRequestMethod(iron::method::Get));
+    let response = request::options("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert!(response.headers.get::<AccessControlAllowOrigin>().is_none());
+    assert!(response.headers.get::<AccessControlAllowMethods>().is_none());
+}
+
+#[test]
+fn test_path_policies() {
+    //! The policy of the first matching path rule is used
+    let public = CorsMiddleware::builder().allow_any_origin().build_policy().unwrap();
+    let admin = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://admin.example.org".to_string()])
+        .build_policy().unwrap();
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org".to_string()])
+        .path_policy("/api/public", public)
+        .path_policy("/api/*/admin", admin)
+        .build().unwrap());
+
+    // Prefix rule
+    let headers = setup_origin_header!("other.org");
+    let response = request::get("http://example.org/api/public/items", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert_eq!(*response.headers.get::<AccessControlAllowOrigin>().unwrap(), AccessControlAllowOrigin::Any);
+
+    // Prefixes only match full path segments
+    let headers = setup_origin_header!("other.org");
+    let response = request::get("http://example.org/api/publicity", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+
+    // Glob rule
+    let headers = setup_origin_header!("admin.example.org");
+    let response = request::get("http://example.org/api/v1/admin", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    let headers = setup_origin_header!("example.org");
+    let response = request::get("http://example.org/api/v1/admin", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+
+    // Default policy
+    let headers = setup_origin_header!("example.org");
+    let response = request::get("http://example.org/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    let headers = setup_origin_header!("admin.example.org");
+    let response = request::get("http://example.org/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+}
+
+#[test]
+fn test_policy_without_handler() {
+    //! Policies can be inspected without an Iron handler
+    let policy = CorsPolicy::builder()
+        .allowed_origins(vec!["http://example.org:3000".to_string()])
+        .allowed_methods(vec![iron::method::Get])
+        .allowed_headers(vec![UniCase("Content-Type".to_string())])
+        .build_policy().unwrap();
+    assert!(policy.is_origin_allowed(&Origin::new("http", "example.org", Some(3000))));
+    assert!(!policy.is_origin_allowed(&Origin::new("http", "example.org", None)));
+    assert!(policy.is_method_allowed(&iron::method::Get));
+    assert!(!policy.is_method_allowed(&iron::method::Post));
+    assert!(policy.is_header_allowed("content-type"));
+    assert!(!policy.is_header_allowed("x-custom"));
+
+    let default = CorsPolicy::default();
+    assert!(default.is_origin_allowed(&Origin::new("http", "example.org", None)));
+    assert!(default.is_method_allowed(&iron::method::Delete));
+}
+
+#[test]
+fn test_policy_shared_between_chains() {
+    //! A cloned policy behaves the same in multiple chains
+    let policy = CorsPolicy::builder()
+        .allowed_origins(vec!["http://example.org:3000".to_string()])
+        .build_policy().unwrap();
+
+    for _ in 0..2 {
+        let mut handler = Chain::new(HelloWorldHandler {});
+        handler.link_around(CorsMiddleware::from_policy(policy.clone()));
+        let headers = setup_origin_header!("example.org", 3000);
+        let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert_eq!(response.status, Some(status::Ok));
+        assert_eq!(*response.headers.get::<AccessControlAllowOrigin>().unwrap(),
+                   AccessControlAllowOrigin::Value("http://example.org:3000".into()));
+    }
+}
+
+#[test]
+fn test_config_into_policy() {
+    //! A plain configuration is validated when turned into a policy
+    let config = CorsConfig {
+        allowed_origins: Some(vec!["http://example.org:3000".to_string()]),
+        allowed_methods: Some(vec!["GET".to_string()]),
+        allow_credentials: true,
+        ..CorsConfig::default()
+    };
+    let policy = config.into_policy().unwrap();
+    assert!(policy.is_origin_allowed(&Origin::new("http", "example.org", Some(3000))));
+    assert!(!policy.is_method_allowed(&iron::method::Post));
+
+    let config = CorsConfig {
+        allowed_origins: Some(vec!["example.org".to_string()]),
+        ..CorsConfig::default()
+    };
+    assert_eq!(config.into_policy().err(), Some(CorsConfigError::InvalidOrigin("example.org".to_string())));
+
+    let config = CorsConfig {
+        allowed_origins: Some(vec!["http://example.org/path".to_string()]),
+        ..CorsConfig::default()
+    };
+    assert!(config.into_policy().is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_deserialize_policy() {
+    //! Policies can be deserialized from a configuration file
+    let policy: CorsPolicy = serde_json::from_str(r#"{
+        "allowed_origins": ["http://example.org:3000"],
+        "allowed_methods": ["GET", "POST"],
+        "max_age": 3600,
+        "disallowed_origin_behavior": "pass_through"
+    }"#).unwrap();
+    assert!(policy.is_origin_allowed(&Origin::new("http", "example.org", Some(3000))));
+    assert!(policy.is_method_allowed(&iron::method::Post));
+    assert!(!policy.is_method_allowed(&iron::method::Delete));
+
+    let result = serde_json::from_str::<CorsPolicy>(r#"{"allowed_origins": ["example.org"]}"#);
+    assert!(result.unwrap_err().to_string().contains("Invalid origin"));
+
+    let result = serde_json::from_str::<CorsPolicy>(r#"{"allow_credentials": true}"#);
+    assert!(result.is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_policy_to_json() {
+    //! The configuration of a policy can be exported as JSON
+    let policy = CorsPolicy::builder()
+        .allowed_origins(vec!["http://example.org:3000".to_string()])
+        .allow_credentials(true)
+        .max_age(Duration::from_secs(600))
+        .disallowed_origin_behavior(DisallowedOriginBehavior::PassThrough)
+        .build_policy()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_str(&policy.to_json()).unwrap();
+    assert_eq!(json["origin_mode"], "whitelist");
+    assert_eq!(json["origins"], serde_json::json!(["http://example.org:3000"]));
+    assert_eq!(json["allow_credentials"], true);
+    assert_eq!(json["max_age"], 600);
+    assert_eq!(json["disallowed_origin_behavior"], "pass_through");
+
+    // Every option that affects decisions is exported
+    let policy = CorsPolicy::builder()
+        .allowed_origins(vec!["https://app.example.com".to_string()])
+        .except_origins(vec!["https://b.example.com".to_string(), "https://a.example.com".to_string()])
+        .allowed_schemes(vec!["https".to_string()])
+        .require_tls(iron_cors::TlsRequirement::All)
+        .header_policy(HeaderPolicy::Wildcard)
+        .skip_same_origin(true)
+        .extra_preflight_headers(vec![("X-Preflight".to_string(), "1".to_string())])
+        .preflight_status(status::NoContent)
+        .build_policy()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_str(&policy.to_json()).unwrap();
+    assert_eq!(json["except_origins"], serde_json::json!(["https://a.example.com", "https://b.example.com"]));
+    assert_eq!(json["allowed_schemes"], serde_json::json!(["https"]));
+    assert_eq!(json["tls_requirement"], "all");
+    assert_eq!(json["header_policy"], "wildcard");
+    assert_eq!(json["skip_same_origin"], true);
+    assert_eq!(json["extra_preflight_headers"], serde_json::json!([["X-Preflight", "1"]]));
+    assert_eq!(json["preflight_status"], 204);
+    assert_eq!(json["raw_origins"], serde_json::json!([]));
+
+    let json: serde_json::Value = serde_json::from_str(&CorsPolicy::default().to_json()).unwrap();
+    assert_eq!(json["origin_mode"], "any");
+    assert!(json["origins"].is_null());
+}
+
+#[test]
+fn test_from_env() {
+    //! The middleware can be configured through environment variables
+    std::env::set_var("TEST_ENV_CORS_ALLOWED_ORIGINS", "http://example.org:3000, http://example.com");
+    std::env::set_var("TEST_ENV_CORS_ALLOW_CREDENTIALS", "true");
+    std::env::set_var("TEST_ENV_CORS_MAX_AGE", "600");
+    let config = CorsConfig::from_env("TEST_ENV_CORS").unwrap();
+    assert_eq!(config.allowed_origins,
+               Some(vec!["http://example.org:3000".to_string(), "http://example.com".to_string()]));
+    assert!(config.allow_credentials);
+    assert_eq!(config.max_age, Some(600));
+
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::from_env("TEST_ENV_CORS").unwrap());
+    let headers = setup_origin_header!("example.org", 3000);
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert!(response.headers.get::<AccessControlAllowCredentials>().is_some());
+}
+
+#[test]
+fn test_from_env_invalid() {
+    //! Invalid environment variables are reported
+    std::env::set_var("TEST_ENV_INVALID_CORS_MAX_AGE", "ten minutes");
+    assert_eq!(CorsConfig::from_env("TEST_ENV_INVALID_CORS").err(), Some(CorsEnvError::InvalidValue {
+        variable: "TEST_ENV_INVALID_CORS_MAX_AGE".to_string(),
+        value: "ten minutes".to_string(),
+    }));
+
+    std::env::set_var("TEST_ENV_ANY_CORS_ALLOWED_ORIGINS", "*");
+    std::env::set_var("TEST_ENV_ANY_CORS_ALLOW_CREDENTIALS", "true");
+    assert_eq!(CorsMiddleware::from_env("TEST_ENV_ANY_CORS").err(),
+               Some(CorsEnvError::Config(CorsConfigError::CredentialsWithAnyOrigin)));
+}
+
+#[test]
+fn test_shared_whitelist() {
+    //! Origins can be added and removed while the middleware is running
+    let origins = SharedOrigins::new(vec!["http://example.org:3000".to_string()]);
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::with_shared_whitelist(origins.clone()));
+
+    let headers = setup_origin_header!("example.com");
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+
+    assert!(origins.insert("http://example.com".to_string()));
+    let headers = setup_origin_header!("example.com");
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+
+    assert!(origins.contains("HTTP://Example.com:80"));
+    assert!(origins.remove("http://example.org:3000"));
+    let headers = setup_origin_header!("example.org", 3000);
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+}
+
+#[test]
+fn test_whitelist_file() {
+    //! The whitelist is reloaded when the file changes
+    let path = std::env::temp_dir().join(format!("iron-cors-whitelist-{}.txt", std::process::id()));
+    std::fs::write(&path, "# Allowed origins\nhttp://example.org:3000\n\n").unwrap();
+
+    let file = WhitelistFile::open(&path).unwrap().check_interval(Duration::from_secs(0));
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::with_whitelist_file(file.clone()));
+
+    let headers = setup_origin_header!("example.org", 3000);
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    let headers = setup_origin_header!("example.com");
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+
+    std::fs::write(&path, "http://example.com\n").unwrap();
+    // Make sure the modification time differs on file systems with coarse timestamps
+    let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+    std::fs::File::options().write(true).open(&path).unwrap()
+        .set_modified(modified + Duration::from_secs(10)).unwrap();
+
+    let headers = setup_origin_header!("example.com");
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    let headers = setup_origin_header!("example.org", 3000);
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+    assert!(file.origins().contains("http://example.com"));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_decision_extension() {
+    //! The CORS decision is available to the inner handler
+    let mut handler = Chain::new(DecisionHandler {});
+    handler.link_around(CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org:3000".to_string()])
+        .disallowed_origin_behavior(DisallowedOriginBehavior::PassThrough)
+        .build().unwrap());
+
+    let cases = [
+        (Headers::new(), CorsDecision::NotCors),
+        (setup_origin_header!("example.org", 3000), CorsDecision::Allowed("http://example.org:3000".to_string())),
+        (setup_origin_header!("example.com"), CorsDecision::Denied("http://example.com".to_string())),
+    ];
+    for (headers, expected) in cases {
+        let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert_eq!(response::extract_body_to_string(response), format!("{:?}", Some(expected)));
+    }
+}
+
+#[test]
+fn test_hooks() {
+    //! The hooks are called with the origin and path of allowed and denied requests
+    let events = Arc::new(Mutex::new(vec![]));
+    let (allowed, denied) = (events.clone(), events.clone());
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org:3000".to_string()])
+        .on_allowed(move |origin, path| allowed.lock().unwrap().push(format!("allowed {} {}", origin, path)))
+        .on_denied(move |origin, path| denied.lock().unwrap().push(format!("denied {} {}", origin, path)))
+        .build().unwrap());
+
+    let headers = setup_origin_header!("example.org", 3000);
+    request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    let headers = setup_origin_header!("example.com");
+    request::get("http://example.org:3000/api/users", headers, &handler).unwrap();
+    request::get("http://example.org:3000/hello", Headers::new(), &handler).unwrap();
+
+    assert_eq!(*events.lock().unwrap(), vec![
+        "allowed http://example.org:3000 /hello".to_string(),
+        "denied http://example.com /api/users".to_string(),
+    ]);
+}
+
+#[test]
+fn test_metrics() {
+    //! Preflights, allowed and denied requests are counted
+    let metrics = Arc::new(CorsMetrics::new());
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org:3000".to_string()])
+        .metrics(metrics.clone())
+        .build().unwrap());
+
+    let mut headers = setup_origin_header!("example.org", 3000);
+    headers.set(AccessControlRequestMethod(iron::method::Get));
+    request::options("http://example.org:3000/hello", headers, &handler).unwrap();
+    for _ in 0..2 {
+        let headers = setup_origin_header!("example.org", 3000);
+        request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    }
+    let headers = setup_origin_header!("example.com");
+    request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    request::get("http://example.org:3000/hello", Headers::new(), &handler).unwrap();
+
+    assert_eq!(metrics.preflights(), 1);
+    assert_eq!(metrics.allowed(), 2);
+    assert_eq!(metrics.denied(), 1);
+    assert_eq!(metrics.cache_hits(), 0);
+
+    // Hits of the preflight cache and the decision cache are counted
+    let metrics = Arc::new(CorsMetrics::new());
+    let handler = CorsMiddleware::builder()
+        .allowed_origin_fn(|origin| origin.host.hostname == "example.org")
+        .decision_cache(Arc::new(iron_cors::DecisionCache::new(10, Duration::from_secs(60))))
+        .preflight_cache(10)
+        .metrics(metrics.clone())
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    for _ in 0..2 {
+        let mut headers = setup_origin_header!("example.org", 3000);
+        headers.set(AccessControlRequestMethod(iron::method::Get));
+        request::options("http://example.org:3000/hello", headers, &handler).unwrap();
+    }
+    assert_eq!(metrics.preflights(), 2);
+    assert_eq!(metrics.cache_hits(), 2);
+}
+
+#[cfg(feature = "prometheus")]
+#[test]
+fn test_prometheus_metrics() {
+    //! Denials are recorded per origin in the registry
+    let registry = prometheus::Registry::new();
+    let mut handler = Chain::new(HelloWorldHandler {});
+    handler.link_around(CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org:3000".to_string()])
+        .prometheus(iron_cors::CorsPrometheusMetrics::new(&registry).unwrap())
+        .build().unwrap());
+
+    let mut headers = setup_origin_header!("example.org", 3000);
+    headers.set(AccessControlRequestMethod(iron::method::Get));
+    request::options("http://example.org:3000/hello", headers, &handler).unwrap();
+    let headers = setup_origin_header!("example.com");
+    request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+
+    let families = registry.gather();
+    let family = |name: &str| families.iter().find(|family| family.get_name() == name).unwrap();
+    let denied = &family("cors_denied_total").get_metric()[0];
+    assert_eq!(denied.get_label()[0].get_value(), "http://example.com");
+    assert_eq!(denied.get_counter().get_value(), 1.0);
+    let preflights = family("cors_preflight_duration_seconds").get_metric()[0].get_histogram();
+    assert_eq!(preflights.get_sample_count(), 1);
+
+    // Origins past the limit are counted as `other`
+    let registry = prometheus::Registry::new();
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org:3000".to_string()])
+        .prometheus(iron_cors::CorsPrometheusMetrics::new(&registry).unwrap().max_origin_labels(2))
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    for host in ["a.example.com", "b.example.com", "c.example.com", "d.example.com", "a.example.com"] {
+        request::get("http://example.org:3000/hello", setup_origin_header!(host), &handler).unwrap();
+    }
+    let families = registry.gather();
+    let denied = families.iter().find(|family| family.get_name() == "cors_denied_total").unwrap();
+    let mut counts = denied.get_metric().iter()
+        .map(|metric| (metric.get_label()[0].get_value().to_string(), metric.get_counter().get_value()))
+        .collect::<Vec<_>>();
+    counts.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(counts, vec![
+        ("http://a.example.com".to_string(), 2.0),
+        ("http://b.example.com".to_string(), 1.0),
+        ("other".to_string(), 2.0),
+    ]);
+}
+
+#[cfg(feature = "core")]
+#[test]
+fn test_core_evaluate() {
+    //! The framework-agnostic interface makes the same decisions
+    use iron_cors::core::{CorsRequest, CorsResponse};
+
+    let policy = CorsPolicy::builder()
+        .allowed_origins(vec!["http://example.org:3000".to_string()])
+        .allowed_methods(vec![iron::method::Get])
+        .build_policy().unwrap();
+
+    let preflight = CorsRequest {
+        method: "OPTIONS",
+        origin: Some("http://example.org:3000"),
+        request_method: Some("GET"),
+        request_headers: Some("X-Custom, Content-Type"),
+        ..CorsRequest::default()
+    };
+    match policy.evaluate(&preflight) {
+        CorsResponse::Preflight { status, headers } => {
+            assert_eq!(status, 200);
+            assert!(headers.contains(&("Access-Control-Allow-Methods".to_string(), "GET".to_string())));
+            assert!(headers.contains(&("Access-Control-Allow-Headers".to_string(), "X-Custom, Content-Type".to_string())));
+        },
+        other => panic!("Unexpected response: {:?}", other),
+    }
+
+    let invalid = CorsRequest { request_method: Some("DELETE"), ..preflight };
+    assert_eq!(policy.evaluate(&invalid), CorsResponse::Reject {
+        status: 400,
+        body: "Invalid CORS request: Method not allowed".to_string(),
+        headers: vec![],
+    });
+    let disallowed = CorsRequest { method: "GET", origin: Some("http://example.com"), ..CorsRequest::default() };
+    assert!(matches!(policy.evaluate(&disallowed), CorsResponse::Reject { status: 400, .. }));
+    assert_eq!(policy.evaluate(&CorsRequest { method: "GET", ..CorsRequest::default() }), CorsResponse::PassThrough);
+}
+
+#[cfg(feature = "core")]
+#[test]
+fn test_core_evaluate_checks() {
+    //! The framework-agnostic interface applies the same checks as the
+    //! middleware
+    use iron_cors::{CsrfHeader, DisallowedOriginBehavior, TlsRequirement};
+    use iron_cors::core::{CorsRequest, CorsResponse};
+
+    let builder = || CorsPolicy::builder().allowed_origins(vec!["https://example.org".to_string()]);
+    let allowed = CorsRequest { method: "POST", origin: Some("https://example.org"), secure: true, ..CorsRequest::default() };
+    let disallowed = CorsRequest { origin: Some("https://example.com"), ..allowed };
+    let rejection = |reason: &str| CorsResponse::Reject {
+        status: 400,
+        body: format!("Invalid CORS request: {}", reason),
+        headers: vec![],
+    };
+
+    // Report-only mode allows the request
+    let policy = builder().report_only(true).build_policy().unwrap();
+    assert!(matches!(policy.evaluate(&disallowed), CorsResponse::Actual { .. }));
+
+    // Insecure requests are handled like requests from disallowed origins
+    let policy = builder().require_tls(TlsRequirement::All).build_policy().unwrap();
+    assert!(matches!(policy.evaluate(&allowed), CorsResponse::Actual { .. }));
+    assert_eq!(policy.evaluate(&CorsRequest { secure: false, ..allowed }), rejection("Origin not allowed"));
+
+    let policy = builder().reject_cross_site(vec![iron::method::Post]).build_policy().unwrap();
+    assert_eq!(policy.evaluate(&CorsRequest { sec_fetch_site: Some("cross-site"), ..allowed }),
+               rejection("Cross-site request not allowed"));
+    assert!(matches!(policy.evaluate(&CorsRequest { sec_fetch_site: Some("same-site"), ..allowed }),
+                     CorsResponse::Actual { .. }));
+
+    let policy = builder()
+        .disallowed_origin_behavior(DisallowedOriginBehavior::PassThrough)
+        .csrf_validator(CsrfHeader::new("X-Requested-With"))
+        .build_policy().unwrap();
+    assert_eq!(policy.evaluate(&disallowed), rejection("CSRF check failed"));
+    assert_eq!(policy.evaluate(&CorsRequest { csrf_validated: true, ..disallowed }), CorsResponse::PassThrough);
+    assert_eq!(policy.evaluate(&CorsRequest { own_origin: Some("https://EXAMPLE.com:443"), ..disallowed }),
+               CorsResponse::PassThrough);
+
+    // Same-origin requests are passed through
+    let policy = builder().skip_same_origin(true).build_policy().unwrap();
+    assert_eq!(policy.evaluate(&disallowed), rejection("Origin not allowed"));
+    assert_eq!(policy.evaluate(&CorsRequest { own_origin: Some("https://example.com"), ..disallowed }),
+               CorsResponse::PassThrough);
+    let policy = builder().trust_sec_fetch_site(true).build_policy().unwrap();
+    assert_eq!(policy.evaluate(&CorsRequest { sec_fetch_site: Some("same-origin"), ..disallowed }),
+               CorsResponse::PassThrough);
+    assert_eq!(policy.evaluate(&CorsRequest { sec_fetch_site: Some("cross-site"), ..disallowed }),
+               rejection("Origin not allowed"));
+}
+
+#[cfg(feature = "core")]
+#[test]
+fn test_core_asterisk_options() {
+    //! Server-wide `OPTIONS *` requests are passed through or answered with
+    //! the allowed methods
+    use iron_cors::AsteriskOptionsBehavior;
+    use iron_cors::core::{CorsRequest, CorsResponse};
+
+    let request = CorsRequest {
+        method: "OPTIONS",
+        origin: Some("http://example.com"),
+        request_method: Some("GET"),
+        target: Some("*"),
+        ..CorsRequest::default()
+    };
+    let builder = || CorsPolicy::builder()
+        .allowed_origins(vec!["http://example.org".to_string()])
+        .allowed_methods(vec![iron::method::Get, iron::method::Post]);
+    assert_eq!(builder().build_policy().unwrap().evaluate(&request), CorsResponse::PassThrough);
+    let policy = builder().asterisk_options(AsteriskOptionsBehavior::Allow).build_policy().unwrap();
+    assert_eq!(policy.evaluate(&request), CorsResponse::Preflight {
+        status: 200,
+        headers: vec![("Allow".to_string(), "GET, POST".to_string())],
+    });
+}
+
+#[test]
+fn test_wrap() {
+    //! A handler can be wrapped directly, without a chain
+    let handler = CorsMiddleware::with_whitelist(["http://example.org"])
+        .wrap(HelloWorldHandler {});
+    let response = request::get("http://example.org:3000/hello", setup_origin_header!("example.org"), &handler).unwrap();
+    assert_eq!(response.headers.get::<AccessControlAllowOrigin>(),
+               Some(&AccessControlAllowOrigin::Value("http://example.org".to_string())));
+    assert_eq!(&response::extract_body_to_string(response), "Hello, world!");
+
+    let response = request::get("http://example.org:3000/hello", setup_origin_header!("example.com"), &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+}
+
+#[test]
+fn test_strict_preflights() {
+    //! Strict mode doesn't echo unconfigured methods and headers, and fails
+    //! preflights without CORS headers
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org:3000".to_string()])
+        .strict(true)
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+
+    let mut headers = setup_origin_header!("example.org", 3000);
+    headers.set(AccessControlRequestMethod(iron::method::Post));
+    headers.set(AccessControlRequestHeaders(vec![UniCase("content-type".to_string())]));
+    let response = request::options("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert!(response.headers.get::<AccessControlAllowOrigin>().is_some());
+
+    let requests = [
+        (iron::method::Extension("PURGE".to_string()), None),
+        (iron::method::Post, Some("X-Custom")),
+    ];
+    for (method, header) in requests {
+        let mut headers = setup_origin_header!("example.org", 3000);
+        headers.set(AccessControlRequestMethod(method));
+        if let Some(header) = header {
+            headers.set(AccessControlRequestHeaders(vec![UniCase(header.to_string())]));
+        }
+        let response = request::options("http://example.org:3000/hello", headers, &handler).unwrap();
+        assert_eq!(response.status, Some(status::Ok));
+        assert!(response.headers.get::<AccessControlAllowOrigin>().is_none());
+        assert!(response.headers.get::<AccessControlAllowMethods>().is_none());
+        assert!(response.headers.get::<AccessControlAllowHeaders>().is_none());
+    }
+}
+
+#[test]
+fn test_enforce_allowed_methods() {
+    //! Actual requests with a method that is not allowed are rejected
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org:3000".to_string()])
+        .allowed_methods(vec![iron::method::Get, iron::method::Post])
+        .enforce_allowed_methods(true)
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+
+    let response = request::get("http://example.org:3000/hello", setup_origin_header!("example.org", 3000), &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+
+    let response = request::delete("http://example.org:3000/hello", setup_origin_header!("example.org", 3000), &handler).unwrap();
+    assert_eq!(response.status, Some(status::MethodNotAllowed));
+    assert_eq!(response.headers.get::<iron::headers::Allow>(),
+               Some(&iron::headers::Allow(vec![iron::method::Get, iron::method::Post])));
+    assert!(response.headers.get::<AccessControlAllowOrigin>().is_none());
+
+    // Same-origin requests and requests without an origin are not affected
+    let response = request::delete("http://example.org:3000/hello", Headers::new(), &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+}
+
+#[test]
+fn test_wildcard_header_policy() {
+    //! The wildcard is announced unless credentials are allowed
+    for (allow_credentials, expect_wildcard) in [(false, true), (true, false)] {
+        let handler = CorsMiddleware::builder()
+            .allowed_origins(vec!["http://example.org:3000".to_string()])
+            .allow_credentials(allow_credentials)
+            .header_policy(HeaderPolicy::Wildcard)
+            .build().unwrap()
+            .wrap(HelloWorldHandler {});
+
+        let mut headers = setup_origin_header!("example.org", 3000);
+        headers.set(AccessControlRequestMethod(iron::method::Put));
+        headers.set(AccessControlRequestHeaders(vec![UniCase("X-Custom".to_string())]));
+        let response = request::options("http://example.org:3000/hello", headers, &handler).unwrap();
+        let (methods, allowed_headers) = if expect_wildcard {
+            (vec![iron::method::Extension("*".to_string())], vec![UniCase("*".to_string()), UniCase("Authorization".to_string())])
+        } else {
+            (vec![iron::method::Put], vec![UniCase("X-Custom".to_string())])
+        };
+        assert_eq!(response.headers.get::<AccessControlAllowMethods>(), Some(&AccessControlAllowMethods(methods)));
+        assert_eq!(response.headers.get::<AccessControlAllowHeaders>(), Some(&AccessControlAllowHeaders(allowed_headers)));
+    }
+}
+
+#[test]
+fn test_allow_any_preflight_headers() {
+    //! Allow-any policies announce common methods and the header wildcard
+    let build = |header_policy| CorsMiddleware::builder()
+        .header_policy(header_policy)
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let preflight = |handler: &_, method| {
+        let mut headers = setup_origin_header!("example.org", 3000);
+        headers.set(AccessControlRequestMethod(method));
+        headers.set(AccessControlRequestHeaders(vec![UniCase("X-Custom".to_string())]));
+        request::options("http://example.org:3000/hello", headers, handler).unwrap()
+    };
+    let wildcard_headers = AccessControlAllowHeaders(vec![UniCase("*".to_string()), UniCase("Authorization".to_string())]);
+
+    let handler = build(HeaderPolicy::Echo);
+    let response = preflight(&handler, iron::method::Put);
+    assert_eq!(response.headers.get::<AccessControlAllowMethods>(), Some(&AccessControlAllowMethods(vec![
+        iron::method::Get, iron::method::Head, iron::method::Post, iron::method::Put,
+        iron::method::Patch, iron::method::Delete, iron::method::Options,
+    ])));
+    assert_eq!(response.headers.get::<AccessControlAllowHeaders>(), Some(&wildcard_headers));
+    let method = iron::method::Extension("PROPFIND".to_string());
+    let response = preflight(&handler, method.clone());
+    assert_eq!(response.headers.get::<AccessControlAllowMethods>(), Some(&AccessControlAllowMethods(vec![method])));
+
+    let handler = build(HeaderPolicy::Wildcard);
+    let response = preflight(&handler, iron::method::Extension("PROPFIND".to_string()));
+    assert_eq!(response.headers.get::<AccessControlAllowMethods>(),
+               Some(&AccessControlAllowMethods(vec![iron::method::Extension("*".to_string())])));
+    assert_eq!(response.headers.get::<AccessControlAllowHeaders>(), Some(&wildcard_headers));
+}
+
+#[test]
+fn test_wildcard_authorization() {
+    //! Authorization is listed next to the wildcard unless disabled
+    let handler = CorsMiddleware::builder()
+        .header_policy(HeaderPolicy::Wildcard)
+        .wildcard_authorization(false)
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+
+    let mut headers = setup_origin_header!("example.org", 3000);
+    headers.set(AccessControlRequestMethod(iron::method::Get));
+    headers.set(AccessControlRequestHeaders(vec![UniCase("Authorization".to_string())]));
+    let response = request::options("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.headers.get::<AccessControlAllowHeaders>(),
+               Some(&AccessControlAllowHeaders(vec![UniCase("*".to_string())])));
+}
+
+#[test]
+fn test_default_allowed_headers() {
+    //! The default headers are announced next to the requested headers
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org:3000".to_string()])
+        .default_allowed_headers(vec![UniCase("Content-Type".to_string())])
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+
+    let mut headers = setup_origin_header!("example.org", 3000);
+    headers.set(AccessControlRequestMethod(iron::method::Get));
+    let response = request::options("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.headers.get::<AccessControlAllowHeaders>(),
+               Some(&AccessControlAllowHeaders(vec![UniCase("Content-Type".to_string())])));
+
+    let mut headers = setup_origin_header!("example.org", 3000);
+    headers.set(AccessControlRequestMethod(iron::method::Get));
+    headers.set(AccessControlRequestHeaders(vec![UniCase("content-type".to_string()), UniCase("X-Custom".to_string())]));
+    let response = request::options("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.headers.get::<AccessControlAllowHeaders>(),
+               Some(&AccessControlAllowHeaders(vec![UniCase("Content-Type".to_string()), UniCase("X-Custom".to_string())])));
+}
+
+#[test]
+fn test_extra_headers() {
+    //! Extra headers are added to preflight and actual responses
+    let handler = CorsMiddleware::builder()
+        .extra_preflight_headers(vec![("Access-Control-Allow-Private-Network".to_string(), "true".to_string())])
+        .extra_actual_headers(vec![("X-Api-Version".to_string(), "2".to_string())])
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+
+    let mut headers = setup_origin_header!("example.org", 3000);
+    headers.set(AccessControlRequestMethod(iron::method::Get));
+    let response = request::options("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.headers.get_raw("Access-Control-Allow-Private-Network"), Some(&[b"true".to_vec()][..]));
+    assert!(response.headers.get_raw("X-Api-Version").is_none());
+
+    let response = request::get("http://example.org:3000/hello", setup_origin_header!("example.org", 3000), &handler).unwrap();
+    assert_eq!(response.headers.get_raw("X-Api-Version"), Some(&[b"2".to_vec()][..]));
+    assert!(response.headers.get_raw("Access-Control-Allow-Private-Network").is_none());
+}
+
+#[test]
+fn test_header_conflict_policy() {
+    //! CORS headers of the inner handler are overwritten, preserved or merged
+    struct CorsHeadersHandler;
+    impl Handler for CorsHeadersHandler {
+        fn handle(&self, _: &mut Request) -> IronResult<Response> {
+            let mut response = Response::with((status::Ok, "Hello, world!"));
+            response.headers.set(AccessControlAllowOrigin::Value("http://example.org:3000".to_string()));
+            response.headers.set(AccessControlExposeHeaders(vec![UniCase("X-Handler".to_string())]));
+            Ok(response)
+        }
+    }
+
+    let cases = [
+        (HeaderConflictPolicy::Overwrite, AccessControlAllowOrigin::Any, vec!["X-Policy"]),
+        (HeaderConflictPolicy::Preserve, AccessControlAllowOrigin::Value("http://example.org:3000".to_string()), vec!["X-Handler"]),
+        (HeaderConflictPolicy::Merge, AccessControlAllowOrigin::Value("http://example.org:3000".to_string()), vec!["X-Handler", "X-Policy"]),
+    ];
+    for (policy, allow_origin, expose_headers) in cases {
+        let handler = CorsMiddleware::builder()
+            .expose_headers(vec!["X-Policy".to_string()])
+            .header_conflict_policy(policy)
+            .build().unwrap()
+            .wrap(CorsHeadersHandler {});
+
+        let response = request::get("http://example.org:3000/hello", setup_origin_header!("example.org", 3000), &handler).unwrap();
+        assert_eq!(response.headers.get::<AccessControlAllowOrigin>(), Some(&allow_origin));
+        let expose_headers = expose_headers.into_iter().map(|header| UniCase(header.to_string())).collect();
+        assert_eq!(response.headers.get::<AccessControlExposeHeaders>(), Some(&AccessControlExposeHeaders(expose_headers)));
+    }
+}
+
+#[test]
+fn test_preflight_cache() {
+    //! Cached preflight responses are the same as uncached ones
+    let origins = vec!["http://example.org:3000".to_string(), "http://example.com".to_string()];
+    let uncached = CorsMiddleware::builder()
+        .allowed_origins(origins.clone())
+        .max_age(Duration::from_secs(60))
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let cached = CorsMiddleware::builder()
+        .allowed_origins(origins)
+        .max_age(Duration::from_secs(60))
+        .preflight_cache(1)
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+
+    let requests = [("example.org", Some(3000)), ("example.com", None), ("example.org", Some(3000)), ("example.org", Some(3000))];
+    for (host, port) in requests {
+        let mut headers = Headers::new();
+        headers.set(Origin::new("http", host, port));
+        headers.set(AccessControlRequestMethod(iron::method::Put));
+        headers.set(AccessControlRequestHeaders(vec![UniCase("X-Custom".to_string())]));
+        let expected = request::options("http://example.org:3000/hello", headers.clone(), &uncached).unwrap();
+        let response = request::options("http://example.org:3000/hello", headers, &cached).unwrap();
+        assert_eq!(response.status, expected.status);
+        assert_eq!(response.headers, expected.headers);
+    }
+}
+
+#[test]
+fn test_long_origin_whitelist() {
+    //! Origins of any length are matched
+    let host = format!("{}.example.org", "a".repeat(300));
+    let handler = CorsMiddleware::with_whitelist([format!("https://{}", host)])
+        .wrap(HelloWorldHandler {});
+
+    let mut headers = Headers::new();
+    headers.set(Origin::new("HTTPS", host.to_uppercase(), Some(443)));
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+
+    let mut headers = Headers::new();
+    headers.set(Origin::new("https", &host, Some(8443)));
+    let response = request::get("http://example.org:3000/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+}
+
+#[test]
+fn test_clone_middleware() {
+    //! One middleware can decorate multiple chains
+    let middleware = CorsMiddleware::with_whitelist(["http://example.org"]);
+    assert!(format!("{:?}", middleware).contains("http://example.org"));
+
+    let mut api = Chain::new(HelloWorldHandler {});
+    api.link_around(middleware.clone());
+    let auth = middleware.wrap(HelloWorldHandler {});
+
+    let api_response = request::get("http://example.org:3000/api", setup_origin_header!("example.org"), &api).unwrap();
+    let auth_response = request::get("http://example.org:3000/auth", setup_origin_header!("example.org"), &auth).unwrap();
+    for response in [api_response, auth_response] {
+        assert_eq!(response.headers.get::<AccessControlAllowOrigin>(),
+                   Some(&AccessControlAllowOrigin::Value("http://example.org".to_string())));
+    }
+}
+
+#[test]
+fn test_cors_mount() {
+    //! Mount points use their own configuration, other paths are not CORS processed
+    use iron_cors::CorsMount;
+
+    let auth = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org".to_string()])
+        .build().unwrap();
+    let mut chain = Chain::new(HelloWorldHandler {});
+    chain.link_around(CorsMount::new().mount("/api", CorsMiddleware::with_allow_any()).mount("/auth", auth));
+
+    let response = request::get("http://example.org:3000/api/users", setup_origin_header!("example.com"), &chain).unwrap();
+    assert_eq!(response.headers.get::<AccessControlAllowOrigin>(), Some(&AccessControlAllowOrigin::Any));
+    let response = request::get("http://example.org:3000/auth", setup_origin_header!("example.com"), &chain).unwrap();
+    assert_eq!(response.status, Some(status::BadRequest));
+    let response = request::get("http://example.org:3000/other", setup_origin_header!("example.com"), &chain).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert!(response.headers.get::<AccessControlAllowOrigin>().is_none());
+
+    let handler = CorsMiddleware::with_allow_any().around_routes(HelloWorldHandler {}, &["/api"]);
+    let response = request::get("http://example.org:3000/api", setup_origin_header!("example.com"), &handler).unwrap();
+    assert_eq!(response.headers.get::<AccessControlAllowOrigin>(), Some(&AccessControlAllowOrigin::Any));
+    let response = request::get("http://example.org:3000/apiary", setup_origin_header!("example.com"), &handler).unwrap();
+    assert!(response.headers.get::<AccessControlAllowOrigin>().is_none());
+}
+
+#[test]
+fn test_policy_provider() {
+    //! The provider selects the policy per request
+    use iron_cors::PolicyProvider;
+
+    struct HostProvider {
+        public: CorsPolicy,
+    }
+    impl PolicyProvider for HostProvider {
+        fn policy_for(&self, req: &Request) -> Option<&CorsPolicy> {
+            match req.url.host() {
+                iron::url::Host::Domain("public.example.org") => Some(&self.public),
+                _ => None,
+            }
+        }
+    }
+
+    let provider = HostProvider { public: CorsPolicy::builder().build_policy().unwrap() };
+    let handler = CorsMiddleware::with_policy_provider(provider).wrap(DecisionHandler {});
+
+    let response = request::get("http://public.example.org/hello", setup_origin_header!("example.com"), &handler).unwrap();
+    assert_eq!(response.headers.get::<AccessControlAllowOrigin>(), Some(&AccessControlAllowOrigin::Any));
+    let response = request::get("http://private.example.org/hello", setup_origin_header!("example.com"), &handler).unwrap();
+    assert!(response.headers.get::<AccessControlAllowOrigin>().is_none());
+    assert_eq!(response::extract_body_to_string(response), "Some(NotCors)");
+}
+
+#[test]
+fn test_policy_set() {
+    //! Requests are mapped to named policies
+    use iron_cors::CorsPolicySet;
+
+    let policies = CorsPolicySet::new(|req: &Request| match req.url.path().first().cloned() {
+        Some("partner") => Some("partner"),
+        Some("missing") => Some("missing"),
+        Some("internal") => None,
+        _ => Some("public"),
+    })
+        .policy("public", CorsPolicy::default())
+        .policy("partner", CorsPolicy::builder()
+            .allowed_origins(vec!["http://partner.example.org".to_string()])
+            .build_policy().unwrap());
+    let mut names = policies.names().collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, vec!["partner", "public"]);
+
+    let handler = CorsMiddleware::with_policy_provider(policies).wrap(DecisionHandler {});
+    let cases = [
+        ("/public", Some("Some(Allowed(\"http://example.com\"))")),
+        ("/partner", None),
+        ("/internal", Some("Some(NotCors)")),
+        ("/missing", Some("Some(NotCors)")),
+    ];
+    for (path, expected) in cases {
+        let url = format!("http://example.org{}", path);
+        let response = request::get(&url, setup_origin_header!("example.com"), &handler).unwrap();
+        match expected {
+            Some(decision) => assert_eq!(response::extract_body_to_string(response), decision),
+            None => assert_eq!(response.status, Some(status::BadRequest)),
+        }
+    }
+}
+
+#[test]
+fn test_host_policies() {
+    //! Requests are mapped to policies by their host name
+    use iron_cors::HostPolicies;
+
+    let only = |origin: &str| CorsPolicy::builder()
+        .allowed_origins(vec![origin.to_string()])
+        .build_policy().unwrap();
+    let policies = HostPolicies::new()
+        .host("api.example.org", only("http://api.example.com"))
+        .host("*.example.org", only("http://tenant.example.com"))
+        .host("*.eu.example.org", only("http://eu.example.com"));
+    let handler = CorsMiddleware::with_policy_provider(policies.clone().default_policy(CorsPolicy::default()))
+        .wrap(HelloWorldHandler {});
+    let cases = [
+        ("api.example.org", "api.example.com"),
+        ("API.example.org", "api.example.com"),
+        ("a.example.org", "tenant.example.com"),
+        ("a.eu.example.org", "eu.example.com"),
+        ("example.org", "anything.example.net"),
+    ];
+    for (host, origin) in cases {
+        let url = format!("http://{}/", host);
+        let response = request::get(&url, setup_origin_header!(origin), &handler).unwrap();
+        assert_eq!(response.status, Some(status::Ok), "{}", host);
+        let response = request::get(&url, setup_origin_header!("other.example.com"), &handler).unwrap();
+        let expected = if host == "example.org" { status::Ok } else { status::BadRequest };
+        assert_eq!(response.status, Some(expected), "{}", host);
+    }
+
+    // Host names are compared case-insensitively
+    assert!(policies.get("A.EU.Example.ORG.").is_some());
+    assert!(policies.get("EXAMPLE.ORG").is_none());
+
+    // Without a default policy, other hosts are passed through
+    assert!(policies.get("example.org").is_none());
+    let handler = CorsMiddleware::with_policy_provider(policies).wrap(DecisionHandler {});
+    let response = request::get("http://example.org/", setup_origin_header!("example.com"), &handler).unwrap();
+    assert_eq!(response::extract_body_to_string(response), "Some(NotCors)");
+}
+
+#[test]
+fn test_trace() {
+    //! The trace explains the decision
+    use iron_cors::CorsTrace;
+
+    struct TraceHandler;
+    impl Handler for TraceHandler {
+        fn handle(&self, req: &mut Request) -> IronResult<Response> {
+            let trace = req.extensions.get::<CorsTrace>().map(ToString::to_string);
+            Ok(Response::with((status::Ok, trace.unwrap_or_default())))
+        }
+    }
+
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org".to_string()])
+        .require_https_origins(true)
+        .disallowed_origin_behavior(DisallowedOriginBehavior::PassThrough)
+        .trace(true)
+        .build().unwrap()
+        .wrap(TraceHandler {});
+
+    let response = request::get("http://example.org/hello", setup_origin_header!("example.org"), &handler).unwrap();
+    assert_eq!(response::extract_body_to_string(response), "require_https_origins failed: the scheme of the origin is http");
+
+    let policy = CorsPolicy::builder()
+        .allowed_origins(vec!["https://example.org".to_string()])
+        .build_policy().unwrap();
+    let trace = policy.explain(&Origin::new("https", "example.com", None));
+    assert!(!trace.is_allowed());
+    assert_eq!(trace.steps().len(), 1);
+    assert_eq!(trace.steps()[0].rule, "allowed_origins");
+    assert_eq!(trace.steps()[0].detail, "https://example.com is not in the whitelist of 1 origins");
+}
+
+#[test]
+fn test_report_only() {
+    //! Rejections are recorded, but the requests are allowed
+    let denied = Arc::new(Mutex::new(vec![]));
+    let denied_hook = denied.clone();
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org".to_string()])
+        .allowed_methods(vec![iron::method::Get])
+        .report_only(true)
+        .on_denied(move |origin, _| denied_hook.lock().unwrap().push(origin.to_string()))
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+
+    let response = request::get("http://example.org/hello", setup_origin_header!("example.com"), &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert_eq!(response.headers.get::<AccessControlAllowOrigin>(),
+               Some(&AccessControlAllowOrigin::Value("http://example.com".to_string())));
+
+    let mut headers = setup_origin_header!("example.org");
+    headers.set(AccessControlRequestMethod(iron::method::Delete));
+    let response = request::options("http://example.org/hello", headers, &handler).unwrap();
+    assert_eq!(response.status, Some(status::Ok));
+    assert_eq!(response.headers.get::<AccessControlAllowMethods>(),
+               Some(&AccessControlAllowMethods(vec![iron::method::Delete])));
+
+    assert_eq!(*denied.lock().unwrap(), vec!["http://example.com", "http://example.org"]);
+}
+
+#[test]
+fn test_event_sink() {
+    //! The sink receives structured events
+    use iron_cors::{CorsEvent, CorsEventSink};
+
+    type Event = (String, Option<&'static str>, bool);
+    struct Collect(Arc<Mutex<Vec<Event>>>);
+    impl CorsEventSink for Collect {
+        fn event(&self, event: &CorsEvent) {
+            self.0.lock().unwrap().push((event.origin.to_string(), event.rule, event.preflight));
+        }
+    }
+
+    let events = Arc::new(Mutex::new(vec![]));
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org".to_string()])
+        .allowed_methods(vec![iron::method::Get])
+        .event_sink(Collect(events.clone()))
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+
+    request::get("http://example.org/hello", setup_origin_header!("example.org"), &handler).unwrap();
+    request::get("http://example.org/hello", setup_origin_header!("example.com"), &handler).unwrap();
+    let mut headers = setup_origin_header!("example.org");
+    headers.set(AccessControlRequestMethod(iron::method::Put));
+    request::options("http://example.org/hello", headers, &handler).unwrap();
+
+    assert_eq!(*events.lock().unwrap(), vec![
+        ("http://example.org".to_string(), None, false),
+        ("http://example.com".to_string(), Some("allowed_origins"), false),
+        ("http://example.org".to_string(), Some("preflight"), true),
+    ]);
+}
+
+#[test]
+fn test_log_levels_and_rate_limit() {
+    //! Messages about rejections can be disabled and rate limited
+    use iron_cors::LogEvent;
+    use std::sync::Once;
+
+    static MESSAGES: Mutex<Vec<String>> = Mutex::new(vec![]);
+    struct CaptureLogger;
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool { true }
+        fn log(&self, record: &log::Record) {
+            // With the `tracing` feature, decisions are also logged at the
+            // debug level
+            if record.target().starts_with("iron_cors") && record.level() <= log::Level::Info {
+                MESSAGES.lock().unwrap().push(format!("{} {}", record.level(), record.args()));
+            }
+        }
+        fn flush(&self) {}
+    }
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        log::set_logger(&CaptureLogger).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+    });
+    let messages = |host: &str| MESSAGES.lock().unwrap().iter().filter(|m| m.contains(host)).cloned().collect::<Vec<_>>();
+
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org".to_string()])
+        .log_level(LogEvent::DisallowedOrigin, Some(log::Level::Info))
+        .log_rate_limit(2, Duration::from_millis(50))
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    for _ in 0..5 {
+        request::get("http://example.org/hello", setup_origin_header!("limited.example.com"), &handler).unwrap();
+    }
+    assert_eq!(messages("limited.example.com"), vec![
+        "INFO Got disallowed CORS request from limited.example.com",
+        "INFO Got disallowed CORS request from limited.example.com",
+    ]);
+    std::thread::sleep(Duration::from_millis(60));
+    request::get("http://example.org/hello", setup_origin_header!("limited.example.com"), &handler).unwrap();
+    let limited = messages("limited.example.com");
+    assert_eq!(limited.len(), 4);
+    assert!(limited[2].starts_with("INFO Suppressed 3 CORS log messages about limited.example.com"));
+
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org".to_string()])
+        .log_level(LogEvent::DisallowedOrigin, None)
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    request::get("http://example.org/hello", setup_origin_header!("silent.example.com"), &handler).unwrap();
+    assert!(messages("silent.example.com").is_empty());
+
+    // Unexpected hosts are logged with the same levels and limits
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org".to_string()])
+        .allowed_hosts(vec!["example.org"])
+        .log_rate_limit(1, Duration::from_secs(60))
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    for _ in 0..3 {
+        request::get("http://unexpected.example.net/hello", Headers::new(), &handler).unwrap();
+    }
+    assert_eq!(messages("unexpected.example.net"), vec![
+        "WARN Rejecting request with unexpected Host unexpected.example.net",
+    ]);
+    let handler = CorsMiddleware::builder()
+        .allowed_hosts(vec!["example.org"])
+        .log_level(LogEvent::UnexpectedHost, None)
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    request::get("http://silent.example.net/hello", Headers::new(), &handler).unwrap();
+    assert!(messages("silent.example.net").is_empty());
+}
+
+#[test]
+fn test_recent_denials() {
+    //! The most recent denials are kept
+    use iron_cors::RecentDenials;
+
+    let denials = Arc::new(RecentDenials::new(2));
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org".to_string()])
+        .recent_denials(denials.clone())
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+
+    for (host, path) in [("a.example.com", "/a"), ("example.org", "/allowed"), ("b.example.com", "/b"), ("c.example.com", "/c")] {
+        let url = format!("http://example.org{}", path);
+        request::get(&url, setup_origin_header!(host), &handler).unwrap();
+    }
+    let entries = denials.entries().into_iter().map(|denial| (denial.origin, denial.path)).collect::<Vec<_>>();
+    assert_eq!(entries, vec![
+        ("http://b.example.com".to_string(), "/b".to_string()),
+        ("http://c.example.com".to_string(), "/c".to_string()),
+    ]);
+    denials.clear();
+    assert!(denials.entries().is_empty());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_debug_handler() {
+    //! The debug handler describes the policy as JSON
+    use iron_cors::CorsDebugHandler;
+
+    let policy = CorsPolicy::builder()
+        .allowed_origins(vec!["https://b.example.com".to_string(), "https://a.example.com".to_string()])
+        .allowed_methods(vec![iron::method::Get])
+        .allow_credentials(true)
+        .max_age(Duration::from_secs(60))
+        .build_policy().unwrap();
+    let handler = CorsDebugHandler::new(policy.clone());
+
+    let res = request::get("http://example.org/cors", Headers::new(), &handler).unwrap();
+    assert_eq!(res.headers.get::<iron::headers::ContentType>(), Some(&iron::headers::ContentType::json()));
+    assert_eq!(response::extract_body_to_string(res), policy.to_json());
+
+    let get = |url: &str| {
+        let res = request::get(url, Headers::new(), &handler).unwrap();
+        serde_json::from_str::<serde_json::Value>(&response::extract_body_to_string(res)).unwrap()
+    };
+    let json = get("http://example.org/cors?origin=https://A.example.com");
+    assert_eq!(json["origins"], serde_json::json!(["https://a.example.com", "https://b.example.com"]));
+    assert_eq!(json["allowed_methods"], serde_json::json!(["GET"]));
+    assert_eq!(json["origin"], "https://A.example.com");
+    assert_eq!(json["origin_allowed"], true);
+    let json = get("http://example.org/cors?origin=https://c.example.com");
+    assert_eq!(json["origin_allowed"], false);
+}
+
+#[test]
+fn test_admin_handler() {
+    //! The admin handler modifies the shared whitelist and persists changes
+    use iron_cors::CorsAdminHandler;
+
+    let origins = SharedOrigins::new(vec!["https://a.example.com".to_string()]);
+    let saved = Arc::new(Mutex::new(vec![]));
+    let saved_clone = saved.clone();
+    let handler = CorsAdminHandler::new(origins.clone(), |req| req.headers.get_raw("X-Admin").is_some())
+        .persistence(move |origins: &HashSet<String>| {
+            saved_clone.lock().unwrap().push(origins.len());
+            Ok(())
+        });
+    let mut admin = Headers::new();
+    admin.set_raw("X-Admin", vec![b"1".to_vec()]);
+
+    let res = request::post("http://example.org/origins", Headers::new(), "https://b.example.com", &handler).unwrap();
+    assert_eq!(res.status, Some(status::Forbidden));
+    let res = request::post("http://example.org/origins", admin.clone(), "b.example.com", &handler).unwrap();
+    assert_eq!(res.status, Some(status::BadRequest));
+
+    let res = request::post("http://example.org/origins", admin.clone(), "HTTPS://B.example.com\n", &handler).unwrap();
+    assert_eq!(response::extract_body_to_string(res), r#"["https://a.example.com","https://b.example.com"]"#);
+    assert!(origins.contains("https://b.example.com"));
+    let res = request::delete("http://example.org/origins?origin=https://a.example.com", admin.clone(), &handler).unwrap();
+    assert_eq!(response::extract_body_to_string(res), r#"["https://b.example.com"]"#);
+    // Unchanged whitelists are not persisted
+    request::delete("http://example.org/origins?origin=https://a.example.com", admin.clone(), &handler).unwrap();
+    assert_eq!(*saved.lock().unwrap(), vec![2, 1]);
+
+    // Changes that can't be persisted are reverted
+    let handler = CorsAdminHandler::new(origins.clone(), |_| true)
+        .persistence(|_: &HashSet<String>| Err(Error::other("read-only")));
+    let res = request::post("http://example.org/origins?origin=https://c.example.com", Headers::new(), "", &handler).unwrap();
+    assert_eq!(res.status, Some(status::InternalServerError));
+    assert!(!origins.contains("https://c.example.com"));
+}
+
+#[test]
+fn test_origin_store() {
+    //! The whitelist can be kept in a custom origin store
+    use iron_cors::OriginStore;
+
+    #[derive(Default)]
+    struct VecStore(Mutex<Vec<String>>);
+
+    impl OriginStore for VecStore {
+        fn contains(&self, origin: &str) -> bool {
+            self.0.lock().unwrap().iter().any(|entry| entry == origin)
+        }
+        fn list(&self) -> Vec<String> {
+            self.0.lock().unwrap().clone()
+        }
+        fn insert(&self, origin: String) -> bool {
+            self.0.lock().unwrap().push(origin);
+            true
+        }
+        fn remove(&self, _origin: &str) -> bool {
+            false
+        }
+    }
+
+    let store = VecStore::default();
+    store.insert("http://example.org".to_string());
+    let handler = CorsMiddleware::with_origin_store(store).wrap(HelloWorldHandler {});
+
+    let res = request::get("http://example.org/", setup_origin_header!("EXAMPLE.org"), &handler).unwrap();
+    assert_eq!(res.status, Some(status::Ok));
+    assert!(res.headers.get::<AccessControlAllowOrigin>().is_some());
+    let res = request::get("http://example.org/", setup_origin_header!("example.com"), &handler).unwrap();
+    assert_eq!(res.status, Some(status::BadRequest));
+
+    // With ignore_port, the store is asked for the origin without its port
+    let store = VecStore::default();
+    store.insert("http://example.org:3000".to_string());
+    let handler = CorsMiddleware::builder()
+        .origin_store(store)
+        .ignore_port(true)
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let res = request::get("http://example.org/", setup_origin_header!("example.org", 8080), &handler).unwrap();
+    assert!(res.headers.get::<AccessControlAllowOrigin>().is_some());
+    let res = request::get("http://example.org/", setup_origin_header!("example.com", 3000), &handler).unwrap();
+    assert_eq!(res.status, Some(status::BadRequest));
+}
+
+#[test]
+fn test_decision_cache() {
+    //! Decisions of dynamic origin checks are cached until they expire or are
+    //! invalidated
+    use iron_cors::DecisionCache;
+
+    let calls = Arc::new(Mutex::new(0));
+    let calls_clone = calls.clone();
+    let cache = Arc::new(DecisionCache::new(10, Duration::from_millis(200)));
+    let handler = CorsMiddleware::builder()
+        .allowed_origin_fn(move |origin| {
+            *calls_clone.lock().unwrap() += 1;
+            origin.host.hostname == "example.org"
+        })
+        .decision_cache(cache.clone())
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+
+    for _ in 0..3 {
+        let res = request::get("http://example.org/", setup_origin_header!("example.org"), &handler).unwrap();
+        assert_eq!(res.status, Some(status::Ok));
+        let res = request::get("http://example.org/", setup_origin_header!("example.com"), &handler).unwrap();
+        assert_eq!(res.status, Some(status::BadRequest));
+    }
+    assert_eq!(*calls.lock().unwrap(), 2);
+
+    cache.invalidate("HTTP://example.org:80");
+    request::get("http://example.org/", setup_origin_header!("example.org"), &handler).unwrap();
+    assert_eq!(*calls.lock().unwrap(), 3);
+
+    std::thread::sleep(Duration::from_millis(250));
+    request::get("http://example.org/", setup_origin_header!("example.com"), &handler).unwrap();
+    assert_eq!(*calls.lock().unwrap(), 4);
+
+    // The least recently used decision is evicted from a full cache
+    let calls = Arc::new(Mutex::new(0));
+    let calls_clone = calls.clone();
+    let handler = CorsMiddleware::builder()
+        .allowed_origin_fn(move |_| {
+            *calls_clone.lock().unwrap() += 1;
+            true
+        })
+        .decision_cache(Arc::new(DecisionCache::new(2, Duration::from_secs(60))))
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let hosts = ["a.example.org", "b.example.org", "a.example.org", "c.example.org", "a.example.org", "b.example.org"];
+    for host in hosts {
+        request::get("http://example.org/", setup_origin_header!(host), &handler).unwrap();
+    }
+    assert_eq!(*calls.lock().unwrap(), 4);
+}
+
+#[test]
+fn test_validator_fallback_and_circuit_breaker() {
+    //! A failing validator falls back to the last known decision, and the
+    //! circuit breaker stops calling it while it fails
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use iron_cors::{CircuitBreaker, CircuitState, ValidatorFallback};
+
+    let failing = Arc::new(AtomicBool::new(false));
+    let calls = Arc::new(AtomicUsize::new(0));
+    let (failing_clone, calls_clone) = (failing.clone(), calls.clone());
+    let breaker = Arc::new(CircuitBreaker::new(2, Duration::from_millis(100)));
+    let handler = CorsMiddleware::builder()
+        .try_origin_fn(move |origin| {
+            calls_clone.fetch_add(1, Ordering::SeqCst);
+            if failing_clone.load(Ordering::SeqCst) {
+                return Err("backend unavailable".into());
+            }
+            Ok(origin.host.hostname == "example.org")
+        })
+        .validator_fallback(ValidatorFallback::LastKnown)
+        .circuit_breaker(breaker.clone())
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let status_for = |host: &str| request::get("http://example.org/", setup_origin_header!(host), &handler).unwrap().status;
+
+    assert_eq!(status_for("example.org"), Some(status::Ok));
+    failing.store(true, Ordering::SeqCst);
+    assert_eq!(status_for("example.org"), Some(status::Ok));
+    assert_eq!(status_for("unknown.example.org"), Some(status::BadRequest));
+    assert_eq!(breaker.state(), CircuitState::Open);
+    assert_eq!(breaker.consecutive_failures(), 2);
+
+    // The open breaker doesn't call the validator
+    assert_eq!(status_for("example.org"), Some(status::Ok));
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+    std::thread::sleep(Duration::from_millis(150));
+    assert_eq!(breaker.state(), CircuitState::HalfOpen);
+    failing.store(false, Ordering::SeqCst);
+    assert_eq!(status_for("example.com"), Some(status::BadRequest));
+    assert_eq!(breaker.state(), CircuitState::Closed);
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn test_validator_timeout() {
+    //! Slow validators time out and the fallback decides
+    use std::time::Instant;
+    use iron_cors::ValidatorFallback;
+
+    let handler = CorsMiddleware::builder()
+        .try_origin_fn(|origin| {
+            if origin.host.hostname == "slow.example.org" {
+                std::thread::sleep(Duration::from_millis(500));
+            }
+            Ok(false)
+        })
+        .validator_timeout(Duration::from_millis(50))
+        .validator_fallback(ValidatorFallback::Allow)
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+
+    let res = request::get("http://example.org/", setup_origin_header!("fast.example.org"), &handler).unwrap();
+    assert_eq!(res.status, Some(status::BadRequest));
+    let start = Instant::now();
+    let res = request::get("http://example.org/", setup_origin_header!("slow.example.org"), &handler).unwrap();
+    assert_eq!(res.status, Some(status::Ok));
+    assert!(start.elapsed() < Duration::from_millis(400));
+}
+
+#[test]
+fn test_spec_compliant_denials() {
+    //! Denied preflights and actual requests are answered without CORS
+    //! headers instead of `400 Bad Request`
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org".to_string()])
+        .allowed_methods(vec![iron::method::Get])
+        .disallowed_origin_behavior(DisallowedOriginBehavior::SpecCompliant)
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+
+    let preflights = [("example.com", iron::method::Get), ("example.org", iron::method::Delete)];
+    for (host, method) in preflights {
+        let mut headers = setup_origin_header!(host);
+        headers.set(AccessControlRequestMethod(method));
+        let res = request::options("http://example.org/hello", headers, &handler).unwrap();
+        assert_eq!(res.status, Some(status::Ok));
+        assert!(res.headers.get::<AccessControlAllowOrigin>().is_none());
+        assert!(res.headers.get::<AccessControlAllowMethods>().is_none());
+        assert_eq!(response::extract_body_to_string(res), "");
+    }
+
+    let res = request::get("http://example.org/hello", setup_origin_header!("example.com"), &handler).unwrap();
+    assert_eq!(res.status, Some(status::Ok));
+    assert!(res.headers.get::<AccessControlAllowOrigin>().is_none());
+    assert_eq!(response::extract_body_to_string(res), "Hello, world!");
+}
+
+#[test]
+fn test_classify() {
+    //! Requests are classified without a policy
+    use iron_cors::{classify, CorsRequestKind};
+
+    struct ClassifyHandler;
+
+    impl Handler for ClassifyHandler {
+        fn handle(&self, req: &mut Request) -> IronResult<Response> {
+            Ok(Response::with((status::Ok, format!("{:?}", classify(req)))))
+        }
+    }
+
+    let mut preflight = setup_origin_header!("example.com");
+    preflight.set(AccessControlRequestMethod(iron::method::Get));
+    let mut null = Headers::new();
+    null.set_raw("Origin", vec![b"null".to_vec()]);
+    let requests = [
+        (iron::method::Get, Headers::new(), CorsRequestKind::NotCors),
+        (iron::method::Get, setup_origin_header!("example.org"), CorsRequestKind::SameOrigin),
+        (iron::method::Get, setup_origin_header!("example.com"), CorsRequestKind::Actual),
+        (iron::method::Get, null, CorsRequestKind::Actual),
+        (iron::method::Options, setup_origin_header!("example.com"), CorsRequestKind::Actual),
+        (iron::method::Options, preflight, CorsRequestKind::Preflight),
+    ];
+    for (method, headers, expected) in requests {
+        let res = request::request(method, "http://example.org/", "", headers, &ClassifyHandler).unwrap();
+        assert_eq!(response::extract_body_to_string(res), format!("{:?}", expected));
+    }
+}
+
+#[test]
+fn test_manual_cors_headers() {
+    //! Handlers that aren't wrapped can apply a policy manually
+    use iron_cors::{apply_cors_headers, build_preflight_response};
+
+    struct ManualHandler {
+        policy: CorsPolicy,
+    }
+
+    impl Handler for ManualHandler {
+        fn handle(&self, req: &mut Request) -> IronResult<Response> {
+            if let Some(response) = build_preflight_response(&self.policy, req) {
+                return Ok(response);
+            }
+            let mut response = Response::with((status::Ok, "Upgrade"));
+            if let Some(origin) = req.headers.get::<Origin>().cloned() {
+                apply_cors_headers(&mut response.headers, &self.policy, &origin);
+            }
+            Ok(response)
+        }
+    }
+
+    let policy = CorsPolicy::builder()
+        .allowed_origins(vec!["http://example.com".to_string()])
+        .allowed_methods(vec![iron::method::Get])
+        .build_policy().unwrap();
+    let handler = ManualHandler { policy };
+
+    let mut headers = setup_origin_header!("example.com");
+    headers.set(AccessControlRequestMethod(iron::method::Get));
+    let res = request::options("http://example.org/ws", headers, &handler).unwrap();
+    assert_eq!(res.status, Some(status::Ok));
+    assert_eq!(res.headers.get::<AccessControlAllowMethods>(), Some(&AccessControlAllowMethods(vec![iron::method::Get])));
+
+    let mut headers = setup_origin_header!("example.net");
+    headers.set(AccessControlRequestMethod(iron::method::Get));
+    let res = request::options("http://example.org/ws", headers, &handler).unwrap();
+    assert_eq!(res.status, Some(status::BadRequest));
+
+    let res = request::get("http://example.org/ws", setup_origin_header!("example.com"), &handler).unwrap();
+    assert_eq!(res.headers.get::<AccessControlAllowOrigin>(), Some(&AccessControlAllowOrigin::Value("http://example.com".to_string())));
+    let res = request::get("http://example.org/ws", setup_origin_header!("example.net"), &handler).unwrap();
+    assert!(res.headers.get::<AccessControlAllowOrigin>().is_none());
+}
+
+#[test]
+fn test_preflight_method_not_allowed() {
+    //! Preflights for a method that is not allowed get a `405` response
+    //! with the allowed methods
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org".to_string()])
+        .allowed_methods(vec![iron::method::Get, iron::method::Post])
+        .allowed_headers(vec![UniCase("Content-Type".to_string())])
+        .preflight_method_not_allowed(true)
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+
+    let mut headers = setup_origin_header!("example.org");
+    headers.set(AccessControlRequestMethod(iron::method::Delete));
+    let res = request::options("http://example.org/hello", headers, &handler).unwrap();
+    assert_eq!(res.status, Some(status::MethodNotAllowed));
+    assert_eq!(res.headers.get::<iron::headers::Allow>(), Some(&iron::headers::Allow(vec![iron::method::Get, iron::method::Post])));
+    assert!(res.headers.get::<AccessControlAllowOrigin>().is_none());
+
+    // Disallowed headers are still rejected with `400 Bad Request`
+    let mut headers = setup_origin_header!("example.org");
+    headers.set(AccessControlRequestMethod(iron::method::Get));
+    headers.set(AccessControlRequestHeaders(vec![UniCase("X-Custom".to_string())]));
+    let res = request::options("http://example.org/hello", headers, &handler).unwrap();
+    assert_eq!(res.status, Some(status::BadRequest));
+}
+
+#[test]
+fn test_preflight_cache_control() {
+    //! Successful preflight responses have `Cache-Control` and an empty body
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.org".to_string()])
+        .preflight_cache_control("public, max-age=600")
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+
+    let mut headers = setup_origin_header!("example.org");
+    headers.set(AccessControlRequestMethod(iron::method::Get));
+    let res = request::options("http://example.org/hello", headers, &handler).unwrap();
+    assert_eq!(res.status, Some(status::Ok));
+    assert_eq!(res.headers.get_raw("Cache-Control"), Some(&[b"public, max-age=600".to_vec()][..]));
+    assert_eq!(res.headers.get::<ContentLength>(), Some(&ContentLength(0)));
+
+    let mut headers = setup_origin_header!("example.com");
+    headers.set(AccessControlRequestMethod(iron::method::Get));
+    let res = request::options("http://example.org/hello", headers, &handler).unwrap();
+    assert!(res.headers.get_raw("Cache-Control").is_none());
+}
+
+#[test]
+fn test_raw_origins() {
+    //! Extension origins are allowed by their exact serialization and
+    //! echoed unchanged
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["https://example.org".to_string()])
+        .allowed_raw_origins(vec![
+            "chrome-extension://abcdefghijklmnop".to_string(),
+            "safari-web-extension://E2E2E2E2-1234".to_string(),
+        ])
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+
+    for origin in ["chrome-extension://abcdefghijklmnop", "safari-web-extension://E2E2E2E2-1234"] {
+        let mut headers = Headers::new();
+        headers.set_raw("Origin", vec![origin.as_bytes().to_vec()]);
+        let res = request::get("http://example.org/", headers, &handler).unwrap();
+        assert_eq!(res.status, Some(status::Ok));
+        assert_eq!(res.headers.get::<AccessControlAllowOrigin>(), Some(&AccessControlAllowOrigin::Value(origin.to_string())));
+    }
+
+    // The comparison is exact
+    let mut headers = Headers::new();
+    headers.set_raw("Origin", vec![b"safari-web-extension://e2e2e2e2-1234".to_vec()]);
+    let res = request::get("http://example.org/", headers, &handler).unwrap();
+    assert_eq!(res.status, Some(status::BadRequest));
+}
+
+#[test]
+fn test_allowed_schemes() {
+    //! Origins with other schemes are rejected even if the host matches
+    let handler = CorsMiddleware::builder()
+        .allowed_origin_fn(|origin| origin.host.hostname == "localhost")
+        .allowed_schemes(vec!["https", "Capacitor"])
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+
+    for (origin, expected) in [("https://localhost", status::Ok), ("capacitor://localhost", status::Ok), ("http://localhost", status::BadRequest)] {
+        let mut headers = Headers::new();
+        headers.set_raw("Origin", vec![origin.as_bytes().to_vec()]);
+        let res = request::get("http://example.org/", headers, &handler).unwrap();
+        assert_eq!(res.status, Some(expected), "{}", origin);
+    }
+}
+
+#[test]
+fn test_malformed_origin_policy() {
+    //! Malformed `Origin` headers are ignored by default, or rejected and
+    //! reported with the raw value
+    use iron_cors::MalformedOriginPolicy;
+
+    let mut headers = Headers::new();
+    headers.set_raw("Origin", vec![b"example.com".to_vec()]);
+
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.com".to_string()])
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let res = request::get("http://example.org/", headers.clone(), &handler).unwrap();
+    assert_eq!(res.status, Some(status::Ok));
+
+    let denied = Arc::new(Mutex::new(vec![]));
+    let denied_clone = denied.clone();
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.com".to_string()])
+        .malformed_origin_policy(MalformedOriginPolicy::Reject)
+        .on_denied(move |origin, _| denied_clone.lock().unwrap().push(origin.to_string()))
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let res = request::get("http://example.org/", headers, &handler).unwrap();
+    assert_eq!(res.status, Some(status::BadRequest));
+    assert_eq!(*denied.lock().unwrap(), vec!["example.com".to_string()]);
+}
+
+#[test]
+fn test_reject_multiple_origins() {
+    //! Requests with multiple `Origin` header values can be rejected
+    let mut headers = Headers::new();
+    headers.set_raw("Origin", vec![b"http://example.com".to_vec(), b"http://evil.example.com".to_vec()]);
+
+    let handler = CorsMiddleware::builder()
+        .allowed_origins(vec!["http://example.com".to_string()])
+        .reject_multiple_origins(true)
+        .build().unwrap()
+        .wrap(HelloWorldHandler {});
+    let res = request::get("http://example.org/", headers, &handler).unwrap();
+    assert_eq!(res.status, Some(status::BadRequest));
+    assert_eq!(response::extract_body_to_string(res), "Invalid CORS request: Multiple Origin headers");
+
+    let mut headers = Headers::new();
+    headers.set_raw("Origin", vec![b"http://example.com, http://evil.example.com".to_vec()]);
+    let res = request::get("http://example.org/", headers, &handler).unwrap();
+    assert_eq!(res.status, Some(status::BadRequest));
+    let res = request::get("http://example.org/", setup_origin_header!("example.com"), &handler).unwrap();
+    assert_eq!(res.status, Some(status::Ok));
+}